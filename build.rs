@@ -1,6 +1,7 @@
 //! Generates a static array of songs from the library CSV, a C-library for testing against, and the unit tests that compare library and our evaluation.
 //!
-//! The songs are `include!`'d as a static array into main.
+//! The songs are `include!`'d as a static array into main. `parity_cases.csv` has the same columns, but its beats are
+//! only used in the tests. They cover syntax the library doesn't exercise much.
 //!
 //! Unfortunately, the test code and C-library are always generated -- I couldn't find a way to make a 'build dev' dependency, so to speak.
 //! Known limitation: https://github.com/rust-lang/cargo/issues/1581
@@ -12,15 +13,78 @@ use std::fs::{self, File};
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 
+/// One row of library.csv or parity_cases.csv
+struct Song {
+    author: String,
+    name: String,
+    description: String,
+    code: String,
+    /// Name of a `parser::Mode` variant. Empty (or missing column) is classic.
+    mode: String,
+    /// C type of 't', like `parser::TType`. Empty (or missing column) is `int`.
+    t_type: String,
+    /// Hertz, like `parser::SampleRate`. Empty (or missing column) is 8000.
+    rate: String,
+}
+
+impl Song {
+    /// Only classic songs can be compared against C
+    fn is_classic(&self) -> bool {
+        matches!(self.mode.as_str(), "" | "classic")
+    }
+
+    fn mode_variant(&self) -> &'static str {
+        match self.mode.as_str() {
+            "" | "classic" => "Classic",
+            "float" => "Float",
+            other => panic!("Unknown mode {other} in library.csv"),
+        }
+    }
+
+    fn t_type_variant(&self) -> &'static str {
+        match self.t_type.as_str() {
+            "" | "int" => "Int",
+            "unsigned" => "Unsigned",
+            other => panic!("Unknown t type {other} in library.csv"),
+        }
+    }
+
+    fn rate_hz(&self) -> u32 {
+        match self.rate.as_str() {
+            "" => 8000,
+            other => match other.parse() {
+                Ok(hz) if hz > 0 => hz,
+                _ => panic!("Unknown sample rate {other} in library.csv"),
+            },
+        }
+    }
+}
+
 fn main() {
     println!("cargo::rerun-if-changed=library.csv");
+    println!("cargo::rerun-if-changed=parity_cases.csv");
     println!("cargo::rerun-if-changed=codegen_templates/generate_references.c");
 
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
-    let csv_path = Path::new(&manifest_dir).join("library.csv");
     let out_dir = env::var("OUT_DIR").unwrap();
 
-    let file = File::open(&csv_path).expect("Failed to open library.csv");
+    let songs = read_songs(&Path::new(&manifest_dir).join("library.csv"));
+    let cases = read_songs(&Path::new(&manifest_dir).join("parity_cases.csv"));
+
+    generate_library_array(&songs, &out_dir);
+
+    let parity_songs: Vec<&Song> = songs
+        .iter()
+        .chain(cases.iter())
+        .filter(|s| s.is_classic())
+        .collect();
+    build_parity_library(&parity_songs, &out_dir, &manifest_dir);
+
+    generate_parity_tests(&parity_songs, &out_dir);
+}
+
+fn read_songs(csv_path: &Path) -> Vec<Song> {
+    let file = File::open(csv_path).unwrap_or_else(|_| panic!("Failed to open {csv_path:?}"));
     let reader = BufReader::new(file);
 
     let mut songs = Vec::new();
@@ -35,20 +99,20 @@ fn main() {
             continue;
         }
 
-        if let Some((author, name, description, code)) = parse_csv_line(&line) {
-            songs.push((author, name, description, code));
+        match parse_csv_line(&line) {
+            Ok(song) => songs.push(song),
+            Err(fields) => panic!(
+                "{fields} fields on line {} of {csv_path:?}, expected 4 to 7. Is a comma unquoted?",
+                line_num + 1
+            ),
         }
     }
-
-    generate_library_array(&songs, &out_dir);
-
-    build_parity_library(&songs, &out_dir, &manifest_dir);
-
-    generate_parity_tests(&songs, &out_dir);
+    songs
 }
 
 /// P.S: This is AI slop. It works so I don't touch it.
-fn parse_csv_line(line: &str) -> Option<(String, String, String, String)> {
+/// How many fields there are, if it's the wrong number
+fn parse_csv_line(line: &str) -> Result<Song, usize> {
     // Simple CSV parser that handles quoted fields with escaped quotes
     let mut fields = Vec::new();
     let mut current = String::new();
@@ -82,15 +146,20 @@ fn parse_csv_line(line: &str) -> Option<(String, String, String, String)> {
     }
     fields.push(current);
 
-    if fields.len() >= 4 {
-        Some((
-            fields[0].clone(),
-            fields[1].clone(),
-            fields[2].clone(),
-            fields[3..].join(","), // Join remaining fields in case code contains commas
-        ))
+    // Mode, t type and rate columns are optional
+    if (4..=7).contains(&fields.len()) {
+        let mut fields = fields.into_iter();
+        Ok(Song {
+            author: fields.next().unwrap(),
+            name: fields.next().unwrap(),
+            description: fields.next().unwrap(),
+            code: fields.next().unwrap(),
+            mode: fields.next().unwrap_or_default(),
+            t_type: fields.next().unwrap_or_default(),
+            rate: fields.next().unwrap_or_default(),
+        })
     } else {
-        None
+        Err(fields.len())
     }
 }
 
@@ -99,11 +168,7 @@ fn escape_raw_string(s: &str) -> String {
     s.replace("\"#", "\"##HASH##")
 }
 
-fn build_parity_library(
-    songs: &[(String, String, String, String)],
-    out_dir: &str,
-    manifest_dir: &str,
-) {
+fn build_parity_library(songs: &[&Song], out_dir: &str, manifest_dir: &str) {
     let template_path = Path::new(manifest_dir).join("codegen_templates/generate_references.c");
     let out_path = Path::new(out_dir).join("generate_references.c");
 
@@ -120,13 +185,18 @@ fn build_parity_library(
     write!(out_buf, "{}", template).unwrap();
     writeln!(out_buf).unwrap();
 
-    // Generate a function for each song
-    for (idx, (_author, _name, _description, code)) in songs.iter().enumerate() {
-        writeln!(
-            out_buf,
-            "uint8_t song_{}(int32_t t) {{ return {}; }}",
-            idx, code
-        )
+    // Generate a function for each song. Every entry of the table takes an `int`, so songs with an unsigned 't'
+    // are wrapped, and the conversion happens when it's passed on.
+    for (idx, song) in songs.iter().enumerate() {
+        let body = c_function_body(&song.code);
+        match song.t_type_variant() {
+            "Unsigned" => writeln!(
+                out_buf,
+                "static uint8_t song_{idx}_unsigned(unsigned t) {{ {body} }}\n\
+                uint8_t song_{idx}(int32_t t) {{ return song_{idx}_unsigned(t); }}"
+            ),
+            _ => writeln!(out_buf, "uint8_t song_{idx}(int32_t t) {{ {body} }}"),
+        }
         .unwrap();
     }
 
@@ -147,7 +217,165 @@ fn build_parity_library(
         .compile("parity_dispatcher");
 }
 
-fn generate_library_array(songs: &[(String, String, String, String)], out_dir: &str) {
+/// Local variables become zeroed `int`s, and the last `;`-separated statement is returned.
+fn c_function_body(code: &str) -> String {
+    let mut body = String::new();
+    let code = strip_comments(code);
+    let code = c_array_literals(&code);
+    let code = code.as_str();
+
+    let locals = c_locals(code);
+    if !locals.is_empty() {
+        let declarations: Vec<String> = locals.iter().map(|name| format!("{name} = 0")).collect();
+        body.push_str(&format!("int {}; ", declarations.join(", ")));
+    }
+
+    let mut statements = split_statements(code);
+    let last = statements.pop().unwrap_or_default();
+    for statement in statements {
+        body.push_str(&format!("{statement}; "));
+    }
+    body.push_str(&format!("return {last};"));
+    body
+}
+
+/// Types that may show up in casts
+const C_KEYWORDS: &[&str] = &[
+    "int", "unsigned", "signed", "char", "short", "long", "float", "double",
+];
+
+/// Names that aren't 't', a keyword or a function call, in order of first appearance. Skips over numbers, so the letters in
+/// hex literals aren't mistaken for names.
+fn c_locals(code: &str) -> Vec<String> {
+    let mut locals: Vec<String> = Vec::new();
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' || c == '\'' {
+            skip_quoted(c, &mut chars, &mut String::new());
+        } else if c.is_ascii_digit() {
+            while chars.next_if(|c| c.is_ascii_alphanumeric()).is_some() {}
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = c.to_string();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let is_call = chars.peek() == Some(&'(');
+            let is_keyword = C_KEYWORDS.contains(&name.as_str());
+            if name != "t" && !is_call && !is_keyword && !locals.contains(&name) {
+                locals.push(name);
+            }
+        }
+    }
+    locals
+}
+
+/// Splits on `;` outside of brackets. A trailing `;` doesn't make an empty statement.
+fn split_statements(code: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                current.push(c);
+                skip_quoted(c, &mut chars, &mut current);
+                continue;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ';' if depth == 0 => {
+                statements.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || statements.is_empty() {
+        statements.push(current);
+    }
+    statements
+}
+
+/// Array literals like `[1, 2][i]` become compound literals like `((int[]){1, 2})[i]`. A '[' that follows an
+/// operand is indexing instead.
+fn c_array_literals(code: &str) -> String {
+    let mut out = String::new();
+    // Whether each open '[' started a literal
+    let mut brackets = Vec::new();
+    let mut follows_operand = false;
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                out.push(c);
+                skip_quoted(c, &mut chars, &mut out);
+            }
+            '[' => {
+                brackets.push(!follows_operand);
+                out.push_str(if follows_operand { "[" } else { "((int[]){" });
+            }
+            ']' => out.push_str(if brackets.pop() == Some(true) {
+                "})"
+            } else {
+                "]"
+            }),
+            _ => out.push(c),
+        }
+        if !c.is_whitespace() {
+            follows_operand =
+                c.is_ascii_alphanumeric() || matches!(c, '_' | ')' | ']' | '"' | '\'');
+        }
+    }
+    out
+}
+
+/// Comments become a space, so the other passes don't find names or `;` inside them.
+fn strip_comments(code: &str) -> String {
+    let mut out = String::new();
+    let mut chars = code.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('"' | '\'', _) => {
+                out.push(c);
+                skip_quoted(c, &mut chars, &mut out);
+            }
+            ('/', Some('/')) => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                out.push(' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Copies the rest of a quoted literal into `out`, through the closing `quote`.
+fn skip_quoted(quote: char, chars: &mut impl Iterator<Item = char>, out: &mut String) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '\\' {
+            out.extend(chars.next());
+        } else if c == quote {
+            return;
+        }
+    }
+}
+
+fn generate_library_array(songs: &[Song], out_dir: &str) {
     let out_path = Path::new(out_dir).join("library_data.rs");
 
     let mut out_buf =
@@ -166,14 +394,17 @@ fn generate_library_array(songs: &[(String, String, String, String)], out_dir: &
     .unwrap();
     writeln!(out_buf, "pub const SONGS: &[Song] = &[").unwrap();
 
-    for (author, name, description, code) in songs {
+    for song in songs {
         writeln!(
             out_buf,
-            "    Song {{ author: r#\"{}\"#, name: r#\"{}\"#, description: r#\"{}\"#, code: r#\"{}\"# }},",
-            escape_raw_string(author),
-            escape_raw_string(name),
-            escape_raw_string(description),
-            escape_raw_string(code)
+            "    Song {{ author: r#\"{}\"#, name: r#\"{}\"#, description: r#\"{}\"#, code: r#\"{}\"#, mode: crate::parser::Mode::{}, t_type: crate::parser::TType::{}, rate: crate::parser::SampleRate::new({}).unwrap() }},",
+            escape_raw_string(&song.author),
+            escape_raw_string(&song.name),
+            escape_raw_string(&song.description),
+            escape_raw_string(&song.code),
+            song.mode_variant(),
+            song.t_type_variant(),
+            song.rate_hz()
         ).unwrap();
     }
 
@@ -181,7 +412,7 @@ fn generate_library_array(songs: &[(String, String, String, String)], out_dir: &
     out_buf.flush().unwrap();
 }
 
-fn generate_parity_tests(songs: &[(String, String, String, String)], out_dir: &str) {
+fn generate_parity_tests(songs: &[&Song], out_dir: &str) {
     // The 'stub' is in the sourcecode so no template is needed
     let out_path = Path::new(out_dir).join("parity_tests.rs");
     let mut out_buf =
@@ -193,10 +424,10 @@ fn generate_parity_tests(songs: &[(String, String, String, String)], out_dir: &s
     )
     .unwrap();
     writeln!(out_buf).unwrap();
-    for (idx, (author, name, _description, code)) in songs.iter().enumerate() {
+    for (idx, song) in songs.iter().enumerate() {
         // Make a snake_case test name from author and song name
-        let author_part = sanitize_identifier(author);
-        let name_part = sanitize_identifier(name);
+        let author_part = sanitize_identifier(&song.author);
+        let name_part = sanitize_identifier(&song.name);
         let parts: Vec<&str> = [author_part.as_str(), name_part.as_str()]
             .into_iter()
             .filter(|s| !s.is_empty())
@@ -211,10 +442,15 @@ fn generate_parity_tests(songs: &[(String, String, String, String)], out_dir: &s
         writeln!(
             out_buf,
             "    let code = r#\"{}\"#;",
-            escape_raw_string(code)
+            escape_raw_string(&song.code)
+        )
+        .unwrap();
+        writeln!(
+            out_buf,
+            "    compare_song(song_idx as i32, code, TType::{});",
+            song.t_type_variant()
         )
         .unwrap();
-        writeln!(out_buf, "    compare_song(song_idx as i32, code);").unwrap();
         writeln!(out_buf, "}}").unwrap();
     }
 
@@ -10,6 +10,9 @@
 // Rust tests, too)
 #pragma clang diagnostic ignored "-Wbitwise-conditional-parentheses"
 #pragma clang diagnostic ignored "-Wparentheses"
+// Mixing signed and unsigned is the point of some beats. Casts, literal suffixes
+// and 'long' need nothing else: they're compiled as written, for x86-64
+#pragma clang diagnostic ignored "-Wsign-compare"
 
 typedef uint8_t beat_function(int t);
 extern beat_function *songs[];
@@ -17,4 +20,5 @@ uint8_t generate_sample(int32_t song_idx, int32_t t) {
   return songs[song_idx](t);
 }
 
-// build.rs will add jump table with a function for each song below.
\ No newline at end of file
+// build.rs will add jump table with a function for each song below. Songs with
+// an unsigned 't' are wrapped, so they fit the table too.
\ No newline at end of file
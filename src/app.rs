@@ -1,21 +1,37 @@
-use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicU64;
 
 use color_eyre::Result;
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent};
 use ratatui::DefaultTerminal;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 
 use crate::{
     app::input::BeatInput,
-    audio::{AudioEvent, StreamStatus, Volume},
+    audio::{
+        AudioEvent, ScopeSample, StreamStatus, Volume,
+        effects::EffectSettings,
+        mix::Combine,
+        record::Recording,
+        switch::{SwitchPolicy, SwitchStatus},
+        transport::{Speed, TransportCommand},
+    },
     event::{Event, EventHandler},
+    parser::{self, CompileOptions, KnobId, Mode, PrintStyle, SampleRate, TType},
 };
 
+mod effects;
 pub mod input; // TODO: Not pretty, has to be pub so we can make it in main :(
+mod knobs;
+mod layers;
 mod library;
 mod scope;
 mod ui;
 
+/// How many samples from 't' = 0 a beat is checked for undefined behaviour. ~8 seconds at 8kHz
+const CHECKED_SAMPLES: i32 = 1 << 16;
+/// How far [`AppEvent::Jump`] goes at a time
+const JUMP_SECONDS: i32 = 5;
+
 /// Every widget owned by [`App`] implements this to handle delegated events
 ///
 /// They should also implement a 'renderable' ratatui trait, but I won't use supertrait here
@@ -32,6 +48,9 @@ pub trait Component {
             {
                 self.handle_key_event(event)
             }
+            Event::Crossterm(crossterm::event::Event::Mouse(event)) => {
+                self.handle_mouse_event(event)
+            }
             Event::Crossterm(_) => None,
             Event::Tick => self.handle_tick(),
             Event::FileWatch(event) => self.handle_filewatch(event),
@@ -43,6 +62,11 @@ pub trait Component {
         None
     }
 
+    #[allow(unused)]
+    fn handle_mouse_event(&mut self, event: MouseEvent) -> Option<AppEvent> {
+        None
+    }
+
     #[allow(unused)]
     fn handle_tick(&mut self) -> Option<AppEvent> {
         None
@@ -58,15 +82,45 @@ pub trait Component {
 /// Returned from component-specific update methods or methods of [`App`]
 /// only these events mutate state directly.
 pub enum AppEvent {
-    /// Input OR Library wants you to play this sick beat
+    /// Input wants you to play this sick beat
     InputReady(String),
-    /// Library wants you to play this AND over-write the Input
-    BeatOverwrite(String),
+    /// Library wants you to play this in its own mode, 't' type and rate, without touching the Input
+    BeatSample(String, Mode, TType, SampleRate),
+    /// Library wants you to play this AND over-write the Input (and its mode, 't' type and rate)
+    BeatOverwrite(String, Mode, TType, SampleRate),
+    /// Input wants this printed canonically, or minified if it already is
+    Reformat(String),
     // All these were formerly immediate & hardcoded in handle_key_event
     VolumeUp,
     VolumeDown,
     Quit,
     TogglePlay,
+    /// Flip between classic and floatbeat for the Input
+    ToggleMode,
+    /// Play the Input at the next of [`SampleRate::COMMON`]
+    CycleRate,
+    /// Play from 't' = 0 again
+    Restart,
+    /// Move 't' this many seconds (of the playing beat), back if negative
+    Jump(i32),
+    ToggleReverse,
+    SpeedUp,
+    SpeedDown,
+    /// Start a loop where it's heard now, then end it there, then stop looping
+    MarkLoop,
+    ToggleRecord,
+    /// Switch new beats in the next way
+    CycleTransition,
+    /// Turn every effect off, or back on
+    ToggleBypass,
+    /// Effects panel wants the audio thread to use these
+    EffectsChanged(EffectSettings),
+    /// A layer's input wants this played in that layer
+    LayerReady(usize, String),
+    /// Layers were added, removed or are heard differently
+    MixChanged,
+    /// Knobs panel wants a knob of the Input's beat turned to this
+    KnobTurned(KnobId, f64),
     /// Changes to this specific view
     ChangeView(View),
     /// Esc action, will close help or return to main view
@@ -83,6 +137,43 @@ pub enum View {
     Main,
     BigLog,
     Library,
+    Layers,
+    Effects,
+    Knobs,
+}
+
+/// A-B repeat, marked at whatever 't' is heard at the time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LoopMarks {
+    #[default]
+    Off,
+    /// Waiting for the other end
+    Start(i32),
+    /// Start is inclusive, end isn't
+    Between(i32, i32),
+}
+
+impl LoopMarks {
+    /// What comes after marking at `t`
+    fn mark(self, t: i32) -> Self {
+        match self {
+            LoopMarks::Off => LoopMarks::Start(t),
+            LoopMarks::Start(start) => {
+                LoopMarks::Between(start.min(t), start.max(t).saturating_add(1))
+            }
+            LoopMarks::Between(..) => LoopMarks::Off,
+        }
+    }
+}
+
+/// How the [`App`] starts out, mostly from the command line
+pub struct AppSettings {
+    /// For the Input
+    pub options: CompileOptions,
+    pub check_undefined: bool,
+    pub combine: Combine,
+    pub switch_policy: SwitchPolicy,
+    pub effects: EffectSettings,
 }
 
 pub struct App<I: BeatInput> {
@@ -94,10 +185,33 @@ pub struct App<I: BeatInput> {
     audio_state: StreamStatus,
     /// No boost, only decrease.
     audio_vol: Volume,
+    /// Of the beat last sent to play. Seconds of 't' are counted in it
+    playing_rate: SampleRate,
+    /// As we wish them, like paused
+    reverse: bool,
+    speed: Speed,
+    loop_marks: LoopMarks,
+    /// Written by the recorder thread
+    recording: &'static Recording,
+    switch_policy: SwitchPolicy,
+    /// Written by the audio thread
+    switch: &'static SwitchStatus,
     // TODO: undo/redo system shouldn't be that hard. later.
     beat_input: I,
+    /// Used for whatever comes out of the Input
+    options: CompileOptions,
+    /// Look for behaviour C leaves undefined in every beat that compiles
+    check_undefined: bool,
     scope: scope::Scope,
     library: library::Library,
+    /// Layer 0 plays the Input
+    layers: layers::Layers,
+    /// What every other layer is compiled with: the Input's options, at the rate layer 0 plays at. A mix plays at
+    /// its first layer's rate, so a layer compiled for another would be off pitch
+    layer_options: CompileOptions,
+    effects: effects::Effects,
+    /// Of the Input's beat
+    knobs: knobs::Knobs,
     view: View,
     /// We can draw the help modal with (over) any view
     show_help: bool,
@@ -106,19 +220,42 @@ pub struct App<I: BeatInput> {
 impl<I: BeatInput> App<I> {
     pub fn new(
         events: EventHandler,
-        consumer: rtrb::Consumer<u8>,
-        t_play: &'static AtomicI32,
+        consumer: rtrb::Consumer<ScopeSample>,
+        played: &'static AtomicU64,
+        recording: &'static Recording,
+        switch: &'static SwitchStatus,
         beat_input: I,
+        settings: AppSettings,
     ) -> Self {
+        let AppSettings {
+            options,
+            check_undefined,
+            combine,
+            switch_policy,
+            effects,
+        } = settings;
         Self {
             running: true,
             events,
             paused: true,
             audio_state: StreamStatus::Unconnected,
             audio_vol: Volume::default(),
+            playing_rate: options.rate,
+            reverse: false,
+            speed: Speed::default(),
+            loop_marks: LoopMarks::Off,
+            recording,
+            switch_policy,
+            switch,
             beat_input,
-            scope: scope::Scope::new(consumer, t_play),
+            options,
+            check_undefined,
+            scope: scope::Scope::new(consumer, played),
             library: library::Library::new(),
+            layers: layers::Layers::new(combine),
+            layer_options: options,
+            effects: effects::Effects::new(effects),
+            knobs: knobs::Knobs::default(),
             view: View::Main,
             show_help: false,
         }
@@ -144,18 +281,42 @@ impl<I: BeatInput> App<I> {
                         if self.paused {
                             self.toggle_playback();
                         }
-                        self.try_beat(&code);
+                        self.try_beat(&code, self.options);
                     }
-                    AppEvent::BeatOverwrite(code) => {
+                    AppEvent::BeatSample(code, mode, t_type, rate) => {
+                        if self.paused {
+                            self.toggle_playback();
+                        }
+                        self.try_beat(
+                            &code,
+                            CompileOptions {
+                                mode,
+                                t_type,
+                                rate,
+                                ..self.options
+                            },
+                        );
+                    }
+                    AppEvent::BeatOverwrite(code, mode, t_type, rate) => {
                         if self.paused {
                             self.toggle_playback();
                         }
                         let _ = self.beat_input.set_buffer(code.clone());
-                        let _ = self.events.new_beat(&code).map_err(|e| {
-                        error!(
-                            "library sent a hardcoded beat that had an error (embarrassing): {e:?}"
-                        )
-                    });
+                        self.options.mode = mode;
+                        self.options.t_type = t_type;
+                        self.options.rate = rate;
+                        match parser::Beat::compile(&code, &self.options) {
+                            Ok(beat) => {
+                                self.playing_rate = rate;
+                                self.play_layer(0, beat, &code);
+                            }
+                            Err(e) => error!(
+                                "library sent a hardcoded beat that had an error (embarrassing): {e:?}"
+                            ),
+                        }
+                    }
+                    AppEvent::Reformat(code) => {
+                        self.reformat(&code);
                     }
                     AppEvent::VolumeUp => {
                         self.incr_volume();
@@ -169,6 +330,80 @@ impl<I: BeatInput> App<I> {
                     AppEvent::TogglePlay => {
                         self.toggle_playback();
                     }
+                    AppEvent::ToggleMode => {
+                        self.options.mode = self.options.mode.toggle();
+                        info!("input mode is now {}", self.options.mode);
+                        self.try_beat(&self.beat_input.get_buffer(), self.options);
+                    }
+                    AppEvent::CycleRate => {
+                        self.options.rate = self.options.rate.next();
+                        info!("input rate is now {}", self.options.rate);
+                        self.try_beat(&self.beat_input.get_buffer(), self.options);
+                    }
+                    AppEvent::Restart => {
+                        self.events.transport(TransportCommand::Seek(0));
+                    }
+                    AppEvent::Jump(seconds) => {
+                        let by = seconds.saturating_mul(self.playing_rate.hz() as i32);
+                        self.events.transport(TransportCommand::Jump(by));
+                    }
+                    AppEvent::ToggleReverse => {
+                        self.reverse = !self.reverse;
+                        self.events
+                            .transport(TransportCommand::Reverse(self.reverse));
+                    }
+                    AppEvent::SpeedUp => {
+                        self.speed = self.speed.faster();
+                        self.events.transport(TransportCommand::Speed(self.speed));
+                    }
+                    AppEvent::SpeedDown => {
+                        self.speed = self.speed.slower();
+                        self.events.transport(TransportCommand::Speed(self.speed));
+                    }
+                    AppEvent::MarkLoop => {
+                        self.loop_marks = self.loop_marks.mark(self.scope.t_heard());
+                        match self.loop_marks {
+                            LoopMarks::Between(start, end) => self
+                                .events
+                                .transport(TransportCommand::Loop(Some((start, end)))),
+                            LoopMarks::Off => self.events.transport(TransportCommand::Loop(None)),
+                            LoopMarks::Start(_) => {}
+                        }
+                    }
+                    AppEvent::ToggleRecord => {
+                        let recording = !self.recording.is_recording();
+                        info!("recording {}", if recording { "on" } else { "off" });
+                        self.recording.set(recording);
+                    }
+                    AppEvent::CycleTransition => {
+                        self.switch_policy.transition = self.switch_policy.transition.next();
+                        info!("switching new beats: {}", self.switch_policy);
+                        self.events
+                            .transport(TransportCommand::Switch(self.switch_policy));
+                    }
+                    AppEvent::ToggleBypass => {
+                        let settings = self.effects.toggle_bypass();
+                        info!(
+                            "effects {}",
+                            if settings.bypass { "bypassed" } else { "on" }
+                        );
+                        self.events.set_effects(settings);
+                    }
+                    AppEvent::EffectsChanged(settings) => {
+                        self.events.set_effects(settings);
+                    }
+                    AppEvent::LayerReady(index, code) => {
+                        if self.paused {
+                            self.toggle_playback();
+                        }
+                        self.try_layer(index, &code);
+                    }
+                    AppEvent::MixChanged => {
+                        self.events.new_mix(self.layers.mix());
+                    }
+                    AppEvent::KnobTurned(id, value) => {
+                        self.turn_knob(id, value);
+                    }
                     AppEvent::ChangeView(view) => {
                         self.change_view(view);
                     }
@@ -216,12 +451,29 @@ impl<I: BeatInput> App<I> {
         // Handle global keys now
         if let crossterm::event::Event::Key(key) = event {
             if key.kind == KeyEventKind::Press {
+                let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
                 if let Some(resp) = match key.code {
                     KeyCode::F(1) => Some(AppEvent::ToggleHelp),
                     KeyCode::F(2) => Some(AppEvent::ChangeView(View::BigLog)),
                     KeyCode::F(3) => Some(AppEvent::Quit),
                     KeyCode::F(4) => Some(AppEvent::TogglePlay),
                     KeyCode::F(5) => Some(AppEvent::ChangeView(View::Library)),
+                    KeyCode::F(6) => Some(AppEvent::ChangeView(View::Layers)),
+                    KeyCode::F(7) => Some(AppEvent::ToggleMode),
+                    KeyCode::F(11) => Some(AppEvent::ChangeView(View::Effects)),
+                    KeyCode::F(9) => Some(AppEvent::CycleRate),
+                    KeyCode::F(10) => Some(AppEvent::ToggleReverse),
+                    KeyCode::F(12) => Some(AppEvent::MarkLoop),
+                    KeyCode::Home => Some(AppEvent::Restart),
+                    KeyCode::Char('r') if ctrl => Some(AppEvent::ToggleRecord),
+                    KeyCode::Char('t') if ctrl => Some(AppEvent::CycleTransition),
+                    KeyCode::Char('b') if ctrl => Some(AppEvent::ToggleBypass),
+                    KeyCode::Char('k') if ctrl => Some(AppEvent::ChangeView(View::Knobs)),
+                    KeyCode::Left if shift => Some(AppEvent::Jump(-JUMP_SECONDS)),
+                    KeyCode::Right if shift => Some(AppEvent::Jump(JUMP_SECONDS)),
+                    KeyCode::Up if shift => Some(AppEvent::SpeedUp),
+                    KeyCode::Down if shift => Some(AppEvent::SpeedDown),
                     KeyCode::Esc => Some(AppEvent::ViewBack),
                     KeyCode::Up => Some(AppEvent::VolumeUp),
                     KeyCode::Down => Some(AppEvent::VolumeDown),
@@ -236,6 +488,15 @@ impl<I: BeatInput> App<I> {
         match self.view {
             View::Main => self.beat_input.handle_event(Event::Crossterm(event)),
             View::Library => self.library.handle_event(Event::Crossterm(event)),
+            // The Input is still edited here while its layer is selected
+            View::Layers => match event {
+                crossterm::event::Event::Key(key) if !self.layers.takes(&key) => {
+                    self.beat_input.handle_event(Event::Crossterm(event))
+                }
+                _ => self.layers.handle_event(Event::Crossterm(event)),
+            },
+            View::Effects => self.effects.handle_event(Event::Crossterm(event)),
+            View::Knobs => self.knobs.handle_event(Event::Crossterm(event)),
             View::BigLog => None,
         }
     }
@@ -244,7 +505,7 @@ impl<I: BeatInput> App<I> {
         // We're leaving the library and may have a 'sample' playing
         // So we need to pull from the buffer to over-write it @ backend
         if self.view == View::Library {
-            self.try_beat(&self.beat_input.get_buffer());
+            self.try_beat(&self.beat_input.get_buffer(), self.options);
         }
         self.view = view;
     }
@@ -283,10 +544,138 @@ impl<I: BeatInput> App<I> {
     }
 
     /// Try-compile and play new are one operation from the user's perspective
-    fn try_beat(&mut self, code: &str) {
-        match self.events.new_beat(code) {
-            Ok(_) => self.beat_input.clear_errors(),
-            Err(errs) => self.beat_input.set_errors(errs),
+    fn try_beat(&mut self, code: &str, options: CompileOptions) {
+        match self.compile(code, &options) {
+            Ok((beat, warnings)) => {
+                self.playing_rate = options.rate;
+                self.beat_input.clear_errors();
+                self.beat_input.set_warnings(code, warnings);
+                self.play_layer(0, beat, code);
+            }
+            Err(errs) => {
+                self.beat_input.set_errors(code, errs);
+                self.beat_input.clear_warnings();
+            }
+        }
+    }
+
+    /// Like [`App::try_beat`] for any other layer, see [`App::layer_options`]
+    fn try_layer(&mut self, index: usize, code: &str) {
+        let compiled = self.compile(code, &self.layer_options);
+        let Some(input) = self.layers.input_mut(index) else {
+            return;
+        };
+        match compiled {
+            Ok((beat, warnings)) => {
+                input.clear_errors();
+                input.set_warnings(code, warnings);
+                self.play_layer(index, beat, code);
+            }
+            Err(errs) => {
+                input.set_errors(code, errs);
+                input.clear_warnings();
+            }
+        }
+    }
+
+    /// Along with its warnings, and those from [`parser::check_undefined`] if we're checking. Warnings are logged too
+    fn compile(
+        &self,
+        code: &str,
+        options: &CompileOptions,
+    ) -> Result<(parser::Beat, Vec<parser::Warning>), Vec<parser::ParseError>> {
+        let beat = parser::Beat::compile(code, options)?;
+        let mut warnings = beat.warnings().to_vec();
+        if self.check_undefined {
+            // Blocks for the whole check, which is much quicker than playing it. It just compiled, so it can't fail
+            // to parse now.
+            let undefined = parser::check_undefined(code, options, 0..CHECKED_SAMPLES);
+            warnings.extend(undefined.unwrap_or_default());
+        }
+        for warning in &warnings {
+            warn!("{warning}");
+        }
+        Ok((beat, warnings))
+    }
+
+    /// Puts `beat` in its layer and sends the whole mix off to play
+    fn play_layer(&mut self, index: usize, beat: parser::Beat, code: &str) {
+        if index == 0 {
+            self.knobs.set_knobs(beat.knobs());
+            let options = CompileOptions {
+                rate: beat.rate(),
+                ..self.options
+            };
+            if options != self.layer_options {
+                self.layer_options = options;
+                self.recompile_layers();
+            }
+        }
+        self.layers.set_beat(index, beat, code);
+        self.events.new_mix(self.layers.mix());
+    }
+
+    /// Every layer besides the first, with the [`App::layer_options`] they're compiled with now. One that doesn't
+    /// compile any more is silent, rather than played at the wrong rate
+    fn recompile_layers(&mut self) {
+        for index in 1.. {
+            let Some(code) = self.layers.code(index).map(str::to_owned) else {
+                break;
+            };
+            if code.is_empty() {
+                continue;
+            }
+            let compiled = self.compile(&code, &self.layer_options);
+            let Some(input) = self.layers.input_mut(index) else {
+                continue;
+            };
+            let beat = match compiled {
+                Ok((beat, warnings)) => {
+                    input.clear_errors();
+                    input.set_warnings(&code, warnings);
+                    beat
+                }
+                Err(errs) => {
+                    input.set_errors(&code, errs);
+                    input.clear_warnings();
+                    parser::Beat::default()
+                }
+            };
+            self.layers.set_beat(index, beat, &code);
+        }
+    }
+
+    /// Heard straight away, since the beat playing reads its knobs as it goes. The Input is rewritten with where
+    /// every knob is now, so compiling it again sounds the same.
+    fn turn_knob(&mut self, id: KnobId, value: f64) {
+        let Some(beat) = self.layers.beat(0) else {
+            return;
+        };
+        if beat.set_knob(id, value).is_none() {
+            return;
+        }
+        let knobs = beat.knobs();
+        let code = parser::knobs::declare(&self.beat_input.get_buffer(), &knobs);
+        let _ = self.beat_input.set_buffer(code);
+        self.knobs.set_knobs(knobs);
+    }
+
+    /// Over-writes the Input. Errors are shown like a failed compile would, and the Input is left alone.
+    fn reformat(&mut self, code: &str) {
+        let formatted =
+            parser::format(code, self.options.mode, PrintStyle::Canonical).and_then(|canonical| {
+                if canonical == code {
+                    parser::format(code, self.options.mode, PrintStyle::Minify)
+                } else {
+                    Ok(canonical)
+                }
+            });
+        match formatted {
+            Ok(formatted) => {
+                let _ = self.beat_input.set_buffer(formatted);
+                self.beat_input.clear_errors();
+            }
+            Err(errs) => self.beat_input.set_errors(code, errs),
         }
     }
 
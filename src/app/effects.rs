@@ -0,0 +1,257 @@
+//! Component for turning effects on and off, and how much of each
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Row, Table, Widget},
+};
+
+use crate::{
+    app::{AppEvent, Component},
+    audio::effects::{EffectSettings, MAX_DELAY_MS},
+};
+
+/// One row each. Those without an on/off of their own belong to the effect above them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Param {
+    Bypass,
+    Crush,
+    CrushBits,
+    CrushHold,
+    DcBlock,
+    HighPass,
+    LowPass,
+    Delay,
+    DelayFeedback,
+    DelayMix,
+    Widen,
+}
+
+const PARAMS: &[Param] = &[
+    Param::Bypass,
+    Param::Crush,
+    Param::CrushBits,
+    Param::CrushHold,
+    Param::DcBlock,
+    Param::HighPass,
+    Param::LowPass,
+    Param::Delay,
+    Param::DelayFeedback,
+    Param::DelayMix,
+    Param::Widen,
+];
+
+/// Cutoffs move by a third of an octave
+const CUTOFF_STEP: f32 = 1.259_921;
+const DELAY_STEP_MS: u32 = 10;
+const AMOUNT_STEP: f32 = 0.05;
+const WIDTH_STEP: f32 = 0.1;
+/// Frames a crushed sample can be held for
+const MAX_HOLD: u16 = 64;
+
+impl Param {
+    fn label(self) -> &'static str {
+        match self {
+            Param::Bypass => "Bypass all",
+            Param::Crush => "Bitcrush",
+            Param::CrushBits => "  Bits",
+            Param::CrushHold => "  Hold",
+            Param::DcBlock => "DC blocker",
+            Param::HighPass => "High-pass",
+            Param::LowPass => "Low-pass",
+            Param::Delay => "Delay",
+            Param::DelayFeedback => "  Feedback",
+            Param::DelayMix => "  Mix",
+            Param::Widen => "Widen",
+        }
+    }
+
+    /// What Enter toggles, if anything
+    fn switch(self, settings: &mut EffectSettings) -> Option<&mut bool> {
+        match self {
+            Param::Bypass => Some(&mut settings.bypass),
+            Param::Crush => Some(&mut settings.crush),
+            Param::DcBlock => Some(&mut settings.dc_block),
+            Param::HighPass => Some(&mut settings.high_pass),
+            Param::LowPass => Some(&mut settings.low_pass),
+            Param::Delay => Some(&mut settings.delay),
+            Param::Widen => Some(&mut settings.widen),
+            Param::CrushBits | Param::CrushHold | Param::DelayFeedback | Param::DelayMix => None,
+        }
+    }
+
+    fn is_on(self, settings: &EffectSettings) -> Option<bool> {
+        let mut settings = *settings;
+        self.switch(&mut settings).map(|on| *on)
+    }
+
+    /// One step up or down. Returns whether anything changed
+    fn adjust(self, settings: &mut EffectSettings, up: bool) -> bool {
+        let sign = if up { 1.0 } else { -1.0 };
+        let old = *settings;
+        match self {
+            Param::Bypass | Param::DcBlock => {}
+            Param::Crush | Param::CrushBits => {
+                settings.crush_bits = match up {
+                    true => settings.crush_bits.saturating_add(1),
+                    false => settings.crush_bits.saturating_sub(1),
+                }
+                .clamp(1, 8);
+            }
+            Param::CrushHold => {
+                settings.crush_hold = match up {
+                    true => settings.crush_hold.saturating_add(1),
+                    false => settings.crush_hold.saturating_sub(1),
+                }
+                .clamp(1, MAX_HOLD);
+            }
+            Param::HighPass => settings.high_pass_hz = step_cutoff(settings.high_pass_hz, up),
+            Param::LowPass => settings.low_pass_hz = step_cutoff(settings.low_pass_hz, up),
+            Param::Delay => {
+                settings.delay_ms = match up {
+                    true => settings.delay_ms.saturating_add(DELAY_STEP_MS),
+                    false => settings.delay_ms.saturating_sub(DELAY_STEP_MS),
+                }
+                .clamp(DELAY_STEP_MS, MAX_DELAY_MS);
+            }
+            Param::DelayFeedback => {
+                settings.delay_feedback = step_amount(settings.delay_feedback, sign * AMOUNT_STEP)
+                    .min(EffectSettings::MAX_FEEDBACK);
+            }
+            Param::DelayMix => {
+                settings.delay_mix = step_amount(settings.delay_mix, sign * AMOUNT_STEP).min(1.0);
+            }
+            Param::Widen => {
+                settings.width =
+                    step_amount(settings.width, sign * WIDTH_STEP).min(EffectSettings::MAX_WIDTH);
+            }
+        }
+        *settings != old
+    }
+
+    fn value(self, settings: &EffectSettings) -> String {
+        match self {
+            Param::Bypass | Param::DcBlock => String::new(),
+            Param::Crush => format!("{} bits, hold {}", settings.crush_bits, settings.crush_hold),
+            Param::CrushBits => settings.crush_bits.to_string(),
+            Param::CrushHold => format!("{} frames", settings.crush_hold),
+            Param::HighPass => format!("{:.0} Hz", settings.high_pass_hz),
+            Param::LowPass => format!("{:.0} Hz", settings.low_pass_hz),
+            Param::Delay => format!("{} ms", settings.delay_ms),
+            Param::DelayFeedback => format!("{:.0}%", settings.delay_feedback * 100.0),
+            Param::DelayMix => format!("{:.0}%", settings.delay_mix * 100.0),
+            Param::Widen => format!("{:.1}", settings.width),
+        }
+    }
+}
+
+/// Rounded to whole Hz, so stepping back and forth lands on the same values
+fn step_cutoff(hz: f32, up: bool) -> f32 {
+    let hz = if up {
+        hz * CUTOFF_STEP
+    } else {
+        hz / CUTOFF_STEP
+    };
+    hz.round()
+        .clamp(EffectSettings::MIN_CUTOFF_HZ, EffectSettings::MAX_CUTOFF_HZ)
+}
+
+/// Ditto, to hundredths. Never below 0
+fn step_amount(amount: f32, by: f32) -> f32 {
+    ((amount + by) * 100.0).round().max(0.0) / 100.0
+}
+
+pub struct Effects {
+    settings: EffectSettings,
+    selected: usize,
+}
+
+impl Effects {
+    pub fn new(settings: EffectSettings) -> Self {
+        Self {
+            settings,
+            selected: 0,
+        }
+    }
+
+    pub fn toggle_bypass(&mut self) -> EffectSettings {
+        self.settings.bypass = !self.settings.bypass;
+        self.settings
+    }
+}
+
+impl Component for Effects {
+    fn handle_key_event(&mut self, key: KeyEvent) -> Option<AppEvent> {
+        let param = PARAMS[self.selected];
+        let changed = match key.code {
+            KeyCode::Tab => {
+                self.selected = (self.selected + 1) % PARAMS.len();
+                false
+            }
+            KeyCode::BackTab => {
+                self.selected = (self.selected + PARAMS.len() - 1) % PARAMS.len();
+                false
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => match param.switch(&mut self.settings) {
+                Some(on) => {
+                    *on = !*on;
+                    true
+                }
+                None => false,
+            },
+            KeyCode::Left => param.adjust(&mut self.settings, false),
+            KeyCode::Right => param.adjust(&mut self.settings, true),
+            _ => false,
+        };
+        changed.then_some(AppEvent::EffectsChanged(self.settings))
+    }
+}
+
+impl Widget for &Effects {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer) {
+        let title = match (self.settings.bypass, self.settings.is_active()) {
+            (true, _) => " Effects (bypassed) ",
+            (false, true) => " Effects ",
+            (false, false) => " Effects (none on) ",
+        };
+        let block = Block::bordered()
+            .title(title)
+            .title_bottom(" Tab: Select | Enter: On/Off | Left/Right: Adjust ")
+            .border_type(BorderType::Rounded);
+
+        let header = Row::new(vec!["", "Effect", "Value"])
+            .style(Style::default().add_modifier(Modifier::BOLD))
+            .height(1);
+
+        let rows = PARAMS.iter().enumerate().map(|(i, &param)| {
+            let on = match param.is_on(&self.settings) {
+                Some(true) => "[x]",
+                Some(false) => "[ ]",
+                None => "",
+            };
+            let style = if i == self.selected {
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                on.to_owned(),
+                param.label().to_owned(),
+                param.value(&self.settings),
+            ])
+            .style(style)
+        });
+
+        let widths = [
+            Constraint::Length(3),
+            Constraint::Length(12),
+            Constraint::Fill(1),
+        ];
+        Table::new(rows, widths)
+            .header(header)
+            .block(block)
+            .render(area, buf);
+    }
+}
@@ -16,13 +16,16 @@ use crossterm::event::{KeyCode, KeyModifiers};
 
 use crate::{
     app::{AppEvent, Component, ui},
-    parser::ParseError,
+    parser::{
+        ParseError, Warning,
+        diagnostic::{Diagnostic, Part, Severity},
+    },
 };
 
 /// Private trait for error storage, used to provide blanket implementations.
 // TODO: More common error display functionality could be packed in here
 trait ErrorStore {
-    fn errors_mut(&mut self) -> &mut Vec<ParseError>;
+    fn diagnostics_mut(&mut self) -> &mut Diagnostics;
 }
 
 #[expect(private_bounds)]
@@ -33,11 +36,28 @@ pub trait BeatInput: WidgetRef + ErrorStore + Component {
     fn height_hint(&self) -> u16;
 
     fn clear_errors(&mut self) {
-        self.errors_mut().clear();
+        self.diagnostics_mut().errors.clear();
     }
 
-    fn set_errors(&mut self, errors: Vec<ParseError>) {
-        *self.errors_mut() = errors;
+    /// `source` is what they were found in
+    fn set_errors(&mut self, source: &str, errors: Vec<ParseError>) {
+        let diagnostics = self.diagnostics_mut();
+        diagnostics.source = source.to_owned();
+        diagnostics.errors = errors
+            .iter()
+            .map(|error| Diagnostic::error(error, source))
+            .collect();
+    }
+
+    fn clear_warnings(&mut self) {
+        self.diagnostics_mut().warnings.clear();
+    }
+
+    /// `source` is what they were found in
+    fn set_warnings(&mut self, source: &str, warnings: Vec<Warning>) {
+        let diagnostics = self.diagnostics_mut();
+        diagnostics.source = source.to_owned();
+        diagnostics.warnings = warnings.iter().map(Diagnostic::warning).collect();
     }
 }
 
@@ -152,36 +172,60 @@ impl Widget for &LineInput {
     }
 }
 
-/// Renders a list of parse errors into the given area.
-/// Displays up to `MAX_ERRORS_SHOWN` errors, with a summary line if there are more.
-fn render_errors(
-    errors: &[ParseError],
-    area: ratatui::prelude::Rect,
-    buf: &mut ratatui::prelude::Buffer,
-) {
-    if errors.is_empty() {
-        return;
-    }
+/// Errors and warnings, with the source they were found in so they can point into it
+#[derive(Debug, Default)]
+struct Diagnostics {
+    source: String,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+}
 
-    let mut error_text: Vec<Line> = errors
-        .iter()
-        .take(ui::MAX_ERRORS_SHOWN)
-        .map(|e| {
-            Line::from(vec![Span::styled(
-                format!("Error: {}", e),
-                Style::default().fg(Color::Red),
-            )])
-        })
-        .collect();
-
-    if errors.len() > ui::MAX_ERRORS_SHOWN {
-        error_text.push(Line::from(vec![Span::styled(
-            format!("...and {} more", errors.len() - ui::MAX_ERRORS_SHOWN),
-            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
-        )]));
+impl Diagnostics {
+    /// Up to `MAX_ERRORS_SHOWN` of them, errors first, with a summary line if there are more.
+    fn lines(&self) -> Vec<Line<'static>> {
+        let count = self.errors.len() + self.warnings.len();
+        let mut lines: Vec<Line> = self
+            .errors
+            .iter()
+            .chain(&self.warnings)
+            .take(ui::MAX_ERRORS_SHOWN)
+            .flat_map(|diagnostic| {
+                let color = match diagnostic.severity {
+                    Severity::Error => Color::Red,
+                    Severity::Warning => Color::Yellow,
+                };
+                diagnostic
+                    .render(&self.source)
+                    .into_iter()
+                    .map(move |line| {
+                        let style = match line.part {
+                            Part::Message => Style::default().fg(color),
+                            Part::Source => Style::default(),
+                            Part::Primary => {
+                                Style::default().fg(color).add_modifier(Modifier::BOLD)
+                            }
+                            Part::Secondary => Style::default().fg(Color::Blue),
+                            Part::Help => Style::default().fg(Color::Cyan),
+                        };
+                        Line::from(Span::styled(line.text, style))
+                    })
+            })
+            .collect();
+
+        if count > ui::MAX_ERRORS_SHOWN {
+            lines.push(Line::from(vec![Span::styled(
+                format!("...and {} more", count - ui::MAX_ERRORS_SHOWN),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            )]));
+        }
+        lines
     }
+}
 
-    Paragraph::new(error_text).render(area, buf);
+impl Widget for &Diagnostics {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer) {
+        Paragraph::new(self.lines()).render(area, buf);
+    }
 }
 
 /// Input widget for editing (and submitting) a bytebeat code and displaying errors.
@@ -189,12 +233,12 @@ fn render_errors(
 #[derive(Debug, Default)]
 pub struct InteractiveInput {
     input: LineInput,
-    errors: Vec<ParseError>,
+    diagnostics: Diagnostics,
 }
 
 impl ErrorStore for InteractiveInput {
-    fn errors_mut(&mut self) -> &mut Vec<ParseError> {
-        &mut self.errors
+    fn diagnostics_mut(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
     }
 }
 
@@ -202,6 +246,7 @@ impl Component for InteractiveInput {
     fn handle_key_event(&mut self, event: crossterm::event::KeyEvent) -> Option<AppEvent> {
         match event.code {
             KeyCode::Enter => return Some(AppEvent::InputReady(self.input.get_buffer())),
+            KeyCode::F(8) => return Some(AppEvent::Reformat(self.input.get_buffer())),
             KeyCode::Backspace => {
                 self.input.remove();
             }
@@ -231,9 +276,11 @@ impl Component for InteractiveInput {
 }
 
 impl BeatInput for InteractiveInput {
-    /// Easy - explodes the string input directly into our captive widget.
+    /// Easy - explodes the string input directly into our captive widget. Cursor goes to the end.
     fn set_buffer(&mut self, buf: String) -> color_eyre::Result<()> {
-        Ok(self.input.buf = buf.chars().collect())
+        self.input.buf = buf.chars().collect();
+        self.input.cursor = self.input.buf.len();
+        Ok(())
     }
 
     fn get_buffer(&self) -> String {
@@ -241,8 +288,8 @@ impl BeatInput for InteractiveInput {
     }
 
     fn height_hint(&self) -> u16 {
-        // 2 for the block, 1 for the LineInput, up to n errors + 1 'n more...'
-        (2 + 1 + self.errors.len().min(ui::MAX_ERRORS_SHOWN + 1)) as u16
+        // 2 for the block, 1 for the LineInput, every line of up to n errors and warnings + 1 'n more...'
+        (2 + 1 + self.diagnostics.lines().len()) as u16
     }
 }
 
@@ -262,7 +309,7 @@ impl WidgetRef for InteractiveInput {
 
         self.input.render(chunks[0], buf);
 
-        render_errors(&self.errors, chunks[1], buf);
+        self.diagnostics.render(chunks[1], buf);
     }
 }
 
@@ -274,12 +321,12 @@ pub struct FileWatchInput {
     blinken: bool,
     blinken_timer: u16,
     buffer: String,
-    errors: Vec<ParseError>,
+    diagnostics: Diagnostics,
 }
 
 impl ErrorStore for FileWatchInput {
-    fn errors_mut(&mut self) -> &mut Vec<ParseError> {
-        &mut self.errors
+    fn diagnostics_mut(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
     }
 }
 
@@ -312,9 +359,10 @@ impl Component for FileWatchInput {
 }
 
 impl BeatInput for FileWatchInput {
-    /// TODO: Writes to the actual file.
-    fn set_buffer(&mut self, _buf: String) -> color_eyre::Result<()> {
-        todo!()
+    /// Only until the file changes again. TODO: Writes to the actual file.
+    fn set_buffer(&mut self, buf: String) -> color_eyre::Result<()> {
+        self.buffer = buf;
+        Ok(())
     }
 
     // TODO: Can this get desynced?
@@ -323,8 +371,8 @@ impl BeatInput for FileWatchInput {
     }
 
     fn height_hint(&self) -> u16 {
-        // 2 for the block, up to n errors + 1 'n more...' (no buffer displayed)
-        (2 + self.errors.len().min(ui::MAX_ERRORS_SHOWN + 1)) as u16
+        // 2 for the block, every line of up to n errors and warnings + 1 'n more...' (no buffer displayed)
+        (2 + self.diagnostics.lines().len()) as u16
     }
 }
 
@@ -338,6 +386,6 @@ impl WidgetRef for FileWatchInput {
         let inner_area = block.inner(area);
         block.render(area, buf);
 
-        render_errors(&self.errors, inner_area, buf);
+        self.diagnostics.render(inner_area, buf);
     }
 }
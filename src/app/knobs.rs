@@ -0,0 +1,189 @@
+//! Component for turning the knobs of the Input's beat while it plays, with keys or the mouse. Knobs are the
+//! `$name`s of a beat, see [`crate::parser::knobs`].
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, BorderType, LineGauge, Paragraph, Widget, Wrap},
+};
+
+use crate::{
+    app::{AppEvent, Component},
+    parser::{KnobId, knobs::Knob},
+};
+
+/// How many steps Alt+Left/Right turns a knob by
+const COARSE_STEPS: f64 = 10.0;
+/// Longest name shown in full
+const NAME_WIDTH: usize = 12;
+
+#[derive(Default)]
+pub struct Knobs {
+    /// Of the Input's beat, as they're turned now
+    knobs: Vec<Knob>,
+    selected: usize,
+    /// Where each knob's row and bar were last drawn, for the mouse
+    rows: Vec<Rect>,
+    bars: Vec<Rect>,
+    /// Held down on this knob's bar
+    dragging: Option<KnobId>,
+}
+
+impl Knobs {
+    /// What the Input's beat has now. The same knob stays selected if it's still there
+    pub fn set_knobs(&mut self, knobs: Vec<Knob>) {
+        let selected = self.knobs.get(self.selected).map(|knob| &knob.name);
+        self.selected = selected
+            .and_then(|name| knobs.iter().position(|knob| knob.name == *name))
+            .unwrap_or(0);
+        if self.dragging.is_some_and(|id| id >= knobs.len()) {
+            self.dragging = None;
+        }
+        self.knobs = knobs;
+    }
+
+    /// Only if it would move
+    fn turn(&self, id: KnobId, to: f64) -> Option<AppEvent> {
+        let knob = self.knobs.get(id)?;
+        let to = knob.fit(to);
+        (to != knob.value).then_some(AppEvent::KnobTurned(id, to))
+    }
+
+    fn nudge(&self, id: KnobId, steps: f64) -> Option<AppEvent> {
+        let knob = self.knobs.get(id)?;
+        self.turn(id, knob.value + steps * knob.step())
+    }
+
+    /// Where `column` is along the bar of `id`
+    fn drag(&self, id: KnobId, column: u16) -> Option<AppEvent> {
+        let (knob, bar) = (self.knobs.get(id)?, self.bars.get(id)?);
+        let along = column.saturating_sub(bar.x) as f64 / bar.width.saturating_sub(1).max(1) as f64;
+        self.turn(id, knob.at(along))
+    }
+}
+
+impl Component for Knobs {
+    fn handle_key_event(&mut self, key: KeyEvent) -> Option<AppEvent> {
+        let steps = match key.modifiers.contains(KeyModifiers::ALT) {
+            true => COARSE_STEPS,
+            false => 1.0,
+        };
+        let count = self.knobs.len().max(1);
+        match key.code {
+            KeyCode::Tab => {
+                self.selected = (self.selected + 1) % count;
+                None
+            }
+            KeyCode::BackTab => {
+                self.selected = (self.selected + count - 1) % count;
+                None
+            }
+            KeyCode::Left => self.nudge(self.selected, -steps),
+            KeyCode::Right => self.nudge(self.selected, steps),
+            _ => None,
+        }
+    }
+
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) -> Option<AppEvent> {
+        let at = Position::new(mouse.column, mouse.row);
+        let row = self.rows.iter().position(|row| row.contains(at));
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.nudge(row?, 1.0),
+            MouseEventKind::ScrollDown => self.nudge(row?, -1.0),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let id = row?;
+                self.selected = id;
+                if !self.bars[id].contains(at) {
+                    return None;
+                }
+                self.dragging = Some(id);
+                self.drag(id, mouse.column)
+            }
+            // Anywhere along, even off the row
+            MouseEventKind::Drag(MouseButton::Left) => self.drag(self.dragging?, mouse.column),
+            MouseEventKind::Up(MouseButton::Left) => {
+                self.dragging = None;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Mutable to remember where the bars are
+impl Widget for &mut Knobs {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .title(" Knobs ")
+            .title_bottom(
+                " Tab: Select | Left/Right: Turn (Alt: further) | Mouse: Scroll, or click the bar ",
+            )
+            .border_type(BorderType::Rounded);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        self.rows.clear();
+        self.bars.clear();
+        if self.knobs.is_empty() {
+            Paragraph::new(
+                "The Input's beat has no knobs. Write one like '$speed' and declare where it starts and how far it \
+                 turns in a comment, like 't * $speed /* $speed = 3 in 1..16 */'",
+            )
+            .wrap(Wrap { trim: true })
+            .style(Style::default().fg(Color::DarkGray))
+            .render(inner, buf);
+            return;
+        }
+
+        let name_width = self
+            .knobs
+            .iter()
+            .map(|knob| knob.name.len() + 1)
+            .max()
+            .unwrap_or(0)
+            .min(NAME_WIDTH + 1) as u16;
+        let rows = Layout::vertical(vec![Constraint::Length(1); self.knobs.len()]).split(inner);
+        for (i, (knob, &row)) in self.knobs.iter().zip(rows.iter()).enumerate() {
+            let [name_area, bar_area, value_area, range_area] = Layout::horizontal([
+                Constraint::Length(name_width + 1),
+                Constraint::Fill(1),
+                Constraint::Length(12),
+                Constraint::Length(20),
+            ])
+            .spacing(1)
+            .areas(row);
+
+            let style = if i == self.selected {
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            let name: String = format!("${}", knob.name)
+                .chars()
+                .take(name_width as usize)
+                .collect();
+            Line::styled(name, style).render(name_area, buf);
+            LineGauge::default()
+                .ratio(knob.position().clamp(0.0, 1.0))
+                .label("")
+                .filled_style(style.fg(Color::Cyan))
+                .unfilled_style(Style::default().fg(Color::DarkGray))
+                .render(bar_area, buf);
+            Line::styled(knob.value.to_string(), style)
+                .right_aligned()
+                .render(value_area, buf);
+            Line::styled(
+                format!("{}..{}", knob.min, knob.max),
+                Style::default().fg(Color::DarkGray),
+            )
+            .render(range_area, buf);
+
+            self.rows.push(row);
+            self.bars.push(bar_area);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Component listing every layer of the mix and how it's heard. The first layer is the Input's beat, and every other
+//! one has an input of its own, edited here when it's selected.
+use std::sync::Arc;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style},
+    widgets::{Block, BorderType, Row, Table, Widget},
+};
+
+use crate::{
+    app::{AppEvent, Component, input::InteractiveInput},
+    audio::mix::{Combine, Layer, LayerSettings, Mix},
+    event::Event,
+    parser,
+};
+
+/// How far Alt+Left/Right moves a layer's gain
+const GAIN_STEP: f32 = 0.1;
+/// How far Alt+,/. moves a layer's 't' offset. A power of two, like most bytebeat rhythms
+const OFFSET_STEP: i32 = 1024;
+/// Including the Input's
+const MAX_LAYERS: usize = 8;
+
+/// What the layer keys do, besides editing the selected layer's input
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Next,
+    Prev,
+    Add,
+    Remove,
+    ToggleMute,
+    ToggleSolo,
+    Gain(f32),
+    Offset(i32),
+    CycleCombine,
+}
+
+impl Command {
+    fn from_key(key: &KeyEvent) -> Option<Self> {
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Tab => Some(Command::Next),
+            KeyCode::BackTab => Some(Command::Prev),
+            KeyCode::Char('n') if ctrl => Some(Command::Add),
+            KeyCode::Char('d') if ctrl => Some(Command::Remove),
+            KeyCode::Char('m') if alt => Some(Command::ToggleMute),
+            KeyCode::Char('s') if alt => Some(Command::ToggleSolo),
+            KeyCode::Char('c') if alt => Some(Command::CycleCombine),
+            KeyCode::Left if alt => Some(Command::Gain(-GAIN_STEP)),
+            KeyCode::Right if alt => Some(Command::Gain(GAIN_STEP)),
+            KeyCode::Char(',') if alt => Some(Command::Offset(-OFFSET_STEP)),
+            KeyCode::Char('.') if alt => Some(Command::Offset(OFFSET_STEP)),
+            _ => None,
+        }
+    }
+}
+
+struct LayerState {
+    /// `None` for the first layer, which is the App's own input
+    input: Option<InteractiveInput>,
+    settings: LayerSettings,
+    /// The last that compiled. Silent until one does
+    beat: Arc<parser::Beat>,
+    /// What `beat` was compiled from
+    code: String,
+}
+
+impl LayerState {
+    fn new(input: Option<InteractiveInput>) -> Self {
+        Self {
+            input,
+            settings: LayerSettings::default(),
+            beat: Arc::new(parser::Beat::default()),
+            code: String::new(),
+        }
+    }
+}
+
+pub struct Layers {
+    /// Never empty
+    layers: Vec<LayerState>,
+    selected: usize,
+    combine: Combine,
+}
+
+impl Layers {
+    pub fn new(combine: Combine) -> Self {
+        Self {
+            layers: vec![LayerState::new(None)],
+            selected: 0,
+            combine,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Unless the first layer is selected. Its input is the App's own
+    pub fn selected_input(&self) -> Option<&InteractiveInput> {
+        self.layers[self.selected].input.as_ref()
+    }
+
+    pub fn input_mut(&mut self, index: usize) -> Option<&mut InteractiveInput> {
+        self.layers.get_mut(index)?.input.as_mut()
+    }
+
+    /// What layer `index` plays now. It's shared with the audio thread
+    pub fn beat(&self, index: usize) -> Option<&parser::Beat> {
+        Some(&self.layers.get(index)?.beat)
+    }
+
+    /// What layer `index` was last compiled from
+    pub fn code(&self, index: usize) -> Option<&str> {
+        Some(&self.layers.get(index)?.code)
+    }
+
+    /// Plays `beat` in layer `index` from the next [`Layers::mix`] on
+    pub fn set_beat(&mut self, index: usize, beat: parser::Beat, code: &str) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.beat = Arc::new(beat);
+            layer.code = code.to_owned();
+        }
+    }
+
+    /// Everything as it should be heard now
+    pub fn mix(&self) -> Mix {
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| Layer {
+                beat: layer.beat.clone(),
+                settings: layer.settings,
+            })
+            .collect();
+        Mix::new(layers, self.combine)
+    }
+
+    /// Whether `key` is for this component, rather than the App's own input
+    pub fn takes(&self, key: &KeyEvent) -> bool {
+        self.selected != 0 || Command::from_key(key).is_some()
+    }
+
+    /// Returns whether the mix changed
+    fn apply(&mut self, command: Command) -> bool {
+        let settings = &mut self.layers[self.selected].settings;
+        match command {
+            Command::Next => {
+                self.selected = (self.selected + 1) % self.layers.len();
+                return false;
+            }
+            Command::Prev => {
+                self.selected = (self.selected + self.layers.len() - 1) % self.layers.len();
+                return false;
+            }
+            Command::Add => {
+                if self.layers.len() >= MAX_LAYERS {
+                    return false;
+                }
+                self.layers
+                    .push(LayerState::new(Some(InteractiveInput::default())));
+                self.selected = self.layers.len() - 1;
+                // Silent until it compiles
+                return false;
+            }
+            Command::Remove => {
+                // The Input's layer stays
+                if self.selected == 0 {
+                    return false;
+                }
+                self.layers.remove(self.selected);
+                self.selected -= 1;
+            }
+            Command::ToggleMute => settings.muted = !settings.muted,
+            Command::ToggleSolo => settings.solo = !settings.solo,
+            Command::Gain(by) => {
+                // Rounded, so stepping back and forth lands on the same values
+                let gain = ((settings.gain + by) * 10.0).round() / 10.0;
+                settings.gain = gain.clamp(0.0, LayerSettings::MAX_GAIN);
+            }
+            Command::Offset(by) => settings.offset = settings.offset.wrapping_add(by),
+            Command::CycleCombine => self.combine = self.combine.next(),
+        }
+        true
+    }
+}
+
+impl Component for Layers {
+    fn handle_key_event(&mut self, key: KeyEvent) -> Option<AppEvent> {
+        if let Some(command) = Command::from_key(&key) {
+            return self.apply(command).then_some(AppEvent::MixChanged);
+        }
+        let selected = self.selected;
+        let input = self.layers[selected].input.as_mut()?;
+        match input.handle_event(Event::Crossterm(crossterm::event::Event::Key(key)))? {
+            AppEvent::InputReady(code) => Some(AppEvent::LayerReady(selected, code)),
+            // Reformatting is only for the Input
+            _ => None,
+        }
+    }
+}
+
+impl Widget for &Layers {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer) {
+        let block = Block::bordered()
+            .title(format!(" Layers - Combine: {} ", self.combine))
+            .border_type(BorderType::Rounded);
+
+        let header = Row::new(vec!["#", "", "Gain", "Offset", "Code"])
+            .style(Style::default().add_modifier(Modifier::BOLD))
+            .height(1);
+
+        let rows = self.layers.iter().enumerate().map(|(i, layer)| {
+            let flags = match (layer.settings.muted, layer.settings.solo) {
+                (true, true) => "M S",
+                (true, false) => "M",
+                (false, true) => "  S",
+                (false, false) => "",
+            };
+            let code = match (i, layer.code.is_empty()) {
+                (0, true) => "(Input)".to_owned(),
+                (_, true) => "(silent)".to_owned(),
+                _ => layer.code.lines().next().unwrap_or_default().to_owned(),
+            };
+            let style = if i == self.selected {
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD)
+            } else if layer.settings.muted {
+                Style::default().fg(Color::DarkGray)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                (i + 1).to_string(),
+                flags.to_owned(),
+                format!("{:.1}", layer.settings.gain),
+                layer.settings.offset.to_string(),
+                code,
+            ])
+            .style(style)
+        });
+
+        let widths = [
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Length(5),
+            Constraint::Length(11),
+            Constraint::Fill(1),
+        ];
+        Table::new(rows, widths)
+            .header(header)
+            .block(block)
+            .render(area, buf);
+    }
+}
@@ -61,14 +61,24 @@ impl Component for Library {
             // Enter overwrites the input with the song
             KeyCode::Enter => {
                 if let Some(song) = self.selected_song() {
-                    return Some(AppEvent::BeatOverwrite(song.code.to_string()));
+                    return Some(AppEvent::BeatOverwrite(
+                        song.code.to_string(),
+                        song.mode,
+                        song.t_type,
+                        song.rate,
+                    ));
                 }
             }
             // Selecting any song samples it by playing without touching buffer
             KeyCode::Char(c) => {
                 self.table_state.select_by_key(c);
                 if let Some(song) = self.selected_song() {
-                    return Some(AppEvent::InputReady(song.code.to_string()));
+                    return Some(AppEvent::BeatSample(
+                        song.code.to_string(),
+                        song.mode,
+                        song.t_type,
+                        song.rate,
+                    ));
                 }
             }
             _ => {}
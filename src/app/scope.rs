@@ -3,9 +3,12 @@
 //! Uses some state from the audio thread to render a chart. Spoiler: It's just a ring buffer and a
 //! ratatui chart plumbed together.
 //!
+//! Samples are charted in the order they're heard, so jumps in 't' (seeking, looping, reversing) just scroll on.
+//! The right channel is charted over the left, whenever it differs.
+//!
 //! FIXME: AI slopped the buffer logic and it's needlessly complicated and probably inefficient
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use ratatui::{
     layout::Rect,
@@ -14,22 +17,26 @@ use ratatui::{
     widgets::{Axis, Block, BorderType, Chart, Dataset, GraphType, Widget},
 };
 
-use crate::app::Component;
+use crate::{app::Component, audio::ScopeSample};
 
 /// How many samples we display on one chart
 const CHART_SAMPLES: usize = 32000;
 
 pub struct Scope {
     /// Samples stream incoming from audio thread
-    consumer: rtrb::Consumer<u8>,
-    /// Highest sample we've read off the ringbuffer
-    t_read: i32,
-    /// Which sample is about to be played, set by audio thread
-    t_play: &'static AtomicI32,
+    consumer: rtrb::Consumer<ScopeSample>,
+    /// How many samples have been heard, set by audio thread
+    played: &'static AtomicU64,
     /// Intermediate queue waiting to be displayed
-    intermediate_queue: VecDeque<u8>,
+    intermediate_queue: VecDeque<ScopeSample>,
     /// The slice we give to the chart
     chart_buffer: VecDeque<(f64, f64)>,
+    /// Same for the right channel
+    right_buffer: VecDeque<(f64, f64)>,
+    /// How many samples in the chart have channels that differ. The right one is only drawn if any do
+    differing: usize,
+    /// How many samples have been pushed to the chart_buffer. Its x axis, since 't' can go anywhere
+    chart_head: u64,
     /// The 't' of the last sample pushed to the chart_buffer
     t_chart_head: i32,
 }
@@ -40,27 +47,37 @@ impl Component for Scope {
         // TODO: This could be done with chunks, maybe faster. probably doesn't matter
         while let Ok(sample) = self.consumer.pop() {
             self.intermediate_queue.push_back(sample);
-            self.t_read += 1;
         }
 
-        // Read t_play logic to decide how many elements go inside of the chart_buffer
-        let play_head = self.t_play.load(Ordering::Relaxed);
+        // Read played to decide how many elements go inside of the chart_buffer
+        let played = self.played.load(Ordering::Relaxed);
 
         // We assume we want to sync the chart to the playback head.
-        // If play_head is ahead of what we've pushed to chart, push more.
-        if play_head > self.t_chart_head {
-            let needed = (play_head - self.t_chart_head) as usize;
+        // If played is ahead of what we've pushed to chart, push more.
+        if played > self.chart_head {
+            let needed = (played - self.chart_head) as usize;
             let available = self.intermediate_queue.len();
             // We can only push what we have
             let count = needed.min(available);
 
             for _ in 0..count {
                 if let Some(sample) = self.intermediate_queue.pop_front() {
-                    self.t_chart_head += 1;
+                    self.chart_head += 1;
+                    self.t_chart_head = sample.t;
+                    let [left, right] = sample.value;
                     self.chart_buffer
-                        .push_back((self.t_chart_head as f64, sample as f64));
+                        .push_back((self.chart_head as f64, left as f64));
+                    self.right_buffer
+                        .push_back((self.chart_head as f64, right as f64));
+                    self.differing += usize::from(left != right);
                     if self.chart_buffer.len() > CHART_SAMPLES {
-                        self.chart_buffer.pop_front();
+                        let popped = self
+                            .chart_buffer
+                            .pop_front()
+                            .zip(self.right_buffer.pop_front());
+                        if popped.is_some_and(|((_, left), (_, right))| left != right) {
+                            self.differing -= 1;
+                        }
                     }
                 }
             }
@@ -74,32 +91,48 @@ impl Widget for &mut Scope {
     fn render(self, area: Rect, buf: &mut ratatui::buffer::Buffer) {
         // Mutating optimization: use make_contiguous to get a slice for the Chart without allocation
         self.chart_buffer.make_contiguous();
+        self.right_buffer.make_contiguous();
         let (data, _) = self.chart_buffer.as_slices();
+        let (right, _) = self.right_buffer.as_slices();
 
-        let latest_t = self.t_chart_head;
+        let latest = self.chart_head;
 
         // Fixed window scrolling logic
         // We want to fill from left to right, then scroll
         let window = CHART_SAMPLES as f64;
-        let end_x = if latest_t < (CHART_SAMPLES as i32) {
+        let end_x = if latest < (CHART_SAMPLES as u64) {
             window
         } else {
-            latest_t as f64
+            latest as f64
         };
         let start_x = end_x - window;
 
-        let datasets = vec![
+        let mut datasets = vec![
             Dataset::default()
                 .marker(Marker::Braille)
                 .graph_type(GraphType::Scatter)
                 .style(Style::default().fg(Color::Cyan))
                 .data(data),
         ];
+        let stereo = self.differing > 0;
+        if stereo {
+            datasets.push(
+                Dataset::default()
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Scatter)
+                    .style(Style::default().fg(Color::Magenta))
+                    .data(right),
+            );
+        }
+        let title = match stereo {
+            true => format!(" Scope (L/R) - t: {} ", self.t_chart_head),
+            false => format!(" Scope - t: {} ", self.t_chart_head),
+        };
 
         let chart = Chart::new(datasets)
             .block(
                 Block::bordered()
-                    .title(format!(" Scope - t: {} ", latest_t))
+                    .title(title)
                     .border_type(BorderType::Rounded),
             )
             .x_axis(
@@ -117,14 +150,21 @@ impl Widget for &mut Scope {
 }
 
 impl Scope {
-    pub fn new(consumer: rtrb::Consumer<u8>, t_play: &'static AtomicI32) -> Self {
+    pub fn new(consumer: rtrb::Consumer<ScopeSample>, played: &'static AtomicU64) -> Self {
         Self {
             consumer,
-            t_read: 0,
-            t_play,
+            played,
             intermediate_queue: VecDeque::with_capacity(4096),
             chart_buffer: VecDeque::with_capacity(CHART_SAMPLES),
+            right_buffer: VecDeque::with_capacity(CHART_SAMPLES),
+            differing: 0,
+            chart_head: 0,
             t_chart_head: -1,
         }
     }
+
+    /// The 't' being heard, about
+    pub fn t_heard(&self) -> i32 {
+        self.t_chart_head
+    }
 }
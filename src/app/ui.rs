@@ -1,8 +1,9 @@
 //! Top-level render code. Limited state mutation (in some components only)
 use crate::{
     App,
-    app::{View, input::BeatInput},
-    audio::{StreamStatus, Volume},
+    app::{LoopMarks, View, input::BeatInput},
+    audio::{StreamStatus, Volume, record::Recording, transport::Speed},
+    parser::{Mode, TType},
 };
 
 use ratatui::{
@@ -10,7 +11,7 @@ use ratatui::{
     layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, BorderType, Borders, Paragraph, Widget},
+    widgets::{Block, BorderType, Borders, Paragraph, Widget, WidgetRef},
 };
 
 /// Used in calculation for [`crate::app::BeatInput::height_hint`]
@@ -27,7 +28,19 @@ const HELP_TEXT: &[&str] = &[
     "  F3: Quit",
     "  F4: Play/Pause",
     "  F5: Library",
-    "  F6: About",
+    "  F6: Layers",
+    "  F7: Toggle Classic/Floatbeat",
+    "  F9: Cycle sample rate",
+    "  F10: Reverse",
+    "  F11: Effects",
+    "  F12: Mark loop start, then end, then stop looping",
+    "  Home: Restart from t = 0",
+    "  Shift+Left/Right: Jump 5 seconds",
+    "  Shift+Up/Down: Speed",
+    "  Ctrl+R: Start/Stop recording",
+    "  Ctrl+T: Cycle how new beats switch in - immediately, crossfaded or quantized",
+    "  Ctrl+B: Bypass/Restore every effect",
+    "  Ctrl+K: Knobs",
     "  Up/Down: Volume",
     "",
     "Interactive Input:",
@@ -36,11 +49,31 @@ const HELP_TEXT: &[&str] = &[
     "  Left/Right: Move cursor",
     "  Ctrl+Left/Right: Jump words",
     "  Enter: Compile and play beat",
+    "  F8: Reformat beat (again to minify)",
     "",
     "Library:",
     "  Enter: Select song on page - over-writes input buffer",
     "  0-9-a-z-A-Z: 'Sample' song on page - doesn't over-write buffer",
     "  Left/Right: Move pages",
+    "",
+    "Layers:",
+    "  Tab/Shift+Tab: Select layer - the first is the Input's, others are edited here",
+    "  Ctrl+N: Add layer",
+    "  Ctrl+D: Remove selected layer",
+    "  Alt+M/Alt+S: Mute/Solo selected layer",
+    "  Alt+Left/Right: Gain",
+    "  Alt+,/Alt+.: Offset 't'",
+    "  Alt+C: Cycle how layers are combined",
+    "",
+    "Effects:",
+    "  Tab/Shift+Tab: Select effect",
+    "  Enter/Space: Turn on/off",
+    "  Left/Right: Adjust",
+    "",
+    "Knobs:",
+    "  Tab/Shift+Tab: Select knob",
+    "  Left/Right: Turn, further with Alt",
+    "  Mouse: Scroll over a knob to turn it, or click/drag along its bar",
 ];
 
 impl<I: BeatInput> Widget for &mut App<I> {
@@ -51,13 +84,23 @@ impl<I: BeatInput> Widget for &mut App<I> {
             .border_type(BorderType::Rounded)
             .title_bottom(controls(self));
 
-        // BigLog and Library views replace the scope and log areas
+        // The selected layer's input takes the Input's place while it's edited
+        let layer_input = match self.view {
+            View::Layers => self.layers.selected_input(),
+            _ => None,
+        };
+        let input_height = match layer_input {
+            Some(input) => input.height_hint(),
+            None => self.beat_input.height_hint(),
+        };
+
+        // BigLog, Library, Layers, Effects and Knobs views replace the scope and log areas
         let display_constraints = match self.view {
-            View::BigLog | View::Library => vec![
-                // BigLog | Library
+            View::BigLog | View::Library | View::Layers | View::Effects | View::Knobs => vec![
+                // BigLog | Library | Layers | Effects | Knobs
                 Constraint::Percentage(95),
                 // Input
-                Constraint::Length(self.beat_input.height_hint()),
+                Constraint::Length(input_height),
                 // Status bar
                 Constraint::Length(3),
             ],
@@ -67,7 +110,7 @@ impl<I: BeatInput> Widget for &mut App<I> {
                 // Logs
                 Constraint::Percentage(15),
                 // Input
-                Constraint::Length(self.beat_input.height_hint()),
+                Constraint::Length(input_height),
                 // Status bar
                 Constraint::Length(3),
             ],
@@ -102,6 +145,15 @@ impl<I: BeatInput> Widget for &mut App<I> {
             View::Library => {
                 (&mut self.library).render(main_interior[0], buf);
             }
+            View::Layers => {
+                self.layers.render(main_interior[0], buf);
+            }
+            View::Effects => {
+                self.effects.render(main_interior[0], buf);
+            }
+            View::Knobs => {
+                self.knobs.render(main_interior[0], buf);
+            }
             View::Main => {
                 self.scope.render(main_interior[0], buf);
 
@@ -119,15 +171,22 @@ impl<I: BeatInput> Widget for &mut App<I> {
 
         // Input and status bar indices shift based on view layout
         let (input_idx, status_idx) = match self.view {
-            View::BigLog | View::Library => (1, 2),
+            View::BigLog | View::Library | View::Layers | View::Effects | View::Knobs => (1, 2),
             View::Main => (2, 3),
         };
 
-        self.beat_input.render(main_interior[input_idx], buf);
+        match layer_input {
+            Some(input) => input.render_ref(main_interior[input_idx], buf),
+            None => self.beat_input.render(main_interior[input_idx], buf),
+        }
 
         let status_area = status_block.inner(main_interior[status_idx]);
         let status_layout = Layout::horizontal([
             Constraint::Fill(1),
+            Constraint::Length(26),
+            Constraint::Length(16),
+            Constraint::Length(48),
+            Constraint::Length(14),
             Constraint::Length(30),
             Constraint::Length(1),
         ])
@@ -138,7 +197,16 @@ impl<I: BeatInput> Widget for &mut App<I> {
             .style(Style::default().add_modifier(Modifier::BOLD))
             .render(status_layout[0], buf);
 
-        draw_volume(status_layout[1], buf, &self.audio_vol);
+        let mode = match (self.options.mode, self.options.t_type) {
+            (Mode::Classic, TType::Unsigned) => format!("Mode: {} (unsigned t)", Mode::Classic),
+            (mode, _) => format!("Mode: {mode}"),
+        };
+        Paragraph::new(mode).render(status_layout[1], buf);
+        Paragraph::new(format!("Rate: {}", self.options.rate.hz())).render(status_layout[2], buf);
+        Paragraph::new(transport_status(self)).render(status_layout[3], buf);
+        draw_recording(status_layout[4], buf, self.recording);
+
+        draw_volume(status_layout[5], buf, &self.audio_vol);
         status_block.render(main_interior[status_idx], buf);
 
         if self.show_help {
@@ -222,11 +290,110 @@ fn controls<I: BeatInput>(state: &'_ App<I>) -> Line<'_> {
         },
     );
     spans.push(lib_span);
+    spans.push(sep.clone());
+
+    let layers_span = Span::styled(
+        "<F6>: Layers",
+        if state.view == View::Layers {
+            active
+        } else {
+            Style::default()
+        },
+    );
+    spans.push(layers_span);
+    spans.push(sep.clone());
+
+    spans.push(Span::raw("<F7>: Mode"));
+    spans.push(sep.clone());
+
+    spans.push(Span::raw("<F9>: Rate"));
+    spans.push(sep.clone());
+
+    let reverse_span = Span::styled(
+        "<F10>: Reverse",
+        if state.reverse {
+            active
+        } else {
+            Style::default()
+        },
+    );
+    spans.push(reverse_span);
+    spans.push(sep.clone());
+
+    let effects_span = Span::styled(
+        "<F11>: Effects",
+        if state.view == View::Effects {
+            active
+        } else {
+            Style::default()
+        },
+    );
+    spans.push(effects_span);
+    spans.push(sep.clone());
+
+    let loop_span = Span::styled(
+        "<F12>: Loop",
+        if state.loop_marks != LoopMarks::Off {
+            active
+        } else {
+            Style::default()
+        },
+    );
+    spans.push(loop_span);
     spans.push(Span::raw(" ")); // Trailing padding
 
     Line::from(spans).centered()
 }
 
+/// Where 't' is as samples and time into the playing beat, then anything unusual about how it's moving
+fn transport_status<I: BeatInput>(state: &App<I>) -> String {
+    let t = state.scope.t_heard();
+    let mut status = format!("t: {t} ({})", minutes_seconds(t, state.playing_rate.hz()));
+    if state.speed != Speed::NORMAL {
+        status.push_str(&format!(" {}", state.speed));
+    }
+    if state.reverse {
+        status.push_str(" ◀");
+    }
+    match state.loop_marks {
+        LoopMarks::Off => {}
+        LoopMarks::Start(_) => status.push_str(" A-"),
+        LoopMarks::Between(..) => status.push_str(" A-B"),
+    }
+    match state.switch.pending_at() {
+        Some(at) => {
+            // Going either way, the switch is ahead
+            let left = (at as i64 - t as i64).unsigned_abs() as f32;
+            let hz = state.playing_rate.hz() as f32 * state.speed.val();
+            status.push_str(&format!(" next in {:.1}s", left / hz));
+        }
+        None if state.switch.is_fading() => status.push_str(" fading"),
+        None => {}
+    }
+    status
+}
+
+/// Like `-1:05`, for 't' samples at `rate` Hz
+fn minutes_seconds(t: i32, rate: u32) -> String {
+    let sign = if t < 0 { "-" } else { "" };
+    let seconds = t.unsigned_abs() / rate;
+    format!("{sign}{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Red while recording, with how long every take so far has been
+fn draw_recording(area: Rect, buf: &mut Buffer, recording: &Recording) {
+    let seconds = recording.elapsed().as_secs();
+    let elapsed = format!("{}:{:02}", seconds / 60, seconds % 60);
+    let (label, style) = match recording.is_recording() {
+        true => (
+            format!("● Rec {elapsed}"),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ),
+        false => (format!("○ Rec {elapsed}"), Style::default()),
+    };
+    Paragraph::new(label).style(style).render(area, buf);
+}
+
 pub fn draw_volume(area: Rect, buf: &mut Buffer, state: &Volume) {
     let label = match state.val() {
         0.8.. => format!("🔊 {}", state),
@@ -4,12 +4,39 @@ mod wasapi;
 #[cfg(target_os = "linux")]
 mod pipewire;
 
-use std::time::Duration;
+#[cfg(target_os = "linux")]
+mod alsa;
+
+mod clocked;
+pub mod effects;
+pub mod mix;
+pub mod record;
+mod resample;
+pub mod switch;
+pub mod transport;
+pub mod wav;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, atomic::AtomicU64, mpsc},
+    time::Duration,
+};
 
-use crate::parser;
+use tracing::trace;
+
+use self::{
+    effects::{EffectSettings, Effects},
+    mix::Mix,
+    record::Tap,
+    resample::Resampler,
+    switch::{Retired, SwitchStatus},
+    transport::{Transport, TransportCommand},
+};
+use crate::{
+    event::Event,
+    parser::{self, SampleRate, Stereo},
+};
 
-/// Hertz
-pub const BITRATE: usize = 8000;
 /// Discrete
 pub const CHANNELS: usize = 2;
 /// Bytes
@@ -18,6 +45,319 @@ pub const STRIDE: usize = size_of::<u8>() * CHANNELS;
 ///
 /// Effectively more of a "no sooner than" than a "every X"
 pub const T_SYNC_INTERVAL: Duration = Duration::from_millis(100);
+/// Samples evaluated at once by [`Filler`]
+pub const BLOCK_LEN: usize = 1024;
+
+/// How samples between a beat's own are made up, when it's played at another rate than its own
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Resample {
+    /// Don't. The device is opened again at every beat's own rate instead. Zero-order hold where it can't be
+    #[default]
+    Off,
+    /// Zero-order hold. Every sample is repeated until the next, for authentic crunch
+    Hold,
+    /// Straight lines between samples
+    Linear,
+    /// Windowed sinc. Smoothest, and filters out what the device rate can't carry
+    Sinc,
+}
+
+/// A sample as the scope gets it, with the 't' it was evaluated for
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScopeSample {
+    pub t: i32,
+    /// Left and right
+    pub value: Stereo,
+}
+
+/// Fills a backend's frames with samples of a mix, at whatever rate the device is running at. Mixes at another
+/// rate (or speed) are resampled. Keeps track of 't' with a [`Transport`], and runs frames through any [`Effects`].
+///
+/// Made before the backend is, and owned by it. RT Safe, besides being made.
+pub struct Filler {
+    resample: Resample,
+    /// What the device runs at while resampling. Otherwise beats decide
+    fixed_rate: SampleRate,
+    /// What the device actually runs at
+    device_rate: SampleRate,
+    /// Whether the last frames were resampled
+    resampling: bool,
+    resampler: Resampler,
+    transport: Transport,
+    /// Samples evaluated since the start, however 't' moved
+    made: u64,
+    /// Scratch space for evaluating without resampling
+    block: [Stereo; BLOCK_LEN],
+    /// (Ideally) loaded with every sample evaluated, in the order they're played. Scope widget uses this to visualize
+    producer: rtrb::Producer<ScopeSample>,
+    /// After evaluating, before recording
+    effects: Effects,
+    /// Gets every frame filled, for recording
+    tap: Tap,
+    /// Shown to the app after every fill
+    switch: &'static SwitchStatus,
+}
+
+impl Filler {
+    pub fn new(
+        producer: rtrb::Producer<ScopeSample>,
+        tap: Tap,
+        resample: Resample,
+        fixed_rate: SampleRate,
+        switch: &'static SwitchStatus,
+        retire: rtrb::Producer<Arc<Mix>>,
+    ) -> Self {
+        let kind = match resample {
+            Resample::Off | Resample::Hold => resample::Kind::Hold,
+            Resample::Linear => resample::Kind::Linear,
+            Resample::Sinc => resample::Kind::Sinc,
+        };
+        Self {
+            resample,
+            fixed_rate,
+            device_rate: fixed_rate,
+            resampling: false,
+            resampler: Resampler::new(kind),
+            transport: Transport::new(retire),
+            made: 0,
+            block: [[0; CHANNELS]; BLOCK_LEN],
+            producer,
+            effects: Effects::new(EffectSettings::default()),
+            tap,
+            switch,
+        }
+    }
+
+    /// What the device should run at to play `mix`
+    pub fn rate_for(&self, mix: &Mix) -> SampleRate {
+        self.resampling_to().unwrap_or(mix.rate())
+    }
+
+    /// The rate every beat is resampled to, unless the device should follow beats
+    pub fn resampling_to(&self) -> Option<SampleRate> {
+        match self.resample {
+            Resample::Off => None,
+            _ => Some(self.fixed_rate),
+        }
+    }
+
+    /// What the device actually runs at, which may not be what [`Filler::rate_for`] asked
+    pub fn set_device_rate(&mut self, rate: SampleRate) {
+        self.device_rate = rate;
+    }
+
+    pub fn device_rate(&self) -> SampleRate {
+        self.device_rate
+    }
+
+    /// Moves 't' for the next fill
+    pub fn transport(&mut self, command: TransportCommand) {
+        self.transport.apply(command);
+    }
+
+    pub fn set_effects(&mut self, settings: EffectSettings) {
+        self.effects.set(settings);
+    }
+
+    /// Fills interleaved `frames` with the next samples of `mix`, left and right, and pushes them to the
+    /// scope and any recording (best effort). A new `mix` takes over from the last like the [`switch::SwitchPolicy`]
+    /// says, so it may not be heard yet
+    pub fn fill(&mut self, mix: &Arc<Mix>, frames: &mut [u8]) {
+        self.transport.arrive(mix);
+        self.fill_frames(frames);
+        self.effects.process(frames, self.device_rate);
+        self.tap.push(frames, self.device_rate);
+        self.switch
+            .set(self.transport.pending_at(), self.transport.is_fading());
+    }
+
+    fn fill_frames(&mut self, frames: &mut [u8]) {
+        // An old mix keeps its own rate until it's switched from
+        let own = self
+            .transport
+            .heard()
+            .map_or(SampleRate::default(), Mix::rate);
+        let speed = self.transport.speed().val();
+        // Playing faster is the same as the mix being at a higher rate
+        let rate = match speed == 1.0 {
+            true => own,
+            false => {
+                SampleRate::new(((own.hz() as f32 * speed).round() as u32).max(1)).unwrap_or(own)
+            }
+        };
+        if rate != self.device_rate {
+            if !self.resampling {
+                self.resampler.reset();
+                self.resampling = true;
+            }
+            let Self {
+                resampler,
+                transport,
+                made,
+                producer,
+                ..
+            } = self;
+            resampler.set_rates(rate, self.device_rate);
+            resampler.fill(frames, |samples| {
+                transport.eval(samples, producer);
+                *made += samples.len() as u64;
+            });
+            return;
+        }
+        self.resampling = false;
+
+        for chunk in frames.chunks_mut(STRIDE * BLOCK_LEN) {
+            let samples = &mut self.block[..chunk.len() / STRIDE];
+            self.transport.eval(samples, &mut self.producer);
+            self.made += samples.len() as u64;
+
+            for (frame, val) in chunk.chunks_exact_mut(STRIDE).zip(samples.iter()) {
+                frame.copy_from_slice(val);
+            }
+        }
+    }
+
+    /// How many samples have been heard, about, if `queued` frames were given to the device but haven't been heard
+    /// yet. Counts the same as samples sent to the scope
+    pub fn played(&self, queued: u32) -> u64 {
+        let lag = match self.resampling {
+            true => self.resampler.lag(queued),
+            false => queued as u64,
+        };
+        self.made.saturating_sub(lag)
+    }
+}
+
+/// Not RT Safe. Writes `len` samples of `beat` from `t` on to `out`, one byte each, or interleaved left and right
+/// for a stereo beat. For rendering without an audio server. Returns the 't' after the last sample.
+pub fn render(
+    beat: &parser::Beat,
+    mut t: i32,
+    len: u64,
+    out: &mut impl std::io::Write,
+) -> std::io::Result<i32> {
+    let mut block = [0; BLOCK_LEN];
+    let mut frames = [[0; CHANNELS]; BLOCK_LEN];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(BLOCK_LEN as u64) as usize;
+        if beat.is_stereo() {
+            let frames = &mut frames[..n];
+            beat.eval_stereo_block(t, frames);
+            out.write_all(frames.as_flattened())?;
+        } else {
+            let samples = &mut block[..n];
+            beat.eval_block(t, samples);
+            out.write_all(samples)?;
+        }
+        t = t.wrapping_add(n as i32);
+        left -= n as u64;
+    }
+    Ok(t)
+}
+
+/// Which [`AudioBackend`] beats are played with. Picked at startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    #[cfg(target_os = "linux")]
+    #[default]
+    Pipewire,
+    /// Linux without Pipewire
+    #[cfg(target_os = "linux")]
+    Alsa,
+    #[cfg(target_os = "windows")]
+    #[default]
+    Wasapi,
+    /// No audio device. Plays to nothing at real-time pace
+    #[cfg_attr(not(any(target_os = "linux", target_os = "windows")), default)]
+    Null,
+    /// Like null, but writes what would have been heard to a WAV file
+    File,
+}
+
+/// Settings only some backends use
+pub struct BackendOptions {
+    /// Written by [`Backend::File`]
+    pub file: PathBuf,
+    /// PCM [`Backend::Alsa`] opens
+    #[cfg(target_os = "linux")]
+    pub alsa_device: String,
+}
+
+impl Backend {
+    pub fn build(self, options: BackendOptions) -> Box<dyn AudioBackend> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Backend::Pipewire => Box::new(pipewire::Pipewire),
+            #[cfg(target_os = "linux")]
+            Backend::Alsa => Box::new(alsa::Alsa::new(options.alsa_device)),
+            #[cfg(target_os = "windows")]
+            Backend::Wasapi => Box::new(wasapi::Wasapi),
+            Backend::Null => Box::new(clocked::Clocked::null()),
+            Backend::File => Box::new(clocked::Clocked::file(options.file)),
+        }
+    }
+}
+
+/// Plays beats somewhere, on a thread of its own.
+///
+/// Backends start paused with a silent mix, and report every [`StreamStatus`] they go through as an
+/// [`AudioEvent`]. While streaming, they fill with [`BackendContext::filler`] and keep [`BackendContext::played`]
+/// near what's been heard, at least every [`T_SYNC_INTERVAL`]. [`AudioCommand::Transport`] and
+/// [`AudioCommand::Effects`] go to the filler. When a mix's [`Filler::rate_for`] changes, they
+/// run the device at the new rate if they can.
+pub trait AudioBackend: Send {
+    /// Blocks, taking `commands` until they disconnect or something goes wrong that can't be recovered from
+    fn run(
+        self: Box<Self>,
+        commands: CommandReceiver,
+        ctx: BackendContext,
+    ) -> color_eyre::Result<()>;
+}
+
+/// What a backend shares with the rest of the app
+pub struct BackendContext {
+    /// Used to communicate with the [`crate::event::EventHandler`]
+    pub event_tx: mpsc::Sender<Event>,
+    /// Evaluates mixes for the device, and feeds the scope
+    pub filler: Filler,
+    /// How many samples sent to the scope have been heard, ISH. See [`Filler::played`]
+    pub played: &'static AtomicU64,
+    /// Mixes the filler is done with. Cleared whenever commands are taken, outside of filling
+    pub retired: Retired,
+}
+
+/// Tracks the current stream state and sends notifications when it changes.
+pub struct StateTracker {
+    current: StreamStatus,
+    event_tx: mpsc::Sender<Event>,
+}
+
+impl StateTracker {
+    pub fn new(event_tx: mpsc::Sender<Event>) -> Self {
+        Self {
+            current: StreamStatus::Unconnected,
+            event_tx,
+        }
+    }
+
+    pub fn set(&mut self, new_status: StreamStatus) {
+        if self.current != new_status {
+            trace!(
+                "audio stream state change: {:?} -> {:?}",
+                self.current, new_status
+            );
+            let _ = self
+                .event_tx
+                .send(Event::Audio(AudioEvent::StateChange(new_status.clone())));
+            self.current = new_status;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current == StreamStatus::Streaming
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// Wrapped float that can represent no volume [`Volume::MUTE`] or
@@ -81,37 +421,15 @@ pub enum AudioCommand {
     Play,
     Pause,
     SetVolume(Volume),
-    NewBeat(parser::Beat),
+    NewMix(std::sync::Arc<Mix>),
+    Transport(TransportCommand),
+    Effects(EffectSettings),
 }
 
-// Re-export the platform-specific main function
-#[cfg(target_os = "linux")]
-pub use pipewire::main;
-
-#[cfg(target_os = "windows")]
-pub use wasapi::main;
-
-// Platform-agnostic channel types for sending commands to the audio thread.
-// On Linux, we use pipewire::channel which integrates with the pipewire main loop.
-// On Windows (and other platforms), we use std::sync::mpsc.
-
-#[cfg(target_os = "linux")]
-pub type CommandSender = ::pipewire::channel::Sender<AudioCommand>;
-#[cfg(target_os = "linux")]
-pub type CommandReceiver = ::pipewire::channel::Receiver<AudioCommand>;
-
-#[cfg(not(target_os = "linux"))]
-pub type CommandSender = std::sync::mpsc::Sender<AudioCommand>;
-#[cfg(not(target_os = "linux"))]
-pub type CommandReceiver = std::sync::mpsc::Receiver<AudioCommand>;
-
-/// Create a new command channel for sending commands to the audio thread.
-#[cfg(target_os = "linux")]
-pub fn command_channel() -> (CommandSender, CommandReceiver) {
-    ::pipewire::channel::channel::<AudioCommand>()
-}
+pub type CommandSender = mpsc::Sender<AudioCommand>;
+pub type CommandReceiver = mpsc::Receiver<AudioCommand>;
 
-#[cfg(not(target_os = "linux"))]
+/// Create a new command channel for sending commands to the audio thread. The same for every backend
 pub fn command_channel() -> (CommandSender, CommandReceiver) {
-    std::sync::mpsc::channel::<AudioCommand>()
+    mpsc::channel::<AudioCommand>()
 }
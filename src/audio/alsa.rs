@@ -0,0 +1,264 @@
+//! ALSA backend for Linux systems without Pipewire. Writes to a PCM device through alsa-lib, in a loop much like the
+//! WASAPI backend's.
+//!
+//! Works without a sound card against ALSA's `null` PCM, or a `file` PCM plugin defined in `~/.asoundrc`.
+//!
+//! The device is opened again whenever the mix's rate changes, unless resampling to a fixed one.
+use std::{
+    io::ErrorKind,
+    sync::{Arc, atomic::Ordering, mpsc::TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use alsa::{
+    Direction, ValueOr,
+    pcm::{Access, Format, HwParams, PCM, State},
+};
+use color_eyre::Result;
+use tracing::{error, info, trace, warn};
+
+use super::{
+    AudioBackend, AudioCommand, BLOCK_LEN, BackendContext, CHANNELS, CommandReceiver, STRIDE,
+    StateTracker, StreamStatus, Volume, mix::Mix,
+};
+use crate::parser::SampleRate;
+
+/// Longest we wait for room in the buffer. Short enough to respond to commands promptly.
+const WAIT_TIMEOUT_MS: u32 = 10;
+/// How far ahead of what's heard ALSA is filled. Microseconds
+const BUFFER_TIME: u32 = 100_000;
+/// Microseconds
+const PERIOD_TIME: u32 = 10_000;
+
+/// [`super::Backend::Alsa`]
+pub struct Alsa {
+    /// PCM name, like `default`, `hw:0` or `null`
+    device: String,
+}
+
+impl Alsa {
+    pub fn new(device: String) -> Self {
+        Self { device }
+    }
+}
+
+impl AudioBackend for Alsa {
+    fn run(self: Box<Self>, command_rx: CommandReceiver, ctx: BackendContext) -> Result<()> {
+        main(&self.device, command_rx, ctx)
+    }
+}
+
+fn main(device: &str, command_rx: CommandReceiver, ctx: BackendContext) -> Result<()> {
+    let BackendContext {
+        event_tx,
+        mut filler,
+        played,
+        mut retired,
+    } = ctx;
+    info!("ALSA thread starting");
+    let mut mix = Arc::new(Mix::default());
+    // What the device is opened at
+    let mut rate = filler.rate_for(&mix);
+    let mut volume = Volume::default();
+    // As the app wishes it. Kept if the device has to be opened again
+    let mut playing = false;
+
+    let mut state_tracker = StateTracker::new(event_tx);
+    let mut last_t_sync = Instant::now();
+    // Scratch space for [`super::Filler`] to fill
+    let mut frames = [0u8; STRIDE * BLOCK_LEN];
+    // Bytes of `frames` filled but not written yet, when the device took fewer than it was given. They've been
+    // counted as made and pushed to the scope, so they're written before anything new is filled
+    let mut unwritten = 0..0;
+
+    loop {
+        state_tracker.set(StreamStatus::Connecting);
+
+        let pcm = match open(device, rate) {
+            Ok((pcm, got)) => {
+                if got != rate {
+                    warn!(
+                        "ALSA device plays at {} instead of {}, resampling",
+                        got, rate
+                    );
+                }
+                filler.set_device_rate(got);
+                pcm
+            }
+            Err(e) => {
+                error!("failed to open ALSA device '{}': {}", device, e);
+                state_tracker.set(StreamStatus::Error);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+        let io = pcm.io_u8()?;
+        let can_pause = pcm.hw_params_current().is_ok_and(|hwp| hwp.can_pause());
+
+        // Start paused - matches pipewire behavior
+        state_tracker.set(StreamStatus::Paused);
+        if playing {
+            state_tracker.set(StreamStatus::Streaming);
+        }
+
+        // Set when the device has to be opened again at another rate
+        let mut reopen = false;
+        loop {
+            // Between fills, not while the device waits on one
+            retired.clear();
+            // Process all pending commands
+            loop {
+                match command_rx.try_recv() {
+                    Ok(cmd) => {
+                        trace!("ALSA thread received command: {:?}", cmd);
+                        match cmd {
+                            AudioCommand::Play => {
+                                if !state_tracker.is_active() {
+                                    resume(&pcm);
+                                    playing = true;
+                                    state_tracker.set(StreamStatus::Streaming);
+                                }
+                            }
+                            AudioCommand::Pause => {
+                                if state_tracker.is_active() {
+                                    pause(&pcm, can_pause);
+                                    playing = false;
+                                    state_tracker.set(StreamStatus::Paused);
+                                }
+                            }
+                            AudioCommand::NewMix(new) => {
+                                let wanted = filler.rate_for(&new);
+                                if wanted != rate {
+                                    info!("ALSA reopening at {}", wanted);
+                                    rate = wanted;
+                                    reopen = true;
+                                }
+                                mix = new;
+                            }
+                            AudioCommand::SetVolume(vol) => volume = vol,
+                            AudioCommand::Transport(command) => filler.transport(command),
+                            AudioCommand::Effects(settings) => filler.set_effects(settings),
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        info!("ALSA command channel disconnected, exiting");
+                        return Ok(());
+                    }
+                }
+            }
+            if reopen {
+                break;
+            }
+
+            if !state_tracker.is_active() {
+                thread::sleep(Duration::from_millis(WAIT_TIMEOUT_MS as u64));
+                continue;
+            }
+
+            // Update played periodically for the Scope widget
+            if last_t_sync.elapsed() >= super::T_SYNC_INTERVAL {
+                // Frames made that haven't been heard yet
+                if let Ok(delay) = pcm.delay() {
+                    let queued = delay as u32 + (unwritten.len() / STRIDE) as u32;
+                    played.store(filler.played(queued), Ordering::Relaxed);
+                }
+                last_t_sync = Instant::now();
+            }
+
+            let res = (|| -> alsa::Result<()> {
+                pcm.wait(Some(WAIT_TIMEOUT_MS))?;
+                let mut room = pcm.avail_update()? as usize;
+                while room > 0 {
+                    if unwritten.is_empty() {
+                        let chunk = &mut frames[..room.min(BLOCK_LEN) * STRIDE];
+                        filler.fill(&mix, chunk);
+                        apply_volume(chunk, volume);
+                        unwritten = 0..chunk.len();
+                    }
+                    let end = unwritten.end.min(unwritten.start + room * STRIDE);
+                    let written = match io.writei(&frames[unwritten.start..end]) {
+                        Ok(written) => written,
+                        // Full after all
+                        Err(e)
+                            if std::io::Error::from_raw_os_error(e.errno()).kind()
+                                == ErrorKind::WouldBlock =>
+                        {
+                            0
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    if written == 0 {
+                        break;
+                    }
+                    unwritten.start += written * STRIDE;
+                    room -= written;
+                }
+                Ok(())
+            })();
+
+            if let Err(e) = res {
+                let reason = e.to_string();
+                if std::io::Error::from_raw_os_error(e.errno()).kind() == ErrorKind::BrokenPipe {
+                    warn!("ALSA underrun, recovering");
+                }
+                // Handles underruns (EPIPE) and suspends, and prepares the device to go again
+                if pcm.try_recover(e, true).is_err() {
+                    error!("ALSA device failed ({}), reopening", reason);
+                    state_tracker.set(StreamStatus::Connecting);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Opens `device` for U8 stereo at `rate`, or as near as it gets, without blocking on writes. Leaves it prepared, so
+/// the first write starts it. Returns the rate it got too.
+fn open(device: &str, rate: SampleRate) -> alsa::Result<(PCM, SampleRate)> {
+    let pcm = PCM::new(device, Direction::Playback, true)?;
+    let got = {
+        let hwp = HwParams::any(&pcm)?;
+        hwp.set_access(Access::RWInterleaved)?;
+        hwp.set_format(Format::U8)?;
+        hwp.set_channels(CHANNELS as u32)?;
+        hwp.set_rate(rate.hz(), ValueOr::Nearest)?;
+        hwp.set_buffer_time_near(BUFFER_TIME, ValueOr::Nearest)?;
+        hwp.set_period_time_near(PERIOD_TIME, ValueOr::Nearest)?;
+        pcm.hw_params(&hwp)?;
+        hwp.get_rate()?
+    };
+    Ok((pcm, SampleRate::new(got).unwrap_or(rate)))
+}
+
+/// Holds on to what's buffered if the device can, or throws it away
+fn pause(pcm: &PCM, can_pause: bool) {
+    let res = if can_pause && pcm.state() == State::Running {
+        pcm.pause(true)
+    } else {
+        pcm.drop()
+    };
+    let _ = res.inspect_err(|e| error!("ALSA thread failed to pause: {}", e));
+}
+
+fn resume(pcm: &PCM) {
+    let res = match pcm.state() {
+        State::Paused => pcm.pause(false),
+        // Dropped, so it needs preparing before the next write can start it
+        State::Setup => pcm.prepare(),
+        _ => Ok(()),
+    };
+    let _ = res.inspect_err(|e| error!("ALSA thread failed to resume: {}", e));
+}
+
+/// A PCM has no volume of its own, like a Pipewire stream or WASAPI session does. So we have to scale samples
+/// towards silence (the middle) ourselves.
+fn apply_volume(frames: &mut [u8], volume: Volume) {
+    if volume == Volume::MAX {
+        return;
+    }
+    for sample in frames {
+        *sample = (128.0 + (*sample as f32 - 128.0) * volume.val()) as u8;
+    }
+}
@@ -0,0 +1,147 @@
+//! Backends without an audio device. They keep time with the clock at the device rate, so the TUI and scope behave as
+//! if something were listening. Good for CI, and machines without an audio server.
+//!
+//! A file can't change its rate halfway through, so the file backend always resamples to the fixed rate.
+use std::{
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering, mpsc::RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use tracing::{error, info, trace};
+
+use super::{
+    AudioBackend, AudioCommand, BLOCK_LEN, BackendContext, CHANNELS, CommandReceiver, STRIDE,
+    StateTracker, StreamStatus, mix::Mix, wav,
+};
+
+/// How long to wait between batches of samples. Commands are taken as soon as they come in.
+const PERIOD: Duration = Duration::from_millis(10);
+
+/// [`super::Backend::Null`], or [`super::Backend::File`] if there's a path
+pub struct Clocked {
+    path: Option<PathBuf>,
+}
+
+impl Clocked {
+    pub fn null() -> Self {
+        Self { path: None }
+    }
+
+    pub fn file(path: PathBuf) -> Self {
+        Self { path: Some(path) }
+    }
+}
+
+impl AudioBackend for Clocked {
+    fn run(self: Box<Self>, commands: CommandReceiver, mut ctx: BackendContext) -> Result<()> {
+        info!("clocked audio thread starting");
+        let mut state_tracker = StateTracker::new(ctx.event_tx.clone());
+        state_tracker.set(StreamStatus::Connecting);
+
+        let mut mix = Arc::new(Mix::default());
+        // The file stays at the fixed rate. Nothing else minds changing
+        let mut rate = match self.path {
+            Some(_) => ctx.filler.device_rate(),
+            None => ctx.filler.rate_for(&mix),
+        };
+        ctx.filler.set_device_rate(rate);
+
+        let mut sink = match &self.path {
+            Some(path) => match wav::Writer::create(path, rate.hz(), CHANNELS as u16) {
+                Ok(writer) => Some(writer),
+                Err(e) => {
+                    error!("failed to create {}: {}", path.display(), e);
+                    state_tracker.set(StreamStatus::Error);
+                    return Err(e.into());
+                }
+            },
+            None => None,
+        };
+
+        // Scratch space for [`super::Filler`] to fill
+        let mut frames = [0u8; STRIDE * BLOCK_LEN];
+        // While streaming: when it started, and how many frames have been made since
+        let mut clock: Option<(Instant, u64)> = None;
+
+        // Start paused - matches pipewire behavior
+        state_tracker.set(StreamStatus::Paused);
+
+        loop {
+            // Between fills
+            ctx.retired.clear();
+            let deadline = Instant::now() + PERIOD;
+            loop {
+                match commands.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(command) => {
+                        trace!("clocked audio thread received command: {:?}", command);
+                        match command {
+                            AudioCommand::Play => {
+                                if !state_tracker.is_active() {
+                                    clock = Some((Instant::now(), 0));
+                                    state_tracker.set(StreamStatus::Streaming);
+                                }
+                            }
+                            AudioCommand::Pause => {
+                                if state_tracker.is_active() {
+                                    clock = None;
+                                    state_tracker.set(StreamStatus::Paused);
+                                }
+                            }
+                            AudioCommand::NewMix(new) => {
+                                let wanted = ctx.filler.rate_for(&new);
+                                if sink.is_none() && wanted != rate {
+                                    rate = wanted;
+                                    ctx.filler.set_device_rate(rate);
+                                    // The clock counts frames at the old rate
+                                    if let Some(clock) = &mut clock {
+                                        *clock = (Instant::now(), 0);
+                                    }
+                                }
+                                mix = new;
+                            }
+                            // Nothing to turn down. A file gets what the mix makes, like `bytebeat render`
+                            AudioCommand::SetVolume(_) => {}
+                            AudioCommand::Transport(command) => ctx.filler.transport(command),
+                            AudioCommand::Effects(settings) => ctx.filler.set_effects(settings),
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        info!("clocked audio command channel disconnected, exiting");
+                        return Ok(());
+                    }
+                }
+            }
+
+            let Some((since, made)) = &mut clock else {
+                continue;
+            };
+            let due = (since.elapsed().as_secs_f64() * rate.hz() as f64) as u64;
+            // After a long stall, skip ahead instead of making it all at once. A device would've underrun
+            let mut left = (due - *made).min(rate.hz() as u64) as usize;
+            *made = due;
+            let mut written = Ok(());
+            while left > 0 {
+                let chunk = &mut frames[..left.min(BLOCK_LEN) * STRIDE];
+                ctx.filler.fill(&mix, chunk);
+                if let Some(writer) = &mut sink {
+                    written = written.and_then(|_| writer.write(chunk));
+                }
+                left -= chunk.len() / STRIDE;
+            }
+            let flushed = match &mut sink {
+                Some(writer) => written.and_then(|_| writer.flush()),
+                None => written,
+            };
+            if let Err(e) = flushed {
+                error!("failed to write audio file: {}", e);
+                state_tracker.set(StreamStatus::Error);
+                return Err(e.into());
+            }
+            // Nothing is buffered, so what was just made is what's playing
+            ctx.played.store(ctx.filler.played(0), Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! Post-processing for the frames the [`super::Filler`] makes, at the device's rate. Raw bytebeat sits well off
+//! centre and aliases like mad, which some like and some don't.
+//!
+//! With nothing on (or everything bypassed) frames are left exactly as evaluated.
+use std::f32::consts::PI;
+
+use super::CHANNELS;
+use crate::parser::SampleRate;
+
+/// Longest echo. Its buffer is made up front for rates up to [`MAX_RATE`], and shorter above it
+pub const MAX_DELAY_MS: u32 = 2000;
+/// Highest device rate a whole [`MAX_DELAY_MS`] fits at
+const MAX_RATE: u64 = 192_000;
+/// How far behind the other channel the widener's made up side signal is. Short enough to be heard as one sound
+const HAAS_MS: u32 = 12;
+/// Pole of the DC blocker. Closer to 1 cuts less of the lows
+const DC_POLE: f32 = 0.995;
+/// Butterworth
+const Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Every effect, in the order they're applied. Off ones keep their settings for when they're on again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectSettings {
+    /// Skips every effect, for output as true to C as it gets
+    pub bypass: bool,
+    pub crush: bool,
+    /// Bits kept of every sample, up to 8
+    pub crush_bits: u8,
+    /// Every sample is held for this many frames, for a lower sample rate
+    pub crush_hold: u16,
+    /// Takes out the offset, since bytebeat is never centred
+    pub dc_block: bool,
+    pub high_pass: bool,
+    pub high_pass_hz: f32,
+    pub low_pass: bool,
+    pub low_pass_hz: f32,
+    pub delay: bool,
+    pub delay_ms: u32,
+    /// Of every echo fed back into the next, below 1
+    pub delay_feedback: f32,
+    /// How loud echoes are against what's played
+    pub delay_mix: f32,
+    pub widen: bool,
+    /// 0 is mono, 1 leaves it alone and above it makes up a side signal
+    pub width: f32,
+}
+
+impl Default for EffectSettings {
+    fn default() -> Self {
+        Self {
+            bypass: false,
+            crush: false,
+            crush_bits: 6,
+            crush_hold: 2,
+            dc_block: false,
+            high_pass: false,
+            high_pass_hz: 40.0,
+            low_pass: false,
+            low_pass_hz: 4000.0,
+            delay: false,
+            delay_ms: 250,
+            delay_feedback: 0.4,
+            delay_mix: 0.5,
+            widen: false,
+            width: 1.5,
+        }
+    }
+}
+
+impl EffectSettings {
+    pub const MAX_FEEDBACK: f32 = 0.95;
+    pub const MAX_WIDTH: f32 = 2.0;
+    pub const MIN_CUTOFF_HZ: f32 = 10.0;
+    pub const MAX_CUTOFF_HZ: f32 = 20000.0;
+
+    /// Whether frames are touched at all
+    pub fn is_active(&self) -> bool {
+        !self.bypass
+            && (self.crush
+                || self.dc_block
+                || self.high_pass
+                || self.low_pass
+                || self.delay
+                || self.widen)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct DcBlocker {
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlocker {
+    fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + DC_POLE * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Transposed direct form II, coefficients from the Audio EQ Cookbook
+#[derive(Debug, Default, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn set(&mut self, high: bool, hz: f32, rate: SampleRate) {
+        // Just under Nyquist, or it's no filter at all
+        let hz = hz.clamp(1.0, rate.hz() as f32 * 0.49);
+        let w0 = 2.0 * PI * hz / rate.hz() as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * Q);
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = match high {
+            false => ((1.0 - cos) / 2.0, 1.0 - cos),
+            true => ((1.0 + cos) / 2.0, -(1.0 + cos)),
+        };
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b0 / a0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Made once, up front. Holds one channel
+#[derive(Debug)]
+struct DelayLine {
+    buf: Box<[f32]>,
+    pos: usize,
+}
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buf: vec![0.0; len].into_boxed_slice(),
+            pos: 0,
+        }
+    }
+
+    /// From `len` samples ago, clamped to what fits
+    fn read(&self, len: usize) -> f32 {
+        let len = len.clamp(1, self.buf.len() - 1);
+        self.buf[(self.pos + self.buf.len() - len) % self.buf.len()]
+    }
+
+    fn write(&mut self, x: f32) {
+        self.buf[self.pos] = x;
+        self.pos = (self.pos + 1) % self.buf.len();
+    }
+
+    fn clear(&mut self) {
+        self.buf.fill(0.0);
+    }
+}
+
+/// The chain, with what every effect remembers between frames. RT safe, besides being made
+#[derive(Debug)]
+pub struct Effects {
+    settings: EffectSettings,
+    /// The device's, which filters and delays are worked out at
+    rate: SampleRate,
+    /// Frames left until the crusher takes another sample
+    hold_left: u16,
+    held: [f32; CHANNELS],
+    dc: [DcBlocker; CHANNELS],
+    high: [Biquad; CHANNELS],
+    low: [Biquad; CHANNELS],
+    echoes: [DelayLine; CHANNELS],
+    /// Mid, for the widener's made up side
+    haas: DelayLine,
+}
+
+impl Effects {
+    /// Not RT safe, since every delay's buffer is made here
+    pub fn new(settings: EffectSettings) -> Self {
+        let echo_len = (MAX_DELAY_MS as u64 * MAX_RATE / 1000) as usize;
+        let haas_len = (HAAS_MS as u64 * MAX_RATE / 1000) as usize + 1;
+        let mut effects = Self {
+            settings,
+            rate: SampleRate::default(),
+            hold_left: 0,
+            held: [0.0; CHANNELS],
+            dc: Default::default(),
+            high: Default::default(),
+            low: Default::default(),
+            echoes: std::array::from_fn(|_| DelayLine::new(echo_len)),
+            haas: DelayLine::new(haas_len),
+        };
+        effects.set_filters();
+        effects
+    }
+
+    pub fn set(&mut self, settings: EffectSettings) {
+        let old = std::mem::replace(&mut self.settings, settings);
+        // Effects start afresh when they're on again, rather than with whatever they last had
+        if old.delay && !settings.delay {
+            self.echoes.iter_mut().for_each(DelayLine::clear);
+        }
+        if old.widen && !settings.widen {
+            self.haas.clear();
+        }
+        if old.dc_block && !settings.dc_block {
+            self.dc = Default::default();
+        }
+        if old.high_pass && !settings.high_pass {
+            self.high.iter_mut().for_each(Biquad::reset);
+        }
+        if old.low_pass && !settings.low_pass {
+            self.low.iter_mut().for_each(Biquad::reset);
+        }
+        self.set_filters();
+    }
+
+    fn set_filters(&mut self) {
+        for (high, low) in self.high.iter_mut().zip(&mut self.low) {
+            high.set(true, self.settings.high_pass_hz, self.rate);
+            low.set(false, self.settings.low_pass_hz, self.rate);
+        }
+    }
+
+    /// RT safe. Runs interleaved `frames`, made at `rate`, through every effect that's on
+    pub fn process(&mut self, frames: &mut [u8], rate: SampleRate) {
+        if !self.settings.is_active() {
+            return;
+        }
+        if rate != self.rate {
+            self.rate = rate;
+            self.set_filters();
+        }
+        let s = self.settings;
+        let echo_len = (s.delay_ms as u64 * rate.hz() as u64 / 1000) as usize;
+        let haas_len = (HAAS_MS as u64 * rate.hz() as u64 / 1000) as usize;
+        let crush_levels = (1u32 << s.crush_bits.clamp(1, 8)) as f32 / 2.0;
+
+        for frame in frames.chunks_exact_mut(CHANNELS) {
+            let mut x = [0.0; CHANNELS];
+            for (x, sample) in x.iter_mut().zip(frame.iter()) {
+                *x = (*sample as f32 - 128.0) / 128.0;
+            }
+
+            if s.crush {
+                if self.hold_left == 0 {
+                    for (held, x) in self.held.iter_mut().zip(&x) {
+                        *held = (x * crush_levels).floor() / crush_levels;
+                    }
+                    self.hold_left = s.crush_hold.max(1);
+                }
+                self.hold_left -= 1;
+                x = self.held;
+            }
+            for (i, x) in x.iter_mut().enumerate() {
+                if s.dc_block {
+                    *x = self.dc[i].process(*x);
+                }
+                if s.high_pass {
+                    *x = self.high[i].process(*x);
+                }
+                if s.low_pass {
+                    *x = self.low[i].process(*x);
+                }
+                if s.delay {
+                    let echo = self.echoes[i].read(echo_len);
+                    self.echoes[i]
+                        .write(*x + echo * s.delay_feedback.min(EffectSettings::MAX_FEEDBACK));
+                    *x += echo * s.delay_mix;
+                }
+            }
+            if s.widen {
+                let [left, right] = x;
+                let mid = (left + right) / 2.0;
+                // Anything past 1 comes from the mid against itself a moment ago, since a mono beat has no side
+                let made_up = (mid - self.haas.read(haas_len)) / 2.0;
+                self.haas.write(mid);
+                let side = (left - right) / 2.0 * s.width + made_up * (s.width - 1.0).max(0.0);
+                x = [mid + side, mid - side];
+            }
+
+            for (sample, x) in frame.iter_mut().zip(x) {
+                *sample = (x * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(settings: EffectSettings, frames: &mut [u8]) {
+        Effects::new(settings).process(frames, SampleRate::CLASSIC);
+    }
+
+    /// `samples`, copied across channels like the filler does
+    fn frames(samples: impl IntoIterator<Item = u8>) -> Vec<u8> {
+        samples.into_iter().flat_map(|s| [s; CHANNELS]).collect()
+    }
+
+    #[test]
+    fn test_off_and_bypass_untouched() {
+        let original = frames((0..=255).cycle().take(4000));
+        let mut out = original.clone();
+        run(EffectSettings::default(), &mut out);
+        assert_eq!(out, original);
+
+        let bypassed = EffectSettings {
+            bypass: true,
+            dc_block: true,
+            crush: true,
+            delay: true,
+            ..Default::default()
+        };
+        run(bypassed, &mut out);
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_dc_block_centres() {
+        let mut out = frames([200; 4000]);
+        run(
+            EffectSettings {
+                dc_block: true,
+                ..Default::default()
+            },
+            &mut out,
+        );
+        assert!(out[0] > 190);
+        assert!(out[out.len() - 2].abs_diff(128) <= 1);
+    }
+
+    #[test]
+    fn test_crush() {
+        let mut out = frames([0, 50, 130, 255, 7, 7]);
+        let settings = EffectSettings {
+            crush: true,
+            crush_bits: 2,
+            crush_hold: 2,
+            ..Default::default()
+        };
+        run(settings, &mut out);
+        // Down to 4 levels, every other sample held
+        assert_eq!(out, frames([0, 0, 128, 128, 0, 0]));
+    }
+
+    #[test]
+    fn test_filters() {
+        // Nyquist is gone through a low-pass, and anything steady through a high-pass
+        let mut out = frames((0..2000).map(|i| if i % 2 == 0 { 64 } else { 192 }));
+        let low = EffectSettings {
+            low_pass: true,
+            low_pass_hz: 500.0,
+            ..Default::default()
+        };
+        run(low, &mut out);
+        assert!(out[1000..].iter().all(|&s| s.abs_diff(128) <= 2));
+
+        let mut out = frames([255; 2000]);
+        let high = EffectSettings {
+            high_pass: true,
+            high_pass_hz: 200.0,
+            ..Default::default()
+        };
+        run(high, &mut out);
+        assert!(out[2000..].iter().all(|&s| s.abs_diff(128) <= 1));
+    }
+
+    #[test]
+    fn test_delay_echoes() {
+        // 10 ms at 8kHz is 80 samples
+        let mut out = frames((0..200).map(|i| if i == 0 { 228 } else { 128 }));
+        let settings = EffectSettings {
+            delay: true,
+            delay_ms: 10,
+            delay_feedback: 0.5,
+            delay_mix: 1.0,
+            ..Default::default()
+        };
+        run(settings, &mut out);
+        let heard: Vec<(usize, u8)> = out
+            .iter()
+            .step_by(CHANNELS)
+            .copied()
+            .enumerate()
+            .filter(|&(_, s)| s != 128)
+            .collect();
+        assert_eq!(heard, [(0, 228), (80, 228), (160, 178)]);
+    }
+
+    #[test]
+    fn test_width() {
+        let mut out = vec![200, 100, 200, 100];
+        let mono = EffectSettings {
+            widen: true,
+            width: 0.0,
+            ..Default::default()
+        };
+        run(mono, &mut out);
+        assert_eq!(out, [150; 4]);
+
+        // A mono beat is made wider with a side of its own
+        let mut out = frames((0..400).map(|i| (i * 7 % 256) as u8));
+        let wide = EffectSettings {
+            widen: true,
+            width: 2.0,
+            ..Default::default()
+        };
+        run(wide, &mut out);
+        assert!(out.chunks_exact(CHANNELS).any(|frame| frame[0] != frame[1]));
+    }
+}
@@ -0,0 +1,278 @@
+//! Several beats playing at once. Every layer is evaluated for the same 't' (plus its own offset), and their samples
+//! are combined into one, channel by channel.
+//!
+//! Every layer plays at the first layer's rate, so the app compiles them all for it.
+use std::{fmt, sync::Arc};
+
+use crate::parser::{self, SampleRate, Stereo};
+
+/// Samples combined at once, on the stack
+const SCRATCH_LEN: usize = 256;
+
+/// How layers' samples are combined into one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Combine {
+    /// Added around the middle, clipping at either end
+    #[default]
+    Sum,
+    /// Added around the middle, then divided by how many layers are heard, so it never clips
+    Mean,
+    /// Bitwise XOR of every layer's sample
+    Xor,
+    /// Bitwise OR of every layer's sample
+    Or,
+}
+
+impl Combine {
+    pub fn next(self) -> Self {
+        match self {
+            Combine::Sum => Combine::Mean,
+            Combine::Mean => Combine::Xor,
+            Combine::Xor => Combine::Or,
+            Combine::Or => Combine::Sum,
+        }
+    }
+}
+
+impl fmt::Display for Combine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Combine::Sum => "Sum",
+            Combine::Mean => "Mean",
+            Combine::Xor => "XOR",
+            Combine::Or => "OR",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How a layer is heard in the mix
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerSettings {
+    /// Scales the sample around the middle, before it's combined. Past 1 it clips
+    pub gain: f32,
+    /// Added to 't' before the layer is evaluated
+    pub offset: i32,
+    pub muted: bool,
+    /// While any layer is soloed, only soloed layers are heard
+    pub solo: bool,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            offset: 0,
+            muted: false,
+            solo: false,
+        }
+    }
+}
+
+impl LayerSettings {
+    pub const MAX_GAIN: f32 = 2.0;
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub beat: Arc<parser::Beat>,
+    pub settings: LayerSettings,
+}
+
+/// What the audio thread plays. Made again whenever a layer changes, and swapped in whole
+#[derive(Debug, Default)]
+pub struct Mix {
+    layers: Vec<Layer>,
+    combine: Combine,
+}
+
+impl Mix {
+    pub fn new(layers: Vec<Layer>, combine: Combine) -> Self {
+        Self { layers, combine }
+    }
+
+    /// Just `beat`, as it is
+    pub fn single(beat: parser::Beat) -> Self {
+        let layer = Layer {
+            beat: Arc::new(beat),
+            settings: LayerSettings::default(),
+        };
+        Self::new(vec![layer], Combine::default())
+    }
+
+    /// The first layer's, which every layer plays at
+    pub fn rate(&self) -> SampleRate {
+        self.layers
+            .first()
+            .map_or(SampleRate::default(), |layer| layer.beat.rate())
+    }
+
+    /// RT safe. Like [`parser::Beat::eval_stereo_block`], for every layer heard at once. Silent (the middle) if none
+    /// are
+    pub fn eval_block(&self, t_start: i32, out: &mut [Stereo]) {
+        let soloing = self.layers.iter().any(|layer| layer.settings.solo);
+        let heard = |layer: &&Layer| !layer.settings.muted && (!soloing || layer.settings.solo);
+        let count = self.layers.iter().filter(heard).count();
+
+        // Nothing to combine
+        let alone = match self.layers.as_slice() {
+            [layer] if count == 1 && layer.settings.gain == 1.0 => Some(layer),
+            _ => None,
+        };
+        if let Some(layer) = alone {
+            layer
+                .beat
+                .eval_stereo_block(t_start.wrapping_add(layer.settings.offset), out);
+            return;
+        }
+
+        let mut scratch = [[0u8; 2]; SCRATCH_LEN];
+        let mut sum = [[0f32; 2]; SCRATCH_LEN];
+        let mut bits = [[0u8; 2]; SCRATCH_LEN];
+        for (chunk, t) in out
+            .chunks_mut(SCRATCH_LEN)
+            .zip((0i32..).step_by(SCRATCH_LEN))
+        {
+            let t = t_start.wrapping_add(t);
+            let n = chunk.len();
+            sum[..n].fill([0.0; 2]);
+            bits[..n].fill([0; 2]);
+            for layer in self.layers.iter().filter(heard) {
+                let frames = &mut scratch[..n];
+                layer
+                    .beat
+                    .eval_stereo_block(t.wrapping_add(layer.settings.offset), frames);
+                let gain = layer.settings.gain;
+                let channels = frames
+                    .iter()
+                    .zip(&mut sum[..n])
+                    .zip(&mut bits[..n])
+                    .flat_map(|((frame, sum), bits)| frame.iter().zip(sum).zip(bits));
+                for ((sample, sum), bits) in channels {
+                    let centered = (*sample as f32 - 128.0) * gain;
+                    match self.combine {
+                        Combine::Sum | Combine::Mean => *sum += centered,
+                        Combine::Xor => *bits ^= to_sample(centered),
+                        Combine::Or => *bits |= to_sample(centered),
+                    }
+                }
+            }
+            for ((frame, sum), bits) in chunk.iter_mut().zip(&sum[..n]).zip(&bits[..n]) {
+                for ((sample, sum), bits) in frame.iter_mut().zip(sum).zip(bits) {
+                    *sample = match (self.combine, count) {
+                        (_, 0) => 128,
+                        (Combine::Sum, _) => to_sample(*sum),
+                        (Combine::Mean, _) => to_sample(*sum / count as f32),
+                        (Combine::Xor | Combine::Or, _) => *bits,
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Back from around the middle, clipping
+fn to_sample(centered: f32) -> u8 {
+    (centered + 128.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CompileOptions;
+
+    fn layer(source: &str, settings: LayerSettings) -> Layer {
+        let beat = parser::Beat::compile(source, &CompileOptions::default()).unwrap();
+        Layer {
+            beat: Arc::new(beat),
+            settings,
+        }
+    }
+
+    /// Of the left channel. Mono beats are the same in both
+    fn eval(mix: &Mix, t: i32, len: usize) -> Vec<u8> {
+        let mut out = vec![[0; 2]; len];
+        mix.eval_block(t, &mut out);
+        out.iter().map(|frame| frame[0]).collect()
+    }
+
+    #[test]
+    fn test_single_layer_unchanged() {
+        let beat = parser::Beat::compile("t*(t>>10)", &CompileOptions::default()).unwrap();
+        let expected: Vec<u8> = (0..1000).map(|t| beat.eval(t)).collect();
+        assert_eq!(eval(&Mix::single(beat), 0, 1000), expected);
+    }
+
+    #[test]
+    fn test_combine_modes() {
+        let layers = vec![
+            layer("200", LayerSettings::default()),
+            layer("100", LayerSettings::default()),
+        ];
+        let mix = |combine| Mix::new(layers.clone(), combine);
+        // 72 + -28 around the middle
+        assert_eq!(eval(&mix(Combine::Sum), 0, 600), [172; 600]);
+        assert_eq!(eval(&mix(Combine::Mean), 0, 3), [150; 3]);
+        assert_eq!(eval(&mix(Combine::Xor), 0, 3), [200 ^ 100; 3]);
+        assert_eq!(eval(&mix(Combine::Or), 0, 3), [200 | 100; 3]);
+
+        let loud = vec![layer("250", LayerSettings::default()); 2];
+        assert_eq!(eval(&Mix::new(loud, Combine::Sum), 0, 3), [255; 3]);
+    }
+
+    #[test]
+    fn test_mute_solo_gain_offset() {
+        let muted = LayerSettings {
+            muted: true,
+            ..Default::default()
+        };
+        let solo = LayerSettings {
+            solo: true,
+            ..Default::default()
+        };
+        let mix = Mix::new(
+            vec![layer("t", muted), layer("50", LayerSettings::default())],
+            Combine::Sum,
+        );
+        assert_eq!(eval(&mix, 0, 3), [50; 3]);
+
+        let mix = Mix::new(
+            vec![layer("t", LayerSettings::default()), layer("50", solo)],
+            Combine::Sum,
+        );
+        assert_eq!(eval(&mix, 0, 3), [50; 3]);
+
+        let half = LayerSettings {
+            gain: 0.5,
+            offset: 100,
+            ..Default::default()
+        };
+        let mix = Mix::new(vec![layer("t", half)], Combine::Sum);
+        // (100 - 128) / 2, (228 - 128) / 2 around the middle
+        assert_eq!(eval(&mix, 0, 1), [114]);
+        assert_eq!(eval(&mix, 128, 1), [178]);
+
+        let mix = Mix::new(vec![layer("t", muted)], Combine::Xor);
+        assert_eq!(eval(&mix, 0, 3), [128; 3]);
+    }
+
+    #[test]
+    fn test_stereo_layers() {
+        let mix = Mix::new(
+            vec![
+                layer("[200, 100]", LayerSettings::default()),
+                layer("150", LayerSettings::default()),
+            ],
+            Combine::Sum,
+        );
+        let mut out = [[0; 2]; 3];
+        mix.eval_block(0, &mut out);
+        // 72 + 22 and -28 + 22 around the middle
+        assert_eq!(out, [[222, 122]; 3]);
+
+        let mut out = [[0; 2]; 2];
+        Mix::single(parser::Beat::compile("[t, t * 2]", &CompileOptions::default()).unwrap())
+            .eval_block(3, &mut out);
+        assert_eq!(out, [[3, 6], [4, 8]]);
+    }
+}
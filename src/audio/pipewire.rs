@@ -1,13 +1,18 @@
 //! Pipewire backend for Linux. Uses the stream API. Mostly uses safe bindings.
 //!
+//! The stream is connected again with another format whenever the mix's rate changes, unless resampling to a fixed
+//! one.
+//!
 //! TODO: May need to handle device {un,re}plugging like in WASAPI?
 use std::{
+    cell::{Cell, RefCell},
     mem,
     sync::{
         Arc, LazyLock,
-        atomic::{AtomicI32, Ordering},
+        atomic::{AtomicU32, AtomicU64, Ordering},
         mpsc,
     },
+    thread,
 };
 
 use arc_swap::ArcSwap;
@@ -22,8 +27,12 @@ use pipewire::{
 use pw::properties::properties;
 use tracing::{error, info, trace, warn};
 
-use super::{AudioCommand, AudioEvent, BITRATE, CHANNELS, STRIDE, StreamStatus, Volume};
-use crate::{event::Event, parser};
+use super::{
+    AudioBackend, AudioCommand, AudioEvent, BackendContext, CHANNELS, CommandReceiver, Filler,
+    STRIDE, StreamStatus, Volume, effects::EffectSettings, mix::Mix, switch::Retired,
+    transport::TransportCommand,
+};
+use crate::{event::Event, parser::SampleRate};
 
 // None of these structs are necessary. They're hopefully optimized out
 // They're used to make it clearer what state each callback relies upon
@@ -40,34 +49,61 @@ struct StateChangeState {
 #[derive(new)]
 struct CommandState {
     stream: StreamRc,
-    beat: &'static ArcSwap<parser::Beat>,
-}
-
-/// Used in the attached timer which updates the 'play head'
-/// for the benefit of the TUI
-#[derive(new)]
-struct TimerState {
-    t_write: &'static AtomicI32,
-    stream: StreamRc,
-    t_play: &'static AtomicI32,
+    mix: &'static ArcSwap<Mix>,
+    /// Hertz the stream is connected at
+    rate: &'static AtomicU32,
+    /// The fixed rate every beat is resampled to, if any. See [`Filler::rate_for`]
+    resampling_to: Option<SampleRate>,
+    /// Where [`AudioCommand::Transport`] and [`AudioCommand::Effects`] go. The filler lives in the process callback
+    to_filler: RefCell<rtrb::Producer<FillerCommand>>,
+    /// Mixes the process callback is done with, dropped here instead
+    retired: RefCell<Retired>,
+    /// As the app wishes it. Both are set again when the stream is connected again
+    #[new(value = "Cell::new(false)")]
+    active: Cell<bool>,
+    #[new(value = "Cell::new(Volume::default())")]
+    volume: Cell<Volume>,
 }
 
 /// Passed solely to the [`on_process`] callback
 #[derive(new)]
 struct ProcessState {
-    /// Used internally to decide what sample to calculate next
-    t_write: &'static AtomicI32,
-    beat: &'static ArcSwap<parser::Beat>,
-    /// (Ideally) loaded with contiguous sample frames. Scope widget uses this to visualize
-    producer: rtrb::Producer<u8>,
+    mix: &'static ArcSwap<Mix>,
+    /// Hertz the stream is connected at
+    rate: &'static AtomicU32,
+    filler: Filler,
+    /// Applied to the filler before frames are made
+    to_filler: rtrb::Consumer<FillerCommand>,
+    /// Estimated every time frames are made, for the Scope widget
+    played: &'static AtomicU64,
 }
 
-pub fn main(
-    event_tx: mpsc::Sender<Event>,
-    command_rx: pipewire::channel::Receiver<AudioCommand>,
-    producer: rtrb::Producer<u8>,
-    t_play: &'static AtomicI32,
-) -> Result<(), pw::Error> {
+/// Commands the main loop passes on to the process callback
+enum FillerCommand {
+    Transport(TransportCommand),
+    Effects(EffectSettings),
+}
+
+/// [`super::Backend::Pipewire`]
+pub struct Pipewire;
+
+impl AudioBackend for Pipewire {
+    fn run(
+        self: Box<Self>,
+        commands: CommandReceiver,
+        ctx: BackendContext,
+    ) -> color_eyre::Result<()> {
+        Ok(main(commands, ctx)?)
+    }
+}
+
+fn main(commands: CommandReceiver, ctx: BackendContext) -> Result<(), pw::Error> {
+    let BackendContext {
+        event_tx,
+        filler,
+        played,
+        retired,
+    } = ctx;
     info!("pipewire thread starting");
     pw::init();
     let main_loop: &'static mut MainLoopRc = Box::leak(Box::new(MainLoopRc::new(None)?));
@@ -87,38 +123,85 @@ pub fn main(
     )?;
 
     // Used in a few callbacks
-    static T_WRITE: AtomicI32 = AtomicI32::new(0);
-    static BEAT: LazyLock<ArcSwap<parser::Beat>> =
-        // 'Silent' beat by default
-        LazyLock::new(|| ArcSwap::new(Arc::new(parser::Beat::default())));
+    static MIX: LazyLock<ArcSwap<Mix>> =
+        // 'Silent' mix by default
+        LazyLock::new(|| ArcSwap::new(Arc::new(Mix::default())));
+    static RATE: AtomicU32 = AtomicU32::new(0);
+    let rate = filler.rate_for(&MIX.load());
+    let resampling_to = filler.resampling_to();
+    RATE.store(rate.hz(), Ordering::Relaxed);
+    // Plenty for commands piling up between two process callbacks
+    let (to_filler_tx, to_filler_rx) = rtrb::RingBuffer::new(64);
     // See struct declarations
     let sts = StateChangeState::new(event_tx);
-    let ts = TimerState::new(&T_WRITE, stream.clone(), t_play);
-    let ps = ProcessState::new(&T_WRITE, &BEAT, producer);
-    let cs = CommandState::new(stream.clone(), &BEAT);
+    let ps = ProcessState::new(&MIX, &RATE, filler, to_filler_rx, played);
+    let cs = CommandState::new(
+        stream.clone(),
+        &MIX,
+        &RATE,
+        resampling_to,
+        RefCell::new(to_filler_tx),
+        RefCell::new(retired),
+    );
+
+    // Commands come over a std channel like every backend's, but only pipewire's own channel can wake its loop
+    let (command_tx, command_rx) = pw::channel::channel::<AudioCommand>();
+    thread::spawn(move || {
+        for command in commands {
+            if command_tx.send(command).is_err() {
+                break;
+            }
+        }
+    });
 
     // Attach a command callback to the mpsc rx so event handler can bark at us
     let _recv = command_rx.attach(main_loop.loop_(), move |msg| {
         trace!("pipewire thread received command: {:?}", msg);
+        // Off the RT thread. Mixes are only replaced after a new one comes in, so they don't wait long
+        cs.retired.borrow_mut().clear();
         match msg {
-            AudioCommand::Play => cs.stream.set_active(true).unwrap(),
-            AudioCommand::Pause => cs.stream.set_active(false).unwrap(),
-            AudioCommand::NewBeat(beat) => {
-                cs.beat.store(Arc::new(beat));
+            AudioCommand::Play => {
+                cs.active.set(true);
+                cs.stream.set_active(true).unwrap()
+            }
+            AudioCommand::Pause => {
+                cs.active.set(false);
+                cs.stream.set_active(false).unwrap()
+            }
+            AudioCommand::NewMix(mix) => {
+                let rate = cs.resampling_to.unwrap_or(mix.rate());
+                cs.mix.store(mix);
+                if rate.hz() != cs.rate.load(Ordering::Relaxed) {
+                    info!("pipewire thread reconnecting at {}", rate);
+                    cs.rate.store(rate.hz(), Ordering::Relaxed);
+                    let _ = cs
+                        .stream
+                        .disconnect()
+                        .and_then(|_| connect(&cs.stream, rate, cs.active.get(), cs.volume.get()))
+                        .inspect_err(|e| error!("pipewire thread failed to reconnect: {}", e));
+                }
             }
             AudioCommand::SetVolume(vol) => {
+                cs.volume.set(vol);
                 set_volume(&cs.stream, vol);
             }
+            AudioCommand::Transport(command) => {
+                let _ = cs
+                    .to_filler
+                    .borrow_mut()
+                    .push(FillerCommand::Transport(command))
+                    .inspect_err(|_| warn!("pipewire thread dropped a transport command"));
+            }
+            AudioCommand::Effects(settings) => {
+                let _ = cs
+                    .to_filler
+                    .borrow_mut()
+                    .push(FillerCommand::Effects(settings))
+                    .inspect_err(|_| warn!("pipewire thread dropped an effects command"));
+            }
         }
     });
 
-    // Attach a timer so we can regularly send the current 't' being played to the scope widget
-    let t_sync_timer = main_loop.loop_().add_timer(move |_| {
-        let head = estimate_play_head(&ts.stream, ts.t_write.load(Ordering::Relaxed));
-        ts.t_play.store(head, Ordering::Relaxed);
-    });
-    t_sync_timer.update_timer(Some(super::T_SYNC_INTERVAL), Some(super::T_SYNC_INTERVAL));
-
     let _listener = stream
         .add_local_listener_with_user_data(ps)
         .process(on_process)
@@ -141,19 +224,48 @@ pub fn main(
         })
         .register()?;
 
-    // Twiddle our audio settings
+    // TODO: Starting at Max is uncomfortable for my system, but is it just me?
+    connect(&stream, rate, false, Volume::default())?;
+
+    info!("pipewire thread startup complete, starting main loop");
+    main_loop.run();
+    info!("pipewire thread exiting");
+    Ok(())
+}
+
+/// Connects `stream` for U8 stereo at `rate`, then sets it up like it was before it was disconnected
+fn connect(
+    stream: &Stream,
+    rate: SampleRate,
+    active: bool,
+    volume: Volume,
+) -> Result<(), pw::Error> {
+    let values = format_pod(rate);
+    let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
+
+    stream.connect(
+        Direction::Output,
+        None,
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
+        &mut params,
+    )?;
+    set_volume(stream, volume);
+    stream.set_active(active)
+}
+
+/// Our audio settings, serialized into a native POD for pipewire
+fn format_pod(rate: SampleRate) -> Vec<u8> {
     use spa::param::audio;
     let mut audio_info = audio::AudioInfoRaw::new();
     audio_info.set_format(audio::AudioFormat::U8);
-    audio_info.set_rate(BITRATE as u32);
+    audio_info.set_rate(rate.hz());
     audio_info.set_channels(CHANNELS as u32);
     let mut position = [0; audio::MAX_CHANNELS];
     position[0] = libspa_sys::SPA_AUDIO_CHANNEL_FL;
     position[1] = libspa_sys::SPA_AUDIO_CHANNEL_FR;
     audio_info.set_position(position);
 
-    // Serialize it into a native POD for pipewire
-    let values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
+    pw::spa::pod::serialize::PodSerializer::serialize(
         std::io::Cursor::new(Vec::new()),
         &pw::spa::pod::Value::Object(pw::spa::pod::Object {
             type_: libspa_sys::SPA_TYPE_OBJECT_Format,
@@ -163,63 +275,36 @@ pub fn main(
     )
     .unwrap()
     .0
-    .into_inner();
-
-    let mut params = [spa::pod::Pod::from_bytes(&values).unwrap()];
-
-    stream.connect(
-        Direction::Output,
-        None,
-        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS,
-        &mut params,
-    )?;
-    // TODO: Starting at Max is uncomfortable for my system, but is it just me?
-    set_volume(&stream, Volume::default());
-    stream.set_active(false)?;
-
-    info!("pipewire thread startup complete, starting main loop");
-    main_loop.run();
-    info!("pipewire thread exiting");
-    Ok(())
+    .into_inner()
 }
 
 fn on_process(s: &Stream, state: &mut ProcessState) {
+    // Changed on the main loop, when the stream was connected again
+    if let Some(rate) = SampleRate::new(state.rate.load(Ordering::Relaxed)) {
+        state.filler.set_device_rate(rate);
+    }
+    while let Ok(command) = state.to_filler.pop() {
+        match command {
+            FillerCommand::Transport(command) => state.filler.transport(command),
+            FillerCommand::Effects(settings) => state.filler.set_effects(settings),
+        }
+    }
     match s.dequeue_buffer() {
         None => warn!("no buffer available for pipewire process thread"),
         Some(mut buffer) => {
             // We may get a valid buffer that is 0-sized(?)
             let n_frames = if let Some(slice) = buffer.datas_mut()[0].data() {
                 let n_frames = slice.len() / STRIDE;
-                for i in 0..n_frames {
-                    // I thought walking an AST like this in a RT audio loop would cause like a million xruns,
-                    // but pw-top stats are about the same as when it was hardcoded. Crazy!
-                    let val = state
-                        .beat
-                        .load()
-                        .eval(state.t_write.load(Ordering::Relaxed));
-                    state
-                        .t_write
-                        .store(state.t_write.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
-
-                    // Copy it across strides
-                    for c in 0..CHANNELS {
-                        let start = i * STRIDE + (c * size_of::<u8>());
-                        let end = start + size_of::<u8>();
-                        let chan = &mut slice[start..end];
-
-                        chan.copy_from_slice(&u8::to_le_bytes(val));
-                    }
-
-                    // Push to visualization buffer (best effort)
-                    // We only need one channel for visualization
-                    if !state.producer.is_full() {
-                        let _ = state.producer.push(val);
-                    }
-                }
+                // Only whole frames, and one mix for the whole buffer
+                state
+                    .filler
+                    .fill(&state.mix.load(), &mut slice[..n_frames * STRIDE]);
                 n_frames
             } else {
                 0
             };
+            let played = state.filler.played(queued_frames(s));
+            state.played.store(played, Ordering::Relaxed);
             // Pipewire must be told which region of this data is valid
             let chunk = &mut buffer.datas_mut()[0].chunk_mut();
             *chunk.offset_mut() = 0;
@@ -239,12 +324,12 @@ fn set_volume(stream: &Stream, volume: Volume) {
         .inspect_err(|e| error!("audio thread reported problem changing volume: {}", e));
 }
 
-/// RT Safe. Shouldn't mutate stream at all. Estimates which 't' we're playing next
+/// RT Safe. Shouldn't mutate stream at all. How far behind what we've produced the sample we're playing next is
 ///
-/// We want to know which 't' sample is playing now
-/// We know how many t's we've produced
+/// We want to know which sample is playing now
+/// We know how many we've produced
 /// We're about to know how many t's are queued, and how many are buffered
-fn estimate_play_head(stream: &Stream, t_write: i32) -> i32 {
+fn queued_frames(stream: &Stream) -> u32 {
     unsafe {
         // It's all numbers inside so zeroed is fine
         let mut time: pipewire_sys::pw_time = mem::zeroed();
@@ -253,6 +338,6 @@ fn estimate_play_head(stream: &Stream, t_write: i32) -> i32 {
             &mut time,
             mem::size_of::<pipewire_sys::pw_time>(),
         );
-        t_write - (time.queued as i32 + time.buffered as i32)
+        (time.queued + time.buffered) as u32
     }
 }
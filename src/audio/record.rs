@@ -0,0 +1,343 @@
+//! Records what the audio callback makes to WAV files, from a thread of its own so the callback never waits on the
+//! disk.
+//!
+//! The [`Tap`] is RT safe and lives in the [`super::Filler`]. It pushes frames to a ring buffer like the scope's, and
+//! [`Recorder`] writes them out. A WAV file can't change rate halfway, so every change of device rate while
+//! recording starts another take, like stopping and starting again would.
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+use tracing::{error, info, warn};
+
+use super::{CHANNELS, STRIDE, wav};
+use crate::parser::SampleRate;
+
+/// Bytes of frames waiting to be written. A few seconds at any common rate
+const RING_LEN: usize = 1 << 20;
+/// How often the recorder thread writes out what's waiting. The header is kept right as of the last time
+const PERIOD: Duration = Duration::from_millis(100);
+
+/// What the app, the [`Tap`] and the [`Recorder`] share
+#[derive(Debug, Default)]
+pub struct Recording {
+    /// As the app wishes it
+    wanted: AtomicBool,
+    /// Milliseconds of frames written in every take so far
+    elapsed_ms: AtomicU64,
+    /// Bytes of frames the ring buffer had no room for
+    dropped: AtomicU64,
+    /// Set when the recorder thread should finish up and exit
+    shutdown: AtomicBool,
+}
+
+impl Recording {
+    pub const fn new() -> Self {
+        Self {
+            wanted: AtomicBool::new(false),
+            elapsed_ms: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts or stops with the next frames made. Pauses aren't recorded, since nothing is made
+    pub fn set(&self, recording: bool) {
+        self.wanted.store(recording, Ordering::Relaxed);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.wanted.load(Ordering::Relaxed)
+    }
+
+    /// Heard in every take so far, about
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.elapsed_ms.load(Ordering::Relaxed))
+    }
+}
+
+/// Where a take starts or stops, among the bytes of frames
+#[derive(Debug, Clone, Copy)]
+enum Mark {
+    Start { at: u64, rate: SampleRate },
+    Stop { at: u64 },
+}
+
+impl Mark {
+    fn at(&self) -> u64 {
+        match self {
+            Mark::Start { at, .. } | Mark::Stop { at } => *at,
+        }
+    }
+}
+
+/// The audio callback's end. RT safe
+pub struct Tap {
+    shared: &'static Recording,
+    frames: rtrb::Producer<u8>,
+    marks: rtrb::Producer<Mark>,
+    /// Bytes of frames pushed so far
+    pushed: u64,
+    /// What the take being pushed is at, if there is one
+    taking: Option<SampleRate>,
+}
+
+impl Tap {
+    /// Interleaved `frames` at `rate`, as they're given to the device. Dropped if the recorder falls behind
+    pub fn push(&mut self, frames: &[u8], rate: SampleRate) {
+        let wanted = self.shared.wanted.load(Ordering::Relaxed);
+        if self.taking.is_some() && (!wanted || self.taking != Some(rate)) {
+            // Without room for the mark, the take carries on until there is
+            if self.marks.push(Mark::Stop { at: self.pushed }).is_err() {
+                return;
+            }
+            self.taking = None;
+        }
+        if !wanted {
+            return;
+        }
+        if self.taking.is_none() {
+            // Marks go before their frames, so the recorder never sees frames without the mark they follow
+            let start = Mark::Start {
+                at: self.pushed,
+                rate,
+            };
+            if self.marks.push(start).is_err() {
+                return;
+            }
+            self.taking = Some(rate);
+        }
+
+        let n = self.frames.slots().min(frames.len()) / STRIDE * STRIDE;
+        if let Ok(chunk) = self.frames.write_chunk_uninit(n) {
+            chunk.fill_from_iter(frames.iter().copied());
+            self.pushed += n as u64;
+        }
+        if n < frames.len() {
+            let dropped = (frames.len() - n) as u64;
+            self.shared.dropped.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writes out what the [`Tap`] pushes, on a thread of its own. Finishes the take being written when dropped, which
+/// includes unwinding from a panic
+pub struct Recorder {
+    shared: &'static Recording,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Not RT Safe. Takes are written to `path`, then `path` with `-2`, `-3` and so on before the extension
+    pub fn spawn(shared: &'static Recording, path: PathBuf) -> (Self, Tap) {
+        let (frames_tx, frames_rx) = rtrb::RingBuffer::new(RING_LEN);
+        let (marks_tx, marks_rx) = rtrb::RingBuffer::new(64);
+        let tap = Tap {
+            shared,
+            frames: frames_tx,
+            marks: marks_tx,
+            pushed: 0,
+            taking: None,
+        };
+        let thread = thread::spawn(move || run(shared, &path, frames_rx, marks_rx));
+        let recorder = Self {
+            shared,
+            thread: Some(thread),
+        };
+        (recorder, tap)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The take being written
+struct Take {
+    writer: wav::Writer,
+    rate: SampleRate,
+    /// Milliseconds recorded before this take
+    before_ms: u64,
+    /// Bytes of frames
+    written: u64,
+}
+
+fn run(
+    shared: &'static Recording,
+    path: &Path,
+    mut frames: rtrb::Consumer<u8>,
+    mut marks: rtrb::Consumer<Mark>,
+) {
+    info!("recorder thread starting");
+    // Bytes of frames taken off the ring so far
+    let mut read: u64 = 0;
+    let mut takes = 0;
+    let mut take: Option<Take> = None;
+    let mut dropped = 0;
+    loop {
+        // Checked before draining, so nothing pushed before shutdown is left behind
+        let shutdown = shared.shutdown.load(Ordering::Relaxed);
+        loop {
+            // Frames first. Any mark they follow was pushed before them, so it's seen too
+            let available = frames.slots() as u64;
+            let next = marks.peek().ok().copied();
+            let until = next.map_or(available, |mark| available.min(mark.at() - read));
+            if until > 0 {
+                let Ok(chunk) = frames.read_chunk(until as usize) else {
+                    break;
+                };
+                let (first, second) = chunk.as_slices();
+                if let Some(take) = &mut take {
+                    take.write(first, shared);
+                    take.write(second, shared);
+                }
+                chunk.commit_all();
+                read += until;
+                continue;
+            }
+            // Otherwise its frames aren't all here yet
+            let Some(mark) = next.filter(|mark| mark.at() == read) else {
+                break;
+            };
+            let _ = marks.pop();
+            if let Some(take) = take.take() {
+                take.finish();
+            }
+            if let Mark::Start { rate, .. } = mark {
+                takes += 1;
+                take = Take::create(&take_path(path, takes), rate, shared);
+            }
+        }
+        if let Some(take) = &mut take {
+            take.flush();
+        }
+
+        let now_dropped = shared.dropped.load(Ordering::Relaxed);
+        if now_dropped != dropped {
+            warn!(
+                "recorder fell behind and dropped {} bytes",
+                now_dropped - dropped
+            );
+            dropped = now_dropped;
+        }
+        if shutdown {
+            if let Some(take) = take.take() {
+                take.finish();
+            }
+            info!("recorder thread exiting");
+            return;
+        }
+        thread::sleep(PERIOD);
+    }
+}
+
+impl Take {
+    fn create(path: &Path, rate: SampleRate, shared: &Recording) -> Option<Self> {
+        match wav::Writer::create(path, rate.hz(), CHANNELS as u16) {
+            Ok(writer) => {
+                info!("recording to {} at {}", path.display(), rate);
+                Some(Self {
+                    writer,
+                    rate,
+                    before_ms: shared.elapsed_ms.load(Ordering::Relaxed),
+                    written: 0,
+                })
+            }
+            Err(e) => {
+                error!("failed to start recording to {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, frames: &[u8], shared: &Recording) {
+        if frames.is_empty() {
+            return;
+        }
+        if let Err(e) = self.writer.write(frames) {
+            error!("failed to write recording: {}", e);
+            return;
+        }
+        self.written += frames.len() as u64;
+        let ms = self.written / STRIDE as u64 * 1000 / self.rate.hz() as u64;
+        shared
+            .elapsed_ms
+            .store(self.before_ms + ms, Ordering::Relaxed);
+    }
+
+    fn flush(&mut self) {
+        let _ = self
+            .writer
+            .flush()
+            .inspect_err(|e| error!("failed to write recording: {}", e));
+    }
+
+    fn finish(mut self) {
+        self.flush();
+        info!("recording finished");
+    }
+}
+
+/// `path` for the first take, then numbered like `recording-2.wav`
+fn take_path(path: &Path, take: u32) -> PathBuf {
+    if take == 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, take, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, take),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_paths() {
+        let path = Path::new("out/recording.wav");
+        assert_eq!(take_path(path, 1), path);
+        assert_eq!(take_path(path, 2), Path::new("out/recording-2.wav"));
+        assert_eq!(take_path(Path::new("take"), 3), Path::new("take-3"));
+    }
+
+    #[test]
+    fn test_takes_follow_rate_and_toggle() {
+        static SHARED: Recording = Recording::new();
+        let dir = std::env::temp_dir().join(format!("bytebeat-record-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("take.wav");
+        let (recorder, mut tap) = Recorder::spawn(&SHARED, path.clone());
+
+        let rate = SampleRate::CLASSIC;
+        tap.push(&[1; 8], rate);
+        SHARED.set(true);
+        tap.push(&[2; 8000 * STRIDE], rate);
+        tap.push(&[3; 4], SampleRate::new(16000).unwrap());
+        SHARED.set(false);
+        tap.push(&[4; 8], rate);
+        drop(recorder);
+
+        let first = std::fs::read(&path).unwrap();
+        assert_eq!(first.len(), wav::HEADER_LEN + 8000 * STRIDE);
+        assert!(first[wav::HEADER_LEN..].iter().all(|&s| s == 2));
+        let second = std::fs::read(dir.join("take-2.wav")).unwrap();
+        assert_eq!(&second[wav::HEADER_LEN..], [3; 4]);
+        assert_eq!(
+            u32::from_le_bytes(second[24..28].try_into().unwrap()),
+            16000
+        );
+        assert_eq!(SHARED.elapsed(), Duration::from_secs(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Makes up samples at the device's rate from samples at a beat's own rate. Asks for just as many samples of the
+//! beat as are needed, so 't' keeps up like playing at the beat's rate would.
+use super::{BLOCK_LEN, CHANNELS, STRIDE};
+use crate::parser::{self, Stereo};
+
+/// Bits after the point in fixed point positions between input samples
+const FRAC_BITS: u32 = 32;
+const ONE: u64 = 1 << FRAC_BITS;
+/// Input samples [`Kind::Sinc`] looks at for every output. Half of them come before where the output falls
+const TAPS: usize = 16;
+/// Index in the window of the input sample at or before where the output falls
+const CENTER: usize = TAPS / 2 - 1;
+/// Precomputed sinc kernels, for this many evenly spaced positions between two input samples
+const PHASE_BITS: u32 = 8;
+const PHASES: usize = 1 << PHASE_BITS;
+
+/// How samples between a beat's own are made up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Zero-order hold
+    Hold,
+    Linear,
+    /// Hann windowed
+    Sinc,
+}
+
+impl Kind {
+    /// How many input samples are looked at for every output, and which of them is at or before where it falls.
+    /// Every one after that is a sample of lag, so only sinc looks at more than it needs
+    fn window(self) -> (usize, usize) {
+        match self {
+            Kind::Hold | Kind::Linear => (2, 0),
+            Kind::Sinc => (TAPS, CENTER),
+        }
+    }
+}
+
+pub struct Resampler {
+    kind: Kind,
+    /// Input and output rates the step and kernels were made for
+    rates: (u32, u32),
+    /// Input samples per output sample
+    step: u64,
+    /// Where the next output falls past the center of the window, as a fraction of one input sample
+    frac: u64,
+    /// The last window of input samples of each channel, then room for a block more. Centered on 0 instead of 128
+    input: [[f32; TAPS + BLOCK_LEN]; CHANNELS],
+    /// Scratch space for evaluating the beat
+    block: [Stereo; BLOCK_LEN],
+    /// Weights of each input in the window, for each phase. Only used by [`Kind::Sinc`]
+    kernels: Box<[[f32; TAPS]; PHASES]>,
+}
+
+impl Resampler {
+    /// Not RT safe, the sinc kernels are allocated here
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            rates: (0, 0),
+            step: ONE,
+            frac: 0,
+            input: [[0.0; TAPS + BLOCK_LEN]; CHANNELS],
+            block: [[0; CHANNELS]; BLOCK_LEN],
+            kernels: Box::new([[0.0; TAPS]; PHASES]),
+        }
+    }
+
+    /// RT safe. Sets up for going from `input` to `output` Hz. Does nothing if that's already the case.
+    pub fn set_rates(&mut self, input: parser::SampleRate, output: parser::SampleRate) {
+        let rates = (input.hz(), output.hz());
+        if self.rates == rates {
+            return;
+        }
+        self.rates = rates;
+        // Absurd rates play slower than they should, instead of needing more than a block for one output
+        // Rounded, so whole ratios like 8000 to 48000 land exactly on every input
+        self.step = (((rates.0 as u64) << FRAC_BITS) + rates.1 as u64 / 2) / rates.1 as u64;
+        self.step = self.step.min((BLOCK_LEN as u64 / 2) << FRAC_BITS);
+        if self.kind == Kind::Sinc {
+            self.make_kernels();
+        }
+    }
+
+    /// RT safe. Forgets every input, so nothing from long ago is heard again
+    pub fn reset(&mut self) {
+        for input in &mut self.input {
+            input[..TAPS].fill(0.0);
+        }
+        self.frac = 0;
+    }
+
+    /// RT safe. Fills interleaved `frames` at the output rate, each channel from its own. `source` is asked for just
+    /// as many input samples as are needed, in order.
+    pub fn fill(&mut self, frames: &mut [u8], mut source: impl FnMut(&mut [Stereo])) {
+        let (taps, _) = self.kind.window();
+        let mut frames = frames.chunks_exact_mut(STRIDE);
+        let mut left = frames.len();
+        while left > 0 {
+            // As many outputs as one block of inputs covers
+            let n = ((((BLOCK_LEN as u64) << FRAC_BITS) - self.frac) / self.step) as usize;
+            let n = n.min(left);
+            let end = self.frac + n as u64 * self.step;
+            // Inputs needed before the window is past all of this chunk's outputs
+            let advance = (end >> FRAC_BITS) as usize;
+
+            let samples = &mut self.block[..advance];
+            source(samples);
+            for (channel, input) in self.input.iter_mut().enumerate() {
+                for (input, frame) in input[taps..].iter_mut().zip(samples.iter()) {
+                    *input = frame[channel] as f32 - 128.0;
+                }
+            }
+
+            let mut pos = self.frac;
+            for frame in frames.by_ref().take(n) {
+                let start = (pos >> FRAC_BITS) as usize;
+                let frac = pos & (ONE - 1);
+                for (sample, input) in frame.iter_mut().zip(&self.input) {
+                    let value = self.interpolate(&input[start..start + taps], frac);
+                    *sample = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+                }
+                pos += self.step;
+            }
+
+            for input in &mut self.input {
+                input.copy_within(advance..advance + taps, 0);
+            }
+            self.frac = end & (ONE - 1);
+            left -= n;
+        }
+    }
+
+    /// Where an output falls `frac` past the center of `window`
+    fn interpolate(&self, window: &[f32], frac: u64) -> f32 {
+        match self.kind {
+            Kind::Hold => window[0],
+            Kind::Linear => {
+                let f = frac as f32 / ONE as f32;
+                window[0] + (window[1] - window[0]) * f
+            }
+            Kind::Sinc => {
+                let kernel = &self.kernels[(frac >> (FRAC_BITS - PHASE_BITS)) as usize];
+                kernel.iter().zip(window).map(|(w, x)| w * x).sum()
+            }
+        }
+    }
+
+    /// How many input samples the one being heard is behind the last one asked for, if `queued` outputs haven't
+    /// been heard yet
+    pub fn lag(&self, queued: u32) -> u64 {
+        let (taps, center) = self.kind.window();
+        (taps - center) as u64 + ((queued as u64 * self.step) >> FRAC_BITS)
+    }
+
+    /// Band limits to whichever of the rates is lower, so downsampling doesn't alias
+    fn make_kernels(&mut self) {
+        let (input, output) = self.rates;
+        let cutoff = (output as f64 / input as f64).min(1.0);
+        let half_width = (TAPS / 2) as f64;
+        for (phase, kernel) in self.kernels.iter_mut().enumerate() {
+            let frac = phase as f64 / PHASES as f64;
+            for (tap, weight) in kernel.iter_mut().enumerate() {
+                // Distance in input samples from where the output falls
+                let x = tap as f64 - CENTER as f64 - frac;
+                let sinc = match x * cutoff {
+                    0.0 => 1.0,
+                    x => (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x),
+                };
+                let window = 0.5 + 0.5 * (std::f64::consts::PI * x / half_width).cos();
+                *weight = (cutoff * sinc * window) as f32;
+            }
+            // Constant input comes out unchanged, whatever the phase
+            let sum: f32 = kernel.iter().sum();
+            kernel.iter_mut().for_each(|weight| *weight /= sum);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Beat, CompileOptions, SampleRate};
+
+    /// Of the left channel. Mono beats are the same in both
+    fn resample(
+        kind: Kind,
+        source: &str,
+        input: u32,
+        output: u32,
+        frames: usize,
+    ) -> (Vec<u8>, i32) {
+        let (out, t) = resample_stereo(kind, source, input, output, frames);
+        (out.iter().map(|frame| frame[0]).collect(), t)
+    }
+
+    fn resample_stereo(
+        kind: Kind,
+        source: &str,
+        input: u32,
+        output: u32,
+        frames: usize,
+    ) -> (Vec<Stereo>, i32) {
+        let options = CompileOptions {
+            rate: SampleRate::new(input).unwrap(),
+            ..Default::default()
+        };
+        let beat = Beat::compile(source, &options).unwrap();
+        let mut resampler = Resampler::new(kind);
+        resampler.set_rates(beat.rate(), SampleRate::new(output).unwrap());
+        let mut out = vec![0; frames * STRIDE];
+        // Uneven chunks, like a device asks for
+        let mut t = 0;
+        for chunk in out.chunks_mut(STRIDE * 333) {
+            resampler.fill(chunk, |samples| {
+                beat.eval_stereo_block(t, samples);
+                t += samples.len() as i32;
+            });
+        }
+        let frames = out
+            .chunks(STRIDE)
+            .map(|frame| [frame[0], frame[1]])
+            .collect();
+        (frames, t)
+    }
+
+    #[test]
+    fn test_hold_repeats_samples() {
+        let (out, t) = resample(Kind::Hold, "t", 8000, 48000, 6000);
+        assert_eq!(t, 1000);
+        // Six of each, after the silent window before the first sample
+        let (taps, center) = Kind::Hold.window();
+        let start = taps - center;
+        assert_eq!(start, 2);
+        for (i, sample) in out.iter().enumerate().skip(start * 6) {
+            assert_eq!(*sample, ((i / 6 - start) % 256) as u8, "frame {i}");
+        }
+    }
+
+    #[test]
+    fn test_evaluates_at_input_rate() {
+        for kind in [Kind::Hold, Kind::Linear, Kind::Sinc] {
+            let (_, t) = resample(kind, "t", 44100, 48000, 48000);
+            assert!((44099..=44101).contains(&t), "{kind:?} went to {t}");
+            let (_, t) = resample(kind, "t", 11025, 8000, 8000);
+            assert!((11024..=11026).contains(&t), "{kind:?} went to {t}");
+        }
+    }
+
+    #[test]
+    fn test_constant_unchanged() {
+        for kind in [Kind::Hold, Kind::Linear, Kind::Sinc] {
+            let (out, _) = resample(kind, "200", 22050, 48000, 4800);
+            assert!(out[TAPS * 3..].iter().all(|s| *s == 200), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn test_channels_kept_apart() {
+        for kind in [Kind::Hold, Kind::Linear, Kind::Sinc] {
+            let (out, _) = resample_stereo(kind, "[200, t & 1 ? 60 : 40]", 8000, 48000, 4800);
+            assert!(out[TAPS * 6..].iter().all(|s| s[0] == 200), "{kind:?}");
+            // Only the right channel moves
+            assert!(out[TAPS * 6..].iter().any(|s| s[1] > 50), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn test_linear_between_samples() {
+        let (out, _) = resample(Kind::Linear, "t * 4", 8000, 16000, 120);
+        // Halfway between every pair of input samples lands an output
+        let (taps, center) = Kind::Linear.window();
+        let start = (taps - center) * 2;
+        for i in (start..120).step_by(2) {
+            assert_eq!(out[i + 1] as i32 - out[i] as i32, 2, "frame {i}");
+        }
+    }
+
+    #[test]
+    fn test_lag_behind_input() {
+        let mut resampler = Resampler::new(Kind::Linear);
+        resampler.set_rates(SampleRate::CLASSIC, SampleRate::new(16000).unwrap());
+        assert_eq!(resampler.lag(0), 2);
+        assert_eq!(resampler.lag(200), 102);
+
+        // Only sinc waits for its taps
+        let mut resampler = Resampler::new(Kind::Sinc);
+        resampler.set_rates(SampleRate::CLASSIC, SampleRate::new(16000).unwrap());
+        assert_eq!(resampler.lag(0), (TAPS - CENTER) as u64);
+    }
+}
@@ -0,0 +1,423 @@
+//! How a new mix takes over from the one playing. Swapping mid-buffer clicks and loses the rhythm, so it can fade
+//! across instead, or wait for 't' to land on the beat.
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+    },
+};
+
+use super::mix::Mix;
+use crate::parser::Stereo;
+
+/// Samples faded at once, on the stack
+const SCRATCH_LEN: usize = 256;
+/// Mixes waiting in [`Retired`]. Only a few are replaced for every new mix
+const RETIRED_LEN: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transition {
+    /// Swap with the next sample
+    #[default]
+    Immediate,
+    /// Fade out the old mix while the new one fades in
+    Crossfade,
+    /// Wait until 't' reaches the next multiple of a power of two, or the loop comes round
+    Quantize,
+}
+
+impl Transition {
+    pub fn next(self) -> Self {
+        match self {
+            Transition::Immediate => Transition::Crossfade,
+            Transition::Crossfade => Transition::Quantize,
+            Transition::Quantize => Transition::Immediate,
+        }
+    }
+}
+
+/// What [`Transition`] to use, and how long it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchPolicy {
+    pub transition: Transition,
+    /// Milliseconds, at the new mix's rate
+    pub crossfade_ms: u32,
+    /// Switches land on multiples of 2 to this
+    pub quantize: u8,
+}
+
+impl SwitchPolicy {
+    /// Of a [`Transition::Quantize`] switch
+    pub const MAX_QUANTIZE: u8 = 30;
+}
+
+impl Default for SwitchPolicy {
+    fn default() -> Self {
+        Self {
+            transition: Transition::Immediate,
+            crossfade_ms: 50,
+            quantize: 16,
+        }
+    }
+}
+
+impl fmt::Display for SwitchPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.transition {
+            Transition::Immediate => write!(f, "immediate"),
+            Transition::Crossfade => write!(f, "crossfade over {}ms", self.crossfade_ms),
+            Transition::Quantize => write!(f, "quantize to 2^{}", self.quantize),
+        }
+    }
+}
+
+/// What the app is shown of a switch. Written by the [`super::Filler`] after every fill, so it runs ahead of what's
+/// heard by however much the device has queued
+#[derive(Debug, Default)]
+pub struct SwitchStatus {
+    pending: AtomicBool,
+    /// First 't' of the new mix, if it's pending
+    at: AtomicI32,
+    fading: AtomicBool,
+}
+
+impl SwitchStatus {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            at: AtomicI32::new(0),
+            fading: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set(&self, pending_at: Option<i32>, fading: bool) {
+        if let Some(at) = pending_at {
+            self.at.store(at, Ordering::Relaxed);
+        }
+        self.pending.store(pending_at.is_some(), Ordering::Relaxed);
+        self.fading.store(fading, Ordering::Relaxed);
+    }
+
+    /// Where a quantized switch will land, if one's waiting
+    pub fn pending_at(&self) -> Option<i32> {
+        match self.pending.load(Ordering::Relaxed) {
+            true => Some(self.at.load(Ordering::Relaxed)),
+            false => None,
+        }
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fading.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Fade {
+    from: Arc<Mix>,
+    /// Samples until `from` is silent
+    left: u32,
+    len: u32,
+}
+
+/// Mixes a [`Switcher`] is done with. Dropping the last of a mix frees every beat in it, which isn't RT safe, so it
+/// happens wherever [`Retired::clear`] is called instead
+pub struct Retired(rtrb::Consumer<Arc<Mix>>);
+
+impl Retired {
+    /// Where a [`Switcher`] sends mixes to, and where they're dropped
+    pub fn new() -> (rtrb::Producer<Arc<Mix>>, Self) {
+        let (retire, retired) = rtrb::RingBuffer::new(RETIRED_LEN);
+        (retire, Self(retired))
+    }
+
+    /// Not RT safe. Drops every mix retired so far
+    pub fn clear(&mut self) {
+        while self.0.pop().is_ok() {}
+    }
+}
+
+/// Owned by the [`super::transport::Transport`], which asks it for samples. RT safe
+#[derive(Debug, Default)]
+pub struct Switcher {
+    policy: SwitchPolicy,
+    /// Where mixes go when they're replaced. Without it, or room in it, they're dropped here
+    retire: Option<rtrb::Producer<Arc<Mix>>>,
+    /// Heard now. Nothing is heard before the first mix arrives
+    playing: Option<Arc<Mix>>,
+    /// Waiting for a quantized switch
+    pending: Option<Arc<Mix>>,
+    fade: Option<Fade>,
+}
+
+impl Switcher {
+    pub fn new(retire: rtrb::Producer<Arc<Mix>>) -> Self {
+        Self {
+            retire: Some(retire),
+            ..Default::default()
+        }
+    }
+
+    /// Applies to mixes arriving from then on
+    pub fn set_policy(&mut self, policy: SwitchPolicy) {
+        self.policy = policy;
+    }
+
+    /// Starts switching to `mix`, unless it's the one playing (or pending) already. Cheap to call with every fill
+    pub fn arrive(&mut self, mix: &Arc<Mix>) {
+        let latest = self.pending.as_ref().or(self.playing.as_ref());
+        if latest.is_some_and(|latest| Arc::ptr_eq(latest, mix)) {
+            return;
+        }
+        if self.playing.is_none() {
+            // Nothing to switch from
+            self.playing = Some(mix.clone());
+            return;
+        }
+        let pending = self.pending.take();
+        self.retire(pending);
+        let fade = self.fade.take();
+        self.retire(fade.map(|fade| fade.from));
+        match self.policy.transition {
+            Transition::Immediate => {
+                let playing = self.playing.replace(mix.clone());
+                self.retire(playing);
+            }
+            Transition::Crossfade => {
+                let len = (self.policy.crossfade_ms as u64 * mix.rate().hz() as u64 / 1000) as u32;
+                let playing = self.playing.replace(mix.clone());
+                match len > 0 {
+                    true => {
+                        self.fade = playing.map(|from| Fade {
+                            from,
+                            left: len,
+                            len,
+                        })
+                    }
+                    false => self.retire(playing),
+                }
+            }
+            Transition::Quantize => self.pending = Some(mix.clone()),
+        }
+    }
+
+    /// Sends `mix` off to be dropped, if there's anywhere to
+    fn retire(&mut self, mix: Option<Arc<Mix>>) {
+        let (Some(mix), Some(retire)) = (mix, &mut self.retire) else {
+            return;
+        };
+        // Full, which it shouldn't ever be. Dropping it here is the best left to do
+        let _ = retire.push(mix);
+    }
+
+    /// What's heard now, which a pending switch hasn't replaced yet
+    pub fn heard(&self) -> Option<&Mix> {
+        self.playing.as_deref()
+    }
+
+    /// Switches to any pending mix now. For when the loop comes round, which is as good as a boundary
+    pub fn land(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let playing = self.playing.replace(pending);
+            self.retire(playing);
+        }
+    }
+
+    /// The first 't' a pending switch is heard at, if `next` is played next
+    pub fn pending_at(&self, next: i32, reverse: bool) -> Option<i32> {
+        self.pending.as_ref()?;
+        let n = 1i64 << self.policy.quantize.min(SwitchPolicy::MAX_QUANTIZE);
+        let at = match reverse {
+            false => (next as i64 + n - 1).div_euclid(n) * n,
+            // Crossing the boundary on the way down
+            true => (next as i64 + 1).div_euclid(n) * n - 1,
+        };
+        Some(at as i32)
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// RT safe. Like [`Mix::eval_block`], for 't' from `first` on, ascending. `reverse` is whether they're played
+    /// backwards, which decides which side of a boundary is old and which way a fade goes
+    pub fn eval_block(&mut self, first: i32, out: &mut [Stereo], reverse: bool) {
+        let last = first.wrapping_add(out.len() as i32 - 1);
+        // Where the new mix starts in `out`. Backwards, it's everything below that
+        let split = match reverse {
+            false => self.pending_at(first, false),
+            true => self.pending_at(last, true).map(|at| at.wrapping_add(1)),
+        }
+        .map(|at| at as i64 - first as i64)
+        // Landing at either end is as good as landing between blocks
+        .filter(|split| (0..=out.len() as i64).contains(split));
+        let Some(split) = split else {
+            self.eval_fading(first, out, reverse);
+            return;
+        };
+        let (low, high) = out.split_at_mut(split as usize);
+        let high_first = first.wrapping_add(split as i32);
+        match reverse {
+            false => {
+                self.eval_playing(first, low);
+                self.land();
+                self.eval_playing(high_first, high);
+            }
+            true => {
+                self.eval_playing(high_first, high);
+                self.land();
+                self.eval_playing(first, low);
+            }
+        }
+    }
+
+    fn eval_playing(&self, first: i32, out: &mut [Stereo]) {
+        match &self.playing {
+            Some(mix) => mix.eval_block(first, out),
+            None => out.fill([128; 2]),
+        }
+    }
+
+    fn eval_fading(&mut self, first: i32, out: &mut [Stereo], reverse: bool) {
+        self.eval_playing(first, out);
+        let Some(fade) = &mut self.fade else {
+            return;
+        };
+        let len = out.len();
+        let mut scratch = [[0u8; 2]; SCRATCH_LEN];
+        for (chunk, t) in out
+            .chunks_mut(SCRATCH_LEN)
+            .zip((0i32..).step_by(SCRATCH_LEN))
+        {
+            let from = &mut scratch[..chunk.len()];
+            fade.from.eval_block(first.wrapping_add(t), from);
+            for (i, (frame, old)) in chunk.iter_mut().zip(from.iter()).enumerate() {
+                // How far into the fade this sample is heard
+                let played = match reverse {
+                    false => t as usize + i,
+                    true => len - 1 - (t as usize + i),
+                };
+                let left = (fade.left as usize).saturating_sub(played);
+                let weight = left as f32 / fade.len as f32;
+                for (sample, old) in frame.iter_mut().zip(old) {
+                    *sample =
+                        (*old as f32 * weight + *sample as f32 * (1.0 - weight)).round() as u8;
+                }
+            }
+        }
+        fade.left = fade.left.saturating_sub(len as u32);
+        if fade.left == 0 {
+            let fade = self.fade.take();
+            self.retire(fade.map(|fade| fade.from));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Beat, CompileOptions};
+
+    fn mix(source: &str) -> Arc<Mix> {
+        Arc::new(Mix::single(
+            Beat::compile(source, &CompileOptions::default()).unwrap(),
+        ))
+    }
+
+    /// Of the left channel. Mono beats are the same in both
+    fn eval(switcher: &mut Switcher, first: i32, len: usize, reverse: bool) -> Vec<u8> {
+        let mut out = vec![[0; 2]; len];
+        switcher.eval_block(first, &mut out, reverse);
+        out.iter().map(|frame| frame[0]).collect()
+    }
+
+    fn policy(transition: Transition) -> SwitchPolicy {
+        SwitchPolicy {
+            transition,
+            crossfade_ms: 1,
+            quantize: 3,
+        }
+    }
+
+    #[test]
+    fn test_immediate() {
+        let mut switcher = Switcher::default();
+        assert_eq!(eval(&mut switcher, 0, 2, false), [128, 128]);
+        switcher.arrive(&mix("10"));
+        switcher.arrive(&mix("20"));
+        assert_eq!(eval(&mut switcher, 0, 2, false), [20, 20]);
+    }
+
+    #[test]
+    fn test_quantize_lands_on_boundary() {
+        let mut switcher = Switcher::default();
+        switcher.set_policy(policy(Transition::Quantize));
+        switcher.arrive(&mix("10"));
+        switcher.arrive(&mix("20"));
+        assert_eq!(switcher.pending_at(3, false), Some(8));
+        assert_eq!(switcher.pending_at(8, false), Some(8));
+        assert_eq!(eval(&mut switcher, 3, 3, false), [10; 3]);
+        assert_eq!(eval(&mut switcher, 6, 4, false), [10, 10, 20, 20]);
+        assert_eq!(switcher.pending_at(10, false), None);
+
+        // Backwards, the new mix starts just below the boundary
+        switcher.arrive(&mix("30"));
+        assert_eq!(switcher.pending_at(20, true), Some(15));
+        assert_eq!(eval(&mut switcher, 14, 4, true), [30, 30, 20, 20]);
+
+        switcher.arrive(&mix("40"));
+        switcher.land();
+        assert_eq!(eval(&mut switcher, 1, 1, false), [40]);
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let mut switcher = Switcher::default();
+        switcher.set_policy(policy(Transition::Crossfade));
+        switcher.arrive(&mix("0"));
+        // 8 samples at 8kHz
+        switcher.arrive(&mix("80"));
+        assert!(switcher.is_fading());
+        assert_eq!(eval(&mut switcher, 0, 4, false), [0, 10, 20, 30]);
+        assert_eq!(eval(&mut switcher, 4, 6, false), [40, 50, 60, 70, 80, 80]);
+        assert!(!switcher.is_fading());
+
+        switcher.arrive(&mix("0"));
+        // Heard from the end
+        assert_eq!(eval(&mut switcher, 0, 4, true), [50, 60, 70, 80]);
+    }
+
+    #[test]
+    fn test_replaced_mixes_are_retired() {
+        let (retire, mut retired) = Retired::new();
+        let mut switcher = Switcher::new(retire);
+        switcher.set_policy(policy(Transition::Crossfade));
+        // Only the switcher has it after this
+        let old = {
+            let old = mix("0");
+            switcher.arrive(&old);
+            Arc::downgrade(&old)
+        };
+        switcher.arrive(&mix("80"));
+        eval(&mut switcher, 0, 16, false);
+        assert!(!switcher.is_fading());
+        assert_eq!(old.strong_count(), 1);
+
+        retired.clear();
+        assert_eq!(old.strong_count(), 0);
+
+        // Every way a mix is replaced
+        switcher.set_policy(policy(Transition::Quantize));
+        let playing = Arc::downgrade(switcher.playing.as_ref().unwrap());
+        let pending = {
+            let pending = mix("1");
+            switcher.arrive(&pending);
+            Arc::downgrade(&pending)
+        };
+        switcher.arrive(&mix("2"));
+        switcher.land();
+        assert_eq!((playing.strong_count(), pending.strong_count()), (1, 1));
+        retired.clear();
+        assert_eq!((playing.strong_count(), pending.strong_count()), (0, 0));
+    }
+}
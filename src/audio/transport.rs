@@ -0,0 +1,251 @@
+//! Where 't' goes while a beat plays. It can start anywhere, count down, go round a loop and play at another speed.
+use std::{fmt, sync::Arc};
+
+use super::{
+    ScopeSample,
+    mix::Mix,
+    switch::{SwitchPolicy, Switcher},
+};
+use crate::parser::Stereo;
+
+/// How many times faster than its own rate a beat plays. Pitch goes up with it, like on a turntable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed(f32);
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self::NORMAL
+    }
+}
+
+impl Speed {
+    pub const NORMAL: Self = Self(1.0);
+    /// What [`Speed::faster`] and [`Speed::slower`] step through
+    const STEPS: &[f32] = &[0.125, 0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 2.0, 4.0, 8.0];
+
+    /// The next step up, or the same if it's the fastest
+    pub fn faster(self) -> Self {
+        let next = Self::STEPS.iter().copied().find(|&step| step > self.0);
+        Self(next.unwrap_or(self.0))
+    }
+
+    /// The next step down, or the same if it's the slowest
+    pub fn slower(self) -> Self {
+        let next = Self::STEPS
+            .iter()
+            .rev()
+            .copied()
+            .find(|&step| step < self.0);
+        Self(next.unwrap_or(self.0))
+    }
+
+    pub fn val(&self) -> f32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
+
+/// Moves 't' around. Sent along with [`super::AudioCommand::Transport`], and applied before the next samples are
+/// evaluated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportCommand {
+    /// Play from this 't' next
+    Seek(i32),
+    /// Move 't' this far, back if negative
+    Jump(i32),
+    /// Play from the first 't' up to (not including) the second over and over, or stop looping. Playing carries on
+    /// from the start (or the end in reverse) if it's outside
+    Loop(Option<(i32, i32)>),
+    /// Count 't' down instead of up
+    Reverse(bool),
+    Speed(Speed),
+    /// How mixes arriving from then on take over
+    Switch(SwitchPolicy),
+}
+
+/// What the [`super::Filler`] evaluates next
+#[derive(Debug, Default)]
+pub struct Transport {
+    /// Next to be evaluated
+    t: i32,
+    reverse: bool,
+    /// Start and end of the loop. Never empty
+    looping: Option<(i32, i32)>,
+    speed: Speed,
+    switcher: Switcher,
+}
+
+impl Transport {
+    /// Mixes it's done with go to `retire`, see [`super::switch::Retired`]
+    pub fn new(retire: rtrb::Producer<Arc<Mix>>) -> Self {
+        Self {
+            switcher: Switcher::new(retire),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(&mut self, command: TransportCommand) {
+        match command {
+            TransportCommand::Seek(t) => self.t = t,
+            TransportCommand::Jump(by) => self.t = self.t.wrapping_add(by),
+            TransportCommand::Loop(looping) => {
+                self.looping = looping.filter(|(start, end)| start < end)
+            }
+            TransportCommand::Reverse(reverse) => self.reverse = reverse,
+            TransportCommand::Speed(speed) => self.speed = speed,
+            TransportCommand::Switch(policy) => self.switcher.set_policy(policy),
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// RT Safe. Plays `mix` from now on, however the [`SwitchPolicy`] says. Nothing happens if it's playing already
+    pub fn arrive(&mut self, mix: &Arc<Mix>) {
+        self.switcher.arrive(mix);
+    }
+
+    /// The mix heard now, if one's arrived. A pending switch hasn't replaced it yet
+    pub fn heard(&self) -> Option<&Mix> {
+        self.switcher.heard()
+    }
+
+    /// First 't' a pending switch is heard at, going the way 't' goes now
+    pub fn pending_at(&self) -> Option<i32> {
+        self.switcher.pending_at(self.t, self.reverse)
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.switcher.is_fading()
+    }
+
+    /// RT Safe. Evaluates the mix heard for as many 't' as fit in `out`, in the order they're played, and sends them
+    /// to the `scope` too (best effort).
+    pub fn eval(&mut self, out: &mut [Stereo], scope: &mut rtrb::Producer<ScopeSample>) {
+        let mut done = 0;
+        while done < out.len() {
+            let left = out.len() - done;
+            // Up to wherever the loop wraps around
+            let (first, len) = match (self.reverse, self.looping) {
+                (false, None) => (self.t, left),
+                (true, None) => (self.t.wrapping_sub(left as i32 - 1), left),
+                (false, Some((start, end))) => {
+                    if !(start..end).contains(&self.t) {
+                        self.t = start;
+                        self.switcher.land();
+                    }
+                    (self.t, left.min((end as i64 - self.t as i64) as usize))
+                }
+                (true, Some((start, end))) => {
+                    if !(start..end).contains(&self.t) {
+                        self.t = end - 1;
+                        self.switcher.land();
+                    }
+                    let len = left.min((self.t as i64 - start as i64 + 1) as usize);
+                    (self.t.wrapping_sub(len as i32 - 1), len)
+                }
+            };
+
+            let samples = &mut out[done..done + len];
+            self.switcher.eval_block(first, samples, self.reverse);
+            if self.reverse {
+                samples.reverse();
+            }
+            let to_scope = scope.slots().min(len);
+            if let Ok(chunk) = scope.write_chunk_uninit(to_scope) {
+                let step = if self.reverse { -1 } else { 1 };
+                chunk.fill_from_iter(samples.iter().zip(0..).map(|(&value, i): (&Stereo, i32)| {
+                    ScopeSample {
+                        t: self.t.wrapping_add(i.wrapping_mul(step)),
+                        value,
+                    }
+                }));
+            }
+
+            self.t = match self.reverse {
+                false => self.t.wrapping_add(len as i32),
+                true => self.t.wrapping_sub(len as i32),
+            };
+            done += len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Beat, CompileOptions};
+
+    /// Plays `t` (as a sample) through `transport`, returning every 't' heard
+    fn play(transport: &mut Transport, len: usize) -> Vec<u8> {
+        let mix = Mix::single(Beat::compile("t", &CompileOptions::default()).unwrap());
+        transport.arrive(&Arc::new(mix));
+        let (mut producer, mut consumer) = rtrb::RingBuffer::new(len);
+        let mut out = vec![[0; 2]; len];
+        // Uneven chunks, like a device asks for
+        for chunk in out.chunks_mut(7) {
+            transport.eval(chunk, &mut producer);
+        }
+        for &[value, _] in &out {
+            let sample = consumer.pop().unwrap();
+            assert_eq!(sample.t as u8, value, "scope got another 't'");
+        }
+        out.iter().map(|frame| frame[0]).collect()
+    }
+
+    #[test]
+    fn test_seek_and_jump() {
+        let mut transport = Transport::default();
+        assert_eq!(play(&mut transport, 3), [0, 1, 2]);
+        transport.apply(TransportCommand::Seek(100));
+        assert_eq!(play(&mut transport, 2), [100, 101]);
+        transport.apply(TransportCommand::Jump(-50));
+        assert_eq!(play(&mut transport, 2), [52, 53]);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let mut transport = Transport::default();
+        transport.apply(TransportCommand::Seek(20));
+        transport.apply(TransportCommand::Reverse(true));
+        let heard = play(&mut transport, 30);
+        let expected: Vec<u8> = (-9..=20).rev().map(|t: i32| t as u8).collect();
+        assert_eq!(heard, expected);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut transport = Transport::default();
+        transport.apply(TransportCommand::Seek(50));
+        transport.apply(TransportCommand::Loop(Some((10, 15))));
+        assert_eq!(
+            play(&mut transport, 12),
+            [10, 11, 12, 13, 14, 10, 11, 12, 13, 14, 10, 11]
+        );
+
+        transport.apply(TransportCommand::Reverse(true));
+        assert_eq!(play(&mut transport, 8), [12, 11, 10, 14, 13, 12, 11, 10]);
+
+        transport.apply(TransportCommand::Loop(None));
+        assert_eq!(play(&mut transport, 3), [9, 8, 7]);
+        // Empty loops are no loop at all
+        transport.apply(TransportCommand::Loop(Some((5, 5))));
+        assert_eq!(play(&mut transport, 2), [6, 5]);
+    }
+
+    #[test]
+    fn test_speed_steps() {
+        let speed = Speed::default();
+        assert_eq!(speed.faster().val(), 1.25);
+        assert_eq!(speed.slower().slower().val(), 0.5);
+        let fastest = (0..20).fold(speed, |speed, _| speed.faster());
+        assert_eq!(fastest.val(), 8.0);
+        assert_eq!(fastest.to_string(), "8x");
+    }
+}
@@ -1,12 +1,10 @@
 //! WASAPI backend for Windows - Vista and later. Very unsafe theoretically and practically because we're `?`-ing our way through Microslop's Win32 API.
 //!
+//! The client is initialized again whenever the mix's rate changes, unless resampling to a fixed one.
+//!
 //! TODO: Handle errors better - invalidations are kind of expected already but not consistently handled.
 use std::{
-    sync::{
-        Arc, LazyLock,
-        atomic::{AtomicI32, Ordering},
-        mpsc::{self, TryRecvError},
-    },
+    sync::{Arc, LazyLock, atomic::Ordering, mpsc::TryRecvError},
     time::{Duration, Instant},
 };
 
@@ -30,8 +28,11 @@ use windows::Win32::{
 
 use windows::core::Error as WindowsError;
 
-use super::{AudioCommand, AudioEvent, BITRATE, CHANNELS, STRIDE, StreamStatus};
-use crate::{event::Event, parser};
+use super::{
+    AudioBackend, AudioCommand, BackendContext, CHANNELS, CommandReceiver, STRIDE, StateTracker,
+    StreamStatus, Volume, mix::Mix,
+};
+use crate::parser::SampleRate;
 
 /// Yeah, duh. But we'll const it.
 const BITS_PER_SAMPLE: u16 = 8;
@@ -40,38 +41,6 @@ const BITS_PER_SAMPLE: u16 = 8;
 /// Short enough to respond to commands promptly.
 const WAIT_TIMEOUT_MS: u32 = 10;
 
-/// Tracks the current stream state and sends notifications when it changes.
-struct StreamStateTracker {
-    current: StreamStatus,
-    event_tx: mpsc::Sender<Event>,
-}
-
-impl StreamStateTracker {
-    fn new(event_tx: mpsc::Sender<Event>) -> Self {
-        Self {
-            current: StreamStatus::Unconnected,
-            event_tx,
-        }
-    }
-
-    fn set(&mut self, new_status: StreamStatus) {
-        if self.current != new_status {
-            trace!(
-                "WASAPI stream state change: {:?} -> {:?}",
-                self.current, new_status
-            );
-            let _ = self
-                .event_tx
-                .send(Event::Audio(AudioEvent::StateChange(new_status.clone())));
-            self.current = new_status;
-        }
-    }
-
-    fn is_active(&self) -> bool {
-        self.current == StreamStatus::Streaming
-    }
-}
-
 /// 'Kinda' Wraps the WASAPI IAudioClient and associated objects we'll use from it.
 struct Device {
     pub audio: IAudioClient,
@@ -89,7 +58,7 @@ impl Device {
     ///
     /// But also if resource or device are invalidated *during* this function.
     /// TODO: Not sure if this is *practically* possible.
-    unsafe fn init(eventw: HANDLE) -> Result<Self> {
+    unsafe fn init(eventw: HANDLE, rate: SampleRate) -> Result<Self> {
         unsafe {
             let enumerator: IMMDeviceEnumerator =
                 CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
@@ -98,12 +67,12 @@ impl Device {
 
             let audio_client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
 
-            // 8-bit Stereo PCM @ 8kHz, naturally
+            // 8-bit Stereo PCM @ the beat's rate, 8kHz naturally
             let format = WAVEFORMATEX {
                 wFormatTag: WAVE_FORMAT_PCM as u16, // why am I casting their const lol
                 nChannels: CHANNELS as u16,
-                nSamplesPerSec: BITRATE as u32,
-                nAvgBytesPerSec: (BITRATE * STRIDE) as u32,
+                nSamplesPerSec: rate.hz(),
+                nAvgBytesPerSec: rate.hz() * STRIDE as u32,
                 nBlockAlign: STRIDE as u16,
                 wBitsPerSample: BITS_PER_SAMPLE,
                 cbSize: 0,
@@ -134,12 +103,9 @@ impl Device {
         }
     }
 
-    /// Estimates which sample is currently being played, accounting for buffered samples.
-    fn estimate_play_head(&self, t_write: i32) -> i32 {
-        unsafe {
-            let padding = self.audio.GetCurrentPadding().unwrap_or(0);
-            t_write - padding as i32
-        }
+    /// Frames written that haven't been played yet
+    fn padding(&self) -> u32 {
+        unsafe { self.audio.GetCurrentPadding().unwrap_or(0) }
     }
 
     /// I'm not actually 100% sure this is immutable after init so we'll just keep calling it
@@ -149,31 +115,50 @@ impl Device {
     }
 }
 
-pub fn main(
-    event_tx: mpsc::Sender<Event>,
-    command_rx: mpsc::Receiver<AudioCommand>,
-    mut producer: rtrb::Producer<u8>,
-    t_play: &'static AtomicI32,
-) -> Result<()> {
+/// [`super::Backend::Wasapi`]
+pub struct Wasapi;
+
+impl AudioBackend for Wasapi {
+    fn run(self: Box<Self>, command_rx: CommandReceiver, ctx: BackendContext) -> Result<()> {
+        main(command_rx, ctx)
+    }
+}
+
+fn main(command_rx: CommandReceiver, ctx: BackendContext) -> Result<()> {
+    let BackendContext {
+        event_tx,
+        mut filler,
+        played,
+        mut retired,
+    } = ctx;
     unsafe {
         info!("WASAPI thread starting");
-        static BEAT: LazyLock<ArcSwap<parser::Beat>> =
-            LazyLock::new(|| ArcSwap::new(Arc::new(parser::Beat::default())));
-        static T_WRITE: AtomicI32 = AtomicI32::new(0);
+        static MIX: LazyLock<ArcSwap<Mix>> =
+            LazyLock::new(|| ArcSwap::new(Arc::new(Mix::default())));
 
         let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
 
         // We can re-use this if we have to re-init
         let buffer_ready = CreateEventW(None, false, false, None)?;
 
-        let mut state_tracker = StreamStateTracker::new(event_tx);
+        let mut state_tracker = StateTracker::new(event_tx);
         let mut last_t_sync = Instant::now();
+        // What the client is initialized at
+        let mut rate = filler.rate_for(&MIX.load());
+        // As the app wishes them. Set again when the client has to be initialized again
+        let mut playing = false;
+        let mut volume = Volume::default();
 
         loop {
             state_tracker.set(StreamStatus::Connecting);
 
-            let device = match Device::init(buffer_ready) {
-                Ok(res) => res,
+            let device = match Device::init(buffer_ready, rate) {
+                Ok(res) => {
+                    // WASAPI converts whatever it's given
+                    filler.set_device_rate(rate);
+                    let _ = res.volume.SetMasterVolume(volume.val(), std::ptr::null());
+                    res
+                }
                 Err(e) => {
                     error!("Failed to initialize WASAPI: {}", e);
                     state_tracker.set(StreamStatus::Error);
@@ -184,8 +169,16 @@ pub fn main(
 
             // Start paused - matches pipewire behavior
             state_tracker.set(StreamStatus::Paused);
+            if playing {
+                let _ = device.audio.Start();
+                state_tracker.set(StreamStatus::Streaming);
+            }
 
+            // Set when the client has to be initialized again at another rate
+            let mut reinit = false;
             loop {
+                // Between fills, not while the device waits on one
+                retired.clear();
                 // Process all pending commands
                 loop {
                     match command_rx.try_recv() {
@@ -195,25 +188,36 @@ pub fn main(
                                 AudioCommand::Play => {
                                     if !state_tracker.is_active() {
                                         let _ = device.audio.Start();
+                                        playing = true;
                                         state_tracker.set(StreamStatus::Streaming);
                                     }
                                 }
                                 AudioCommand::Pause => {
                                     if state_tracker.is_active() {
                                         let _ = device.audio.Stop();
+                                        playing = false;
                                         state_tracker.set(StreamStatus::Paused);
                                     }
                                 }
-                                AudioCommand::NewBeat(beat) => {
-                                    BEAT.store(Arc::new(beat));
+                                AudioCommand::NewMix(mix) => {
+                                    let wanted = filler.rate_for(&mix);
+                                    if wanted != rate {
+                                        info!("WASAPI re-initializing at {}", wanted);
+                                        rate = wanted;
+                                        reinit = true;
+                                    }
+                                    MIX.store(mix);
                                 }
                                 AudioCommand::SetVolume(vol) => {
+                                    volume = vol;
                                     // Just assume it is as we've set
                                     // TODO: We *could* make an event callback & send what it actually is
                                     // to the UI as an event
                                     let _ =
                                         device.volume.SetMasterVolume(vol.val(), std::ptr::null());
                                 }
+                                AudioCommand::Transport(command) => filler.transport(command),
+                                AudioCommand::Effects(settings) => filler.set_effects(settings),
                             }
                         }
                         Err(TryRecvError::Empty) => break,
@@ -223,11 +227,14 @@ pub fn main(
                         }
                     }
                 }
+                if reinit {
+                    let _ = device.audio.Stop();
+                    break;
+                }
 
-                // Update T_PLAY periodically for the Scope widget
+                // Update played periodically for the Scope widget
                 if last_t_sync.elapsed() >= super::T_SYNC_INTERVAL {
-                    let head = device.estimate_play_head(T_WRITE.load(Ordering::Relaxed));
-                    t_play.store(head, Ordering::Relaxed);
+                    played.store(filler.played(device.padding()), Ordering::Relaxed);
                     last_t_sync = Instant::now();
                 }
 
@@ -252,19 +259,8 @@ pub fn main(
                         (frames_available * CHANNELS as u32) as usize,
                     );
 
-                    for frame in 0..frames_available {
-                        let sample = BEAT.load().eval(T_WRITE.fetch_add(1, Ordering::Relaxed));
-
-                        // Write to both channels (stereo)
-                        let idx = (frame * CHANNELS as u32) as usize;
-                        samples[idx] = sample;
-                        samples[idx + 1] = sample;
-
-                        // Push to visualization buffer (best effort)
-                        if !producer.is_full() {
-                            let _ = producer.push(sample);
-                        }
-                    }
+                    // One mix for the whole buffer
+                    filler.fill(&MIX.load(), samples);
 
                     device.render.ReleaseBuffer(frames_available, 0)?;
                     Ok(())
@@ -0,0 +1,174 @@
+//! Just enough of RIFF WAVE to write what beats make: unsigned 8-bit PCM, interleaved if there's more than one
+//! channel.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Bytes before the first sample
+pub const HEADER_LEN: usize = 44;
+
+/// For `data_len` bytes of samples, which are followed by [`padding`]. `None` if that's more than a WAV file can
+/// hold.
+pub fn header(rate: u32, channels: u16, data_len: u64) -> Option<[u8; HEADER_LEN]> {
+    // Everything after the RIFF chunk's own id and size counts towards it, padding too
+    let riff_len =
+        u32::try_from(data_len + padding(data_len).len() as u64 + HEADER_LEN as u64 - 8).ok()?;
+    let data_len = data_len as u32;
+    let block_align = channels * size_of::<u8>() as u16;
+    let byte_rate = rate * block_align as u32;
+
+    let mut header = [0; HEADER_LEN];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &riff_len.to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        // Size of the rest of the fmt chunk
+        &16u32.to_le_bytes(),
+        // PCM
+        &1u16.to_le_bytes(),
+        &channels.to_le_bytes(),
+        &rate.to_le_bytes(),
+        &byte_rate.to_le_bytes(),
+        &block_align.to_le_bytes(),
+        // Bits per sample
+        &8u16.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ];
+    let mut at = 0;
+    for field in fields {
+        header[at..at + field.len()].copy_from_slice(field);
+        at += field.len();
+    }
+    Some(header)
+}
+
+/// Goes after `data_len` bytes of samples. RIFF chunks are an even length, but the data chunk's size doesn't count
+/// the pad byte
+pub fn padding(data_len: u64) -> &'static [u8] {
+    &[0][..(data_len % 2) as usize]
+}
+
+/// Streams samples to a WAV file. The header is right as of the last [`Writer::flush`], so the file stays playable
+/// if we never get to finish it.
+pub struct Writer {
+    file: BufWriter<File>,
+    rate: u32,
+    channels: u16,
+    /// Bytes of samples so far
+    data_len: u64,
+}
+
+impl Writer {
+    pub fn create(path: &Path, rate: u32, channels: u16) -> io::Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            rate,
+            channels,
+            data_len: 0,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Interleaved, if there's more than one channel
+    pub fn write(&mut self, samples: &[u8]) -> io::Result<()> {
+        self.file.write_all(samples)?;
+        self.data_len += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Writes out everything so far, and the header and padding to match
+    pub fn flush(&mut self) -> io::Result<()> {
+        let header = header(self.rate, self.channels, self.data_len).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::FileTooLarge, "too long for a WAV file")
+        })?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        // The next samples go over any padding
+        let end = SeekFrom::Start(HEADER_LEN as u64 + self.data_len);
+        self.file.seek(end)?;
+        self.file.write_all(padding(self.data_len))?;
+        self.file.seek(end)?;
+        self.file.flush()
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_mono_8khz() {
+        let header = header(8000, 1, 8000).unwrap();
+        assert_eq!(&header[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(header[4..8].try_into().unwrap()), 8036);
+        assert_eq!(&header[8..16], b"WAVEfmt ");
+        // Channels, rate, byte rate, block align, bits
+        assert_eq!(u16::from_le_bytes([header[22], header[23]]), 1);
+        assert_eq!(u32::from_le_bytes(header[24..28].try_into().unwrap()), 8000);
+        assert_eq!(u32::from_le_bytes(header[28..32].try_into().unwrap()), 8000);
+        assert_eq!(u16::from_le_bytes([header[32], header[33]]), 1);
+        assert_eq!(u16::from_le_bytes([header[34], header[35]]), 8);
+        assert_eq!(&header[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(header[40..44].try_into().unwrap()), 8000);
+    }
+
+    #[test]
+    fn test_header_pads_odd_data() {
+        let header = header(8000, 1, 7).unwrap();
+        // Counts the pad byte, but the data chunk doesn't
+        assert_eq!(u32::from_le_bytes(header[4..8].try_into().unwrap()), 44);
+        assert_eq!(u32::from_le_bytes(header[40..44].try_into().unwrap()), 7);
+        assert_eq!(padding(7), [0]);
+        assert!(padding(8).is_empty());
+    }
+
+    #[test]
+    fn test_header_too_long() {
+        assert!(header(8000, 1, u32::MAX as u64).is_none());
+    }
+
+    #[test]
+    fn test_writer_keeps_header_current() {
+        let path =
+            std::env::temp_dir().join(format!("bytebeat-wav-test-{}.wav", std::process::id()));
+        let mut writer = Writer::create(&path, 8000, 2).unwrap();
+        writer.write(&[1, 2, 3, 4]).unwrap();
+        writer.flush().unwrap();
+        writer.write(&[5, 6]).unwrap();
+        drop(writer);
+
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written[..HEADER_LEN], header(8000, 2, 6).unwrap());
+        assert_eq!(written[HEADER_LEN..], [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_writer_pads_odd_data() {
+        let path =
+            std::env::temp_dir().join(format!("bytebeat-wav-odd-test-{}.wav", std::process::id()));
+        let mut writer = Writer::create(&path, 8000, 1).unwrap();
+        writer.write(&[1, 2, 3]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[HEADER_LEN..], [1, 2, 3, 0]);
+        // Written over the padding
+        writer.write(&[4, 5]).unwrap();
+        drop(writer);
+
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written[..HEADER_LEN], header(8000, 1, 5).unwrap());
+        assert_eq!(written[HEADER_LEN..], [1, 2, 3, 4, 5, 0]);
+    }
+}
@@ -1,8 +1,11 @@
 use crate::app::AppEvent;
-use crate::audio::{self, AudioCommand, AudioEvent, Volume};
-use crate::parser::{self};
+use crate::audio::{
+    self, AudioCommand, AudioEvent, Volume, effects::EffectSettings, mix::Mix,
+    transport::TransportCommand,
+};
 use color_eyre::eyre::WrapErr;
 use crossterm::event::{self, Event as CrosstermEvent};
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 use std::{sync::mpsc, time::Duration};
@@ -87,15 +90,22 @@ impl EventHandler {
         let _ = self.audio_sender.send(AudioCommand::SetVolume(vol));
     }
 
-    /// Attempt to compile a new beat. Return an error, or send it to the audio thread if successful.
-    // TODO: This can be made async if we give this duty to `EventThread` and send a message back to App.
-    //     Investigate lag!
-    pub fn new_beat(&self, beat: &str) -> color_eyre::Result<(), Vec<parser::ParseError>> {
-        trace!("event handler recieved beat: {}", beat);
-        let beat = parser::Beat::compile(beat)?;
-        trace!("compilation complete; event handler sending new beat command");
-        let _ = self.audio_sender.send(AudioCommand::NewBeat(beat));
-        Ok(())
+    /// Enqueue a command moving 't' for the audio thread to recieve.
+    pub fn transport(&self, command: TransportCommand) {
+        trace!("event handler sending transport command: {:?}", command);
+        let _ = self.audio_sender.send(AudioCommand::Transport(command));
+    }
+
+    /// Enqueue new effect settings for the audio thread to recieve.
+    pub fn set_effects(&self, settings: EffectSettings) {
+        trace!("event handler sending effects command: {:?}", settings);
+        let _ = self.audio_sender.send(AudioCommand::Effects(settings));
+    }
+
+    /// Enqueue a new mix for the audio thread to play. Beats in it are compiled by the [`crate::app::App`] already
+    pub fn new_mix(&self, mix: Mix) {
+        trace!("event handler sending new mix command");
+        let _ = self.audio_sender.send(AudioCommand::NewMix(Arc::new(mix)));
     }
 
     pub fn enqueue_app_event(&self, event: AppEvent) {
@@ -1,19 +1,34 @@
-use color_eyre::Result;
+use color_eyre::{Result, eyre::eyre};
 use notify::Watcher;
 use std::{
-    sync::{atomic::AtomicI32, mpsc},
+    io::Write,
+    sync::{atomic::AtomicU64, mpsc},
     thread,
 };
-use tracing::info;
+use tracing::{error, info};
 use tracing_subscriber::{EnvFilter, prelude::*};
 use tui_logger::{LevelFilter, TuiLoggerFile};
 
 use crate::{
     app::{
-        App,
+        App, AppSettings,
         input::{FileWatchInput, InteractiveInput},
     },
+    audio::{
+        Backend, BackendContext, BackendOptions, Filler, Resample, ScopeSample,
+        effects::{EffectSettings, MAX_DELAY_MS},
+        mix::Combine,
+        record::{Recorder, Recording},
+        switch::{Retired, SwitchPolicy, SwitchStatus, Transition},
+        transport::TransportCommand,
+        wav,
+    },
     event::EventHandler,
+    library_data::{SONGS, Song},
+    parser::{
+        Beat, CompileOptions, Mode, OutOfBounds, ParseError, PrintStyle, SampleRate, TType,
+        diagnostic::Diagnostic,
+    },
 };
 
 mod app;
@@ -27,6 +42,9 @@ mod library_data {
         pub name: &'static str,
         pub description: &'static str,
         pub code: &'static str,
+        pub mode: crate::parser::Mode,
+        pub t_type: crate::parser::TType,
+        pub rate: crate::parser::SampleRate,
     }
 
     // Build.rs will add the const array below.
@@ -34,11 +52,14 @@ mod library_data {
 }
 mod parser;
 
-use clap::{Parser, builder::ArgPredicate};
+use clap::{Parser, Subcommand, builder::ArgPredicate};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Do this instead of starting the TUI
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Log to file. May provide filename, or default to "bytebeat.log"
     #[arg(short = 'l', long = "log-file", num_args = 0..=1, default_missing_value = "bytebeat.log")]
     log_file: Option<std::path::PathBuf>,
@@ -57,12 +78,195 @@ struct Cli {
         default_value_if("watch_file", ArgPredicate::IsPresent, "false")
     )]
     interactive: bool,
+    /// How beats from the input are evaluated. May be toggled in the TUI
+    #[arg(short = 'm', long = "mode", value_enum, default_value_t = Mode::Classic, global = true)]
+    mode: Mode,
+    /// What indexing past either end of a string or array literal yields. Applies to library songs too
+    #[arg(long = "out-of-bounds", value_enum, default_value_t = OutOfBounds::Zero, global = true)]
+    out_of_bounds: OutOfBounds,
+    /// C type of 't' for classic beats from the input. `unsigned` makes `t >> 31` and comparisons unsigned
+    #[arg(long = "t-type", value_enum, default_value_t = TType::Int, global = true)]
+    t_type: TType,
+    /// Samples per second for beats from the input, like 44100 or 44.1k. May be cycled in the TUI
+    #[arg(short = 'r', long = "rate", default_value = "8000", global = true)]
+    rate: SampleRate,
+    /// Check classic beats for behaviour C leaves undefined, like signed overflow, and warn about it
+    #[arg(long = "check-undefined", default_value = "false")]
+    check_undefined: bool,
+    /// How layers added in the TUI are mixed together. May be cycled in the TUI
+    #[arg(long = "combine", value_enum, default_value_t = Combine::Sum)]
+    combine: Combine,
+    /// How a new beat takes over from the one playing. May be cycled in the TUI
+    #[arg(long = "transition", value_enum, default_value_t = Transition::Immediate)]
+    transition: Transition,
+    /// Milliseconds a crossfade takes
+    #[arg(long = "crossfade-ms", default_value = "50")]
+    crossfade_ms: u32,
+    /// Quantized switches wait for 't' to reach a multiple of 2 to this
+    #[arg(long = "quantize", default_value = "16", value_parser = clap::value_parser!(u8).range(0..=SwitchPolicy::MAX_QUANTIZE as i64))]
+    quantize: u8,
+    /// Where audio goes. `null` and `file` need no audio device, and play along in real time
+    #[arg(short = 'b', long = "backend", value_enum, default_value_t = Backend::default())]
+    backend: Backend,
+    /// WAV file the `file` backend writes to. Overwritten every time
+    #[arg(long = "backend-file", default_value = "bytebeat.wav")]
+    backend_file: std::path::PathBuf,
+    /// Play every beat at --device-rate, making up samples in between like this. `off` plays beats at their own rate
+    #[arg(long = "resample", value_enum, default_value_t = Resample::Off)]
+    resample: Resample,
+    /// What --resample resamples to, and what the `file` backend writes at
+    #[arg(long = "device-rate", default_value = "48000")]
+    device_rate: SampleRate,
+    /// WAV file recordings go to. Overwritten every run, and numbered like `recording-2.wav` after the first take
+    #[arg(long = "record-file", default_value = "recording.wav")]
+    record_file: std::path::PathBuf,
+    #[command(flatten)]
+    effects: EffectArgs,
+    /// PCM the `alsa` backend plays to, like `hw:0` or `null`
+    #[cfg(target_os = "linux")]
+    #[arg(long = "alsa-device", default_value = "default")]
+    alsa_device: String,
+}
+
+/// Every effect is off unless it's given. All of them may be changed in the TUI
+#[derive(clap::Args)]
+#[command(next_help_heading = "Effects")]
+struct EffectArgs {
+    /// Start with every effect bypassed, for output as true to C as it gets
+    #[arg(long = "bypass-effects", default_value = "false")]
+    bypass_effects: bool,
+    /// Keep this many bits of every sample, 1 to 8
+    #[arg(long = "crush-bits", value_parser = clap::value_parser!(u8).range(1..=8))]
+    crush_bits: Option<u8>,
+    /// Hold every sample for this many frames, for a lower sample rate
+    #[arg(long = "crush-hold", value_parser = clap::value_parser!(u16).range(1..=64))]
+    crush_hold: Option<u16>,
+    /// Take out the DC offset bytebeat always has
+    #[arg(long = "dc-block", default_value = "false")]
+    dc_block: bool,
+    /// Cut below this many Hz
+    #[arg(long = "high-pass")]
+    high_pass: Option<f32>,
+    /// Cut above this many Hz
+    #[arg(long = "low-pass")]
+    low_pass: Option<f32>,
+    /// Echo after this many milliseconds
+    #[arg(long = "delay", value_parser = clap::value_parser!(u32).range(1..=MAX_DELAY_MS as i64))]
+    delay: Option<u32>,
+    /// How much of every echo comes back, 0 to 0.95
+    #[arg(long = "delay-feedback", default_value = "0.4")]
+    delay_feedback: f32,
+    /// How loud echoes are, 0 to 1
+    #[arg(long = "delay-mix", default_value = "0.5")]
+    delay_mix: f32,
+    /// Stereo width. 0 is mono, and above 1 makes a mono beat wider
+    #[arg(long = "width")]
+    width: Option<f32>,
+}
+
+impl EffectArgs {
+    fn settings(&self) -> EffectSettings {
+        let defaults = EffectSettings::default();
+        let cutoff =
+            |hz: f32| hz.clamp(EffectSettings::MIN_CUTOFF_HZ, EffectSettings::MAX_CUTOFF_HZ);
+        let crush = self.crush_bits.is_some() || self.crush_hold.is_some();
+        EffectSettings {
+            bypass: self.bypass_effects,
+            crush,
+            // Only what's given is crushed
+            crush_bits: self
+                .crush_bits
+                .unwrap_or(if crush { 8 } else { defaults.crush_bits }),
+            crush_hold: self
+                .crush_hold
+                .unwrap_or(if crush { 1 } else { defaults.crush_hold }),
+            dc_block: self.dc_block,
+            high_pass: self.high_pass.is_some(),
+            high_pass_hz: self.high_pass.map_or(defaults.high_pass_hz, cutoff),
+            low_pass: self.low_pass.is_some(),
+            low_pass_hz: self.low_pass.map_or(defaults.low_pass_hz, cutoff),
+            delay: self.delay.is_some(),
+            delay_ms: self.delay.unwrap_or(defaults.delay_ms),
+            delay_feedback: self.delay_feedback.clamp(0.0, EffectSettings::MAX_FEEDBACK),
+            delay_mix: self.delay_mix.clamp(0.0, 1.0),
+            widen: self.width.is_some(),
+            width: self.width.map_or(defaults.width, |width| {
+                width.clamp(0.0, EffectSettings::MAX_WIDTH)
+            }),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a beat back out with only the parentheses it needs, then exit. Comments are dropped
+    Format {
+        /// Read the beat from this file instead of stdin
+        #[arg(value_parser = readable_file)]
+        file: Option<std::path::PathBuf>,
+        #[arg(short = 's', long = "style", value_enum, default_value_t = PrintStyle::Canonical)]
+        style: PrintStyle,
+    },
+    /// Evaluate a beat without an audio server and write the samples out, then exit
+    Render(RenderArgs),
+}
+
+#[derive(clap::Args)]
+struct RenderArgs {
+    /// The beat itself. Read from stdin if neither this, --file nor --song is given. May start with `-`, like
+    /// `-(t>>3)`
+    #[arg(allow_hyphen_values = true, conflicts_with_all = ["file", "song"])]
+    code: Option<String>,
+    /// Read the beat from this file
+    #[arg(short = 'f', long = "file", value_parser = readable_file, conflicts_with = "song")]
+    file: Option<std::path::PathBuf>,
+    /// Render the library song with this name, or at this position in the library counting from 1. Its own mode,
+    /// 't' type and rate are used
+    #[arg(long = "song")]
+    song: Option<String>,
+    /// Write to this file instead of stdout
+    #[arg(short = 'o', long = "output")]
+    output: Option<std::path::PathBuf>,
+    /// How the samples are written
+    #[arg(long = "format", value_enum, default_value_t = RenderFormat::Wav)]
+    format: RenderFormat,
+    /// Seconds to render
+    #[arg(
+        short = 'd',
+        long = "duration",
+        default_value_t = 30.0,
+        conflicts_with = "samples"
+    )]
+    duration: f64,
+    /// Samples to render, instead of a duration
+    #[arg(short = 'n', long = "samples")]
+    samples: Option<u64>,
+    /// 't' of the first sample
+    #[arg(long = "start", default_value_t = 0, allow_negative_numbers = true)]
+    start: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum RenderFormat {
+    /// Unsigned 8-bit PCM with a header, playable anywhere
+    Wav,
+    /// Just the samples, one byte each. Stereo beats are interleaved, left first
+    Raw,
 }
 
 // TODO: This function has become a dumping ground, some of it should probably go in App. some should ???
 fn main() -> Result<()> {
     color_eyre::install()?;
     let cli = Cli::parse();
+    if let Some(command) = cli.command {
+        let options = CompileOptions {
+            mode: cli.mode,
+            out_of_bounds: cli.out_of_bounds,
+            t_type: cli.t_type,
+            rate: cli.rate,
+        };
+        return run_command(command, options);
+    }
 
     let (level_str, level_enum) = if cli.verbose {
         ("trace", LevelFilter::Trace)
@@ -94,13 +298,23 @@ fn main() -> Result<()> {
     let (command_tx, command_rx) = audio::command_channel();
 
     // For audio visualization widget. Audio thread produces, App consumes
-    // 64000 samples @ 8kHz = 8 seconds of buffer (and 62.5KiB)
+    // 64000 samples @ 8kHz = 8 seconds of buffer (and 500KiB with their 't'), less at higher rates
     // We'll probably only want to display 4 at once, maximum
-    let (producer, consumer) = rtrb::RingBuffer::<u8>::new(64000);
-    // Represents that the 't'th-ISH sample will play next
+    let (producer, consumer) = rtrb::RingBuffer::<ScopeSample>::new(64000);
+    // Represents how many of those samples-ISH have been heard
     // Audio thread gets a to set it, App gets a copy to read it
     // The scope widget needs this to look useful and track where-about we're at.
-    static T_PLAY: AtomicI32 = AtomicI32::new(0);
+    static PLAYED: AtomicU64 = AtomicU64::new(0);
+    // Toggled by App, and written on a thread of its own. Finished when this drops, even on a panic
+    static RECORDING: Recording = Recording::new();
+    let (_recorder, tap) = Recorder::spawn(&RECORDING, cli.record_file);
+    // Where a quantized switch will land, written after every fill
+    static SWITCH: SwitchStatus = SwitchStatus::new();
+    let switch_policy = SwitchPolicy {
+        transition: cli.transition,
+        crossfade_ms: cli.crossfade_ms,
+        quantize: cli.quantize,
+    };
 
     // Set up file watching input if requested. The watcher must be kept alive.
     let (_watcher, file_watch_rx) = match cli.watch_file {
@@ -114,21 +328,179 @@ fn main() -> Result<()> {
     let events = EventHandler::new(command_tx, file_watch_rx);
     // TODO: maybe hoist channel creation for term here also
     let terminal_tx = events.get_term_sender();
-    // Pipewire loop needs to tx states to App and rx commands from it (brokered by event handler)
-    thread::spawn(move || crate::audio::main(terminal_tx, command_rx, producer, &T_PLAY));
+    // Backend needs to tx states to App and rx commands from it (brokered by event handler)
+    let backend = cli.backend.build(BackendOptions {
+        file: cli.backend_file,
+        #[cfg(target_os = "linux")]
+        alsa_device: cli.alsa_device,
+    });
+    let (retire, retired) = Retired::new();
+    let mut filler = Filler::new(
+        producer,
+        tap,
+        cli.resample,
+        cli.device_rate,
+        &SWITCH,
+        retire,
+    );
+    filler.transport(TransportCommand::Switch(switch_policy));
+    let effects = cli.effects.settings();
+    filler.set_effects(effects);
+    let ctx = BackendContext {
+        event_tx: terminal_tx,
+        filler,
+        played: &PLAYED,
+        retired,
+    };
+    thread::spawn(move || {
+        let _ = backend
+            .run(command_rx, ctx)
+            .inspect_err(|e| error!("audio backend stopped: {e}"));
+    });
     // App owns the event handler struct (but NOT the event thread!)
     let terminal = ratatui::init();
+    // For the knobs. Not fatal, they turn with keys too
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::event::EnableMouseCapture);
+    let settings = AppSettings {
+        options: CompileOptions {
+            mode: cli.mode,
+            out_of_bounds: cli.out_of_bounds,
+            t_type: cli.t_type,
+            rate: cli.rate,
+        },
+        check_undefined: cli.check_undefined,
+        combine: cli.combine,
+        switch_policy,
+        effects,
+    };
     // We need to split here because App is generic over these possible input widgets TODO: Do this inside App?
     let result = if cli.interactive {
-        App::new(events, consumer, &T_PLAY, InteractiveInput::default()).run(terminal)
+        App::new(
+            events,
+            consumer,
+            &PLAYED,
+            &RECORDING,
+            &SWITCH,
+            InteractiveInput::default(),
+            settings,
+        )
+        .run(terminal)
     } else {
-        App::new(events, consumer, &T_PLAY, FileWatchInput::default()).run(terminal)
+        App::new(
+            events,
+            consumer,
+            &PLAYED,
+            &RECORDING,
+            &SWITCH,
+            FileWatchInput::default(),
+            settings,
+        )
+        .run(terminal)
     };
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::event::DisableMouseCapture);
     ratatui::restore();
     info!("app done: {:?}", result);
     result
 }
 
+/// Commands run without the TUI, or any logging
+fn run_command(command: Command, options: CompileOptions) -> Result<()> {
+    match command {
+        Command::Format { file, style } => {
+            let source = match file {
+                Some(path) => std::fs::read_to_string(path)?,
+                None => std::io::read_to_string(std::io::stdin())?,
+            };
+            let source = source.trim();
+            match parser::format(source, options.mode, style) {
+                Ok(formatted) => {
+                    println!("{formatted}");
+                    Ok(())
+                }
+                Err(errors) => Err(report_errors(&errors, source)),
+            }
+        }
+        Command::Render(args) => render(args, options),
+    }
+}
+
+/// Prints every error to stderr and sums them up
+fn report_errors(errors: &[ParseError], source: &str) -> color_eyre::Report {
+    for error in errors {
+        eprint!("{}", Diagnostic::error(error, source).render_text(source));
+    }
+    eyre!("beat has {} error(s)", errors.len())
+}
+
+/// Compiles the beat and writes as many samples of it as asked
+fn render(args: RenderArgs, mut options: CompileOptions) -> Result<()> {
+    let RenderArgs {
+        code,
+        file,
+        song,
+        output,
+        format,
+        duration,
+        samples,
+        start,
+    } = args;
+    let source = match (code, file, song) {
+        (Some(code), _, _) => code,
+        (_, Some(path), _) => std::fs::read_to_string(path)?,
+        (_, _, Some(name)) => {
+            let song = find_song(&name).ok_or_else(|| eyre!("no song '{name}' in the library"))?;
+            options.mode = song.mode;
+            options.t_type = song.t_type;
+            options.rate = song.rate;
+            song.code.to_owned()
+        }
+        _ => std::io::read_to_string(std::io::stdin())?,
+    };
+    let source = source.trim();
+    let beat = match Beat::compile(source, &options) {
+        Ok(beat) => beat,
+        Err(errors) => return Err(report_errors(&errors, source)),
+    };
+    for warning in beat.warnings() {
+        eprint!("{}", Diagnostic::warning(warning).render_text(source));
+    }
+
+    let rate = beat.rate().hz();
+    let len = match samples {
+        Some(samples) => samples,
+        None if duration.is_finite() && duration >= 0.0 => (duration * rate as f64).round() as u64,
+        None => return Err(eyre!("duration must be 0 seconds or more, not {duration}")),
+    };
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = std::io::BufWriter::new(&mut out);
+    let channels = if beat.is_stereo() { 2 } else { 1 };
+    let data_len = len * channels as u64;
+    if format == RenderFormat::Wav {
+        let header = wav::header(rate, channels, data_len)
+            .ok_or_else(|| eyre!("{len} samples are too many for a WAV file, try --format raw"))?;
+        out.write_all(&header)?;
+    }
+    audio::render(&beat, start, len, &mut out)?;
+    if format == RenderFormat::Wav {
+        out.write_all(wav::padding(data_len))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn find_song(query: &str) -> Option<&'static Song> {
+    if let Ok(position) = query.parse::<usize>() {
+        return position.checked_sub(1).and_then(|idx| SONGS.get(idx));
+    }
+    SONGS
+        .iter()
+        .find(|song| !song.name.is_empty() && song.name.eq_ignore_ascii_case(query.trim()))
+}
+
 fn setup_watch(
     path: &std::path::Path,
 ) -> Result<
@@ -1,20 +1,50 @@
-//! Converts [`String`] input to functions that evaluate into classic (i32 -> u8) bytebeat, or accrues a
-//! vec full of errors while trying.
+//! Converts [`String`] input to functions that evaluate into classic (i32 -> u8) bytebeat or floatbeat
+//! (f64 -> u8), or accrues a vec full of errors while trying.
 //!
 //! LLM SLOP PRESENCE: EXTREME
+mod bytecode;
+mod checked;
+mod ctype;
+pub mod diagnostic;
+mod eval;
+mod fold;
+pub mod knobs;
+mod lanes;
 pub mod lex;
+mod lint;
 pub mod parse;
+mod print;
+mod typecheck;
 
 use std::fmt;
+use std::num::NonZeroU32;
 use std::ops::Deref;
+use std::str::FromStr;
 
+use self::bytecode::Compiled;
+use self::ctype::{CInt, CType};
+use self::eval::{Arithmetic, Frame, Value};
+use self::knobs::{Knob, KnobValues};
+use self::lanes::CompiledLanes;
 use self::parse::Parser;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     /// Must be 't'
     Variable,
+    /// Any other name. Either a [`Builtin`] or a local variable
+    Ident(String),
+    /// `$` and a name, without the `$`. Turned while the beat plays, see [`knobs`]
+    Knob(String),
+    /// Integer literal that's an `int` in C
     Number(i32),
+    /// Integer literal of another C type, because of its suffix or size
+    TypedNumber(CInt),
+    Float(f64),
+    /// Double quoted, or single quoted with more or less than one character
+    Str(String),
+    /// Single quoted, like `'a'`
+    Char(char),
     Op(Operator),
     /// Represents an lexer-specific error. Not directly parsable.
     // Is this a smart way to do lazy errors, or a hack? Both?
@@ -22,6 +52,25 @@ pub enum Token {
     Eof,
 }
 
+/// As it would be described to whoever wrote it
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Variable => write!(f, "'t'"),
+            Token::Ident(name) => write!(f, "name '{name}'"),
+            Token::Knob(name) => write!(f, "knob '${name}'"),
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::TypedNumber(c) => write!(f, "number {}", c.wide()),
+            Token::Float(x) => write!(f, "number {x}"),
+            Token::Str(text) => write!(f, "string \"{text}\""),
+            Token::Char(c) => write!(f, "character '{c}'"),
+            Token::Op(op) => write!(f, "'{op}'"),
+            Token::Err(e) => write!(f, "{e}"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
 pub type Column = usize;
 pub type Line = usize;
 
@@ -82,6 +131,9 @@ pub enum Operator {
     Mod,
     Lparen,
     Rparen,
+    /// Starts an array literal, or indexes one when postfix
+    Lbracket,
+    Rbracket,
     // Bitwise
     Rsh,
     Lsh,
@@ -105,16 +157,272 @@ pub enum Operator {
     Question,
     /// Part of the ternary operator.
     Colon,
+    /// Separates arguments of a [`Builtin`] call, or evaluates both sides and yields the right
+    Comma,
+    /// Like [`Operator::Comma`], but binds looser. May also end the input.
+    Semicolon,
+    // Assignment
+    Assign,
+    PlusAssign,
+    MinusAssign,
+    MulAssign,
+    DivAssign,
+    ModAssign,
+    AndAssign,
+    OrAssign,
+    XorAssign,
+    LshAssign,
+    RshAssign,
+}
+
+impl Operator {
+    pub fn is_assignment(self) -> bool {
+        self == Operator::Assign || self.compound_base().is_some()
+    }
+
+    /// The operator a compound assignment applies, e.g. [`Operator::Plus`] for `+=`
+    pub fn compound_base(self) -> Option<Operator> {
+        let base = match self {
+            Operator::PlusAssign => Operator::Plus,
+            Operator::MinusAssign => Operator::Minus,
+            Operator::MulAssign => Operator::Mul,
+            Operator::DivAssign => Operator::Div,
+            Operator::ModAssign => Operator::Mod,
+            Operator::AndAssign => Operator::And,
+            Operator::OrAssign => Operator::Or,
+            Operator::XorAssign => Operator::BitXor,
+            Operator::LshAssign => Operator::Lsh,
+            Operator::RshAssign => Operator::Rsh,
+            _ => return None,
+        };
+        Some(base)
+    }
+
+    /// As it's written in a beat
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Mod => "%",
+            Operator::Lparen => "(",
+            Operator::Rparen => ")",
+            Operator::Lbracket => "[",
+            Operator::Rbracket => "]",
+            Operator::Rsh => ">>",
+            Operator::Lsh => "<<",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::BitXor => "^",
+            Operator::BitNot => "~",
+            Operator::LogAnd => "&&",
+            Operator::LogOr => "||",
+            Operator::LogNot => "!",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Gt => ">",
+            Operator::Lt => "<",
+            Operator::Ge => ">=",
+            Operator::Le => "<=",
+            Operator::Question => "?",
+            Operator::Colon => ":",
+            Operator::Comma => ",",
+            Operator::Semicolon => ";",
+            Operator::Assign => "=",
+            Operator::PlusAssign => "+=",
+            Operator::MinusAssign => "-=",
+            Operator::MulAssign => "*=",
+            Operator::DivAssign => "/=",
+            Operator::ModAssign => "%=",
+            Operator::AndAssign => "&=",
+            Operator::OrAssign => "|=",
+            Operator::XorAssign => "^=",
+            Operator::LshAssign => "<<=",
+            Operator::RshAssign => ">>=",
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// Functions from math.h and JavaScript's `Math` that may be called by name.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Builtin {
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Exp,
+    Log,
+    Log2,
+    Pow,
+    Sqrt,
+    Cbrt,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+    Min,
+    Max,
+    /// Deterministic: seeded by 't' and how many times it was already called for that 't'
+    Random,
+}
+
+impl Builtin {
+    /// Largest [`Builtin::arity`], so evaluation can keep arguments on the stack
+    pub const MAX_ARITY: usize = 2;
+
+    pub const ALL: [Builtin; 21] = [
+        Builtin::Sin,
+        Builtin::Cos,
+        Builtin::Tan,
+        Builtin::Asin,
+        Builtin::Acos,
+        Builtin::Atan,
+        Builtin::Atan2,
+        Builtin::Exp,
+        Builtin::Log,
+        Builtin::Log2,
+        Builtin::Pow,
+        Builtin::Sqrt,
+        Builtin::Cbrt,
+        Builtin::Abs,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Round,
+        Builtin::Trunc,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Random,
+    ];
+
+    /// Accepts the C spelling where it differs from JavaScript
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        let builtin = match name {
+            "sin" => Builtin::Sin,
+            "cos" => Builtin::Cos,
+            "tan" => Builtin::Tan,
+            "asin" => Builtin::Asin,
+            "acos" => Builtin::Acos,
+            "atan" => Builtin::Atan,
+            "atan2" => Builtin::Atan2,
+            "exp" => Builtin::Exp,
+            "log" => Builtin::Log,
+            "log2" => Builtin::Log2,
+            "pow" => Builtin::Pow,
+            "sqrt" => Builtin::Sqrt,
+            "cbrt" => Builtin::Cbrt,
+            "abs" | "fabs" => Builtin::Abs,
+            "floor" => Builtin::Floor,
+            "ceil" => Builtin::Ceil,
+            "round" => Builtin::Round,
+            "trunc" => Builtin::Trunc,
+            "min" | "fmin" => Builtin::Min,
+            "max" | "fmax" => Builtin::Max,
+            "random" => Builtin::Random,
+            _ => return None,
+        };
+        Some(builtin)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Asin => "asin",
+            Builtin::Acos => "acos",
+            Builtin::Atan => "atan",
+            Builtin::Atan2 => "atan2",
+            Builtin::Exp => "exp",
+            Builtin::Log => "log",
+            Builtin::Log2 => "log2",
+            Builtin::Pow => "pow",
+            Builtin::Sqrt => "sqrt",
+            Builtin::Cbrt => "cbrt",
+            Builtin::Abs => "abs",
+            Builtin::Floor => "floor",
+            Builtin::Ceil => "ceil",
+            Builtin::Round => "round",
+            Builtin::Trunc => "trunc",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+            Builtin::Random => "random",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Random => 0,
+            Builtin::Atan2 | Builtin::Pow | Builtin::Min | Builtin::Max => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
 pub type NodeId = usize;
+/// Slot of a local variable in a [`Beat`], in order of first appearance
+pub type LocalId = usize;
+
+/// Most local variables a single [`Beat`] may use. They live on the stack during evaluation.
+pub const MAX_LOCALS: usize = 32;
+
+/// Slot of a knob in a [`Beat`], in order of first appearance
+pub type KnobId = usize;
+
+/// Most knobs a single [`Beat`] may use
+pub const MAX_KNOBS: usize = 8;
+
+/// Left side of an assignment
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Place {
+    T,
+    Local(LocalId),
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum ASTNode {
     Literal(i32),
+    /// Only produced for [`Mode::Classic`], for literals that aren't an `int`
+    TypedLiteral(CInt),
+    /// Only produced for [`Mode::Float`]
+    FloatLiteral(f64),
     Variable,
+    /// Zero until assigned, for every evaluation
+    Local(LocalId),
+    /// Whatever the knob is turned to. Can't be assigned
+    Knob(KnobId),
+    /// Any [`Operator::is_assignment`] operator. Yields the assigned value.
+    Assign(Operator, Place, NodeId),
+    /// [`Operator::Minus`], [`Operator::LogNot`] or [`Operator::BitNot`]. Unary plus is dropped by the parser.
+    Unary(Operator, NodeId),
+    /// Only produced for [`Mode::Classic`]. Also made for conversions C does implicitly, see [`typecheck`].
+    Cast(CType, NodeId),
     Binary(Operator, NodeId, NodeId),
     Ternary(NodeId, NodeId, NodeId),
+    /// Arguments are checked against [`Builtin::arity`] by the parser
+    Call(Builtin, Vec<NodeId>),
+    /// String literal. Only valid as the table of an [`ASTNode::Index`]
+    Str(String),
+    /// Array literal. Only valid as the table of an [`ASTNode::Index`], or as the whole beat for stereo
+    Array(Vec<NodeId>),
+    /// Table (string or array literal) and index. Like C, every array element is evaluated in order, then the index.
+    Index(NodeId, NodeId),
     /// Because [`Beat`] uses these too, we're making invalid state representable.
     /// there's logic elsewhere that should prevent creation of a valid beat with these.
     Error(Span),
@@ -128,16 +436,45 @@ use tracing::error;
 pub enum ParseError {
     #[error("Unexpected end of file at {0}")]
     UnexpectedEof(Span),
-    // TODO: not pretty that we debug fmt this (it's user facing)
-    // but I don't feel like impl fmt for every token right now
-    #[error("Expected operator, found {0:?} at {1}")]
-    ExpectedOperator(Token, Span),
+    /// Also has the [`Span`] of the token before the one found
+    #[error("Expected operator, found {0} at {1}")]
+    ExpectedOperator(Token, Span, Span),
+    /// Also has the [`Span`] of the '(' it would match
     #[error("Expected matching ')' at {0}")]
-    UnmatchedParenthesis(Span),
-    #[error("Unexpected prefix operator: {0:?} at {1}")]
+    UnmatchedParenthesis(Span, Span),
+    #[error("Unexpected prefix operator '{0}' at {1}")]
     UnexpectedPrefix(Operator, Span),
+    /// A ')', ']' or ':' that doesn't close anything
+    #[error("Unexpected {0} at {1}")]
+    UnexpectedToken(Token, Span),
+    /// Also has the [`Span`] of the '?'
     #[error("Expected ':' in ternary expression at {0}")]
-    ExpectedTernaryColon(Span),
+    ExpectedTernaryColon(Span, Span),
+    #[error("Float literal in classic mode (switch to floatbeat?) at {0}")]
+    FloatInClassic(Span),
+    #[error("Unknown function '{0}' at {1}")]
+    UnknownIdentifier(String, Span),
+    #[error("Can only assign to 't' or a variable at {1}")]
+    InvalidAssignment(Operator, Span),
+    #[error("Variable '{0}' is one too many, only {max} allowed at {1}", max = MAX_LOCALS)]
+    TooManyLocals(String, Span),
+    #[error("Knob '${0}' is one too many, only {max} allowed at {1}", max = MAX_KNOBS)]
+    TooManyKnobs(String, Span),
+    #[error("Function '{0}' must be called like {0}(...) at {1}")]
+    ExpectedCall(Builtin, Span),
+    #[error("Function '{0}' takes {1} argument(s), but found {2} at {3}")]
+    WrongArity(Builtin, usize, usize, Span),
+    /// Also has the [`Span`] of the '[' it would match
+    #[error("Expected matching ']' at {0}")]
+    UnmatchedBracket(Span, Span),
+    #[error("Only string and array literals can be indexed at {0}")]
+    NotIndexable(Span),
+    #[error("String or array literal must be indexed like \"abc\"[t & 3] at {0}")]
+    Unindexed(Span),
+    #[error("Stereo beats are written [left, right], but found {0} channel(s) at {1}")]
+    Channels(usize, Span),
+    #[error("Can't cast to '{0}', only to C integer types in classic mode, at {1}")]
+    InvalidCast(String, Span),
     #[error("Lexer: {0} at {1}")]
     LexError(LexError, Span),
 }
@@ -148,149 +485,657 @@ pub enum ParseError {
 pub enum LexError {
     #[error("Expected valid base {0} number: {1}")]
     ImproperNumber(i8, std::num::ParseIntError),
-    #[error("Assignment or single = not supported")]
-    SolitaryEquals,
+    #[error("Expected valid float: {0}")]
+    ImproperFloat(std::num::ParseFloatError),
     #[error("Unexpected character: {0}")]
     UnexpectedChar(char),
+    #[error("Expected closing {0} before the end of the line")]
+    UnterminatedString(char),
+    #[error("Expected closing */ before the end of the input")]
+    UnterminatedComment,
+}
+
+/// Doesn't stop a beat from compiling, but it may not do what was meant, or sound the same elsewhere
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum Warning {
+    #[error("{0} at {1}")]
+    Lint(Lint, Span),
+    #[error("Undefined in C: {0} at {1}, first when t = {2}")]
+    Undefined(Undefined, Span, i32),
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Warning::Lint(_, span) | Warning::Undefined(_, span, _) => *span,
+        }
+    }
+}
+
+/// Probably not what was meant, found without evaluating the beat. Every [`Beat`] has them, see [`Beat::warnings`].
+#[derive(Error, Debug, PartialEq, Clone, Copy)]
+pub enum Lint {
+    #[error("Shift by {0} isn't within the {1} bits of the type")]
+    ShiftCount(i128, u32),
+    #[error("Division by a constant zero")]
+    DivisionByZero,
+    #[error("'& 0' is always 0")]
+    AndZero,
+    #[error("Comparison is always {0}")]
+    AlwaysSame(bool),
+    #[error("'{0}' binds tighter than '{1}', parentheses would make that clear")]
+    Precedence(Operator, Operator),
+    #[error("Assignment used as a condition, did you mean '=='?")]
+    AssignInCondition,
+}
+
+/// Behaviour C leaves undefined. [`Beat`] defines all of it, see [`check_undefined`].
+#[derive(Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Undefined {
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("signed overflow")]
+    SignedOverflow,
+    #[error("shift by a negative count, or at least the width of the type")]
+    ShiftCount,
+    #[error("left shift of a negative number")]
+    NegativeShift,
+    #[error("index out of bounds")]
+    IndexOutOfBounds,
+}
+
+/// Decides the type of 't', literals and intermediates, and how the result becomes a u8 sample.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// i32 with C semantics. Output wraps around to u8.
+    #[default]
+    Classic,
+    /// f64 with JavaScript semantics. Output in -1.0..1.0 is scaled to u8.
+    Float,
+}
+
+impl Mode {
+    pub fn toggle(self) -> Self {
+        match self {
+            Mode::Classic => Mode::Float,
+            Mode::Float => Mode::Classic,
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Classic => write!(f, "Classic"),
+            Mode::Float => write!(f, "Floatbeat"),
+        }
+    }
+}
+
+/// What indexing a string or array literal out of its bounds yields, instead of undefined behavior.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutOfBounds {
+    /// Wraps around, so `[1, 2][2]` is 1. Negative indices count from the end.
+    Wrap,
+    /// 0, like an out of bounds JavaScript index would in any bitwise operation.
+    #[default]
+    Zero,
+}
+
+impl OutOfBounds {
+    /// Position of `index` in a table of `len`, or None if the result is 0. Non-finite float indices are None.
+    pub fn resolve(self, index: Option<i64>, len: usize) -> Option<usize> {
+        let index = index?;
+        if len == 0 {
+            return None;
+        }
+        match self {
+            OutOfBounds::Wrap => Some(index.rem_euclid(len as i64) as usize),
+            OutOfBounds::Zero => usize::try_from(index).ok().filter(|i| *i < len),
+        }
+    }
+}
+
+/// C type of 't' in [`Mode::Classic`], like the parameter of `main(t)` would be declared. Locals are always `int`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TType {
+    #[default]
+    Int,
+    /// Division, `>>` and comparisons treat 't' as unsigned, and so does anything it's mixed with.
+    Unsigned,
+}
+
+impl fmt::Display for TType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TType::Int => write!(f, "int"),
+            TType::Unsigned => write!(f, "unsigned"),
+        }
+    }
+}
+
+/// Samples per second a beat is meant to be heard at, so how many 't' go by each second. Never 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SampleRate(NonZeroU32);
+
+impl SampleRate {
+    /// What bytebeat was born at, and what most songs expect
+    pub const CLASSIC: Self = Self(NonZeroU32::new(8000).unwrap());
+    /// Rates Dollchan songs are commonly written for, in the order [`SampleRate::next`] goes through them
+    pub const COMMON: &[Self] = &[
+        Self::CLASSIC,
+        Self(NonZeroU32::new(11025).unwrap()),
+        Self(NonZeroU32::new(16000).unwrap()),
+        Self(NonZeroU32::new(22050).unwrap()),
+        Self(NonZeroU32::new(32000).unwrap()),
+        Self(NonZeroU32::new(44100).unwrap()),
+        Self(NonZeroU32::new(48000).unwrap()),
+    ];
+
+    pub const fn new(hz: u32) -> Option<Self> {
+        match NonZeroU32::new(hz) {
+            Some(hz) => Some(Self(hz)),
+            None => None,
+        }
+    }
+
+    pub const fn hz(self) -> u32 {
+        self.0.get()
+    }
+
+    /// The next of [`SampleRate::COMMON`] above this one, wrapping around to the lowest
+    pub fn next(self) -> Self {
+        Self::COMMON
+            .iter()
+            .copied()
+            .find(|&rate| rate > self)
+            .unwrap_or(Self::COMMON[0])
+    }
+}
+
+impl Default for SampleRate {
+    fn default() -> Self {
+        Self::CLASSIC
+    }
+}
+
+impl fmt::Display for SampleRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.hz())
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    /// Hertz, like `44100`. A `k` suffix means thousands, like `44.1k`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hz = match s.strip_suffix(['k', 'K']) {
+            Some(khz) => khz
+                .parse::<f64>()
+                .ok()
+                .map(|khz| (khz * 1000.0).round())
+                .filter(|hz| (0.0..=u32::MAX as f64).contains(hz))
+                .map(|hz| hz as u32),
+            None => s.parse::<u32>().ok(),
+        };
+        hz.and_then(Self::new)
+            .ok_or_else(|| format!("'{s}' isn't a sample rate above 0, like 8000 or 44.1k"))
+    }
+}
+
+/// How [`format`] lays out a beat. Parentheses are only kept where they're needed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrintStyle {
+    /// Spaces around binary operators, all on one line
+    #[default]
+    Canonical,
+    /// No whitespace at all, for the shortest code
+    Minify,
+    /// Anything too long for one line is split, one sub-expression per line
+    Expand,
+}
+
+/// Parses `source` and prints it back in `style`. It's printed as written, constants aren't folded. Comments are
+/// dropped, besides knob declarations, which are put in one at the end.
+pub fn format(source: &str, mode: Mode, style: PrintStyle) -> Result<String, Vec<ParseError>> {
+    if source.is_empty() {
+        return Ok(String::new());
+    }
+    let mut nodes = Vec::new();
+    let mut parser = Parser::new(source, &mut nodes, mode);
+    let root = parser.parse()?;
+    let knob_names = parser.take_knobs();
+    let locals = parser.into_locals();
+    let printed = print::print(&nodes, root, &locals, &knob_names, style);
+    Ok(knobs::declare(&printed, &knobs::declared(source, mode)))
+}
+
+/// Evaluates every 't' in `ts` like [`Beat`] would, and warns about everything a C compiler is allowed to do
+/// differently. Only the first 't' is kept for each part of the beat. Floatbeats have JavaScript semantics, which
+/// leave nothing undefined.
+pub fn check_undefined(
+    source: &str,
+    options: &CompileOptions,
+    ts: std::ops::Range<i32>,
+) -> Result<Vec<Warning>, Vec<ParseError>> {
+    if source.is_empty() || options.mode == Mode::Float {
+        return Ok(Vec::new());
+    }
+    let parsed = Parsed::new(source, options.mode, options.t_type)?;
+    let knobs: Vec<f64> = parsed.knobs.iter().map(|knob| knob.value).collect();
+    Ok(checked::check(&parsed, options.out_of_bounds, ts, &knobs))
+}
+
+/// Everything besides the source text that decides what a [`Beat`] sounds like.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CompileOptions {
+    pub mode: Mode,
+    pub out_of_bounds: OutOfBounds,
+    /// Floatbeats ignore this
+    pub t_type: TType,
+    /// Doesn't change any sample, only how fast they're played
+    pub rate: SampleRate,
+}
+
+/// Does evaluating this have effects besides its value?
+fn has_side_effects(nodes: &[ASTNode], id: NodeId) -> bool {
+    match &nodes[id] {
+        ASTNode::Assign(..) | ASTNode::Call(Builtin::Random, _) => true,
+        ASTNode::Literal(_)
+        | ASTNode::TypedLiteral(_)
+        | ASTNode::FloatLiteral(_)
+        | ASTNode::Variable
+        | ASTNode::Local(_)
+        | ASTNode::Knob(_)
+        | ASTNode::Str(_)
+        | ASTNode::Error(_) => false,
+        ASTNode::Unary(_, operand) | ASTNode::Cast(_, operand) => has_side_effects(nodes, *operand),
+        ASTNode::Binary(_, l, r) | ASTNode::Index(l, r) => {
+            has_side_effects(nodes, *l) || has_side_effects(nodes, *r)
+        }
+        ASTNode::Ternary(c, a, b) => [c, a, b].iter().any(|id| has_side_effects(nodes, **id)),
+        ASTNode::Call(_, items) | ASTNode::Array(items) => {
+            items.iter().any(|id| has_side_effects(nodes, *id))
+        }
+    }
+}
+
+/// A beat parsed and typed, but not folded or lowered yet
+#[derive(Debug)]
+struct Parsed {
+    nodes: Vec<ASTNode>,
+    /// Where each node came from, by [`NodeId`]
+    spans: Vec<Span>,
+    /// How many pairs of parentheses each node was written in, by [`NodeId`]. Shorter than `nodes` when typing
+    /// added some.
+    parentheses: Vec<u8>,
+    /// Of every channel. Just one for mono beats, or left and right
+    roots: Vec<NodeId>,
+    arithmetic: Arithmetic,
+    /// As they're declared in the source, by [`KnobId`]
+    knobs: Vec<Knob>,
+}
+
+impl Parsed {
+    fn new(source: &str, mode: Mode, t_type: TType) -> Result<Parsed, Vec<ParseError>> {
+        let mut nodes = Vec::new();
+        let mut parser = Parser::new(source, &mut nodes, mode);
+        let root = parser.parse()?;
+        let mut spans = parser.take_spans();
+        let parentheses = parser.take_parentheses();
+        let knobs = knobs::resolve(&parser.take_knobs(), source, mode);
+        // The parser only lets an array be the whole beat if it's `[left, right]`
+        let roots = match &nodes[root] {
+            ASTNode::Array(items) => items.clone(),
+            _ => vec![root],
+        };
+        let (roots, arithmetic) = match mode {
+            Mode::Classic => {
+                // Channels are typed on their own, but evaluated alike
+                let mut arithmetic = Arithmetic::Int;
+                let roots = roots
+                    .into_iter()
+                    .map(|root| {
+                        let (root, channel) =
+                            typecheck::check(&mut nodes, &mut spans, root, t_type);
+                        if channel == Arithmetic::C {
+                            arithmetic = Arithmetic::C;
+                        }
+                        root
+                    })
+                    .collect();
+                (roots, arithmetic)
+            }
+            Mode::Float => (roots, Arithmetic::Float),
+        };
+        Ok(Parsed {
+            nodes,
+            spans,
+            parentheses,
+            roots,
+            arithmetic,
+            knobs,
+        })
+    }
+}
+
+/// Left and right sample for one 't'. Mono beats have the same in both
+pub type Stereo = [u8; 2];
+
+/// Samples evaluated at once by [`Beat::eval_stereo_block`], on the stack
+const SCRATCH_LEN: usize = 256;
+
+/// One channel of a [`Beat`]. Each is evaluated on its own, so assignments in one aren't seen by the other
+#[derive(Debug)]
+struct Channel {
+    root: NodeId,
+    /// What's actually evaluated, unless it's too deep. Then the tree is walked.
+    program: Option<Compiled>,
+    /// Used by [`Beat::eval_block`] when the beat allows it
+    lanes: Option<CompiledLanes>,
 }
 
 #[derive(Debug, Default)]
-/// AST of a classic bytebeat function. May be evaluated for 't' into a u8 sample. Can be empty, and produce no sound.
+/// AST of a bytebeat function. May be evaluated for 't' into a u8 sample, or two for a stereo beat written
+/// `[left, right]`. Can be empty, and produce no sound.
 pub struct Beat {
     // Could be a real arena but not practically necessary
     nodes: Vec<ASTNode>,
-    root: NodeId,
+    /// Left first. Empty for an empty beat
+    channels: Vec<Channel>,
+    mode: Mode,
+    /// Decided by the mode, and in classic mode by [`typecheck`]
+    arithmetic: Arithmetic,
+    out_of_bounds: OutOfBounds,
+    rate: SampleRate,
+    /// From [`lint::lint`]
+    warnings: Vec<Warning>,
+    /// As declared, by [`KnobId`]. Where they're turned to now is in `knob_values`
+    knobs: Vec<Knob>,
+    knob_values: KnobValues,
 }
 
 impl Beat {
     /// Attempt to turn a string into an evaluable beat. Empty strings produce silent beats.
-    pub fn compile(source: &str) -> Result<Beat, Vec<ParseError>> {
+    pub fn compile(source: &str, options: &CompileOptions) -> Result<Beat, Vec<ParseError>> {
         if source.is_empty() {
-            Ok(Beat::default())
+            Ok(Beat {
+                mode: options.mode,
+                out_of_bounds: options.out_of_bounds,
+                rate: options.rate,
+                ..Default::default()
+            })
         } else {
-            let mut nodes = Vec::new();
-            let root = Parser::new(source, &mut nodes).parse()?;
-            Ok(Beat { nodes, root })
+            let parsed = Parsed::new(source, options.mode, options.t_type)?;
+            let warnings = lint::lint(&parsed, options.t_type);
+            let Parsed {
+                mut nodes,
+                roots,
+                arithmetic,
+                knobs,
+                ..
+            } = parsed;
+            let knob_values = KnobValues::default();
+            for (id, knob) in knobs.iter().enumerate() {
+                knob_values.set(id, knob.value);
+            }
+            let roots: Vec<_> = roots
+                .into_iter()
+                .map(|root| fold::fold(&mut nodes, root, arithmetic, options.out_of_bounds))
+                .collect();
+            let channels = roots
+                .into_iter()
+                .map(|root| Channel {
+                    root,
+                    program: Compiled::new(&nodes, root, arithmetic, options.out_of_bounds),
+                    lanes: CompiledLanes::new(&nodes, root, arithmetic, options.out_of_bounds),
+                })
+                .collect();
+            Ok(Beat {
+                nodes,
+                channels,
+                mode: options.mode,
+                arithmetic,
+                out_of_bounds: options.out_of_bounds,
+                rate: options.rate,
+                warnings,
+                knobs,
+                knob_values,
+            })
         }
     }
 
+    /// Whatever looked suspicious while compiling. It's played anyway.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn rate(&self) -> SampleRate {
+        self.rate
+    }
+
+    /// Whether it's written `[left, right]`
+    pub fn is_stereo(&self) -> bool {
+        self.channels.len() > 1
+    }
+
+    /// Every knob, turned to where it is now
+    pub fn knobs(&self) -> Vec<Knob> {
+        let mut knobs = self.knobs.clone();
+        for (id, knob) in knobs.iter_mut().enumerate() {
+            knob.value = self.knob_values.get(id);
+        }
+        knobs
+    }
+
+    /// Turns knob `id` as close to `value` as its range allows, and returns where it ended up. Heard from the next
+    /// sample evaluated, without compiling again.
+    pub fn set_knob(&self, id: KnobId, value: f64) -> Option<f64> {
+        let value = self.knobs.get(id)?.fit(value);
+        self.knob_values.set(id, value);
+        Some(value)
+    }
+
+    /// Of the left channel, which is the only one for mono beats
     pub fn eval(&self, t: i32) -> u8 {
+        self.eval_channel(0, t, &self.knob_values.snapshot())
+    }
+
+    pub fn eval_stereo(&self, t: i32) -> Stereo {
+        let knobs = self.knob_values.snapshot();
+        let left = self.eval_channel(0, t, &knobs);
+        match self.is_stereo() {
+            true => [left, self.eval_channel(1, t, &knobs)],
+            false => [left, left],
+        }
+    }
+
+    /// Same as [`Beat::eval`] for every 't' from `t_start` on, wrapping around. Many 't' are evaluated at once
+    /// unless the beat assigns or calls `random()` conditionally.
+    pub fn eval_block(&self, t_start: i32, out: &mut [u8]) {
+        self.eval_channel_block(0, t_start, out, &self.knob_values.snapshot());
+    }
+
+    /// RT safe. Same as [`Beat::eval_stereo`] for every 't' from `t_start` on, like [`Beat::eval_block`]
+    pub fn eval_stereo_block(&self, t_start: i32, out: &mut [Stereo]) {
+        // A knob turned halfway through is heard from the next block
+        let knobs = self.knob_values.snapshot();
+        let mut scratch = [0u8; SCRATCH_LEN];
+        for (chunk, t) in out
+            .chunks_mut(SCRATCH_LEN)
+            .zip((0i32..).step_by(SCRATCH_LEN))
+        {
+            let t = t_start.wrapping_add(t);
+            let samples = &mut scratch[..chunk.len()];
+            self.eval_channel_block(0, t, samples, &knobs);
+            for (frame, &sample) in chunk.iter_mut().zip(samples.iter()) {
+                *frame = [sample, sample];
+            }
+            if self.is_stereo() {
+                self.eval_channel_block(1, t, samples, &knobs);
+                for (frame, &sample) in chunk.iter_mut().zip(samples.iter()) {
+                    frame[1] = sample;
+                }
+            }
+        }
+    }
+
+    /// `knobs` are from [`KnobValues::snapshot`]
+    fn eval_channel(&self, channel: usize, t: i32, knobs: &[f64]) -> u8 {
+        let knobs = &knobs[..self.knobs.len()];
+        match self.channels.get(channel) {
+            Some(Channel {
+                program: Some(program),
+                ..
+            }) => program.eval(t, knobs),
+            Some(channel) => self.eval_tree(channel.root, t, knobs),
+            None => 0,
+        }
+    }
+
+    fn eval_channel_block(&self, channel: usize, t_start: i32, out: &mut [u8], knobs: &[f64]) {
+        match self.channels.get(channel) {
+            Some(Channel {
+                lanes: Some(lanes), ..
+            }) => lanes.eval_block(t_start, out, &knobs[..self.knobs.len()]),
+            _ => {
+                for (sample, t) in out.iter_mut().zip(0..) {
+                    *sample = self.eval_channel(channel, t_start.wrapping_add(t), knobs);
+                }
+            }
+        }
+    }
+
+    /// Reference implementation of [`Beat::eval`], by recursively walking the AST from a channel's `root`
+    fn eval_tree(&self, root: NodeId, t: i32, knobs: &[f64]) -> u8 {
         if self.nodes.is_empty() {
             0
         } else {
-            self.eval_node(self.root, t) as u8
+            match self.arithmetic {
+                Arithmetic::Int => {
+                    let mut frame = Frame::<i32>::new(t, knobs);
+                    self.eval_node(root, &mut frame).to_sample()
+                }
+                Arithmetic::C => {
+                    let mut frame = Frame::<CInt>::new(t, knobs);
+                    self.eval_node(root, &mut frame).to_sample()
+                }
+                Arithmetic::Float => {
+                    let mut frame = Frame::<f64>::new(t, knobs);
+                    self.eval_node(root, &mut frame).to_sample()
+                }
+            }
         }
     }
 
-    fn eval_node(&self, id: NodeId, t: i32) -> i32 {
+    /// Operands are evaluated left to right, so side effects of assignments happen in source order.
+    fn eval_node<V: Value>(&self, id: NodeId, frame: &mut Frame<V>) -> V {
         match &self.nodes[id] {
-            ASTNode::Literal(n) => *n,
-            ASTNode::Variable => t,
+            ASTNode::Literal(n) => V::from_int(*n),
+            ASTNode::TypedLiteral(c) => V::from_typed(*c),
+            ASTNode::FloatLiteral(f) => V::from_float(*f),
+            ASTNode::Variable => frame.get(Place::T),
+            ASTNode::Local(id) => frame.get(Place::Local(*id)),
+            ASTNode::Knob(id) => frame.knob(*id),
+            ASTNode::Assign(op, place, value) => {
+                let current = frame.get(*place);
+                let v = self.eval_node(*value, frame);
+                let v = match op.compound_base() {
+                    Some(base) => V::binary(base, current, v),
+                    None => v,
+                };
+                frame.set(*place, v);
+                v
+            }
+            ASTNode::Unary(op, operand) => {
+                let v = self.eval_node(*operand, frame);
+                V::unary(*op, v)
+            }
+            ASTNode::Cast(ty, operand) => {
+                let v = self.eval_node(*operand, frame);
+                V::cast(*ty, v)
+            }
+            ASTNode::Binary(op @ (Operator::LogAnd | Operator::LogOr), left, right) => {
+                let l: V = self.eval_node(*left, frame);
+                let short_circuit = match op {
+                    Operator::LogAnd => !l.truthy(),
+                    _ => l.truthy(),
+                };
+                // Once the left side decides the result, it's also a stand-in for the right
+                let r = if short_circuit {
+                    l
+                } else {
+                    self.eval_node(*right, frame)
+                };
+                V::binary(*op, l, r)
+            }
             ASTNode::Binary(op, left, right) => {
-                let l = self.eval_node(*left, t);
-                let r = self.eval_node(*right, t);
-                match op {
-                    Operator::Plus => l.wrapping_add(r),
-                    Operator::Minus => l.wrapping_sub(r),
-                    Operator::Mul => l.wrapping_mul(r),
-                    Operator::Div => {
-                        if r == 0 {
-                            0
-                        } else {
-                            l.wrapping_div(r)
-                        }
-                    }
-                    Operator::Mod => {
-                        if r == 0 {
-                            0
-                        } else {
-                            l.wrapping_rem(r)
-                        }
-                    }
-                    Operator::And => l & r,
-                    Operator::Or => l | r,
-                    Operator::BitXor => l ^ r,
-                    Operator::Lsh => l.wrapping_shl(r as u32),
-                    Operator::Rsh => l.wrapping_shr(r as u32),
-                    Operator::LogAnd => {
-                        if l != 0 && r != 0 {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::LogOr => {
-                        if l != 0 || r != 0 {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Eq => {
-                        if l == r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Ne => {
-                        if l != r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Gt => {
-                        if l > r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Lt => {
-                        if l < r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Ge => {
-                        if l >= r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::Le => {
-                        if l <= r {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operator::BitNot => !r,
-                    Operator::LogNot => {
-                        if r == 0 {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    _ => 0,
-                }
+                let l = self.eval_node(*left, frame);
+                let r = self.eval_node(*right, frame);
+                V::binary(*op, l, r)
             }
             ASTNode::Ternary(cond, true_branch, false_branch) => {
-                let c = self.eval_node(*cond, t);
-                if c != 0 {
-                    self.eval_node(*true_branch, t)
+                let c: V = self.eval_node(*cond, frame);
+                if c.truthy() {
+                    self.eval_node(*true_branch, frame)
                 } else {
-                    self.eval_node(*false_branch, t)
+                    self.eval_node(*false_branch, frame)
+                }
+            }
+            ASTNode::Call(Builtin::Random, _) => {
+                let bits = frame.random();
+                V::from_random(bits)
+            }
+            ASTNode::Call(builtin, args) => {
+                let mut values = [V::from_int(0); Builtin::MAX_ARITY];
+                for (value, arg) in values.iter_mut().zip(args) {
+                    *value = self.eval_node(*arg, frame);
+                }
+                V::call(*builtin, &values[..args.len()])
+            }
+            ASTNode::Index(table, index) => match &self.nodes[*table] {
+                ASTNode::Str(s) => {
+                    let index: V = self.eval_node(*index, frame);
+                    self.out_of_bounds
+                        .resolve(index.to_index(), V::str_len(s))
+                        .and_then(|i| V::str_element(s, i))
                 }
+                // Nothing can tell that the other elements were skipped
+                ASTNode::Array(items) if !has_side_effects(&self.nodes, id) => {
+                    let index: V = self.eval_node(*index, frame);
+                    self.out_of_bounds
+                        .resolve(index.to_index(), items.len())
+                        .map(|i| self.eval_node(items[i], frame))
+                }
+                ASTNode::Array(items) => {
+                    // Allocates, but only when the bytecode's stack is too shallow for the beat
+                    let values: Vec<V> = items
+                        .iter()
+                        .map(|item| self.eval_node(*item, frame))
+                        .collect();
+                    let index: V = self.eval_node(*index, frame);
+                    self.out_of_bounds
+                        .resolve(index.to_index(), values.len())
+                        .map(|i| values[i])
+                }
+                _ => None,
+            }
+            .unwrap_or(V::from_int(0)),
+            // Parser only allows these inside an Index
+            ASTNode::Str(_) | ASTNode::Array(_) => {
+                error!("Beat is evaluating an unindexed table. This is a program bug!");
+                V::from_int(0)
             }
             // This shouldn't ever happen!
             ASTNode::Error(_) => {
                 error!("Beat is evaluating an AST that has error nodes. This is a program bug!");
-                0
+                V::from_int(0)
             }
         }
     }
@@ -310,12 +1155,578 @@ mod tests {
         fn generate_sample(song_idx: i32, t: i32) -> u8;
     }
 
-    fn compare_song(song_idx: i32, beat: &str) {
-        let ours = Beat::compile(beat).expect("expected beat to compile in our parser");
+    fn compare_song(song_idx: i32, beat: &str, t_type: TType) {
+        let options = CompileOptions {
+            t_type,
+            ..Default::default()
+        };
+        let ours = Beat::compile(beat, &options).expect("expected beat to compile in our parser");
         for t in 0..SAMPLES_TO_COMPARE {
             assert_eq!(unsafe { generate_sample(song_idx, t) }, ours.eval(t))
         }
     }
 
+    fn float_beat(beat: &str) -> Beat {
+        let options = CompileOptions {
+            mode: Mode::Float,
+            ..Default::default()
+        };
+        Beat::compile(beat, &options).expect("expected floatbeat to compile")
+    }
+
+    #[test]
+    fn test_floatbeat_output_scaling() {
+        let beat = float_beat("t / 4 - 1");
+        assert_eq!(beat.eval(0), 0);
+        assert_eq!(beat.eval(4), 128);
+        assert_eq!(beat.eval(8), 255);
+        // Clipped, not wrapped
+        assert_eq!(beat.eval(1000), 255);
+    }
+
+    #[test]
+    fn test_floatbeat_division_is_not_integer() {
+        let beat = float_beat("(t / 3) % 1 > .3 ? 1 : -1");
+        assert_eq!(beat.eval(0), 0);
+        assert_eq!(beat.eval(1), 255);
+        assert_eq!(beat.eval(2), 255);
+        assert_eq!(beat.eval(3), 0);
+    }
+
+    #[test]
+    fn test_floatbeat_bitwise_like_js() {
+        // (t * 1.5 & 3) -> ToInt32 truncates before the and
+        let beat = float_beat("(t * 1.5 & 3) - 1");
+        assert_eq!(beat.eval(1), 128); // 1.5 -> 1, 1 & 3 = 1, 1 - 1 = 0
+        assert_eq!(beat.eval(2), 255); // 3 & 3 = 3, clipped
+    }
+
+    #[test]
+    fn test_floatbeat_builtins() {
+        let beat = float_beat("sin(t * 3.141592653589793 / 2)");
+        assert_eq!(beat.eval(0), 128);
+        assert_eq!(beat.eval(1), 255);
+        assert_eq!(beat.eval(3), 0);
+
+        let beat = float_beat("Math.min(pow(2, t), 4) / 4");
+        assert_eq!(beat.eval(1), float_beat(".5").eval(0));
+        assert_eq!(beat.eval(10), 255);
+    }
+
+    #[test]
+    fn test_random_repeatable() {
+        let beat = float_beat("random() * 2 - 1");
+        let again = float_beat("random() * 2 - 1");
+        let samples: Vec<u8> = (0..256).map(|t| beat.eval(t)).collect();
+        assert!(samples.iter().zip(0..).all(|(s, t)| *s == again.eval(t)));
+        // It's noise, so it shouldn't be stuck on one value
+        assert!(samples.iter().any(|s| *s != samples[0]));
+    }
+
+    #[test]
+    fn test_classic_builtins() {
+        let beat = Beat::compile("abs(t - 10) + max(t, 3)", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), 13);
+        assert_eq!(beat.eval(12), 14);
+    }
+
+    #[test]
+    fn test_classic_mode_unchanged_by_floats() {
+        let classic = Beat::compile("t / 3", &CompileOptions::default()).unwrap();
+        assert_eq!(classic.eval(5), 1);
+        assert!(Beat::compile("t * 1.5", &CompileOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_locals_start_at_zero_every_eval() {
+        let beat = Beat::compile("a += t; a", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(5), 5);
+        assert_eq!(beat.eval(5), 5);
+        assert_eq!(beat.eval(7), 7);
+    }
+
+    #[test]
+    fn test_short_circuit_skips_side_effects() {
+        let beat = Beat::compile(
+            "a = 1, t && (a = 2), t || (a += 10), a",
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(beat.eval(0), 11);
+        assert_eq!(beat.eval(1), 2);
+
+        // JS yields the deciding operand, and still skips the right side
+        let beat = float_beat("a = .5, b = (t || (a = -1)), b - a");
+        assert_eq!(beat.eval(0), float_beat("-1 - -1").eval(0));
+        assert_eq!(beat.eval(1), float_beat(".5").eval(0));
+    }
+
+    #[test]
+    fn test_string_indexing() {
+        // C gives the character code, and the NUL terminator is in bounds
+        let beat = Beat::compile("\"6689\"[t]", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), b'6');
+        assert_eq!(beat.eval(3), b'9');
+        assert_eq!(beat.eval(4), 0);
+        assert_eq!(beat.eval(5), 0);
+
+        // JavaScript converts the character to a number
+        let beat = float_beat("'0246'[t] / 3 - 1");
+        assert_eq!(beat.eval(0), 0);
+        assert_eq!(beat.eval(3), 255);
+    }
+
+    #[test]
+    fn test_array_indexing() {
+        let beat = Beat::compile("[1, t, 3 * t][t & 3]", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), 1);
+        assert_eq!(beat.eval(1), 1);
+        assert_eq!(beat.eval(2), 6);
+        assert_eq!(beat.eval(3), 0);
+
+        // Every element is evaluated in order, then the index, like C
+        let beat =
+            Beat::compile("a = 1, [a = 5, a += 2][t], a", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), 7);
+        assert_eq!(beat.eval(1), 7);
+        let beat =
+            Beat::compile("a = 1, [a = 5, a += 2][t] + a", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), 12);
+        assert_eq!(beat.eval(1), 14);
+        let beat = Beat::compile("[a, 5][(a = 3) & 0]", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(0), 0);
+    }
+
+    #[test]
+    fn test_out_of_bounds_wrap() {
+        let options = CompileOptions {
+            out_of_bounds: OutOfBounds::Wrap,
+            ..Default::default()
+        };
+        let beat = Beat::compile("[10, 20, 30][t - 1]", &options).unwrap();
+        assert_eq!(beat.eval(0), 30);
+        assert_eq!(beat.eval(3), 30);
+        assert_eq!(beat.eval(4), 10);
+    }
+
+    #[test]
+    fn test_char_literal() {
+        let beat = Beat::compile("t + 'a'", &CompileOptions::default()).unwrap();
+        assert_eq!(beat.eval(1), b'b');
+    }
+
+    #[test]
+    fn test_c_integer_types() {
+        let classic = |source: &str| Beat::compile(source, &CompileOptions::default()).unwrap();
+        assert_eq!(classic("(unsigned char)(t * 3) >> 4").eval(100), 2);
+        assert_eq!(classic("(t * 3) >> 4").eval(100), 18);
+        assert_eq!(classic("-1 < 1u").eval(0), 0);
+        assert_eq!(classic("-1 < 1").eval(0), 1);
+        // Too big for an `int`, so it's `unsigned` like in C
+        assert_eq!(classic("t * 0xCA98CA98 >> 28").eval(1), 12);
+        assert_eq!(classic("1L << 40 >> 38").eval(0), 4);
+        // Both branches have the type of the pair
+        assert_eq!(classic("(t ? -1 : 1u) >> 31").eval(1), 1);
+        // The sum is `unsigned`, but 't' stays an `int`
+        assert_eq!(classic("t += 1u, t >> 31").eval(-2), 255);
+    }
+
+    #[test]
+    fn test_unsigned_t() {
+        let unsigned = |source: &str| {
+            let options = CompileOptions {
+                t_type: TType::Unsigned,
+                ..Default::default()
+            };
+            Beat::compile(source, &options).unwrap()
+        };
+        let int = |source: &str| Beat::compile(source, &CompileOptions::default()).unwrap();
+        assert_eq!(unsigned("t >> 31").eval(-1), 1);
+        assert_eq!(int("t >> 31").eval(-1), 255);
+        assert_eq!(unsigned("t > 5").eval(-1), 1);
+        assert_eq!(int("t > 5").eval(-1), 0);
+        // Locals are still `int`s
+        assert_eq!(unsigned("a = t, a >> 31").eval(-1), 255);
+        // Floatbeats ignore it
+        let options = CompileOptions {
+            mode: Mode::Float,
+            t_type: TType::Unsigned,
+            ..Default::default()
+        };
+        assert!(
+            Beat::compile("t / 2", &options).unwrap().channels[0]
+                .program
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_check_undefined() {
+        let check =
+            |source: &str| check_undefined(source, &CompileOptions::default(), 0..256).unwrap();
+        assert_eq!(
+            check("t / (t & 1)"),
+            vec![Warning::Undefined(
+                Undefined::DivisionByZero,
+                Span::new(0, 2, 2),
+                0
+            )]
+        );
+        assert_eq!(
+            check("t << 31"),
+            vec![Warning::Undefined(
+                Undefined::SignedOverflow,
+                Span::new(0, 2, 3),
+                1
+            )]
+        );
+        // Folding would have hidden this one
+        assert_eq!(
+            check("1 << 31 | t"),
+            vec![Warning::Undefined(
+                Undefined::SignedOverflow,
+                Span::new(0, 2, 3),
+                0
+            )]
+        );
+        // Unselected elements are evaluated too
+        assert_eq!(
+            check("[1 / (t & 0), 2][1]"),
+            vec![Warning::Undefined(
+                Undefined::DivisionByZero,
+                Span::new(0, 3, 3),
+                0
+            )]
+        );
+        assert_eq!(check("t * (t >> 8)"), vec![]);
+        assert_eq!(check("(t * 5u) << 31"), vec![]);
+        let float = CompileOptions {
+            mode: Mode::Float,
+            ..Default::default()
+        };
+        assert_eq!(check_undefined("t / 0", &float, 0..256), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_bytecode_matches_tree_walker() {
+        for song in crate::library_data::SONGS {
+            let options = CompileOptions {
+                mode: song.mode,
+                t_type: song.t_type,
+                ..Default::default()
+            };
+            // The parity tests already report songs we can't compile
+            let Ok(beat) = Beat::compile(song.code, &options) else {
+                continue;
+            };
+            let program = beat.channels[0]
+                .program
+                .as_ref()
+                .expect("library songs should fit the stack");
+            // A couple of bars from the start, and some of the wraparound
+            for t in (0..1 << 14).chain(i32::MAX - 1024..=i32::MAX) {
+                assert_eq!(
+                    program.eval(t, &[]),
+                    beat.eval_tree(beat.channels[0].root, t, &[]),
+                    "{} by {} at t = {t}",
+                    song.name,
+                    song.author
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_folding_matches_unfolded() {
+        for song in crate::library_data::SONGS {
+            let options = CompileOptions {
+                mode: song.mode,
+                t_type: song.t_type,
+                ..Default::default()
+            };
+            let Ok(folded) = Beat::compile(song.code, &options) else {
+                continue;
+            };
+            // Walked as parsed
+            let parsed = Parsed::new(song.code, song.mode, song.t_type).unwrap();
+            let root = parsed.roots[0];
+            let unfolded = Beat {
+                nodes: parsed.nodes,
+                mode: song.mode,
+                arithmetic: parsed.arithmetic,
+                ..Default::default()
+            };
+            for t in (0..SAMPLES_TO_COMPARE).chain(i32::MAX - 256..=i32::MAX) {
+                assert_eq!(
+                    folded.eval(t),
+                    unfolded.eval_tree(root, t, &[]),
+                    "{} by {} at t = {t}",
+                    song.name,
+                    song.author
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        // Long enough to expand, which the library doesn't have
+        let long_call = crate::library_data::Song {
+            name: "long call",
+            code: "t > max(1234567890 + t * 99999, t >> 3 & 1234567 | t * 7654321)",
+            ..crate::library_data::SONGS[0]
+        };
+        for song in crate::library_data::SONGS.iter().chain([&long_call]) {
+            let options = CompileOptions {
+                mode: song.mode,
+                t_type: song.t_type,
+                ..Default::default()
+            };
+            let Ok(beat) = Beat::compile(song.code, &options) else {
+                continue;
+            };
+
+            let mut printed = Vec::new();
+            for style in [
+                PrintStyle::Canonical,
+                PrintStyle::Minify,
+                PrintStyle::Expand,
+            ] {
+                let text = format(song.code, song.mode, style).unwrap();
+                assert_eq!(
+                    format(&text, song.mode, style).unwrap(),
+                    text,
+                    "{style:?} of {}",
+                    song.name
+                );
+                printed.push(text);
+            }
+            // Folded beats have literals the parser doesn't make, like negative numbers
+            let mut nodes = Vec::new();
+            let mut parser = Parser::new(song.code, &mut nodes, song.mode);
+            let root = parser.parse().unwrap();
+            let mut spans = parser.take_spans();
+            let knobs = parser.take_knobs();
+            let locals = parser.into_locals();
+            let (root, arithmetic) = match song.mode {
+                Mode::Classic => typecheck::check(&mut nodes, &mut spans, root, song.t_type),
+                Mode::Float => (root, Arithmetic::Float),
+            };
+            let root = fold::fold(&mut nodes, root, arithmetic, OutOfBounds::default());
+            printed.push(print::print(
+                &nodes,
+                root,
+                &locals,
+                &knobs,
+                PrintStyle::Minify,
+            ));
+
+            for text in printed {
+                let reprinted = Beat::compile(&text, &options).unwrap();
+                for t in (0..1 << 12).chain(i32::MAX - 16..=i32::MAX) {
+                    assert_eq!(reprinted.eval(t), beat.eval(t), "{text} at t = {t}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_block_matches_eval() {
+        let mut block = vec![0; 4099];
+        for song in crate::library_data::SONGS {
+            let options = CompileOptions {
+                mode: song.mode,
+                t_type: song.t_type,
+                ..Default::default()
+            };
+            // The parity tests already report songs we can't compile
+            let Ok(beat) = Beat::compile(song.code, &options) else {
+                continue;
+            };
+            for t_start in [0, 1 << 16, i32::MAX - 2048] {
+                beat.eval_block(t_start, &mut block);
+                for (sample, i) in block.iter().zip(0..) {
+                    let t = t_start.wrapping_add(i);
+                    assert_eq!(
+                        *sample,
+                        beat.eval(t),
+                        "{} by {} at t = {t}",
+                        song.name,
+                        song.author
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_block_without_lanes() {
+        let beat = Beat::compile(
+            "a = 1, t & 4 ? (a = t) : 0, a * random()",
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        assert!(beat.channels[0].lanes.is_none());
+        let mut block = [0; 100];
+        beat.eval_block(7, &mut block);
+        for (sample, t) in block.iter().zip(7..) {
+            assert_eq!(*sample, beat.eval(t));
+        }
+    }
+
+    /// `cargo test --release -- --ignored --nocapture bench_eval_block` to compare with [`Beat::eval`]
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_eval_block() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        const SAMPLES: usize = 1 << 20;
+        let beats: Vec<Beat> = crate::library_data::SONGS
+            .iter()
+            .filter_map(|song| {
+                let options = CompileOptions {
+                    mode: song.mode,
+                    ..Default::default()
+                };
+                Beat::compile(song.code, &options).ok()
+            })
+            .collect();
+        let mut block = vec![0; 1024];
+
+        let start = Instant::now();
+        for beat in &beats {
+            for t in 0..SAMPLES as i32 {
+                black_box(beat.eval(black_box(t)));
+            }
+        }
+        let per_sample = start.elapsed();
+
+        let start = Instant::now();
+        for beat in &beats {
+            for t_start in (0..SAMPLES as i32).step_by(block.len()) {
+                beat.eval_block(black_box(t_start), &mut block);
+                black_box(&block);
+            }
+        }
+        let blocks = start.elapsed();
+
+        let total = (SAMPLES * beats.len()) as f64;
+        println!(
+            "{} library beats, {SAMPLES} samples each\n\
+             eval:       {per_sample:?} ({:.1} Msamples/s)\n\
+             eval_block: {blocks:?} ({:.1} Msamples/s)",
+            beats.len(),
+            total / per_sample.as_secs_f64() / 1e6,
+            total / blocks.as_secs_f64() / 1e6,
+        );
+    }
+
+    #[test]
+    fn test_stereo() {
+        let options = CompileOptions::default();
+        let beat = Beat::compile("[t, a = t * 2, a >> 1]", &options);
+        assert!(
+            matches!(beat, Err(errors) if errors == [ParseError::Channels(3, Span::new(0, 0, 0))])
+        );
+
+        let beat = Beat::compile("[t, (a = t * 3, a)]", &options).unwrap();
+        assert!(beat.is_stereo());
+        assert_eq!(beat.eval_stereo(5), [5, 15]);
+        // Mono is the left channel
+        assert_eq!(beat.eval(5), 5);
+        let mut block = [[0; 2]; 300];
+        beat.eval_stereo_block(-2, &mut block);
+        for (frame, t) in block.iter().zip(-2..) {
+            assert_eq!(*frame, beat.eval_stereo(t));
+        }
+
+        let mono = Beat::compile("[t, t * 2][t & 1]", &options).unwrap();
+        assert!(!mono.is_stereo());
+        assert_eq!(mono.eval_stereo(3), [6, 6]);
+        assert!(Beat::compile("[t, t] + 1", &options).is_err());
+        assert!(Beat::compile("([t, t])", &options).is_err());
+        assert_eq!(
+            format("[t,t>>1]", Mode::Classic, PrintStyle::Canonical).unwrap(),
+            "[t, t >> 1]"
+        );
+    }
+
+    #[test]
+    fn test_knobs() {
+        let options = CompileOptions::default();
+        let beat =
+            Beat::compile("t * $speed + $offset /* $speed = 3 in 1..8 */", &options).unwrap();
+        assert_eq!(beat.eval(2), 6);
+        let knobs = beat.knobs();
+        assert_eq!(knobs[0].max, 8.0);
+        // Undeclared knobs start at the bottom of a byte
+        assert_eq!(knobs[1], knobs::Knob::new("offset", Mode::Classic));
+
+        // Turned while it's shared, like the audio thread does
+        let beat = std::sync::Arc::new(beat);
+        assert_eq!(beat.set_knob(0, 20.0), Some(8.0));
+        assert_eq!(beat.set_knob(1, 1.4), Some(1.0));
+        assert_eq!(beat.set_knob(2, 1.0), None);
+        assert_eq!(beat.eval(2), 17);
+        let mut block = [[0; 2]; 40];
+        beat.eval_stereo_block(0, &mut block);
+        assert!(
+            block
+                .iter()
+                .zip(0..)
+                .all(|(frame, t)| frame[0] == beat.eval(t))
+        );
+        assert!(beat.channels[0].lanes.is_some());
+
+        let float = float_beat("sin(t * $x) /* $x = .5 in 0..2 */");
+        assert_eq!(float.knobs()[0].value, 0.5);
+        assert_eq!(float.eval(3), float_beat("sin(t * .5)").eval(3));
+
+        // Declarations outlive the comments they're in
+        assert_eq!(
+            format(
+                "t*$a // $a = 3 in 0..16",
+                Mode::Classic,
+                PrintStyle::Canonical
+            )
+            .unwrap(),
+            "t * $a /* $a = 3 in 0..16 */"
+        );
+        let check = |source: &str| check_undefined(source, &options, 0..1).unwrap();
+        assert_eq!(check("t / $a").len(), 1);
+        assert_eq!(check("t / $a /* $a = 1 in 1..4 */").len(), 0);
+    }
+
+    #[test]
+    fn test_deep_beat_falls_back_to_tree() {
+        let depth = bytecode::MAX_STACK + 1;
+        let source = format!("{}t{}", "(t+".repeat(depth), ")".repeat(depth));
+        let beat = Beat::compile(&source, &CompileOptions::default()).unwrap();
+        assert!(beat.channels[0].program.is_none());
+        assert_eq!(beat.eval(1), (depth + 1) as u8);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        assert_eq!("44100".parse(), Ok(SampleRate::new(44100).unwrap()));
+        assert_eq!("44.1k".parse(), Ok(SampleRate::new(44100).unwrap()));
+        assert_eq!("11.025K".parse(), Ok(SampleRate::new(11025).unwrap()));
+        assert!("0".parse::<SampleRate>().is_err());
+        assert!("-8000".parse::<SampleRate>().is_err());
+        assert!("fast".parse::<SampleRate>().is_err());
+
+        assert_eq!(SampleRate::CLASSIC.next().hz(), 11025);
+        assert_eq!(SampleRate::new(12000).unwrap().next().hz(), 16000);
+        assert_eq!(SampleRate::new(96000).unwrap().next(), SampleRate::CLASSIC);
+
+        let options = CompileOptions {
+            rate: SampleRate::new(22050).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(Beat::compile("t", &options).unwrap().rate().hz(), 22050);
+        assert_eq!(Beat::compile("", &options).unwrap().rate().hz(), 22050);
+    }
+
     include!(concat!(env!("OUT_DIR"), "/parity_tests.rs"));
 }
@@ -0,0 +1,486 @@
+//! Lowers a [`Beat`]'s AST into a flat, postfix program for a stack machine, so evaluating a sample is a loop
+//! over a slice instead of recursion through the arena. Ternaries, short-circuiting and array elements are jumps,
+//! unless an element has side effects: then every element is evaluated before the index, like C does.
+//!
+//! The tree walker in [`Beat`] stays as the reference implementation, and as the fallback for programs that need
+//! more than [`MAX_STACK`].
+//!
+//! [`Beat`]: super::Beat
+use super::ctype::{CInt, CType};
+use super::eval::{Arithmetic, Frame, Value};
+use super::{ASTNode, Builtin, KnobId, NodeId, Operator, OutOfBounds, Place, has_side_effects};
+
+/// Deepest the value stack of a [`Program`] may get. It lives on the (real) stack during evaluation.
+pub const MAX_STACK: usize = 64;
+
+/// Index into [`Program::code`]
+type Address = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instr<V> {
+    Push(V),
+    Load(Place),
+    Knob(KnobId),
+    /// Assigns the top of the stack, and leaves it there as the result
+    Store(Place),
+    Pop,
+    Unary(Operator),
+    Binary(Operator),
+    Cast(CType),
+    /// Pops [`Builtin::arity`] arguments
+    Call(Builtin),
+    Random,
+    Jump(Address),
+    /// Pops the condition
+    JumpIfFalse(Address),
+    /// For [`Operator::LogAnd`] and [`Operator::LogOr`]. If the left operand on top of the stack decides the
+    /// result, replaces it with the result and jumps. Otherwise the right operand is evaluated next.
+    ShortCircuit(Operator, Address),
+    /// Pops an index and pushes the element of [`Program::strings`]
+    StrIndex(usize),
+    /// Pops an index and jumps to the code of that element of [`Program::arrays`]
+    ArrayIndex(usize),
+    /// Pops an index and that many elements, pushes the indexed element
+    ArraySelect(usize),
+}
+
+/// Every element's code ends with a jump to `end`
+#[derive(Debug, Clone, PartialEq)]
+struct ArrayTable {
+    elements: Vec<Address>,
+    end: Address,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program<V> {
+    code: Vec<Instr<V>>,
+    /// Elements of each string literal, already converted for the mode
+    strings: Vec<Vec<V>>,
+    arrays: Vec<ArrayTable>,
+    out_of_bounds: OutOfBounds,
+}
+
+/// A [`Program`] in the [`Value`] type of its [`Arithmetic`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Compiled {
+    Int(Program<i32>),
+    C(Program<CInt>),
+    Float(Program<f64>),
+}
+
+impl Compiled {
+    /// None if evaluating the AST would need a deeper stack than [`MAX_STACK`]
+    pub fn new(
+        nodes: &[ASTNode],
+        root: NodeId,
+        arithmetic: Arithmetic,
+        out_of_bounds: OutOfBounds,
+    ) -> Option<Compiled> {
+        match arithmetic {
+            Arithmetic::Int => Program::lower(nodes, root, out_of_bounds).map(Compiled::Int),
+            Arithmetic::C => Program::lower(nodes, root, out_of_bounds).map(Compiled::C),
+            Arithmetic::Float => Program::lower(nodes, root, out_of_bounds).map(Compiled::Float),
+        }
+    }
+
+    /// `knobs` by [`KnobId`]
+    pub fn eval(&self, t: i32, knobs: &[f64]) -> u8 {
+        match self {
+            Compiled::Int(program) => program.eval(t, knobs).to_sample(),
+            Compiled::C(program) => program.eval(t, knobs).to_sample(),
+            Compiled::Float(program) => program.eval(t, knobs).to_sample(),
+        }
+    }
+}
+
+impl<V: Value> Program<V> {
+    fn lower(nodes: &[ASTNode], root: NodeId, out_of_bounds: OutOfBounds) -> Option<Self> {
+        let mut lowering = Lowering {
+            nodes,
+            program: Program {
+                code: Vec::new(),
+                strings: Vec::new(),
+                arrays: Vec::new(),
+                out_of_bounds,
+            },
+            depth: 0,
+            max_depth: 0,
+        };
+        lowering.lower(root);
+        (lowering.max_depth <= MAX_STACK).then_some(lowering.program)
+    }
+
+    pub fn eval(&self, t: i32, knobs: &[f64]) -> V {
+        let mut frame = Frame::<V>::new(t, knobs);
+        let mut stack = [V::from_int(0); MAX_STACK];
+        // Points past the top of the stack
+        let mut sp = 0;
+        let mut pc = 0;
+
+        while let Some(instr) = self.code.get(pc) {
+            pc += 1;
+            match *instr {
+                Instr::Push(v) => {
+                    stack[sp] = v;
+                    sp += 1;
+                }
+                Instr::Load(place) => {
+                    stack[sp] = frame.get(place);
+                    sp += 1;
+                }
+                Instr::Knob(id) => {
+                    stack[sp] = frame.knob(id);
+                    sp += 1;
+                }
+                Instr::Store(place) => frame.set(place, stack[sp - 1]),
+                Instr::Pop => sp -= 1,
+                Instr::Unary(op) => stack[sp - 1] = V::unary(op, stack[sp - 1]),
+                Instr::Binary(op) => {
+                    sp -= 1;
+                    stack[sp - 1] = V::binary(op, stack[sp - 1], stack[sp]);
+                }
+                Instr::Cast(ty) => stack[sp - 1] = V::cast(ty, stack[sp - 1]),
+                Instr::Call(builtin) => {
+                    let args = sp - builtin.arity();
+                    let result = V::call(builtin, &stack[args..sp]);
+                    stack[args] = result;
+                    sp = args + 1;
+                }
+                Instr::Random => {
+                    stack[sp] = V::from_random(frame.random());
+                    sp += 1;
+                }
+                Instr::Jump(target) => pc = target,
+                Instr::JumpIfFalse(target) => {
+                    sp -= 1;
+                    if !stack[sp].truthy() {
+                        pc = target;
+                    }
+                }
+                Instr::ShortCircuit(op, target) => {
+                    let l = stack[sp - 1];
+                    let decided = match op {
+                        Operator::LogAnd => !l.truthy(),
+                        _ => l.truthy(),
+                    };
+                    if decided {
+                        // Same stand-in for the right operand as the tree walker
+                        stack[sp - 1] = V::binary(op, l, l);
+                        pc = target;
+                    }
+                }
+                Instr::StrIndex(id) => {
+                    let elements = &self.strings[id];
+                    let index = self
+                        .out_of_bounds
+                        .resolve(stack[sp - 1].to_index(), elements.len());
+                    stack[sp - 1] = index.map_or(V::from_int(0), |i| elements[i]);
+                }
+                Instr::ArrayIndex(id) => {
+                    let table = &self.arrays[id];
+                    sp -= 1;
+                    let index = self
+                        .out_of_bounds
+                        .resolve(stack[sp].to_index(), table.elements.len());
+                    match index {
+                        Some(i) => pc = table.elements[i],
+                        None => {
+                            stack[sp] = V::from_int(0);
+                            sp += 1;
+                            pc = table.end;
+                        }
+                    }
+                }
+                Instr::ArraySelect(len) => {
+                    sp -= len;
+                    let index = self
+                        .out_of_bounds
+                        .resolve(stack[sp + len - 1].to_index(), len);
+                    stack[sp - 1] = index.map_or(V::from_int(0), |i| stack[sp - 1 + i]);
+                }
+            }
+        }
+
+        stack[0]
+    }
+}
+
+struct Lowering<'a, V> {
+    nodes: &'a [ASTNode],
+    program: Program<V>,
+    /// Of the value stack at the current end of the code
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<V: Value> Lowering<'_, V> {
+    fn emit(&mut self, instr: Instr<V>) -> Address {
+        match instr {
+            Instr::Push(_) | Instr::Load(_) | Instr::Knob(_) | Instr::Random => self.depth += 1,
+            Instr::Pop | Instr::Binary(_) | Instr::JumpIfFalse(_) | Instr::ArrayIndex(_) => {
+                self.depth -= 1
+            }
+            // Arguments become the result
+            Instr::Call(builtin) => self.depth = self.depth + 1 - builtin.arity(),
+            Instr::ArraySelect(len) => self.depth -= len,
+            Instr::Store(_)
+            | Instr::Unary(_)
+            | Instr::Cast(_)
+            | Instr::Jump(_)
+            | Instr::ShortCircuit(_, _)
+            | Instr::StrIndex(_) => {}
+        }
+        self.max_depth = self.max_depth.max(self.depth);
+        self.program.code.push(instr);
+        self.program.code.len() - 1
+    }
+
+    fn here(&self) -> Address {
+        self.program.code.len()
+    }
+
+    /// Points a jump emitted earlier at the current end of the code
+    fn patch(&mut self, jump: Address) {
+        let target = self.here();
+        match &mut self.program.code[jump] {
+            Instr::Jump(to) | Instr::JumpIfFalse(to) | Instr::ShortCircuit(_, to) => *to = target,
+            other => unreachable!("Patching {other:?}, which isn't a jump"),
+        }
+    }
+
+    /// Leaves exactly one more value on the stack
+    fn lower(&mut self, id: NodeId) {
+        match &self.nodes[id] {
+            ASTNode::Literal(n) => {
+                self.emit(Instr::Push(V::from_int(*n)));
+            }
+            ASTNode::TypedLiteral(c) => {
+                self.emit(Instr::Push(V::from_typed(*c)));
+            }
+            ASTNode::FloatLiteral(f) => {
+                self.emit(Instr::Push(V::from_float(*f)));
+            }
+            ASTNode::Variable => {
+                self.emit(Instr::Load(Place::T));
+            }
+            ASTNode::Local(local) => {
+                self.emit(Instr::Load(Place::Local(*local)));
+            }
+            ASTNode::Knob(knob) => {
+                self.emit(Instr::Knob(*knob));
+            }
+            ASTNode::Assign(op, place, value) => {
+                match op.compound_base() {
+                    Some(base) => {
+                        self.emit(Instr::Load(*place));
+                        self.lower(*value);
+                        self.emit(Instr::Binary(base));
+                    }
+                    None => self.lower(*value),
+                }
+                self.emit(Instr::Store(*place));
+            }
+            ASTNode::Unary(op, operand) => {
+                self.lower(*operand);
+                self.emit(Instr::Unary(*op));
+            }
+            ASTNode::Cast(ty, operand) => {
+                self.lower(*operand);
+                self.emit(Instr::Cast(*ty));
+            }
+            ASTNode::Binary(op @ (Operator::LogAnd | Operator::LogOr), left, right) => {
+                self.lower(*left);
+                let jump = self.emit(Instr::ShortCircuit(*op, 0));
+                self.lower(*right);
+                self.emit(Instr::Binary(*op));
+                self.patch(jump);
+            }
+            ASTNode::Binary(Operator::Comma | Operator::Semicolon, left, right) => {
+                self.lower(*left);
+                self.emit(Instr::Pop);
+                self.lower(*right);
+            }
+            ASTNode::Binary(op, left, right) => {
+                self.lower(*left);
+                self.lower(*right);
+                self.emit(Instr::Binary(*op));
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                self.lower(*cond);
+                let to_false = self.emit(Instr::JumpIfFalse(0));
+                let depth = self.depth;
+                self.lower(*true_branch);
+                let to_end = self.emit(Instr::Jump(0));
+                self.patch(to_false);
+                self.depth = depth;
+                self.lower(*false_branch);
+                self.patch(to_end);
+            }
+            ASTNode::Call(Builtin::Random, _) => {
+                self.emit(Instr::Random);
+            }
+            ASTNode::Call(builtin, args) => {
+                for arg in args {
+                    self.lower(*arg);
+                }
+                self.emit(Instr::Call(*builtin));
+            }
+            ASTNode::Index(table, index) => match &self.nodes[*table] {
+                ASTNode::Str(s) => {
+                    self.lower(*index);
+                    let elements = (0..V::str_len(s))
+                        .map(|i| V::str_element(s, i).unwrap_or(V::from_int(0)))
+                        .collect();
+                    self.program.strings.push(elements);
+                    self.emit(Instr::StrIndex(self.program.strings.len() - 1));
+                }
+                ASTNode::Array(items) if has_side_effects(self.nodes, id) => {
+                    for item in items {
+                        self.lower(*item);
+                    }
+                    self.lower(*index);
+                    self.emit(Instr::ArraySelect(items.len()));
+                }
+                ASTNode::Array(items) => {
+                    self.lower(*index);
+                    self.lower_array(items);
+                }
+                // Parser doesn't allow this
+                _ => {
+                    self.lower(*index);
+                    self.emit(Instr::Pop);
+                    self.emit(Instr::Push(V::from_int(0)));
+                }
+            },
+            // Parser doesn't allow unindexed tables, and a Beat with errors is never compiled
+            ASTNode::Str(_) | ASTNode::Array(_) | ASTNode::Error(_) => {
+                self.emit(Instr::Push(V::from_int(0)));
+            }
+        }
+    }
+
+    /// Only the selected element is evaluated, which nothing can tell apart when none have side effects
+    fn lower_array(&mut self, items: &[NodeId]) {
+        let id = self.program.arrays.len();
+        self.program.arrays.push(ArrayTable {
+            elements: Vec::with_capacity(items.len()),
+            end: 0,
+        });
+        self.emit(Instr::ArrayIndex(id));
+
+        let depth = self.depth;
+        let mut to_end = Vec::with_capacity(items.len());
+        for item in items {
+            let start = self.here();
+            self.program.arrays[id].elements.push(start);
+            self.depth = depth;
+            self.lower(*item);
+            to_end.push(self.emit(Instr::Jump(0)));
+        }
+        for jump in to_end {
+            self.patch(jump);
+        }
+        // Out of bounds pushes a 0 instead
+        self.depth = depth + 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        self.program.arrays[id].end = self.here();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Mode;
+    use crate::parser::parse::Parser;
+
+    fn lower<V: Value>(source: &str, mode: Mode) -> Program<V> {
+        let mut nodes = Vec::new();
+        let root = Parser::new(source, &mut nodes, mode).parse().unwrap();
+        Program::lower(&nodes, root, OutOfBounds::Zero).unwrap()
+    }
+
+    #[test]
+    fn test_postfix_order() {
+        let program = lower::<i32>("t * 2 + 1", Mode::Classic);
+        assert_eq!(
+            program.code,
+            vec![
+                Instr::Load(Place::T),
+                Instr::Push(2),
+                Instr::Binary(Operator::Mul),
+                Instr::Push(1),
+                Instr::Binary(Operator::Plus),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ternary_jumps() {
+        let program = lower::<i32>("t ? 1 : 2", Mode::Classic);
+        assert_eq!(
+            program.code,
+            vec![
+                Instr::Load(Place::T),
+                Instr::JumpIfFalse(4),
+                Instr::Push(1),
+                Instr::Jump(5),
+                Instr::Push(2),
+            ]
+        );
+        assert_eq!(program.eval(0, &[]), 2);
+        assert_eq!(program.eval(3, &[]), 1);
+    }
+
+    #[test]
+    fn test_array_elements_jump_to_end() {
+        let program = lower::<i32>("[t, 5][t] + 1", Mode::Classic);
+        assert_eq!(program.arrays[0].elements, vec![2, 4]);
+        assert_eq!(program.arrays[0].end, 6);
+        assert_eq!(program.eval(0, &[]), 1);
+        assert_eq!(program.eval(1, &[]), 6);
+        assert_eq!(program.eval(2, &[]), 1);
+    }
+
+    #[test]
+    fn test_array_side_effects_evaluate_every_element() {
+        let program = lower::<i32>("a = 1, [a = 5, a += 2][t] + a", Mode::Classic);
+        assert!(program.arrays.is_empty());
+        assert_eq!(program.eval(0, &[]), 12);
+        assert_eq!(program.eval(1, &[]), 14);
+        assert_eq!(program.eval(2, &[]), 7);
+    }
+
+    #[test]
+    fn test_casts_convert_the_top() {
+        let program = lower::<CInt>("(unsigned char)t + 1u", Mode::Classic);
+        assert_eq!(program.code[1], Instr::Cast(CType::UChar));
+        assert_eq!(program.eval(255, &[]), CInt::new(256, CType::UInt));
+        assert_eq!(program.eval(256, &[]), CInt::new(1, CType::UInt));
+    }
+
+    #[test]
+    fn test_float_strings_are_converted_once() {
+        let program = lower::<f64>("'1 a'[t]", Mode::Float);
+        assert_eq!(program.strings[0][..2], [1.0, 0.0]);
+        assert!(program.strings[0][2].is_nan());
+    }
+
+    #[test]
+    fn test_knobs_are_read_not_stored() {
+        let program = lower::<i32>("$a * t + $b", Mode::Classic);
+        assert_eq!(program.code[0], Instr::Knob(0));
+        assert_eq!(program.eval(2, &[3.0, 1.0]), 7);
+        // Truncated like a C cast
+        assert_eq!(program.eval(2, &[3.9]), 6);
+    }
+
+    #[test]
+    fn test_too_deep_is_refused() {
+        let source = format!("{}t{}", "(t+".repeat(MAX_STACK), ")".repeat(MAX_STACK));
+        let mut nodes = Vec::new();
+        let root = Parser::new(&source, &mut nodes, Mode::Classic)
+            .parse()
+            .unwrap();
+        assert!(Program::<i32>::lower(&nodes, root, OutOfBounds::Zero).is_none());
+    }
+}
@@ -0,0 +1,279 @@
+//! Walks a classic beat like [`Beat`] does, but notices whenever it does something C leaves undefined. [`Beat`]
+//! defines all of it, division by zero is 0 and overflow wraps, but a C compiler may do anything instead.
+//!
+//! It walks the beat before folding, so constant subtrees are checked too. Every value is a [`CInt`], which
+//! behaves exactly like `i32` for beats that are all `int`.
+//!
+//! [`Beat`]: super::Beat
+use super::ctype::CInt;
+use super::eval::{Frame, Value};
+use super::{ASTNode, Builtin, NodeId, Operator, OutOfBounds, Parsed, Place, Undefined, Warning};
+
+/// Evaluates every 't' in `ts`, with the knobs turned to `knobs`. Only the first 't' is kept for each node and kind
+/// of undefined behaviour.
+pub fn check(
+    parsed: &Parsed,
+    out_of_bounds: OutOfBounds,
+    ts: impl Iterator<Item = i32>,
+    knobs: &[f64],
+) -> Vec<Warning> {
+    let mut checker = Checker {
+        parsed,
+        out_of_bounds,
+        t: 0,
+        found: Vec::new(),
+    };
+    for t in ts {
+        checker.t = t;
+        // Channels are evaluated on their own
+        for &root in &parsed.roots {
+            let mut frame = Frame::<CInt>::new(t, knobs);
+            checker.eval(root, &mut frame);
+        }
+    }
+    checker.found
+}
+
+/// Undefined behaviour of `l op r`, if there's any
+pub fn binary(op: Operator, l: CInt, r: CInt) -> Option<Undefined> {
+    match op {
+        Operator::Lsh | Operator::Rsh => {
+            let ty = l.ty();
+            if r.wide() < 0 || r.wide() >= ty.width() as i128 {
+                Some(Undefined::ShiftCount)
+            } else if op == Operator::Rsh || ty.is_unsigned() {
+                None
+            } else if l.wide() < 0 {
+                Some(Undefined::NegativeShift)
+            } else {
+                overflows(l.wide() << r.wide(), l)
+            }
+        }
+        Operator::Plus | Operator::Minus | Operator::Mul | Operator::Div | Operator::Mod => {
+            let ty = l.ty().common(r.ty());
+            let (a, b) = (CInt::cast(ty, l), CInt::cast(ty, r));
+            let (a_wide, b_wide) = (a.wide(), b.wide());
+            let exact = match op {
+                Operator::Plus => a_wide + b_wide,
+                Operator::Minus => a_wide - b_wide,
+                Operator::Mul => a_wide * b_wide,
+                _ if b_wide == 0 => return Some(Undefined::DivisionByZero),
+                // Only `MIN / -1` is too big, and C makes `MIN % -1` undefined along with it
+                _ => a_wide / b_wide,
+            };
+            if ty.is_unsigned() {
+                None
+            } else {
+                overflows(exact, a)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Undefined behaviour of negating `v`, if there's any. Also what `abs` does.
+pub fn negate(v: CInt) -> Option<Undefined> {
+    if v.ty().is_unsigned() {
+        None
+    } else {
+        overflows(-v.wide(), v)
+    }
+}
+
+/// [`Undefined::SignedOverflow`] if `exact` doesn't fit the type of `like`
+fn overflows(exact: i128, like: CInt) -> Option<Undefined> {
+    let fits = i64::try_from(exact).is_ok_and(|bits| CInt::new(bits, like.ty()).wide() == exact);
+    (!fits).then_some(Undefined::SignedOverflow)
+}
+
+struct Checker<'a> {
+    parsed: &'a Parsed,
+    out_of_bounds: OutOfBounds,
+    /// Being evaluated
+    t: i32,
+    found: Vec<Warning>,
+}
+
+impl Checker<'_> {
+    fn record(&mut self, undefined: Option<Undefined>, id: NodeId) {
+        let Some(undefined) = undefined else {
+            return;
+        };
+        let span = self.parsed.spans[id];
+        let known = self.found.iter().any(|warning| {
+            matches!(warning, Warning::Undefined(kind, at, _) if *kind == undefined && *at == span)
+        });
+        if !known {
+            self.found.push(Warning::Undefined(undefined, span, self.t));
+        }
+    }
+
+    /// Same order of evaluation as [`super::Beat`]'s walker
+    fn eval(&mut self, id: NodeId, frame: &mut Frame<CInt>) -> CInt {
+        match &self.parsed.nodes[id] {
+            ASTNode::Literal(n) => CInt::from_int(*n),
+            ASTNode::TypedLiteral(c) => *c,
+            ASTNode::Variable => frame.get(Place::T),
+            ASTNode::Local(local) => frame.get(Place::Local(*local)),
+            ASTNode::Knob(knob) => frame.knob(*knob),
+            ASTNode::Assign(op, place, value) => {
+                let current = frame.get(*place);
+                let v = self.eval(*value, frame);
+                let v = match op.compound_base() {
+                    Some(base) => {
+                        self.record(binary(base, current, v), id);
+                        CInt::binary(base, current, v)
+                    }
+                    None => v,
+                };
+                frame.set(*place, v);
+                v
+            }
+            ASTNode::Unary(op, operand) => {
+                let v = self.eval(*operand, frame);
+                if *op == Operator::Minus {
+                    self.record(negate(v), id);
+                }
+                CInt::unary(*op, v)
+            }
+            ASTNode::Cast(ty, operand) => {
+                let v = self.eval(*operand, frame);
+                CInt::cast(*ty, v)
+            }
+            ASTNode::Binary(op @ (Operator::LogAnd | Operator::LogOr), left, right) => {
+                let l = self.eval(*left, frame);
+                let short_circuit = match op {
+                    Operator::LogAnd => !l.truthy(),
+                    _ => l.truthy(),
+                };
+                let r = if short_circuit {
+                    l
+                } else {
+                    self.eval(*right, frame)
+                };
+                CInt::binary(*op, l, r)
+            }
+            ASTNode::Binary(op, left, right) => {
+                let l = self.eval(*left, frame);
+                let r = self.eval(*right, frame);
+                self.record(binary(*op, l, r), id);
+                CInt::binary(*op, l, r)
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                let c = self.eval(*cond, frame);
+                if c.truthy() {
+                    self.eval(*true_branch, frame)
+                } else {
+                    self.eval(*false_branch, frame)
+                }
+            }
+            ASTNode::Call(Builtin::Random, _) => CInt::from_random(frame.random()),
+            ASTNode::Call(builtin, args) => {
+                let mut values = [CInt::from_int(0); Builtin::MAX_ARITY];
+                for (value, arg) in values.iter_mut().zip(args) {
+                    *value = self.eval(*arg, frame);
+                }
+                if *builtin == Builtin::Abs {
+                    self.record(negate(values[0]), id);
+                }
+                CInt::call(*builtin, &values[..args.len()])
+            }
+            ASTNode::Index(table, index) => {
+                // Every array element is evaluated before the index, like C does
+                let values: Vec<CInt> = match &self.parsed.nodes[*table] {
+                    ASTNode::Array(items) => {
+                        items.iter().map(|item| self.eval(*item, frame)).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let index = self.eval(*index, frame).to_index();
+                let len = match &self.parsed.nodes[*table] {
+                    ASTNode::Str(s) => CInt::str_len(s),
+                    _ => values.len(),
+                };
+                // Whatever --out-of-bounds says, C leaves it undefined
+                let in_bounds = index.is_some_and(|i| (0..len as i64).contains(&i));
+                self.record((!in_bounds).then_some(Undefined::IndexOutOfBounds), id);
+
+                match (
+                    &self.parsed.nodes[*table],
+                    self.out_of_bounds.resolve(index, len),
+                ) {
+                    (ASTNode::Str(s), Some(i)) => {
+                        CInt::str_element(s, i).unwrap_or(CInt::from_int(0))
+                    }
+                    (ASTNode::Array(_), Some(i)) => values[i],
+                    _ => CInt::from_int(0),
+                }
+            }
+            ASTNode::FloatLiteral(_) | ASTNode::Str(_) | ASTNode::Array(_) | ASTNode::Error(_) => {
+                CInt::from_int(0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ctype::CType;
+
+    fn int(n: i32) -> CInt {
+        CInt::from_int(n)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(
+            binary(Operator::Plus, int(i32::MAX), int(1)),
+            Some(Undefined::SignedOverflow)
+        );
+        assert_eq!(binary(Operator::Minus, int(i32::MIN), int(-1)), None);
+        assert_eq!(
+            binary(Operator::Mod, int(i32::MIN), int(-1)),
+            Some(Undefined::SignedOverflow)
+        );
+        assert_eq!(
+            binary(Operator::Div, int(5), int(0)),
+            Some(Undefined::DivisionByZero)
+        );
+        assert_eq!(negate(int(i32::MIN)), Some(Undefined::SignedOverflow));
+        // Unsigned arithmetic wraps in C too
+        let max = CInt::new(u32::MAX as i64, CType::UInt);
+        assert_eq!(binary(Operator::Plus, max, int(1)), None);
+        assert_eq!(binary(Operator::Mul, int(-1), max), None);
+        assert_eq!(
+            binary(Operator::Plus, CInt::new(i64::MAX, CType::Long), int(1)),
+            Some(Undefined::SignedOverflow)
+        );
+    }
+
+    #[test]
+    fn test_shifts() {
+        assert_eq!(
+            binary(Operator::Rsh, int(1), int(32)),
+            Some(Undefined::ShiftCount)
+        );
+        assert_eq!(
+            binary(Operator::Lsh, int(1), int(-1)),
+            Some(Undefined::ShiftCount)
+        );
+        assert_eq!(
+            binary(Operator::Lsh, int(-1), int(1)),
+            Some(Undefined::NegativeShift)
+        );
+        assert_eq!(
+            binary(Operator::Lsh, int(1), int(31)),
+            Some(Undefined::SignedOverflow)
+        );
+        assert_eq!(binary(Operator::Rsh, int(-8), int(1)), None);
+        assert_eq!(
+            binary(Operator::Lsh, CInt::new(1, CType::UInt), int(31)),
+            None
+        );
+        assert_eq!(
+            binary(Operator::Lsh, CInt::new(1, CType::Long), int(40)),
+            None
+        );
+    }
+}
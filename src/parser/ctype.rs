@@ -0,0 +1,403 @@
+//! C's integer types for classic beats that need more than `int`: casts, `U` and `L` literals, literals too big for
+//! `int`, and an `unsigned` 't'. Sizes are those of x86-64 Linux, so `long` is 64 bits.
+//!
+//! [`CInt`] carries its type, and applies C's promotions and usual arithmetic conversions to its operands. Beats
+//! that only ever have `int` values are still evaluated with plain `i32`, see [`super::typecheck`].
+use std::fmt;
+
+use super::eval::Value;
+use super::{ASTNode, Builtin, Operator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CType {
+    /// Signed, like it is on x86
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+}
+
+impl CType {
+    /// Type of a cast from its keywords, like `["unsigned", "char"]`. None if C doesn't have it.
+    pub fn from_keywords(words: &[&str]) -> Option<CType> {
+        let count = |keyword: &str| words.iter().filter(|w| **w == keyword).count();
+        let (signed, unsigned) = (count("signed"), count("unsigned"));
+        let (chars, shorts, ints, longs) =
+            (count("char"), count("short"), count("int"), count("long"));
+        let sizes = (chars > 0) as usize + (shorts > 0) as usize + (longs > 0) as usize;
+        if words.is_empty()
+            || signed + unsigned > 1
+            || chars > 1
+            || shorts > 1
+            || ints > 1
+            || longs > 2
+            || sizes > 1
+            || (chars > 0 && ints > 0)
+            || signed + unsigned + chars + shorts + ints + longs != words.len()
+        {
+            return None;
+        }
+        let ty = match (unsigned > 0, chars > 0, shorts > 0, longs > 0) {
+            (false, true, _, _) => CType::Char,
+            (true, true, _, _) => CType::UChar,
+            (false, _, true, _) => CType::Short,
+            (true, _, true, _) => CType::UShort,
+            (false, _, _, true) => CType::Long,
+            (true, _, _, true) => CType::ULong,
+            (false, ..) => CType::Int,
+            (true, ..) => CType::UInt,
+        };
+        Some(ty)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CType::Char => "char",
+            CType::UChar => "unsigned char",
+            CType::Short => "short",
+            CType::UShort => "unsigned short",
+            CType::Int => "int",
+            CType::UInt => "unsigned",
+            CType::Long => "long",
+            CType::ULong => "unsigned long",
+        }
+    }
+
+    pub fn is_unsigned(self) -> bool {
+        matches!(
+            self,
+            CType::UChar | CType::UShort | CType::UInt | CType::ULong
+        )
+    }
+
+    /// What a value of this type is converted to before any arithmetic. Everything smaller than `int` fits in one.
+    pub fn promoted(self) -> CType {
+        match self {
+            CType::Char | CType::UChar | CType::Short | CType::UShort => CType::Int,
+            ty => ty,
+        }
+    }
+
+    /// Usual arithmetic conversions. `long` can hold every `unsigned`, so the two make a `long`.
+    pub fn common(self, other: CType) -> CType {
+        let (a, b) = (self.promoted(), other.promoted());
+        match a.width().cmp(&b.width()) {
+            std::cmp::Ordering::Greater => a,
+            std::cmp::Ordering::Less => b,
+            std::cmp::Ordering::Equal if a.is_unsigned() => a,
+            std::cmp::Ordering::Equal => b,
+        }
+    }
+
+    /// Reduced to this type like a conversion would, and extended back to 64 bits. Sign extended for signed
+    /// types, zero extended for unsigned ones, and [`CType::ULong`] is reinterpreted.
+    pub fn wrap(self, bits: i64) -> i64 {
+        match self {
+            CType::Char => bits as i8 as i64,
+            CType::UChar => bits as u8 as i64,
+            CType::Short => bits as i16 as i64,
+            CType::UShort => bits as u16 as i64,
+            CType::Int => bits as i32 as i64,
+            CType::UInt => bits as u32 as i64,
+            CType::Long | CType::ULong => bits,
+        }
+    }
+
+    /// Smallest and largest value, before promotion
+    pub fn bounds(self) -> (i128, i128) {
+        let (min, max) = match self {
+            CType::Char => (i8::MIN as i64, i8::MAX as i64),
+            CType::UChar => (0, u8::MAX as i64),
+            CType::Short => (i16::MIN as i64, i16::MAX as i64),
+            CType::UShort => (0, u16::MAX as i64),
+            CType::Int => (i32::MIN as i64, i32::MAX as i64),
+            CType::UInt => (0, u32::MAX as i64),
+            CType::Long => (i64::MIN, i64::MAX),
+            CType::ULong => return (0, u64::MAX as i128),
+        };
+        (min as i128, max as i128)
+    }
+
+    /// In bits, once promoted
+    pub fn width(self) -> u32 {
+        match self {
+            CType::Long | CType::ULong => 64,
+            _ => 32,
+        }
+    }
+}
+
+impl fmt::Display for CType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// An integer of a promoted [`CType`]: `int`, `unsigned`, `long` or `unsigned long`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CInt {
+    /// See [`CType::wrap`]
+    bits: i64,
+    ty: CType,
+}
+
+impl CInt {
+    /// `bits` converted to `ty`, then promoted
+    pub fn new(bits: i64, ty: CType) -> Self {
+        Self {
+            bits: ty.wrap(bits),
+            ty: ty.promoted(),
+        }
+    }
+
+    pub fn ty(self) -> CType {
+        self.ty
+    }
+
+    /// Two's complement bits, as if it were a `long`
+    pub fn bits(self) -> i64 {
+        self.bits
+    }
+
+    /// The value itself, which an `i64` can't hold for every `unsigned long`
+    pub fn wide(self) -> i128 {
+        match self.ty {
+            CType::ULong => self.bits as u64 as i128,
+            _ => self.bits as i128,
+        }
+    }
+
+    fn int(n: i32) -> Self {
+        Self::new(n as i64, CType::Int)
+    }
+
+    fn from_bool(b: bool) -> Self {
+        Self::int(b as i32)
+    }
+
+    /// Converted to `ty`, which must already be promoted
+    fn to(self, ty: CType) -> i128 {
+        CInt::new(self.bits, ty).wide()
+    }
+}
+
+/// Classic bytebeat with C's other integer types. Like `i32`, division by zero is defined as 0, and shifts only use
+/// as many low bits of the count as the width of the type needs, like x86 does.
+impl Value for CInt {
+    fn from_int(n: i32) -> Self {
+        CInt::int(n)
+    }
+
+    fn from_float(f: f64) -> Self {
+        CInt::int(f as i32)
+    }
+
+    fn from_typed(c: CInt) -> Self {
+        c
+    }
+
+    fn from_random(bits: u64) -> Self {
+        CInt::int(i32::from_random(bits))
+    }
+
+    fn truthy(self) -> bool {
+        self.bits != 0
+    }
+
+    fn unary(op: Operator, v: Self) -> Self {
+        match op {
+            Operator::Minus => CInt::new(v.bits.wrapping_neg(), v.ty),
+            Operator::BitNot => CInt::new(!v.bits, v.ty),
+            Operator::LogNot => CInt::from_bool(v.bits == 0),
+            _ => v,
+        }
+    }
+
+    fn binary(op: Operator, l: Self, r: Self) -> Self {
+        match op {
+            Operator::LogAnd => return CInt::from_bool(l.truthy() && r.truthy()),
+            Operator::LogOr => return CInt::from_bool(l.truthy() || r.truthy()),
+            Operator::Comma | Operator::Semicolon => return r,
+            // The type of the left operand, on its own
+            Operator::Lsh | Operator::Rsh => {
+                let count = r.bits as u32 & (l.ty.width() - 1);
+                let shifted = match op {
+                    Operator::Lsh => l.bits << count,
+                    // Arithmetic for signed types, and the unsigned ones are zero extended
+                    _ => (l.wide() >> count) as i64,
+                };
+                return CInt::new(shifted, l.ty);
+            }
+            _ => {}
+        }
+
+        let ty = l.ty.common(r.ty);
+        let (a, b) = (l.to(ty), r.to(ty));
+        let result = match op {
+            Operator::Plus => a + b,
+            Operator::Minus => a - b,
+            Operator::Mul => a.wrapping_mul(b),
+            Operator::Div => a.checked_div(b).unwrap_or(0),
+            Operator::Mod => a.checked_rem(b).unwrap_or(0),
+            Operator::And => a & b,
+            Operator::Or => a | b,
+            Operator::BitXor => a ^ b,
+            Operator::Eq => return CInt::from_bool(a == b),
+            Operator::Ne => return CInt::from_bool(a != b),
+            Operator::Gt => return CInt::from_bool(a > b),
+            Operator::Lt => return CInt::from_bool(a < b),
+            Operator::Ge => return CInt::from_bool(a >= b),
+            Operator::Le => return CInt::from_bool(a <= b),
+            _ => 0,
+        };
+        CInt::new(result as i64, ty)
+    }
+
+    /// Only `int` constants, which can't change the type of the other operand
+    fn is_identity(op: Operator, c: Self, c_left: bool) -> bool {
+        c.ty == CType::Int && i32::is_identity(op, c.bits as i32, c_left)
+    }
+
+    fn to_literal(self) -> ASTNode {
+        match self.ty {
+            CType::Int => ASTNode::Literal(self.bits as i32),
+            _ => ASTNode::TypedLiteral(self),
+        }
+    }
+
+    fn cast(ty: CType, v: Self) -> Self {
+        CInt::new(v.bits, ty)
+    }
+
+    /// `abs` `min` and `max` stay exact, the rest go through f64 and are truncated to an `int` like `i32` does.
+    fn call(builtin: Builtin, args: &[Self]) -> Self {
+        match builtin {
+            Builtin::Abs if args[0].wide() < 0 => CInt::unary(Operator::Minus, args[0]),
+            Builtin::Abs => args[0],
+            Builtin::Min | Builtin::Max => {
+                let ty = args[0].ty.common(args[1].ty);
+                let (a, b) = (CInt::cast(ty, args[0]), CInt::cast(ty, args[1]));
+                let a_wins = match builtin {
+                    Builtin::Min => a.wide() <= b.wide(),
+                    _ => a.wide() >= b.wide(),
+                };
+                if a_wins { a } else { b }
+            }
+            _ => {
+                let mut floats = [0.0; Builtin::MAX_ARITY];
+                for (f, arg) in floats.iter_mut().zip(args) {
+                    *f = arg.wide() as f64;
+                }
+                CInt::from_float(f64::call(builtin, &floats[..args.len()]))
+            }
+        }
+    }
+
+    fn to_sample(self) -> u8 {
+        self.bits as u8
+    }
+
+    fn to_index(self) -> Option<i64> {
+        i64::try_from(self.wide()).ok()
+    }
+
+    fn str_len(s: &str) -> usize {
+        i32::str_len(s)
+    }
+
+    fn str_element(s: &str, index: usize) -> Option<Self> {
+        i32::str_element(s, index).map(CInt::int)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u(n: i64) -> CInt {
+        CInt::new(n, CType::UInt)
+    }
+
+    #[test]
+    fn test_from_keywords() {
+        assert_eq!(CType::from_keywords(&["unsigned"]), Some(CType::UInt));
+        assert_eq!(CType::from_keywords(&["signed", "char"]), Some(CType::Char));
+        assert_eq!(
+            CType::from_keywords(&["long", "unsigned", "int"]),
+            Some(CType::ULong)
+        );
+        assert_eq!(CType::from_keywords(&["long", "long"]), Some(CType::Long));
+        assert_eq!(CType::from_keywords(&["short", "short"]), None);
+        assert_eq!(CType::from_keywords(&["signed", "unsigned"]), None);
+        assert_eq!(CType::from_keywords(&["char", "int"]), None);
+        assert_eq!(CType::from_keywords(&["double"]), None);
+    }
+
+    #[test]
+    fn test_usual_arithmetic_conversions() {
+        assert_eq!(CType::Char.common(CType::UShort), CType::Int);
+        assert_eq!(CType::Int.common(CType::UInt), CType::UInt);
+        assert_eq!(CType::UInt.common(CType::Long), CType::Long);
+        assert_eq!(CType::Long.common(CType::ULong), CType::ULong);
+    }
+
+    #[test]
+    fn test_mixed_signedness() {
+        let minus_one = CInt::from_int(-1);
+        // -1 becomes 4294967295
+        assert_eq!(
+            CInt::binary(Operator::Lt, minus_one, u(1)),
+            CInt::from_int(0)
+        );
+        assert_eq!(CInt::binary(Operator::Div, minus_one, u(2)), u(0x7FFF_FFFF));
+        // ...but a `long` can hold either
+        let long = CInt::new(2, CType::Long);
+        assert_eq!(
+            CInt::binary(Operator::Div, minus_one, long),
+            CInt::new(0, CType::Long)
+        );
+        assert_eq!(CInt::binary(Operator::Mul, u(0x1_0000), u(0x1_0000)), u(0));
+    }
+
+    #[test]
+    fn test_shifts_keep_the_left_type() {
+        assert_eq!(
+            CInt::binary(Operator::Rsh, u(0x8000_0000), CInt::from_int(31)),
+            u(1)
+        );
+        assert_eq!(
+            CInt::binary(Operator::Rsh, CInt::from_int(i32::MIN), u(31)),
+            CInt::from_int(-1)
+        );
+        // Counts are masked to the width
+        assert_eq!(CInt::binary(Operator::Lsh, u(1), CInt::from_int(33)), u(2));
+        assert_eq!(
+            CInt::binary(Operator::Lsh, CInt::new(1, CType::Long), CInt::from_int(33)),
+            CInt::new(1 << 33, CType::Long)
+        );
+    }
+
+    #[test]
+    fn test_casts_wrap() {
+        assert_eq!(
+            CInt::cast(CType::UChar, CInt::from_int(-1)),
+            CInt::from_int(255)
+        );
+        assert_eq!(
+            CInt::cast(CType::Char, CInt::from_int(200)),
+            CInt::from_int(-56)
+        );
+        assert_eq!(
+            CInt::cast(CType::UInt, CInt::from_int(-1)),
+            u(u32::MAX as i64)
+        );
+        assert_eq!(
+            CInt::cast(CType::ULong, CInt::from_int(-1)).wide(),
+            u64::MAX as i128
+        );
+    }
+}
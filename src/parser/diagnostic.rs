@@ -0,0 +1,433 @@
+//! Errors and warnings rendered against the source they were found in: the line is echoed with a caret under the
+//! [`Span`], other places that explain it are pointed out, and a [`Fix`] is suggested when there's an obvious one.
+//!
+//! Rendering only makes text, one [`Rendered`] line at a time, so the CLI can print it and the TUI can style it.
+use std::fmt::Write;
+
+use super::{Builtin, Lint, Operator, ParseError, Span, Warning};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// What a rendered line shows, so it can be styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// What went wrong, and where
+    Message,
+    /// A line of the source, as it is or with the fix applied
+    Source,
+    /// Carets under the [`Diagnostic::span`]
+    Primary,
+    /// Dashes under one of the [`Diagnostic::notes`]
+    Secondary,
+    /// Suggestion, or tildes under what the fix changed
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub part: Part,
+    pub text: String,
+}
+
+/// Replaces the text at a [`Span`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl Fix {
+    pub fn apply(&self, source: &str) -> String {
+        let mut fixed = String::with_capacity(source.len() + self.replacement.len());
+        for (number, line) in source.split_inclusive('\n').enumerate() {
+            if number != self.span.line {
+                fixed.push_str(line);
+                continue;
+            }
+            for (col, c) in line.chars().enumerate() {
+                if col == self.span.start {
+                    fixed.push_str(&self.replacement);
+                }
+                if !(self.span.start..=self.span.end).contains(&col) {
+                    fixed.push(c);
+                }
+            }
+        }
+        fixed
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// Next to the carets
+    pub label: Option<String>,
+    /// Other places that explain it, like the '(' that a ')' is missing for
+    pub notes: Vec<(Span, String)>,
+    pub help: Option<String>,
+    /// Shown with the help, applied to the source
+    pub fix: Option<Fix>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: String, span: Span) -> Self {
+        Self {
+            severity,
+            message,
+            span,
+            label: None,
+            notes: Vec::new(),
+            help: None,
+            fix: None,
+        }
+    }
+
+    fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    fn note(mut self, span: Span, note: &str) -> Self {
+        self.notes.push((span, note.to_owned()));
+        self
+    }
+
+    fn help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    fn fix(mut self, span: Span, replacement: &str) -> Self {
+        self.fix = Some(Fix {
+            span,
+            replacement: replacement.to_owned(),
+        });
+        self
+    }
+
+    /// `=` where a comparison makes more sense
+    fn suggest_equals(self, span: Span) -> Self {
+        self.help("did you mean '=='?".to_owned()).fix(span, "==")
+    }
+
+    /// `source` is what the error was found in
+    pub fn error(error: &ParseError, source: &str) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Error, error.to_string(), error_span(error));
+        match error {
+            ParseError::UnexpectedEof(_) => diagnostic.label("expected more after this"),
+            ParseError::ExpectedOperator(found, span, previous) => {
+                let diagnostic = diagnostic.label("expected an operator before this");
+                let found = text(source, *span).unwrap_or_else(|| found.to_string());
+                match text(source, *previous) {
+                    Some(previous) => diagnostic.help(format!(
+                        "insert an operator between '{previous}' and '{found}'"
+                    )),
+                    None => diagnostic,
+                }
+            }
+            ParseError::UnmatchedParenthesis(_, open) => diagnostic
+                .label("expected ')' here")
+                .note(*open, "to match this '('"),
+            ParseError::UnexpectedPrefix(..) => diagnostic.label("expected an operand here"),
+            ParseError::UnexpectedToken(..) => diagnostic.label("nothing to close here"),
+            ParseError::ExpectedTernaryColon(_, question) => diagnostic
+                .label("expected ':' here")
+                .note(*question, "to go with this '?'"),
+            ParseError::UnknownIdentifier(name, span) => match closest_builtin(name) {
+                Some(builtin) => diagnostic
+                    .help(format!("did you mean '{builtin}'?"))
+                    .fix(*span, builtin.name()),
+                None => diagnostic,
+            },
+            ParseError::InvalidAssignment(op, span) => {
+                let diagnostic = diagnostic.label("the left side isn't 't' or a variable");
+                match op {
+                    Operator::Assign => diagnostic.suggest_equals(*span),
+                    _ => diagnostic,
+                }
+            }
+            ParseError::UnmatchedBracket(_, open) => diagnostic
+                .label("expected ']' here")
+                .note(*open, "to match this '['"),
+            ParseError::FloatInClassic(_)
+            | ParseError::TooManyLocals(..)
+            | ParseError::TooManyKnobs(..)
+            | ParseError::ExpectedCall(..)
+            | ParseError::WrongArity(..)
+            | ParseError::NotIndexable(_)
+            | ParseError::Unindexed(_)
+            | ParseError::Channels(..)
+            | ParseError::InvalidCast(..)
+            | ParseError::LexError(..) => diagnostic,
+        }
+    }
+
+    pub fn warning(warning: &Warning) -> Self {
+        let diagnostic = Diagnostic::new(Severity::Warning, warning.to_string(), warning.span());
+        match warning {
+            Warning::Lint(Lint::AssignInCondition, span) => diagnostic.suggest_equals(*span),
+            Warning::Lint(..) | Warning::Undefined(..) => diagnostic,
+        }
+    }
+
+    /// Every line of `source` that's pointed at is echoed, in order, with its markers under it
+    pub fn render(&self, source: &str) -> Vec<Rendered> {
+        let prefix = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        let mut rendered = vec![Rendered {
+            part: Part::Message,
+            text: format!("{prefix}: {}", self.message),
+        }];
+
+        let primary = (
+            self.span,
+            self.label.as_deref().unwrap_or(""),
+            Part::Primary,
+        );
+        let notes = self
+            .notes
+            .iter()
+            .map(|(span, note)| (*span, note.as_str(), Part::Secondary));
+        let mut marks: Vec<_> = std::iter::once(primary).chain(notes).collect();
+        // Stable, so the carets stay above the notes on their line
+        marks.sort_by_key(|(span, ..)| span.line);
+        let mut echoed = None;
+        for (span, label, part) in marks {
+            let line = source_line(source, span.line);
+            if echoed != Some(span.line) {
+                rendered.push(source_rendered(line));
+                echoed = Some(span.line);
+            }
+            let marker = if part == Part::Primary { '^' } else { '-' };
+            rendered.push(Rendered {
+                part,
+                text: marked(line, span, marker, label),
+            });
+        }
+
+        if let Some(help) = &self.help {
+            rendered.push(Rendered {
+                part: Part::Help,
+                text: format!(" = help: {help}"),
+            });
+        }
+        if let Some(fix) = &self.fix {
+            let fixed = fix.apply(source);
+            let line = source_line(&fixed, fix.span.line);
+            let changed = Span::new(
+                fix.span.line,
+                fix.span.start,
+                fix.span.start + fix.replacement.chars().count().max(1) - 1,
+            );
+            rendered.push(source_rendered(line));
+            rendered.push(Rendered {
+                part: Part::Help,
+                text: marked(line, changed, '~', ""),
+            });
+        }
+        rendered
+    }
+
+    /// [`Diagnostic::render`] as plain text, one line after another
+    pub fn render_text(&self, source: &str) -> String {
+        let mut text = String::new();
+        for line in self.render(source) {
+            let _ = writeln!(text, "{}", line.text);
+        }
+        text
+    }
+}
+
+fn error_span(error: &ParseError) -> Span {
+    match error {
+        ParseError::UnexpectedEof(span)
+        | ParseError::ExpectedOperator(_, span, _)
+        | ParseError::UnmatchedParenthesis(span, _)
+        | ParseError::UnexpectedPrefix(_, span)
+        | ParseError::UnexpectedToken(_, span)
+        | ParseError::ExpectedTernaryColon(span, _)
+        | ParseError::FloatInClassic(span)
+        | ParseError::UnknownIdentifier(_, span)
+        | ParseError::InvalidAssignment(_, span)
+        | ParseError::TooManyLocals(_, span)
+        | ParseError::TooManyKnobs(_, span)
+        | ParseError::ExpectedCall(_, span)
+        | ParseError::WrongArity(.., span)
+        | ParseError::UnmatchedBracket(span, _)
+        | ParseError::NotIndexable(span)
+        | ParseError::Unindexed(span)
+        | ParseError::Channels(_, span)
+        | ParseError::InvalidCast(_, span)
+        | ParseError::LexError(_, span) => *span,
+    }
+}
+
+/// Empty past the end of the source, where the end of input may be
+fn source_line(source: &str, line: usize) -> &str {
+    source.lines().nth(line).unwrap_or("")
+}
+
+fn source_rendered(line: &str) -> Rendered {
+    Rendered {
+        part: Part::Source,
+        text: format!(" | {line}"),
+    }
+}
+
+/// What's at the span, if it's in the source and not just its end
+fn text(source: &str, span: Span) -> Option<String> {
+    let text: String = source_line(source, span.line)
+        .chars()
+        .skip(span.start)
+        .take(span.end + 1 - span.start)
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
+
+/// `marker` under every column of the span, then the label. Tabs before it are kept so the columns line up.
+fn marked(line: &str, span: Span, marker: char, label: &str) -> String {
+    let mut text = String::from(" | ");
+    text.extend(
+        line.chars()
+            .chain(std::iter::repeat(' '))
+            .take(span.start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' }),
+    );
+    text.extend(std::iter::repeat_n(marker, span.end + 1 - span.start));
+    if !label.is_empty() {
+        text.push(' ');
+        text.push_str(label);
+    }
+    text
+}
+
+/// Only calls are unknown identifiers, so it's probably a misspelled [`Builtin`]
+fn closest_builtin(name: &str) -> Option<Builtin> {
+    Builtin::ALL
+        .into_iter()
+        .map(|builtin| (distance(name, builtin.name()), builtin))
+        .filter(|(distance, builtin)| *distance <= 2 && *distance < builtin.name().len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, builtin)| builtin)
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Mode, parse::Parser};
+
+    fn first_error(source: &str) -> Diagnostic {
+        let mut arena = Vec::new();
+        let errors = Parser::new(source, &mut arena, Mode::Classic)
+            .parse()
+            .unwrap_err();
+        Diagnostic::error(&errors[0], source)
+    }
+
+    fn texts(rendered: &[Rendered]) -> Vec<&str> {
+        rendered.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_caret_under_span() {
+        let rendered = first_error("t 3").render("t 3");
+        assert_eq!(
+            texts(&rendered),
+            vec![
+                "Error: Expected operator, found number 3 at line 0 col 2",
+                " | t 3",
+                " |   ^ expected an operator before this",
+                " = help: insert an operator between 't' and '3'",
+            ]
+        );
+        assert_eq!(rendered[2].part, Part::Primary);
+    }
+
+    #[test]
+    fn test_notes_point_at_the_opening() {
+        let source = "(t * (2 + t)";
+        assert_eq!(
+            texts(&first_error(source).render(source))[1..],
+            [
+                " | (t * (2 + t)",
+                " |             ^ expected ')' here",
+                " | - to match this '('",
+            ]
+        );
+        let source = "t ? 1\n  + 2";
+        assert_eq!(
+            texts(&first_error(source).render(source))[1..],
+            [
+                " | t ? 1",
+                " |   - to go with this '?'",
+                " |   + 2",
+                " |      ^ expected ':' here",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fixes() {
+        let source = "t % 8 = 0 ? t : 0";
+        let diagnostic = first_error(source);
+        let fix = diagnostic.fix.clone().unwrap();
+        assert_eq!(fix.apply(source), "t % 8 == 0 ? t : 0");
+        assert_eq!(
+            texts(&diagnostic.render(source))[3..],
+            [
+                " = help: did you mean '=='?",
+                " | t % 8 == 0 ? t : 0",
+                " |       ~~",
+            ]
+        );
+        // Only the line that's fixed is changed
+        let source = "a = 1;\nt * sine(t)";
+        let fix = first_error(source).fix.unwrap();
+        assert_eq!(fix.apply(source), "a = 1;\nt * sin(t)");
+        assert_eq!(first_error("t * blorp(t)").fix, None);
+        assert_eq!(first_error("t += 1 += 2").fix, None);
+    }
+
+    #[test]
+    fn test_tabs_line_up() {
+        let source = "\tt 3";
+        assert_eq!(
+            texts(&first_error(source).render(source))[2],
+            " | \t  ^ expected an operator before this"
+        );
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("sine", "sin"), 1);
+        assert_eq!(distance("flor", "floor"), 1);
+        assert_eq!(distance("abs", "abs"), 0);
+        assert_eq!(distance("", "pow"), 3);
+        assert_eq!(closest_builtin("sqr"), Some(Builtin::Sqrt));
+        assert_eq!(closest_builtin("f"), None);
+    }
+}
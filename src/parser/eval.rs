@@ -0,0 +1,533 @@
+//! Per-[`Mode`] arithmetic for [`super::Beat`]. The AST walker is generic over [`Value`], so both modes share
+//! one arena and one walker and only differ in how a single operator is applied. Classic beats that need C's other
+//! integer types use [`CInt`], which lives in its own module.
+//!
+//! [`Mode`]: super::Mode
+use super::ctype::{CInt, CType};
+use super::{ASTNode, Builtin, KnobId, MAX_KNOBS, MAX_LOCALS, Operator, Place};
+
+/// Which [`Value`] a beat is evaluated with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// `i32`, for classic beats where every value is an `int`
+    #[default]
+    Int,
+    /// [`CInt`], for classic beats with any other C type
+    C,
+    /// `f64`, for floatbeats
+    Float,
+}
+
+/// Mutable state for a single evaluation. Lives on the stack so evaluating never allocates.
+#[derive(Debug)]
+pub struct Frame<V> {
+    /// May be assigned to, so it's not necessarily the 't' we started with
+    t: V,
+    locals: [V; MAX_LOCALS],
+    /// Where each knob was turned to when evaluation started
+    knobs: [V; MAX_KNOBS],
+    /// The 't' we started with
+    seed_t: i32,
+    random_calls: u64,
+}
+
+/// Arbitrary, but fixed so every run of a beat sounds the same
+const SEED: u64 = 0xB17E_BEA7;
+
+/// 64 random bits, decided by the starting 't' and how many calls came before during this evaluation (from 1).
+pub fn random_bits(seed_t: i32, call: u64) -> u64 {
+    // splitmix64 finalizer
+    let mut z = SEED ^ (seed_t as u32 as u64) ^ (call << 32);
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl<V: Value> Frame<V> {
+    /// `knobs` by [`KnobId`]. Any past the end are 0
+    pub fn new(t: i32, knobs: &[f64]) -> Self {
+        Self {
+            t: V::from_int(t),
+            locals: [V::from_int(0); MAX_LOCALS],
+            knobs: std::array::from_fn(|id| V::from_float(knobs.get(id).copied().unwrap_or(0.0))),
+            seed_t: t,
+            random_calls: 0,
+        }
+    }
+
+    pub fn get(&self, place: Place) -> V {
+        match place {
+            Place::T => self.t,
+            Place::Local(id) => self.locals[id],
+        }
+    }
+
+    pub fn set(&mut self, place: Place, value: V) {
+        match place {
+            Place::T => self.t = value,
+            Place::Local(id) => self.locals[id] = value,
+        }
+    }
+
+    pub fn knob(&self, id: KnobId) -> V {
+        self.knobs[id]
+    }
+
+    /// See [`random_bits`]
+    pub fn random(&mut self) -> u64 {
+        self.random_calls += 1;
+        random_bits(self.seed_t, self.random_calls)
+    }
+}
+
+/// Intermediate type of an evaluation mode.
+pub trait Value: Copy + std::fmt::Debug {
+    fn from_int(n: i32) -> Self;
+    fn from_float(f: f64) -> Self;
+    /// Literal of a C type other than `int`
+    fn from_typed(c: CInt) -> Self;
+    /// Turns [`Frame::random`] into whatever `random()` returns in this mode
+    fn from_random(bits: u64) -> Self;
+    /// Is this 'true' for the ternary and logical operators?
+    fn truthy(self) -> bool;
+    /// Any operator of [`ASTNode::Unary`]
+    fn unary(op: Operator, v: Self) -> Self;
+    /// [`Operator::Comma`] and [`Operator::Semicolon`] yield `r`. Short-circuiting is up to the caller.
+    fn binary(op: Operator, l: Self, r: Self) -> Self;
+    /// Is `x op c` exactly `x` for every `x`? Or `c op x`, when `c_left`.
+    fn is_identity(op: Operator, c: Self, c_left: bool) -> bool;
+    /// Node that evaluates to this
+    fn to_literal(self) -> ASTNode;
+    /// Converts like a C cast of `v` to `ty` would
+    fn cast(ty: CType, v: Self) -> Self;
+    /// Any [`Builtin`] besides [`Builtin::Random`]. `args` has exactly [`Builtin::arity`] members.
+    fn call(builtin: Builtin, args: &[Self]) -> Self;
+    /// Map the final result of an evaluation to an audio sample
+    fn to_sample(self) -> u8;
+    /// Integer part, if there is one, to index a table with
+    fn to_index(self) -> Option<i64>;
+    /// How many elements a string literal has
+    fn str_len(s: &str) -> usize;
+    /// Element `index` of a string literal, or None if it's past the end
+    fn str_element(s: &str, index: usize) -> Option<Self>;
+}
+
+fn from_bool(b: bool) -> i32 {
+    if b { 1 } else { 0 }
+}
+
+/// Classic bytebeat: C semantics on i32, except division by zero is defined as 0.
+impl Value for i32 {
+    fn from_int(n: i32) -> Self {
+        n
+    }
+
+    /// Parser refuses float literals in classic mode, but truncating is what C would do anyway.
+    fn from_float(f: f64) -> Self {
+        f as i32
+    }
+
+    /// Beats with these aren't evaluated as `i32`, but its bits would be the same.
+    fn from_typed(c: CInt) -> Self {
+        c.bits() as i32
+    }
+
+    /// Like POSIX `random()`: 0 to 2^31 - 1
+    fn from_random(bits: u64) -> Self {
+        (bits >> 33) as i32
+    }
+
+    fn truthy(self) -> bool {
+        self != 0
+    }
+
+    #[inline]
+    fn unary(op: Operator, v: Self) -> Self {
+        match op {
+            Operator::Minus => v.wrapping_neg(),
+            Operator::BitNot => !v,
+            Operator::LogNot => from_bool(v == 0),
+            _ => v,
+        }
+    }
+
+    #[inline]
+    fn binary(op: Operator, l: Self, r: Self) -> Self {
+        match op {
+            Operator::Plus => l.wrapping_add(r),
+            Operator::Minus => l.wrapping_sub(r),
+            Operator::Mul => l.wrapping_mul(r),
+            Operator::Div => {
+                if r == 0 {
+                    0
+                } else {
+                    l.wrapping_div(r)
+                }
+            }
+            Operator::Mod => {
+                if r == 0 {
+                    0
+                } else {
+                    l.wrapping_rem(r)
+                }
+            }
+            Operator::And => l & r,
+            Operator::Or => l | r,
+            Operator::BitXor => l ^ r,
+            Operator::Lsh => l.wrapping_shl(r as u32),
+            Operator::Rsh => l.wrapping_shr(r as u32),
+            Operator::LogAnd => from_bool(l != 0 && r != 0),
+            Operator::LogOr => from_bool(l != 0 || r != 0),
+            Operator::Eq => from_bool(l == r),
+            Operator::Ne => from_bool(l != r),
+            Operator::Gt => from_bool(l > r),
+            Operator::Lt => from_bool(l < r),
+            Operator::Ge => from_bool(l >= r),
+            Operator::Le => from_bool(l <= r),
+            Operator::Comma | Operator::Semicolon => r,
+            _ => 0,
+        }
+    }
+
+    fn is_identity(op: Operator, c: Self, c_left: bool) -> bool {
+        match (op, c) {
+            (Operator::Plus | Operator::Or | Operator::BitXor, 0) | (Operator::Mul, 1) => true,
+            (Operator::And, -1) => true,
+            (Operator::Minus | Operator::Lsh | Operator::Rsh, 0) | (Operator::Div, 1) => !c_left,
+            _ => false,
+        }
+    }
+
+    fn to_literal(self) -> ASTNode {
+        ASTNode::Literal(self)
+    }
+
+    /// Exact for `int` and the types smaller than it, which are all a beat of `i32` may cast to.
+    fn cast(ty: CType, v: Self) -> Self {
+        ty.wrap(v as i64) as i32
+    }
+
+    /// Integer functions stay exact, the rest go through f64 and get truncated back like a C cast.
+    fn call(builtin: Builtin, args: &[Self]) -> Self {
+        match builtin {
+            Builtin::Abs => args[0].wrapping_abs(),
+            Builtin::Min => args[0].min(args[1]),
+            Builtin::Max => args[0].max(args[1]),
+            _ => {
+                let mut floats = [0.0; Builtin::MAX_ARITY];
+                for (f, arg) in floats.iter_mut().zip(args) {
+                    *f = *arg as f64;
+                }
+                f64::call(builtin, &floats[..args.len()]) as i32
+            }
+        }
+    }
+
+    fn to_sample(self) -> u8 {
+        self as u8
+    }
+
+    fn to_index(self) -> Option<i64> {
+        Some(self as i64)
+    }
+
+    /// Bytes, and the NUL terminator C would put at the end
+    fn str_len(s: &str) -> usize {
+        s.len() + 1
+    }
+
+    /// `char` is signed, like it is on x86
+    fn str_element(s: &str, index: usize) -> Option<Self> {
+        if index == s.len() {
+            Some(0)
+        } else {
+            s.as_bytes().get(index).map(|b| *b as i8 as i32)
+        }
+    }
+}
+
+/// JavaScript's ToInt32, which bitwise operators apply to their operands.
+fn to_int32(f: f64) -> i32 {
+    if f.is_finite() {
+        f.trunc().rem_euclid(4_294_967_296.0) as u32 as i32
+    } else {
+        0
+    }
+}
+
+fn from_bool_f(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
+
+/// Floatbeat: JavaScript number semantics like the Dollchan composer uses.
+///
+/// Bitwise operators go through i32, division by zero is infinite, and `&&` `||` yield an operand
+/// rather than 0 or 1.
+impl Value for f64 {
+    fn from_int(n: i32) -> Self {
+        n as f64
+    }
+
+    fn from_float(f: f64) -> Self {
+        f
+    }
+
+    fn from_typed(c: CInt) -> Self {
+        c.wide() as f64
+    }
+
+    /// Like `Math.random()`: 0.0 to 1.0, exclusive
+    fn from_random(bits: u64) -> Self {
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn truthy(self) -> bool {
+        self != 0.0 && !self.is_nan()
+    }
+
+    #[inline]
+    fn unary(op: Operator, v: Self) -> Self {
+        match op {
+            Operator::Minus => -v,
+            Operator::BitNot => !to_int32(v) as f64,
+            Operator::LogNot => from_bool_f(!v.truthy()),
+            _ => v,
+        }
+    }
+
+    #[inline]
+    fn binary(op: Operator, l: Self, r: Self) -> Self {
+        match op {
+            Operator::Plus => l + r,
+            Operator::Minus => l - r,
+            Operator::Mul => l * r,
+            Operator::Div => l / r,
+            // Same as JS: truncated remainder with the sign of the dividend
+            Operator::Mod => l % r,
+            Operator::And => (to_int32(l) & to_int32(r)) as f64,
+            Operator::Or => (to_int32(l) | to_int32(r)) as f64,
+            Operator::BitXor => (to_int32(l) ^ to_int32(r)) as f64,
+            Operator::Lsh => to_int32(l).wrapping_shl(to_int32(r) as u32) as f64,
+            Operator::Rsh => to_int32(l).wrapping_shr(to_int32(r) as u32) as f64,
+            Operator::LogAnd => {
+                if l.truthy() {
+                    r
+                } else {
+                    l
+                }
+            }
+            Operator::LogOr => {
+                if l.truthy() {
+                    l
+                } else {
+                    r
+                }
+            }
+            Operator::Eq => from_bool_f(l == r),
+            Operator::Ne => from_bool_f(l != r),
+            Operator::Gt => from_bool_f(l > r),
+            Operator::Lt => from_bool_f(l < r),
+            Operator::Ge => from_bool_f(l >= r),
+            Operator::Le => from_bool_f(l <= r),
+            Operator::Comma | Operator::Semicolon => r,
+            _ => 0.0,
+        }
+    }
+
+    /// Fewer than in classic mode: bitwise operators truncate, and `-0 + 0` is `0`
+    fn is_identity(op: Operator, c: Self, c_left: bool) -> bool {
+        match op {
+            Operator::Mul => c == 1.0,
+            Operator::Div => c == 1.0 && !c_left,
+            // `x - -0` is `x + 0`
+            Operator::Minus => c == 0.0 && c.is_sign_positive() && !c_left,
+            _ => false,
+        }
+    }
+
+    fn to_literal(self) -> ASTNode {
+        ASTNode::FloatLiteral(self)
+    }
+
+    /// JavaScript has no casts, and the parser doesn't allow them. This is what a typed array would store.
+    fn cast(ty: CType, v: Self) -> Self {
+        Self::from_typed(CInt::new(to_int32(v) as i64, ty))
+    }
+
+    fn call(builtin: Builtin, args: &[Self]) -> Self {
+        match builtin {
+            Builtin::Sin => args[0].sin(),
+            Builtin::Cos => args[0].cos(),
+            Builtin::Tan => args[0].tan(),
+            Builtin::Asin => args[0].asin(),
+            Builtin::Acos => args[0].acos(),
+            Builtin::Atan => args[0].atan(),
+            Builtin::Atan2 => args[0].atan2(args[1]),
+            Builtin::Exp => args[0].exp(),
+            Builtin::Log => args[0].ln(),
+            Builtin::Log2 => args[0].log2(),
+            Builtin::Pow => args[0].powf(args[1]),
+            Builtin::Sqrt => args[0].sqrt(),
+            Builtin::Cbrt => args[0].cbrt(),
+            Builtin::Abs => args[0].abs(),
+            Builtin::Floor => args[0].floor(),
+            Builtin::Ceil => args[0].ceil(),
+            // JS rounds halves up, not away from zero
+            Builtin::Round => (args[0] + 0.5).floor(),
+            Builtin::Trunc => args[0].trunc(),
+            // Unlike f64::min, JS doesn't ignore NaN
+            Builtin::Min if args[0].is_nan() || args[1].is_nan() => f64::NAN,
+            Builtin::Min => args[0].min(args[1]),
+            Builtin::Max if args[0].is_nan() || args[1].is_nan() => f64::NAN,
+            Builtin::Max => args[0].max(args[1]),
+            // Walker handles this, it needs the frame
+            Builtin::Random => 0.0,
+        }
+    }
+
+    /// -1.0..1.0 is spread over the whole u8 range. Anything outside is clipped, NaN is silence.
+    fn to_sample(self) -> u8 {
+        if self.is_nan() {
+            128
+        } else {
+            ((self.clamp(-1.0, 1.0) + 1.0) * 127.5).round() as u8
+        }
+    }
+
+    /// JavaScript wouldn't find anything at a fractional index, but beats index with `t / 1000` all the time.
+    fn to_index(self) -> Option<i64> {
+        self.is_finite().then(|| self.trunc() as i64)
+    }
+
+    fn str_len(s: &str) -> usize {
+        s.chars().count()
+    }
+
+    /// Like JavaScript's `Number()` on a one-character string: digits are their value, whitespace is 0
+    fn str_element(s: &str, index: usize) -> Option<Self> {
+        s.chars().nth(index).map(|c| match c.to_digit(10) {
+            Some(digit) => digit as f64,
+            None if c.is_whitespace() => 0.0,
+            None => f64::NAN,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_int32_wraps_like_js() {
+        assert_eq!(to_int32(1.9), 1);
+        assert_eq!(to_int32(-1.9), -1);
+        assert_eq!(to_int32(4_294_967_296.0 + 5.0), 5);
+        assert_eq!(to_int32(2_147_483_648.0), i32::MIN);
+        assert_eq!(to_int32(f64::NAN), 0);
+        assert_eq!(to_int32(f64::INFINITY), 0);
+    }
+
+    #[test]
+    fn test_float_sample_mapping() {
+        assert_eq!((-1.0_f64).to_sample(), 0);
+        assert_eq!(1.0_f64.to_sample(), 255);
+        assert_eq!(0.0_f64.to_sample(), 128);
+        assert_eq!(7.0_f64.to_sample(), 255);
+        assert_eq!(f64::NAN.to_sample(), 128);
+    }
+
+    #[test]
+    fn test_float_logical_yields_operand() {
+        assert_eq!(f64::binary(Operator::LogAnd, 2.0, 3.0), 3.0);
+        assert_eq!(f64::binary(Operator::LogAnd, 0.0, 3.0), 0.0);
+        assert_eq!(f64::binary(Operator::LogOr, 0.0, 3.0), 3.0);
+        assert_eq!(f64::binary(Operator::LogOr, 2.0, 3.0), 2.0);
+    }
+
+    #[test]
+    fn test_random_is_deterministic_and_in_range() {
+        for t in 0..1000 {
+            assert_eq!(
+                Frame::<i32>::new(t, &[]).random(),
+                Frame::<i32>::new(t, &[]).random()
+            );
+        }
+        let mut frame = Frame::<i32>::new(5, &[]);
+        let first = frame.random();
+        let second = frame.random();
+        assert_ne!(first, second, "calls within one evaluation should differ");
+
+        for t in 0..1000 {
+            let mut frame = Frame::<f64>::new(t, &[]);
+            let f = f64::from_random(frame.random());
+            assert!((0.0..1.0).contains(&f));
+            assert!(i32::from_random(frame.random()) >= 0);
+        }
+    }
+
+    #[test]
+    fn test_random_ignores_assigned_t() {
+        let mut frame = Frame::<i32>::new(7, &[]);
+        let expected = Frame::<i32>::new(7, &[]).random();
+        frame.set(Place::T, 100);
+        assert_eq!(frame.random(), expected);
+    }
+
+    #[test]
+    fn test_builtins_classic_truncate() {
+        assert_eq!(i32::call(Builtin::Sqrt, &[17]), 4);
+        assert_eq!(i32::call(Builtin::Pow, &[2, 10]), 1024);
+        assert_eq!(i32::call(Builtin::Abs, &[i32::MIN]), i32::MIN);
+        assert_eq!(i32::call(Builtin::Min, &[-3, 2]), -3);
+        // C would give (int)sin(t), which is mostly 0
+        assert_eq!(i32::call(Builtin::Sin, &[3]), 0);
+    }
+
+    #[test]
+    fn test_builtins_float_like_js() {
+        assert_eq!(f64::call(Builtin::Round, &[-2.5]), -2.0);
+        assert_eq!(f64::call(Builtin::Round, &[2.5]), 3.0);
+        assert!(f64::call(Builtin::Max, &[f64::NAN, 1.0]).is_nan());
+        assert_eq!(f64::call(Builtin::Floor, &[-0.5]), -1.0);
+    }
+
+    #[test]
+    fn test_string_elements() {
+        assert_eq!(i32::str_len("héllo"), 7);
+        assert_eq!(i32::str_element("a\u{ff}", 1), Some(-61));
+        assert_eq!(i32::str_element("ab", 2), Some(0));
+        assert_eq!(i32::str_element("ab", 3), None);
+
+        assert_eq!(f64::str_len("héllo"), 5);
+        assert_eq!(f64::str_element("12 4", 1), Some(2.0));
+        assert_eq!(f64::str_element("12 4", 2), Some(0.0));
+        assert!(f64::str_element("ab", 0).unwrap().is_nan());
+        assert_eq!(f64::str_element("ab", 2), None);
+    }
+
+    #[test]
+    fn test_float_bitwise_truncates() {
+        assert_eq!(f64::binary(Operator::And, 7.9, 3.2), 3.0);
+        assert_eq!(f64::binary(Operator::Rsh, -8.5, 1.0), -4.0);
+        assert_eq!(f64::unary(Operator::BitNot, 0.5), -1.0);
+    }
+
+    #[test]
+    fn test_negation() {
+        assert_eq!(i32::unary(Operator::Minus, i32::MIN), i32::MIN);
+        assert_eq!(i32::unary(Operator::LogNot, -3), 0);
+        // Unlike `0 - x`, like JS
+        assert!(f64::unary(Operator::Minus, 0.0).is_sign_negative());
+    }
+
+    #[test]
+    fn test_identities() {
+        assert!(i32::is_identity(Operator::Or, 0, true));
+        assert!(i32::is_identity(Operator::Rsh, 0, false));
+        assert!(!i32::is_identity(Operator::Rsh, 0, true));
+        assert!(!f64::is_identity(Operator::Or, 0.0, false));
+        assert!(f64::is_identity(Operator::Minus, 0.0, false));
+        assert!(!f64::is_identity(Operator::Minus, -0.0, false));
+    }
+}
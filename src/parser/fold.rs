@@ -0,0 +1,325 @@
+//! Simplifies a parsed AST before it's lowered, so work that's the same for every 't' is done once at compile time.
+//!
+//! Constant subtrees become literals, computed with the same [`Value`] arithmetic as evaluation, so a folded beat
+//! sounds bit-for-bit the same. Identities like `x|0` are dropped, constant ternary conditions leave only the branch
+//! that would be evaluated, and constant table indices only the element, unless the others have side effects.
+//!
+//! Nodes are rewritten in place. Whatever is no longer reachable from the new root stays in the arena, unused.
+use std::marker::PhantomData;
+
+use super::ctype::CInt;
+use super::eval::{Arithmetic, Value};
+use super::{ASTNode, Builtin, NodeId, Operator, OutOfBounds, has_side_effects};
+
+/// Returns the new root
+pub fn fold(
+    nodes: &mut [ASTNode],
+    root: NodeId,
+    arithmetic: Arithmetic,
+    out_of_bounds: OutOfBounds,
+) -> NodeId {
+    match arithmetic {
+        Arithmetic::Int => Folder::<i32>::new(nodes, out_of_bounds).fold(root),
+        Arithmetic::C => Folder::<CInt>::new(nodes, out_of_bounds).fold(root),
+        Arithmetic::Float => Folder::<f64>::new(nodes, out_of_bounds).fold(root),
+    }
+}
+
+struct Folder<'a, V> {
+    nodes: &'a mut [ASTNode],
+    out_of_bounds: OutOfBounds,
+    value: PhantomData<V>,
+}
+
+impl<'a, V: Value> Folder<'a, V> {
+    fn new(nodes: &'a mut [ASTNode], out_of_bounds: OutOfBounds) -> Self {
+        Self {
+            nodes,
+            out_of_bounds,
+            value: PhantomData,
+        }
+    }
+
+    fn constant(&self, id: NodeId) -> Option<V> {
+        match self.nodes[id] {
+            ASTNode::Literal(n) => Some(V::from_int(n)),
+            ASTNode::TypedLiteral(c) => Some(V::from_typed(c)),
+            ASTNode::FloatLiteral(f) => Some(V::from_float(f)),
+            _ => None,
+        }
+    }
+
+    /// Replaces the node with a literal
+    fn literal(&mut self, id: NodeId, value: V) -> NodeId {
+        self.nodes[id] = value.to_literal();
+        id
+    }
+
+    /// Folds the children of the node first. Returns the node to use in its place.
+    fn fold(&mut self, id: NodeId) -> NodeId {
+        match self.nodes[id].clone() {
+            ASTNode::Unary(op, operand) => {
+                let operand = self.fold(operand);
+                self.nodes[id] = ASTNode::Unary(op, operand);
+                match self.constant(operand) {
+                    Some(v) => self.literal(id, V::unary(op, v)),
+                    None => id,
+                }
+            }
+            ASTNode::Cast(ty, operand) => {
+                let operand = self.fold(operand);
+                self.nodes[id] = ASTNode::Cast(ty, operand);
+                match self.constant(operand) {
+                    Some(v) => self.literal(id, V::cast(ty, v)),
+                    None => id,
+                }
+            }
+            ASTNode::Assign(op, place, value) => {
+                let value = self.fold(value);
+                self.nodes[id] = ASTNode::Assign(op, place, value);
+                id
+            }
+            ASTNode::Binary(op, left, right) => {
+                let left = self.fold(left);
+                let right = self.fold(right);
+                self.nodes[id] = ASTNode::Binary(op, left, right);
+                self.binary(id, op, left, right)
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                let cond = self.fold(cond);
+                match self.constant(cond) {
+                    Some(c) if c.truthy() => self.fold(true_branch),
+                    Some(_) => self.fold(false_branch),
+                    None => {
+                        let true_branch = self.fold(true_branch);
+                        let false_branch = self.fold(false_branch);
+                        self.nodes[id] = ASTNode::Ternary(cond, true_branch, false_branch);
+                        id
+                    }
+                }
+            }
+            // Different every call
+            ASTNode::Call(Builtin::Random, _) => id,
+            ASTNode::Call(builtin, args) => {
+                let args: Vec<NodeId> = args.into_iter().map(|arg| self.fold(arg)).collect();
+                let values: Option<Vec<V>> = args.iter().map(|arg| self.constant(*arg)).collect();
+                self.nodes[id] = ASTNode::Call(builtin, args);
+                match values {
+                    Some(values) => self.literal(id, V::call(builtin, &values)),
+                    None => id,
+                }
+            }
+            ASTNode::Index(table, index) => {
+                let index = self.fold(index);
+                if let ASTNode::Array(items) = self.nodes[table].clone() {
+                    let items = items.into_iter().map(|item| self.fold(item)).collect();
+                    self.nodes[table] = ASTNode::Array(items);
+                }
+                self.nodes[id] = ASTNode::Index(table, index);
+                match self.constant(index) {
+                    Some(i) => self.index(id, table, i),
+                    None => id,
+                }
+            }
+            ASTNode::Literal(_)
+            | ASTNode::TypedLiteral(_)
+            | ASTNode::FloatLiteral(_)
+            | ASTNode::Variable
+            | ASTNode::Local(_)
+            | ASTNode::Knob(_)
+            | ASTNode::Str(_)
+            | ASTNode::Array(_)
+            | ASTNode::Error(_) => id,
+        }
+    }
+
+    /// Operands are already folded
+    fn binary(&mut self, id: NodeId, op: Operator, left: NodeId, right: NodeId) -> NodeId {
+        match (op, self.constant(left), self.constant(right)) {
+            (Operator::LogAnd | Operator::LogOr, Some(l), r) => {
+                let decided = match op {
+                    Operator::LogAnd => !l.truthy(),
+                    _ => l.truthy(),
+                };
+                // Same stand-in for the right operand as the tree walker
+                match (decided, r) {
+                    (true, _) => self.literal(id, V::binary(op, l, l)),
+                    (false, Some(r)) => self.literal(id, V::binary(op, l, r)),
+                    (false, None) => id,
+                }
+            }
+            // Could still skip side effects of the right operand
+            (Operator::LogAnd | Operator::LogOr, None, _) => id,
+            (Operator::Comma | Operator::Semicolon, Some(_), _) => right,
+            (_, Some(l), Some(r)) => self.literal(id, V::binary(op, l, r)),
+            (_, Some(l), None) if V::is_identity(op, l, true) => right,
+            (_, None, Some(r)) if V::is_identity(op, r, false) => left,
+            _ => id,
+        }
+    }
+
+    /// Only the selected element is kept, if dropping the others can't be noticed
+    fn index(&mut self, id: NodeId, table: NodeId, index: V) -> NodeId {
+        let element = match &self.nodes[table] {
+            ASTNode::Str(s) => self
+                .out_of_bounds
+                .resolve(index.to_index(), V::str_len(s))
+                .and_then(|i| V::str_element(s, i)),
+            ASTNode::Array(items) => {
+                let selected = self.out_of_bounds.resolve(index.to_index(), items.len());
+                let dropped_effects = items
+                    .iter()
+                    .enumerate()
+                    .any(|(i, item)| Some(i) != selected && has_side_effects(self.nodes, *item));
+                match selected {
+                    _ if dropped_effects => return id,
+                    Some(i) => return items[i],
+                    None => None,
+                }
+            }
+            // Parser doesn't allow this
+            _ => return id,
+        };
+        self.literal(id, element.unwrap_or(V::from_int(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ctype::CType;
+    use crate::parser::{Mode, Parsed, TType};
+
+    fn folded(source: &str, mode: Mode) -> (Vec<ASTNode>, NodeId) {
+        let Parsed {
+            mut nodes,
+            roots,
+            arithmetic,
+            ..
+        } = Parsed::new(source, mode, TType::Int).unwrap();
+        let root = fold(&mut nodes, roots[0], arithmetic, OutOfBounds::Zero);
+        (nodes, root)
+    }
+
+    fn folded_classic(source: &str) -> ASTNode {
+        let (nodes, root) = folded(source, Mode::Classic);
+        nodes[root].clone()
+    }
+
+    #[test]
+    fn test_constants_fold() {
+        assert_eq!(folded_classic("8*1000/8"), ASTNode::Literal(1000));
+        assert_eq!(folded_classic("(1<<12)"), ASTNode::Literal(4096));
+        assert_eq!(
+            folded_classic("-(2147483647 + 1)"),
+            ASTNode::Literal(i32::MIN)
+        );
+        assert_eq!(folded_classic("~0 + !0 + -1"), ASTNode::Literal(-1));
+        assert_eq!(folded_classic("1/0"), ASTNode::Literal(0));
+        assert_eq!(folded_classic("pow(2, 3 + 1)"), ASTNode::Literal(16));
+        assert_eq!(folded_classic("'a'"), ASTNode::Literal(97));
+    }
+
+    #[test]
+    fn test_constants_fold_inside() {
+        let (nodes, root) = folded("t * (2 + 3)", Mode::Classic);
+        let ASTNode::Binary(Operator::Mul, l, r) = nodes[root] else {
+            panic!("Top structure wrong: {:?}", nodes[root]);
+        };
+        assert_eq!(nodes[l], ASTNode::Variable);
+        assert_eq!(nodes[r], ASTNode::Literal(5));
+    }
+
+    #[test]
+    fn test_identities_dropped() {
+        assert_eq!(folded_classic("t|0"), ASTNode::Variable);
+        assert_eq!(folded_classic("0|t*1+0"), ASTNode::Variable);
+        assert_eq!(folded_classic("(t>>0)/1-0"), ASTNode::Variable);
+        assert!(matches!(
+            folded_classic("0-t"),
+            ASTNode::Binary(Operator::Minus, _, _)
+        ));
+        assert!(matches!(
+            folded_classic("1<<t"),
+            ASTNode::Binary(Operator::Lsh, _, _)
+        ));
+
+        // `|0` truncates floats
+        let (nodes, root) = folded("t/3|0", Mode::Float);
+        assert!(matches!(nodes[root], ASTNode::Binary(Operator::Or, _, _)));
+        let (nodes, root) = folded("t*1", Mode::Float);
+        assert_eq!(nodes[root], ASTNode::Variable);
+    }
+
+    #[test]
+    fn test_dead_branches() {
+        assert_eq!(folded_classic("1 ? t : t*2"), ASTNode::Variable);
+        assert_eq!(folded_classic("(4>>3) ? a = 1 : t"), ASTNode::Variable);
+        assert_eq!(folded_classic("0 && (a = t)"), ASTNode::Literal(0));
+        assert_eq!(folded_classic("3 || random()"), ASTNode::Literal(1));
+        assert_eq!(folded_classic("[t, 2, 3][0]"), ASTNode::Variable);
+        assert_eq!(folded_classic("[t, 2, 3][3]"), ASTNode::Literal(0));
+        assert_eq!(folded_classic("\"ab\"[1]"), ASTNode::Literal(98));
+        assert_eq!(folded_classic("1, t"), ASTNode::Variable);
+    }
+
+    #[test]
+    fn test_side_effects_kept() {
+        assert!(matches!(
+            folded_classic("t && 0"),
+            ASTNode::Binary(Operator::LogAnd, _, _)
+        ));
+        assert!(matches!(
+            folded_classic("a = 2 * 3"),
+            ASTNode::Assign(Operator::Assign, _, _)
+        ));
+        assert!(matches!(
+            folded_classic("random() | 0 * 4"),
+            ASTNode::Call(Builtin::Random, _)
+        ));
+        assert!(matches!(
+            folded_classic("(a = 1, 5) ? t : 0"),
+            ASTNode::Ternary(_, _, _)
+        ));
+        assert!(matches!(
+            folded_classic("[a = 1, t][1]"),
+            ASTNode::Index(_, _)
+        ));
+        assert!(matches!(
+            folded_classic("[a = 1, t][2]"),
+            ASTNode::Index(_, _)
+        ));
+        // The selected element is evaluated either way
+        assert!(matches!(
+            folded_classic("[t, a = 1][1]"),
+            ASTNode::Assign(Operator::Assign, _, _)
+        ));
+    }
+
+    #[test]
+    fn test_casts_fold() {
+        assert_eq!(folded_classic("(unsigned char)300"), ASTNode::Literal(44));
+        assert_eq!(
+            folded_classic("(short)-40000 >> 1"),
+            ASTNode::Literal(12768)
+        );
+        assert_eq!(
+            folded_classic("-1u >> 4"),
+            ASTNode::TypedLiteral(CInt::new(0x0FFF_FFFF, CType::UInt))
+        );
+        assert_eq!(folded_classic("(int)(1L << 40 | 7)"), ASTNode::Literal(7));
+        // `0u` would make it unsigned
+        assert!(matches!(
+            folded_classic("t | 0u"),
+            ASTNode::Binary(Operator::Or, _, _)
+        ));
+    }
+
+    #[test]
+    fn test_float_folds_like_js() {
+        let (nodes, root) = folded("1/-0", Mode::Float);
+        assert_eq!(nodes[root], ASTNode::FloatLiteral(f64::NEG_INFINITY));
+        let (nodes, root) = folded("sin(0) + 1.5", Mode::Float);
+        assert_eq!(nodes[root], ASTNode::FloatLiteral(1.5));
+    }
+}
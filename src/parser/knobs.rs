@@ -0,0 +1,320 @@
+//! Knobs are the `$name`s of a beat, turned while it plays. Where each one starts and how far it turns is declared
+//! in a comment, like `/* $speed = 3 in 0..16 */`, so the beat text still sounds the same wherever it's played.
+//!
+//! Several declarations can share a comment, separated by `,` or `;`. Anything else in a comment is left alone.
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{KnobId, MAX_KNOBS, Mode};
+
+/// Fraction of a knob's range [`Knob::step`] turns it by
+const STEPS: f64 = 100.0;
+/// Floatbeat knobs are rounded to this many decimal places
+const DECIMALS: i32 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Knob {
+    /// Without the `$`
+    pub name: String,
+    pub value: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Classic beats only get whole numbers, since they're truncated to an `int` anyway
+    pub whole: bool,
+}
+
+impl Knob {
+    /// For a knob that isn't declared. It starts at 0, and turns through a byte for classic beats or to 1 for
+    /// floatbeats.
+    pub fn new(name: &str, mode: Mode) -> Self {
+        let max = match mode {
+            Mode::Classic => 255.0,
+            Mode::Float => 1.0,
+        };
+        Self {
+            name: name.to_owned(),
+            value: 0.0,
+            min: 0.0,
+            max,
+            whole: mode == Mode::Classic,
+        }
+    }
+
+    /// `value` within the range, rounded. NaN is the bottom of the range
+    pub fn fit(&self, value: f64) -> f64 {
+        let value = if value.is_nan() { self.min } else { value };
+        let value = match self.whole {
+            true => value.round(),
+            false => {
+                let scale = 10f64.powi(DECIMALS);
+                (value * scale).round() / scale
+            }
+        };
+        value.clamp(self.min, self.max)
+    }
+
+    /// How far one press turns it. Never less than 1 for whole numbers
+    pub fn step(&self) -> f64 {
+        let step = (self.max - self.min) / STEPS;
+        match self.whole {
+            true => step.round().max(1.0),
+            false => step,
+        }
+    }
+
+    /// Where `value` is from the bottom of the range to the top, 0 to 1
+    pub fn position(&self) -> f64 {
+        match self.max > self.min {
+            true => (self.value - self.min) / (self.max - self.min),
+            false => 0.0,
+        }
+    }
+
+    /// The value at `position`, like [`Knob::position`] gives
+    pub fn at(&self, position: f64) -> f64 {
+        self.fit(self.min + position.clamp(0.0, 1.0) * (self.max - self.min))
+    }
+}
+
+/// How it's declared
+impl fmt::Display for Knob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "${} = {} in {}..{}",
+            self.name, self.value, self.min, self.max
+        )
+    }
+}
+
+/// Every knob declared in `source`, in the order they're written. The first declaration of a name wins.
+pub fn declared(source: &str, mode: Mode) -> Vec<Knob> {
+    let mut knobs: Vec<Knob> = Vec::new();
+    for (_, knob) in declarations(source, mode) {
+        if !knobs.iter().any(|k| k.name == knob.name) {
+            knobs.push(knob);
+        }
+    }
+    knobs
+}
+
+/// What each of `names` starts at, declared in `source` or not
+pub fn resolve(names: &[String], source: &str, mode: Mode) -> Vec<Knob> {
+    let declared = declared(source, mode);
+    names
+        .iter()
+        .map(|name| {
+            declared
+                .iter()
+                .find(|knob| knob.name == *name)
+                .cloned()
+                .unwrap_or_else(|| Knob::new(name, mode))
+        })
+        .collect()
+}
+
+/// `source` with every one of `knobs` declared as it is now. Declarations are rewritten where they are, and any
+/// that aren't there yet are added in a comment at the end.
+pub fn declare(source: &str, knobs: &[Knob]) -> String {
+    // Any mode will do, only where they are is needed
+    let found = declarations(source, Mode::Float);
+    let mut out = String::with_capacity(source.len());
+    let mut copied = 0;
+    let mut missing: Vec<&Knob> = knobs.iter().collect();
+    for (range, old) in found {
+        let Some(i) = missing.iter().position(|knob| knob.name == old.name) else {
+            continue;
+        };
+        out.push_str(&source[copied..range.start]);
+        out.push_str(&missing.remove(i).to_string());
+        copied = range.end;
+    }
+    out.push_str(&source[copied..]);
+    if !missing.is_empty() {
+        let missing: Vec<String> = missing.iter().map(|knob| knob.to_string()).collect();
+        if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+            out.push(' ');
+        }
+        out.push_str(&format!("/* {} */", missing.join(", ")));
+    }
+    out
+}
+
+/// Byte range of every declaration in a comment, and what it declares
+fn declarations(source: &str, mode: Mode) -> Vec<(Range<usize>, Knob)> {
+    let mut found = Vec::new();
+    for body in comments(source) {
+        let mut start = body.start;
+        for part in source[body.clone()].split([',', ';']) {
+            let range = start..start + part.len();
+            start = range.end + 1;
+            // Only the declaration is replaced, not the whitespace around it
+            let trimmed = part.trim_start();
+            let range =
+                range.start + part.len() - trimmed.len()..range.start + part.trim_end().len();
+            if let Some(knob) = parse(trimmed.trim_end(), mode) {
+                found.push((range, knob));
+            }
+        }
+    }
+    found
+}
+
+/// `$name = value`, then maybe `in min..max`
+fn parse(text: &str, mode: Mode) -> Option<Knob> {
+    let (name, rest) = text.strip_prefix('$')?.split_once('=')?;
+    let name = name.trim();
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if name.is_empty() || !valid {
+        return None;
+    }
+    let mut knob = Knob::new(name, mode);
+    let (value, range) = match rest.split_once(" in ") {
+        Some((value, range)) => (value, Some(range)),
+        None => (rest, None),
+    };
+    if let Some(range) = range {
+        let (min, max) = range.split_once("..")?;
+        let (min, max) = (number(min)?, number(max)?);
+        if min > max {
+            return None;
+        }
+        (knob.min, knob.max) = (min, max);
+    }
+    knob.value = knob.fit(number(value)?);
+    Some(knob)
+}
+
+fn number(text: &str) -> Option<f64> {
+    text.trim().parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// Byte ranges of what's inside every `/* */` and `//` comment. Quotes are skipped, like the lexer does.
+fn comments(source: &str) -> Vec<Range<usize>> {
+    let mut found = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                while let Some((_, next)) = chars.next_if(|&(_, next)| next != '\n') {
+                    if next == c {
+                        break;
+                    }
+                }
+            }
+            '/' => match chars.peek() {
+                Some((_, '*')) => {
+                    let start = i + 2;
+                    let end = source[start..]
+                        .find("*/")
+                        .map_or(source.len(), |end| start + end);
+                    found.push(start..end);
+                    while chars.next_if(|&(j, _)| j < end + 2).is_some() {}
+                }
+                Some((_, '/')) => {
+                    let start = i + 2;
+                    let end = source[start..]
+                        .find('\n')
+                        .map_or(source.len(), |end| start + end);
+                    found.push(start..end);
+                    while chars.next_if(|&(j, _)| j < end).is_some() {}
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    found
+}
+
+/// Where every knob of a [`super::Beat`] is turned to now. Shared with the audio thread, so turning one is a store
+/// and evaluation never waits for the UI.
+#[derive(Debug, Default)]
+pub struct KnobValues([AtomicU64; MAX_KNOBS]);
+
+impl KnobValues {
+    pub fn get(&self, id: KnobId) -> f64 {
+        f64::from_bits(self.0[id].load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, id: KnobId, value: f64) {
+        self.0[id].store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// RT safe. Every knob at once, so a whole block is evaluated with the same values
+    pub fn snapshot(&self) -> [f64; MAX_KNOBS] {
+        std::array::from_fn(|id| self.get(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn knob(name: &str, value: f64, min: f64, max: f64) -> Knob {
+        Knob {
+            name: name.to_owned(),
+            value,
+            min,
+            max,
+            whole: true,
+        }
+    }
+
+    #[test]
+    fn test_declared() {
+        let source = "t * $a /* $a = 3 in 0..16, note; $b=-2 in -4..4 */ // $c = 300\n$a = 1";
+        assert_eq!(
+            declared(source, Mode::Classic),
+            [
+                knob("a", 3.0, 0.0, 16.0),
+                knob("b", -2.0, -4.0, 4.0),
+                // Clamped to the range it wasn't given
+                knob("c", 255.0, 0.0, 255.0),
+            ]
+        );
+        // Not in a comment, or not a declaration
+        assert!(
+            declared(
+                "\"/* $a = 1 */\"[t] /* $a = x */ /* $b = 1 in 2..1 */",
+                Mode::Classic
+            )
+            .is_empty()
+        );
+
+        let float = declared("/* $x = .25 */", Mode::Float);
+        assert_eq!(float[0].value, 0.25);
+        assert_eq!(float[0].max, 1.0);
+        assert!(!float[0].whole);
+    }
+
+    #[test]
+    fn test_declare() {
+        let mut a = knob("a", 3.0, 0.0, 16.0);
+        let source = "t*$a/*$a=1 in 0..16,hi*/";
+        assert_eq!(declare(source, &[a.clone()]), "t*$a/*$a = 3 in 0..16,hi*/");
+        a.value = 7.0;
+        let b = knob("b", 0.0, 0.0, 255.0);
+        assert_eq!(
+            declare("t*$a*$b /* $a = 3 in 0..16 */", &[a.clone(), b.clone()]),
+            "t*$a*$b /* $a = 7 in 0..16 */ /* $b = 0 in 0..255 */"
+        );
+        assert_eq!(declare("t*$a\n", &[a]), "t*$a\n/* $a = 7 in 0..16 */");
+    }
+
+    #[test]
+    fn test_fit_and_step() {
+        let classic = knob("a", 0.0, 0.0, 1000.0);
+        assert_eq!(classic.fit(3.6), 4.0);
+        assert_eq!(classic.fit(-1.0), 0.0);
+        assert_eq!(classic.fit(f64::NAN), 0.0);
+        assert_eq!(classic.step(), 10.0);
+        assert_eq!(knob("b", 0.0, 0.0, 16.0).step(), 1.0);
+
+        let float = Knob::new("x", Mode::Float);
+        assert_eq!(float.fit(0.1 + 0.2), 0.3);
+        assert_eq!(float.step(), 0.01);
+        assert_eq!(float.at(0.5), 0.5);
+    }
+}
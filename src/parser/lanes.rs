@@ -0,0 +1,429 @@
+//! Evaluates [`LANES`] consecutive 't' at once: every instruction works on a whole lane of values, so each one is a
+//! tight loop LLVM can vectorize.
+//!
+//! There's no control flow, so both sides of a ternary and the right side of `&&` `||` are evaluated and the result
+//! is selected per lane. That's only the same as evaluating one 't' at a time when the skipped code has no side
+//! effects, so beats that assign or call `random()` conditionally aren't lowered. Every array element is evaluated
+//! anyway, like C does.
+use super::ctype::{CInt, CType};
+use super::eval::{Arithmetic, Value, random_bits};
+use super::{
+    ASTNode, Builtin, KnobId, MAX_KNOBS, MAX_LOCALS, NodeId, Operator, OutOfBounds, Place,
+    has_side_effects,
+};
+
+/// How many 't' are evaluated at once
+pub const LANES: usize = 16;
+/// Deepest the lane stack may get. Smaller than [`super::bytecode::MAX_STACK`], since each slot is a whole lane.
+const MAX_LANE_STACK: usize = 32;
+
+type Lane<V> = [V; LANES];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instr<V> {
+    Push(V),
+    Load(Place),
+    Knob(KnobId),
+    /// Assigns the top of the stack, and leaves it there as the result
+    Store(Place),
+    Pop,
+    Unary(Operator),
+    Binary(Operator),
+    Cast(CType),
+    /// [`Operator::LogAnd`] or [`Operator::LogOr`] with both operands evaluated
+    Logical(Operator),
+    /// Pops the condition and both branches, pushes one of the branches
+    Select,
+    /// Pops [`Builtin::arity`] arguments
+    Call(Builtin),
+    Random,
+    /// Pops an index and pushes the element of [`LaneProgram::strings`]
+    StrIndex(usize),
+    /// Pops an index and that many elements, pushes the indexed element
+    ArrayIndex(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaneProgram<V> {
+    code: Vec<Instr<V>>,
+    /// Elements of each string literal, already converted for the mode
+    strings: Vec<Vec<V>>,
+    /// How many locals are used, so only those need resetting
+    locals: usize,
+    out_of_bounds: OutOfBounds,
+}
+
+/// A [`LaneProgram`] in the [`Value`] type of its [`Arithmetic`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompiledLanes {
+    Int(LaneProgram<i32>),
+    C(LaneProgram<CInt>),
+    Float(LaneProgram<f64>),
+}
+
+impl CompiledLanes {
+    /// None if the AST has conditional side effects, or needs a deeper stack than there is
+    pub fn new(
+        nodes: &[ASTNode],
+        root: NodeId,
+        arithmetic: Arithmetic,
+        out_of_bounds: OutOfBounds,
+    ) -> Option<CompiledLanes> {
+        match arithmetic {
+            Arithmetic::Int => LaneProgram::lower(nodes, root, out_of_bounds).map(Self::Int),
+            Arithmetic::C => LaneProgram::lower(nodes, root, out_of_bounds).map(Self::C),
+            Arithmetic::Float => LaneProgram::lower(nodes, root, out_of_bounds).map(Self::Float),
+        }
+    }
+
+    /// `knobs` by [`KnobId`], the same for the whole block
+    pub fn eval_block(&self, t_start: i32, out: &mut [u8], knobs: &[f64]) {
+        match self {
+            CompiledLanes::Int(program) => program.eval_block(t_start, out, knobs),
+            CompiledLanes::C(program) => program.eval_block(t_start, out, knobs),
+            CompiledLanes::Float(program) => program.eval_block(t_start, out, knobs),
+        }
+    }
+}
+
+/// Scratch space of a [`LaneProgram`]. Big enough that it's made once per block, not once per lane.
+struct LaneState<V> {
+    stack: [Lane<V>; MAX_LANE_STACK],
+    locals: [Lane<V>; MAX_LOCALS],
+    knobs: [V; MAX_KNOBS],
+}
+
+impl<V: Value> LaneProgram<V> {
+    fn lower(nodes: &[ASTNode], root: NodeId, out_of_bounds: OutOfBounds) -> Option<Self> {
+        let mut lowering = Lowering {
+            nodes,
+            program: LaneProgram {
+                code: Vec::new(),
+                strings: Vec::new(),
+                locals: 0,
+                out_of_bounds,
+            },
+            depth: 0,
+            max_depth: 0,
+        };
+        lowering.lower(root)?;
+        (lowering.max_depth <= MAX_LANE_STACK).then_some(lowering.program)
+    }
+
+    fn eval_block(&self, t_start: i32, out: &mut [u8], knobs: &[f64]) {
+        let mut state = LaneState {
+            stack: [[V::from_int(0); LANES]; MAX_LANE_STACK],
+            locals: [[V::from_int(0); LANES]; MAX_LOCALS],
+            knobs: std::array::from_fn(|id| V::from_float(knobs.get(id).copied().unwrap_or(0.0))),
+        };
+        let mut t = t_start;
+        for chunk in out.chunks_mut(LANES) {
+            let lane = self.eval_lane(t, &mut state);
+            for (sample, v) in chunk.iter_mut().zip(lane) {
+                *sample = v.to_sample();
+            }
+            t = t.wrapping_add(LANES as i32);
+        }
+    }
+
+    fn eval_lane(&self, t_start: i32, state: &mut LaneState<V>) -> Lane<V> {
+        let seeds: [i32; LANES] = std::array::from_fn(|i| t_start.wrapping_add(i as i32));
+        let mut t = seeds.map(V::from_int);
+        for local in &mut state.locals[..self.locals] {
+            *local = [V::from_int(0); LANES];
+        }
+        let mut random_calls = 0;
+        let stack = &mut state.stack;
+        // Points past the top of the stack
+        let mut sp = 0;
+
+        for instr in &self.code {
+            match *instr {
+                Instr::Push(v) => {
+                    stack[sp] = [v; LANES];
+                    sp += 1;
+                }
+                Instr::Load(place) => {
+                    stack[sp] = match place {
+                        Place::T => t,
+                        Place::Local(id) => state.locals[id],
+                    };
+                    sp += 1;
+                }
+                Instr::Knob(id) => {
+                    stack[sp] = [state.knobs[id]; LANES];
+                    sp += 1;
+                }
+                Instr::Store(place) => match place {
+                    Place::T => t = stack[sp - 1],
+                    Place::Local(id) => state.locals[id] = stack[sp - 1],
+                },
+                Instr::Pop => sp -= 1,
+                Instr::Unary(op) => {
+                    for v in &mut stack[sp - 1] {
+                        *v = V::unary(op, *v);
+                    }
+                }
+                Instr::Binary(op) => {
+                    sp -= 1;
+                    let r = stack[sp];
+                    binary_lanes(op, &mut stack[sp - 1], &r);
+                }
+                Instr::Cast(ty) => {
+                    for v in &mut stack[sp - 1] {
+                        *v = V::cast(ty, *v);
+                    }
+                }
+                Instr::Logical(op) => {
+                    sp -= 1;
+                    let (l, r) = (stack[sp - 1], stack[sp]);
+                    stack[sp - 1] = std::array::from_fn(|i| {
+                        let decided = match op {
+                            Operator::LogAnd => !l[i].truthy(),
+                            _ => l[i].truthy(),
+                        };
+                        // Same stand-in for the right operand as the tree walker
+                        V::binary(op, l[i], if decided { l[i] } else { r[i] })
+                    });
+                }
+                Instr::Select => {
+                    sp -= 2;
+                    let (cond, a, b) = (stack[sp - 1], stack[sp], stack[sp + 1]);
+                    stack[sp - 1] =
+                        std::array::from_fn(|i| if cond[i].truthy() { a[i] } else { b[i] });
+                }
+                Instr::Call(builtin) => {
+                    let args = sp - builtin.arity();
+                    let result = std::array::from_fn(|i| {
+                        let mut values = [V::from_int(0); Builtin::MAX_ARITY];
+                        for (value, arg) in values.iter_mut().zip(&stack[args..sp]) {
+                            *value = arg[i];
+                        }
+                        V::call(builtin, &values[..builtin.arity()])
+                    });
+                    stack[args] = result;
+                    sp = args + 1;
+                }
+                Instr::Random => {
+                    random_calls += 1;
+                    stack[sp] = seeds.map(|seed| V::from_random(random_bits(seed, random_calls)));
+                    sp += 1;
+                }
+                Instr::StrIndex(id) => {
+                    let elements = &self.strings[id];
+                    let index = stack[sp - 1];
+                    stack[sp - 1] = std::array::from_fn(|i| {
+                        self.out_of_bounds
+                            .resolve(index[i].to_index(), elements.len())
+                            .map_or(V::from_int(0), |e| elements[e])
+                    });
+                }
+                Instr::ArrayIndex(len) => {
+                    sp -= len;
+                    let index = stack[sp + len - 1];
+                    let elements = &stack[sp - 1..sp - 1 + len];
+                    let result = std::array::from_fn(|i| {
+                        self.out_of_bounds
+                            .resolve(index[i].to_index(), len)
+                            .map_or(V::from_int(0), |e| elements[e][i])
+                    });
+                    stack[sp - 1] = result;
+                }
+            }
+        }
+
+        stack[0]
+    }
+}
+
+/// Matching outside the loop leaves each loop with a single operation, which LLVM can vectorize
+fn binary_lanes<V: Value>(op: Operator, l: &mut Lane<V>, r: &Lane<V>) {
+    macro_rules! unswitch {
+        ($($op:ident)*) => {
+            match op {
+                $(Operator::$op => {
+                    for (l, r) in l.iter_mut().zip(r) {
+                        *l = V::binary(Operator::$op, *l, *r);
+                    }
+                })*
+                _ => {
+                    for (l, r) in l.iter_mut().zip(r) {
+                        *l = V::binary(op, *l, *r);
+                    }
+                }
+            }
+        };
+    }
+    unswitch!(Plus Minus Mul Div Mod And Or BitXor Lsh Rsh Eq Ne Gt Lt Ge Le);
+}
+
+struct Lowering<'a, V> {
+    nodes: &'a [ASTNode],
+    program: LaneProgram<V>,
+    /// Of the lane stack at the current end of the code
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<V: Value> Lowering<'_, V> {
+    fn emit(&mut self, instr: Instr<V>) {
+        match instr {
+            Instr::Push(_) | Instr::Load(_) | Instr::Knob(_) | Instr::Random => self.depth += 1,
+            Instr::Pop | Instr::Binary(_) | Instr::Logical(_) => self.depth -= 1,
+            Instr::Select => self.depth -= 2,
+            Instr::ArrayIndex(len) => self.depth -= len,
+            // Arguments become the result
+            Instr::Call(builtin) => self.depth = self.depth + 1 - builtin.arity(),
+            Instr::Store(_) | Instr::Unary(_) | Instr::Cast(_) | Instr::StrIndex(_) => {}
+        }
+        self.max_depth = self.max_depth.max(self.depth);
+        self.program.code.push(instr);
+    }
+
+    /// None if something can't be evaluated unconditionally
+    fn lower_pure(&mut self, id: NodeId) -> Option<()> {
+        if has_side_effects(self.nodes, id) {
+            None
+        } else {
+            self.lower(id)
+        }
+    }
+
+    /// Leaves exactly one more lane on the stack
+    fn lower(&mut self, id: NodeId) -> Option<()> {
+        match &self.nodes[id] {
+            ASTNode::Literal(n) => self.emit(Instr::Push(V::from_int(*n))),
+            ASTNode::TypedLiteral(c) => self.emit(Instr::Push(V::from_typed(*c))),
+            ASTNode::FloatLiteral(f) => self.emit(Instr::Push(V::from_float(*f))),
+            ASTNode::Variable => self.emit(Instr::Load(Place::T)),
+            ASTNode::Local(local) => {
+                self.program.locals = self.program.locals.max(local + 1);
+                self.emit(Instr::Load(Place::Local(*local)));
+            }
+            ASTNode::Knob(knob) => self.emit(Instr::Knob(*knob)),
+            ASTNode::Assign(op, place, value) => {
+                if let Place::Local(local) = place {
+                    self.program.locals = self.program.locals.max(local + 1);
+                }
+                match op.compound_base() {
+                    Some(base) => {
+                        self.emit(Instr::Load(*place));
+                        self.lower(*value)?;
+                        self.emit(Instr::Binary(base));
+                    }
+                    None => self.lower(*value)?,
+                }
+                self.emit(Instr::Store(*place));
+            }
+            ASTNode::Unary(op, operand) => {
+                self.lower(*operand)?;
+                self.emit(Instr::Unary(*op));
+            }
+            ASTNode::Cast(ty, operand) => {
+                self.lower(*operand)?;
+                self.emit(Instr::Cast(*ty));
+            }
+            ASTNode::Binary(op @ (Operator::LogAnd | Operator::LogOr), left, right) => {
+                self.lower(*left)?;
+                self.lower_pure(*right)?;
+                self.emit(Instr::Logical(*op));
+            }
+            ASTNode::Binary(Operator::Comma | Operator::Semicolon, left, right) => {
+                self.lower(*left)?;
+                self.emit(Instr::Pop);
+                self.lower(*right)?;
+            }
+            ASTNode::Binary(op, left, right) => {
+                self.lower(*left)?;
+                self.lower(*right)?;
+                self.emit(Instr::Binary(*op));
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                self.lower(*cond)?;
+                self.lower_pure(*true_branch)?;
+                self.lower_pure(*false_branch)?;
+                self.emit(Instr::Select);
+            }
+            ASTNode::Call(Builtin::Random, _) => self.emit(Instr::Random),
+            ASTNode::Call(builtin, args) => {
+                for arg in args {
+                    self.lower(*arg)?;
+                }
+                self.emit(Instr::Call(*builtin));
+            }
+            ASTNode::Index(table, index) => {
+                match &self.nodes[*table] {
+                    ASTNode::Str(s) => {
+                        self.lower(*index)?;
+                        let elements = (0..V::str_len(s))
+                            .map(|i| V::str_element(s, i).unwrap_or(V::from_int(0)))
+                            .collect();
+                        self.program.strings.push(elements);
+                        self.emit(Instr::StrIndex(self.program.strings.len() - 1));
+                    }
+                    ASTNode::Array(items) => {
+                        for item in items {
+                            self.lower(*item)?;
+                        }
+                        self.lower(*index)?;
+                        self.emit(Instr::ArrayIndex(items.len()));
+                    }
+                    // Parser doesn't allow this
+                    _ => return None,
+                }
+            }
+            // Parser doesn't allow unindexed tables, and a Beat with errors is never compiled
+            ASTNode::Str(_) | ASTNode::Array(_) | ASTNode::Error(_) => return None,
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Mode;
+    use crate::parser::parse::Parser;
+
+    fn lower(source: &str) -> Option<LaneProgram<i32>> {
+        let mut nodes = Vec::new();
+        let root = Parser::new(source, &mut nodes, Mode::Classic)
+            .parse()
+            .unwrap();
+        LaneProgram::lower(&nodes, root, OutOfBounds::Zero)
+    }
+
+    #[test]
+    fn test_conditional_side_effects_refused() {
+        assert!(lower("a = t, t & 1 ? a : -a").is_some());
+        assert!(lower("t & 1 ? (a = t) : 0").is_none());
+        assert!(lower("t && random()").is_none());
+        // Unconditional is fine
+        assert!(lower("random() * t").is_some());
+        assert!(lower("[t, a += 1][t & 1]").is_some());
+    }
+
+    #[test]
+    fn test_select_and_arrays() {
+        let program = lower("t & 1 ? [10, 20, t][t >> 1 & 3] : t && 7").unwrap();
+        let mut out = [0; LANES + 3];
+        program.eval_block(0, &mut out, &[]);
+        assert_eq!(out[..8], [0, 10, 1, 20, 1, 5, 1, 0]);
+    }
+
+    #[test]
+    fn test_every_element_before_the_index() {
+        let program = lower("a = 1, [a = 5, a += 2][t & 1] + a").unwrap();
+        let mut out = [0; 4];
+        program.eval_block(0, &mut out, &[]);
+        assert_eq!(out, [12, 14, 12, 14]);
+    }
+
+    #[test]
+    fn test_block_wraps_around() {
+        let program = lower("t >> 24").unwrap();
+        let mut out = [0; 4];
+        program.eval_block(i32::MAX - 1, &mut out, &[]);
+        assert_eq!(out, [127, 127, 128, 128]);
+    }
+}
@@ -1,12 +1,14 @@
-//! Simple lexer with 1-token lookahead that handles a subset of C relevant to classic bytebeat. Only intended for a single statement of 1+ expression.
+//! Simple lexer with 1-token lookahead that handles a subset of C relevant to classic bytebeat. Statements are separated by `;` tokens.
 //!
-//! Column and line aware.
+//! Column and line aware. `//` and `/* */` comments are skipped like whitespace, and block comments may span lines.
 use std::{i32, iter::Peekable, num::IntErrorKind, str::Chars};
 
 use tracing::warn;
 
 use crate::parser::{Column, LexError, Line, Operator, Span, Spanned, Token};
 
+use super::ctype::{CInt, CType};
+
 pub struct Lexer<'a> {
     chars: Peekable<Chars<'a>>,
     // Used to create spans for tokens
@@ -47,7 +49,9 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn next(&mut self) -> Spanned<Token> {
-        self.skip_whitespace();
+        if let Some(opening) = self.skip_trivia() {
+            return Spanned::new(Token::Err(LexError::UnterminatedComment), opening);
+        }
         let (start_line, start_col) = (self.line, self.col);
         let token = match self.chars.peek() {
             Some(&c) => {
@@ -55,23 +59,23 @@ impl<'a> Lexer<'a> {
                     // I: Operators, including multi-char
                     '+' => {
                         self.bump();
-                        Token::Op(Operator::Plus)
+                        self.maybe_assign(Operator::Plus, Operator::PlusAssign)
                     }
                     '-' => {
                         self.bump();
-                        Token::Op(Operator::Minus)
+                        self.maybe_assign(Operator::Minus, Operator::MinusAssign)
                     }
                     '/' => {
                         self.bump();
-                        Token::Op(Operator::Div)
+                        self.maybe_assign(Operator::Div, Operator::DivAssign)
                     }
                     '*' => {
                         self.bump();
-                        Token::Op(Operator::Mul)
+                        self.maybe_assign(Operator::Mul, Operator::MulAssign)
                     }
                     '%' => {
                         self.bump();
-                        Token::Op(Operator::Mod)
+                        self.maybe_assign(Operator::Mod, Operator::ModAssign)
                     }
                     '&' => {
                         self.bump();
@@ -79,7 +83,7 @@ impl<'a> Lexer<'a> {
                             self.bump();
                             Token::Op(Operator::LogAnd)
                         } else {
-                            Token::Op(Operator::And)
+                            self.maybe_assign(Operator::And, Operator::AndAssign)
                         }
                     }
                     '|' => {
@@ -88,12 +92,12 @@ impl<'a> Lexer<'a> {
                             self.bump();
                             Token::Op(Operator::LogOr)
                         } else {
-                            Token::Op(Operator::Or)
+                            self.maybe_assign(Operator::Or, Operator::OrAssign)
                         }
                     }
                     '^' => {
                         self.bump();
-                        Token::Op(Operator::BitXor)
+                        self.maybe_assign(Operator::BitXor, Operator::XorAssign)
                     }
                     '~' => {
                         self.bump();
@@ -114,8 +118,7 @@ impl<'a> Lexer<'a> {
                             self.bump();
                             Token::Op(Operator::Eq)
                         } else {
-                            // Hey pal, this isn't that kind of statement!
-                            Token::Err(LexError::SolitaryEquals)
+                            Token::Op(Operator::Assign)
                         }
                     }
                     '?' => {
@@ -134,12 +137,20 @@ impl<'a> Lexer<'a> {
                         self.bump();
                         Token::Op(Operator::Rparen)
                     }
+                    '[' => {
+                        self.bump();
+                        Token::Op(Operator::Lbracket)
+                    }
+                    ']' => {
+                        self.bump();
+                        Token::Op(Operator::Rbracket)
+                    }
                     '<' => {
                         self.bump(); // consume first <
                         if let Some(&next) = self.chars.peek() {
                             if next == '<' {
                                 self.bump();
-                                Token::Op(Operator::Lsh)
+                                self.maybe_assign(Operator::Lsh, Operator::LshAssign)
                             } else if next == '=' {
                                 self.bump();
                                 Token::Op(Operator::Le)
@@ -155,7 +166,7 @@ impl<'a> Lexer<'a> {
                         if let Some(&next) = self.chars.peek() {
                             if next == '>' {
                                 self.bump();
-                                Token::Op(Operator::Rsh)
+                                self.maybe_assign(Operator::Rsh, Operator::RshAssign)
                             } else if next == '=' {
                                 self.bump();
                                 Token::Op(Operator::Ge)
@@ -166,12 +177,14 @@ impl<'a> Lexer<'a> {
                             Token::Op(Operator::Gt)
                         }
                     }
-                    // II: Numbers (always into i32) may use C-syntax for base 2, 8, 10, 16
+                    // II: Numbers may use C-syntax for base 2, 8, 10, 16. Decimals may also be floats.
                     '0' => {
                         // Leading 0 may be 0 or a base that's not 10
                         self.bump();
                         if let Some(&next) = self.chars.peek() {
-                            if next == 'x' {
+                            if next == '.' || self.exponent_ahead() {
+                                self.lex_float(String::from("0"))
+                            } else if next == 'x' {
                                 self.bump();
                                 self.lex_number(16)
                             } else if next == 'b' {
@@ -180,8 +193,8 @@ impl<'a> Lexer<'a> {
                             } else if next.is_ascii_digit() {
                                 self.lex_number(8)
                             } else {
-                                // 0 and then another token
-                                Token::Number(0)
+                                // 0 and then another token, or a suffix
+                                self.integer(0, 8)
                             }
                         } else {
                             // 0 and then EOF
@@ -189,18 +202,35 @@ impl<'a> Lexer<'a> {
                         }
                     }
                     '1'..='9' => self.lex_number(10),
-                    // III: Variables. Could be anything, but we restrict to 't' for the users' sanity.
-                    't' => {
+                    '.' => {
+                        // Can only be a float with the leading 0 left out, like `.25`
                         self.bump();
-                        Token::Variable
+                        if self.chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                            self.lex_float(String::from("0."))
+                        } else {
+                            Token::Err(LexError::UnexpectedChar('.'))
+                        }
+                    }
+                    // III: Names. 't' is the only variable, anything else might be a function. '$' starts a knob.
+                    'a'..='z' | 'A'..='Z' | '_' => self.lex_ident(),
+                    '$' => self.lex_knob(),
+                    ',' => {
+                        self.bump();
+                        Token::Op(Operator::Comma)
                     }
+                    ';' => {
+                        self.bump();
+                        Token::Op(Operator::Semicolon)
+                    }
+                    // IV: Strings and characters
+                    '"' | '\'' => self.lex_quoted(c),
                     _ => {
                         self.bump();
                         Token::Err(LexError::UnexpectedChar(c))
                     }
                 }
             }
-            // IV: End
+            // V: End
             None => Token::Eof,
         };
 
@@ -217,6 +247,16 @@ impl<'a> Lexer<'a> {
         Spanned::new(token, Span::new(start_line, start_col, end_col))
     }
 
+    /// For operators that have a compound assignment form, e.g. `+` and `+=`. Operator must already be consumed.
+    fn maybe_assign(&mut self, op: Operator, assign: Operator) -> Token {
+        if let Some('=') = self.chars.peek() {
+            self.bump();
+            Token::Op(assign)
+        } else {
+            Token::Op(op)
+        }
+    }
+
     /// Skips unicode whitespace and both line-endings (\r\n, \n), not equipped for bizarre unicode linebreaks and etc.
     fn skip_whitespace(&mut self) {
         while let Some(&peeked) = self.chars.peek() {
@@ -228,7 +268,47 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    /// More robust helper for lexing ASCII numbers with any base. Must be called after separators removed
+    /// Skips whitespace and comments until the next token. An unterminated block comment eats the rest of the input,
+    /// and the [`Span`] of its opening `/*` is returned to report it.
+    fn skip_trivia(&mut self) -> Option<Span> {
+        loop {
+            self.skip_whitespace();
+            let mut ahead = self.chars.clone();
+            if ahead.next() != Some('/') {
+                return None;
+            }
+            match ahead.next() {
+                Some('/') => {
+                    while self.chars.peek().is_some_and(|&c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                Some('*') => {
+                    let opening = Span::new(self.line, self.col, self.col + 1);
+                    if !self.skip_block_comment() {
+                        return Some(opening);
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// From the opening `/*` through the closing `*/`. Returns false if the input ended first.
+    fn skip_block_comment(&mut self) -> bool {
+        self.bump();
+        self.bump();
+        while let Some(c) = self.bump() {
+            if c == '*' && self.chars.peek() == Some(&'/') {
+                self.bump();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// More robust helper for lexing ASCII numbers with any base, and their suffix. Must be called after separators
+    /// removed
     fn lex_number(&mut self, radix: u32) -> Token {
         let mut digits = String::new();
         while let Some(&peeked) = self.chars.peek() {
@@ -239,22 +319,189 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        i32::from_str_radix(&digits, radix).map_or_else(
-            |e| match e.kind() {
+        // Only decimals have a fractional part or exponent
+        if radix == 10 && (self.chars.peek() == Some(&'.') || self.exponent_ahead()) {
+            return self.lex_float(digits);
+        }
+
+        match u64::from_str_radix(&digits, radix) {
+            Ok(n) => self.integer(n, radix),
+            Err(e) => match e.kind() {
                 IntErrorKind::PosOverflow => {
-                    warn!("lexer is setting overflowing number {digits} to i32 max");
-                    Token::Number(i32::MAX)
+                    warn!("lexer is setting overflowing number {digits} to u64 max");
+                    self.integer(u64::MAX, radix)
                 }
                 IntErrorKind::NegOverflow => {
                     unreachable!() // Parser does negation. `-` is a prefix operator
                 }
-                IntErrorKind::Zero => Token::Number(0),
+                IntErrorKind::Zero => self.integer(0, radix),
                 IntErrorKind::Empty | IntErrorKind::InvalidDigit | _ => {
                     Token::Err(LexError::ImproperNumber(radix as i8, e))
                 }
             },
-            |n| Token::Number(n),
-        )
+        }
+    }
+
+    /// Consumes a `U` and/or `L` (or `LL`) suffix, and types the literal like C would: the first type it fits in,
+    /// out of the ones its suffix and base allow. Anything too big for a `long` is `unsigned long`, like GCC does.
+    fn integer(&mut self, value: u64, radix: u32) -> Token {
+        let (mut unsigned, mut long) = (false, false);
+        loop {
+            match self.chars.peek() {
+                Some('u' | 'U') if !unsigned => {
+                    self.bump();
+                    unsigned = true;
+                }
+                Some(&l @ ('l' | 'L')) if !long => {
+                    self.bump();
+                    if self.chars.peek() == Some(&l) {
+                        self.bump();
+                    }
+                    long = true;
+                }
+                _ => break,
+            }
+        }
+
+        let types: &[CType] = match (unsigned, long, radix == 10) {
+            (false, false, true) => &[CType::Int, CType::Long],
+            (false, false, false) => &[CType::Int, CType::UInt, CType::Long, CType::ULong],
+            (true, false, _) => &[CType::UInt, CType::ULong],
+            (false, true, true) => &[CType::Long],
+            (false, true, false) => &[CType::Long, CType::ULong],
+            (true, true, _) => &[CType::ULong],
+        };
+        let fits = |ty: &&CType| match ty {
+            CType::Int => value <= i32::MAX as u64,
+            CType::UInt => value <= u32::MAX as u64,
+            CType::Long => value <= i64::MAX as u64,
+            _ => true,
+        };
+        match types.iter().find(fits).unwrap_or(&CType::ULong) {
+            CType::Int => Token::Number(value as i32),
+            ty => Token::TypedNumber(CInt::new(value as i64, *ty)),
+        }
+    }
+
+    /// Names are C identifiers. `Math.` is skipped so JavaScript style calls like `Math.sin(t)` work too.
+    fn lex_ident(&mut self) -> Token {
+        let mut name = String::new();
+        self.push_ident_chars(&mut name);
+
+        if name == "Math" && self.chars.peek() == Some(&'.') {
+            self.bump();
+            name.clear();
+            self.push_ident_chars(&mut name);
+        }
+
+        if name == "t" {
+            Token::Variable
+        } else {
+            Token::Ident(name)
+        }
+    }
+
+    /// String or character literal, from the opening quote through the closing one. Escapes are the common subset
+    /// of C and JavaScript, and any other escaped character stands for itself.
+    fn lex_quoted(&mut self, quote: char) -> Token {
+        self.bump();
+        let mut text = String::new();
+        loop {
+            match self.chars.peek() {
+                Some(&c) if c == quote => {
+                    self.bump();
+                    break;
+                }
+                // Tokens may not span lines
+                None | Some('\n' | '\r') => return Token::Err(LexError::UnterminatedString(quote)),
+                Some('\\') => {
+                    self.bump();
+                    let escaped = match self.chars.peek() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        None | Some('\n' | '\r') => {
+                            return Token::Err(LexError::UnterminatedString(quote));
+                        }
+                        Some(&c) => c,
+                    };
+                    self.bump();
+                    text.push(escaped);
+                }
+                Some(_) => text.push(self.bump().unwrap()),
+            }
+        }
+
+        let mut chars = text.chars();
+        match (quote, chars.next(), chars.next()) {
+            ('\'', Some(c), None) => Token::Char(c),
+            _ => Token::Str(text),
+        }
+    }
+
+    /// Continues a decimal from its integer digits (or `0.` for a leading '.') through the fraction and exponent.
+    fn lex_float(&mut self, mut text: String) -> Token {
+        if !text.ends_with('.') && self.chars.peek() == Some(&'.') {
+            text.push(self.bump().unwrap());
+        }
+        self.push_digits(&mut text);
+
+        if self.exponent_ahead() {
+            // 'e' and maybe a sign, the digits are guaranteed
+            text.push(self.bump().unwrap());
+            if let Some('+' | '-') = self.chars.peek() {
+                text.push(self.bump().unwrap());
+            }
+            self.push_digits(&mut text);
+        }
+
+        text.parse::<f64>()
+            .map_or_else(|e| Token::Err(LexError::ImproperFloat(e)), Token::Float)
+    }
+
+    /// Is there an exponent like `e5` or `E-2` next? Otherwise an 'e' would belong to the next token.
+    fn exponent_ahead(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        if !matches!(ahead.next(), Some('e' | 'E')) {
+            return false;
+        }
+        if let Some('+' | '-') = ahead.peek() {
+            ahead.next();
+        }
+        ahead.next().is_some_and(|c| c.is_ascii_digit())
+    }
+
+    /// `$` and a name, like `$speed`. A lone `$` isn't anything
+    fn lex_knob(&mut self) -> Token {
+        self.bump();
+        let mut name = String::new();
+        self.push_ident_chars(&mut name);
+        if name.is_empty() {
+            Token::Err(LexError::UnexpectedChar('$'))
+        } else {
+            Token::Knob(name)
+        }
+    }
+
+    fn push_ident_chars(&mut self, name: &mut String) {
+        while let Some(&peeked) = self.chars.peek() {
+            if peeked.is_ascii_alphanumeric() || peeked == '_' {
+                name.push(self.bump().unwrap());
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn push_digits(&mut self, text: &mut String) {
+        while let Some(&peeked) = self.chars.peek() {
+            if peeked.is_ascii_digit() {
+                text.push(self.bump().unwrap());
+            } else {
+                break;
+            }
+        }
     }
 }
 
@@ -422,12 +669,61 @@ mod tests {
     }
 
     #[test]
-    fn test_error_tokens() {
-        let input = "=";
+    fn test_assignment_operators() {
+        let input = "a=t;a+=1,a<<=2>>=3==4";
+        let mut lexer = Lexer::new(input);
+
+        assert_token(&mut lexer, Token::Ident("a".to_string()), 0, 0, 0);
+        assert_token(&mut lexer, Token::Op(Operator::Assign), 0, 1, 1);
+        assert_token(&mut lexer, Token::Variable, 0, 2, 2);
+        assert_token(&mut lexer, Token::Op(Operator::Semicolon), 0, 3, 3);
+        assert_token(&mut lexer, Token::Ident("a".to_string()), 0, 4, 4);
+        assert_token(&mut lexer, Token::Op(Operator::PlusAssign), 0, 5, 6);
+        assert_token(&mut lexer, Token::Number(1), 0, 7, 7);
+        assert_token(&mut lexer, Token::Op(Operator::Comma), 0, 8, 8);
+        assert_token(&mut lexer, Token::Ident("a".to_string()), 0, 9, 9);
+        assert_token(&mut lexer, Token::Op(Operator::LshAssign), 0, 10, 12);
+        assert_token(&mut lexer, Token::Number(2), 0, 13, 13);
+        assert_token(&mut lexer, Token::Op(Operator::RshAssign), 0, 14, 16);
+        assert_token(&mut lexer, Token::Number(3), 0, 17, 17);
+        assert_token(&mut lexer, Token::Op(Operator::Eq), 0, 18, 19);
+        assert_token(&mut lexer, Token::Number(4), 0, 20, 20);
+    }
+
+    #[test]
+    fn test_strings_and_chars() {
+        let input = r#""66\"89"[t] 'a' '\'' 'ab' [1]"#;
         let mut lexer = Lexer::new(input);
 
-        assert_token(&mut lexer, Token::Err(LexError::SolitaryEquals), 0, 0, 0);
+        assert_token(&mut lexer, Token::Str("66\"89".to_string()), 0, 0, 7);
+        assert_token(&mut lexer, Token::Op(Operator::Lbracket), 0, 8, 8);
+        assert_token(&mut lexer, Token::Variable, 0, 9, 9);
+        assert_token(&mut lexer, Token::Op(Operator::Rbracket), 0, 10, 10);
+        assert_token(&mut lexer, Token::Char('a'), 0, 12, 14);
+        assert_token(&mut lexer, Token::Char('\''), 0, 16, 19);
+        assert_token(&mut lexer, Token::Str("ab".to_string()), 0, 21, 24);
+        assert_token(&mut lexer, Token::Op(Operator::Lbracket), 0, 26, 26);
+        assert_token(&mut lexer, Token::Number(1), 0, 27, 27);
+        assert_token(&mut lexer, Token::Op(Operator::Rbracket), 0, 28, 28);
+    }
 
+    #[test]
+    fn test_unterminated_string() {
+        let input = "\"abc\nt";
+        let mut lexer = Lexer::new(input);
+
+        assert_token(
+            &mut lexer,
+            Token::Err(LexError::UnterminatedString('"')),
+            0,
+            0,
+            3,
+        );
+        assert_token(&mut lexer, Token::Variable, 1, 0, 0);
+    }
+
+    #[test]
+    fn test_error_tokens() {
         let input = "@";
         let mut lexer = Lexer::new(input);
 
@@ -458,8 +754,11 @@ mod tests {
 
     #[test]
     fn test_decimal_overflow() {
-        let mut lexer = Lexer::new("9999999999999999999");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 18);
+        let mut lexer = Lexer::new("9999999999999999999 99999999999999999999");
+        let big = CInt::new(9999999999999999999_u64 as i64, CType::ULong);
+        assert_token(&mut lexer, Token::TypedNumber(big), 0, 0, 18);
+        let max = CInt::new(u64::MAX as i64, CType::ULong);
+        assert_token(&mut lexer, Token::TypedNumber(max), 0, 20, 39);
     }
 
     #[test]
@@ -478,8 +777,39 @@ mod tests {
 
     #[test]
     fn test_decimal_one_above_max() {
+        // Decimals are never unsigned without a suffix
         let mut lexer = Lexer::new("2147483648");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 9);
+        let long = CInt::new(2147483648, CType::Long);
+        assert_token(&mut lexer, Token::TypedNumber(long), 0, 0, 9);
+    }
+
+    #[test]
+    fn test_suffixes() {
+        let mut lexer = Lexer::new("5u 5L 5ul 5LLU 0U 0x10l 7lu");
+        let typed = |ty| Token::TypedNumber(CInt::new(5, ty));
+        assert_token(&mut lexer, typed(CType::UInt), 0, 0, 1);
+        assert_token(&mut lexer, typed(CType::Long), 0, 3, 4);
+        assert_token(&mut lexer, typed(CType::ULong), 0, 6, 8);
+        assert_token(&mut lexer, typed(CType::ULong), 0, 10, 13);
+        let zero = CInt::new(0, CType::UInt);
+        assert_token(&mut lexer, Token::TypedNumber(zero), 0, 15, 16);
+        let hex = CInt::new(16, CType::Long);
+        assert_token(&mut lexer, Token::TypedNumber(hex), 0, 18, 22);
+        let seven = CInt::new(7, CType::ULong);
+        assert_token(&mut lexer, Token::TypedNumber(seven), 0, 24, 26);
+    }
+
+    #[test]
+    fn test_suffix_only_once() {
+        let mut lexer = Lexer::new("5uu");
+        assert_token(
+            &mut lexer,
+            Token::TypedNumber(CInt::new(5, CType::UInt)),
+            0,
+            0,
+            1,
+        );
+        assert_token(&mut lexer, Token::Ident("u".to_string()), 0, 2, 2);
     }
 
     // ==================== Base 16 (Hexadecimal) Tests ====================
@@ -505,7 +835,8 @@ mod tests {
     #[test]
     fn test_hex_overflow() {
         let mut lexer = Lexer::new("0xFFFFFFFF");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 9);
+        let unsigned = CInt::new(0xFFFFFFFF, CType::UInt);
+        assert_token(&mut lexer, Token::TypedNumber(unsigned), 0, 0, 9);
     }
 
     #[test]
@@ -546,8 +877,11 @@ mod tests {
 
     #[test]
     fn test_hex_one_above_max() {
-        let mut lexer = Lexer::new("0x80000000");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 9);
+        let mut lexer = Lexer::new("0x80000000 0x100000000");
+        let unsigned = CInt::new(0x80000000, CType::UInt);
+        assert_token(&mut lexer, Token::TypedNumber(unsigned), 0, 0, 9);
+        let long = CInt::new(0x100000000, CType::Long);
+        assert_token(&mut lexer, Token::TypedNumber(long), 0, 11, 21);
     }
 
     // ==================== Base 2 (Binary) Tests ====================
@@ -566,9 +900,10 @@ mod tests {
 
     #[test]
     fn test_binary_overflow() {
-        // 33 ones will overflow i32
+        // 33 ones will overflow u32
         let mut lexer = Lexer::new("0b111111111111111111111111111111111");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 34);
+        let long = CInt::new((1 << 33) - 1, CType::Long);
+        assert_token(&mut lexer, Token::TypedNumber(long), 0, 0, 34);
     }
 
     #[test]
@@ -612,7 +947,8 @@ mod tests {
     #[test]
     fn test_octal_overflow() {
         let mut lexer = Lexer::new("077777777777777");
-        assert_token(&mut lexer, Token::Number(i32::MAX), 0, 0, 14);
+        let long = CInt::new(0o77777777777777, CType::Long);
+        assert_token(&mut lexer, Token::TypedNumber(long), 0, 0, 14);
     }
 
     #[test]
@@ -656,6 +992,153 @@ mod tests {
         assert_token(&mut lexer, Token::Number(5), 0, 2, 2);
     }
 
+    // ==================== Name Tests ====================
+
+    #[test]
+    fn test_ident_call() {
+        let mut lexer = Lexer::new("pow(t,2)");
+        assert_token(&mut lexer, Token::Ident("pow".to_string()), 0, 0, 2);
+        assert_token(&mut lexer, Token::Op(Operator::Lparen), 0, 3, 3);
+        assert_token(&mut lexer, Token::Variable, 0, 4, 4);
+        assert_token(&mut lexer, Token::Op(Operator::Comma), 0, 5, 5);
+        assert_token(&mut lexer, Token::Number(2), 0, 6, 6);
+        assert_token(&mut lexer, Token::Op(Operator::Rparen), 0, 7, 7);
+    }
+
+    #[test]
+    fn test_ident_not_t() {
+        // Only a lone 't' is the variable
+        let mut lexer = Lexer::new("tan t_2 t");
+        assert_token(&mut lexer, Token::Ident("tan".to_string()), 0, 0, 2);
+        assert_token(&mut lexer, Token::Ident("t_2".to_string()), 0, 4, 6);
+        assert_token(&mut lexer, Token::Variable, 0, 8, 8);
+    }
+
+    #[test]
+    fn test_ident_math_prefix() {
+        let mut lexer = Lexer::new("Math.sin");
+        assert_token(&mut lexer, Token::Ident("sin".to_string()), 0, 0, 7);
+        assert_token(&mut lexer, Token::Eof, 0, 8, 8);
+    }
+
+    #[test]
+    fn test_knob() {
+        let mut lexer = Lexer::new("$speed*$t $");
+        assert_token(&mut lexer, Token::Knob("speed".to_string()), 0, 0, 5);
+        assert_token(&mut lexer, Token::Op(Operator::Mul), 0, 6, 6);
+        assert_token(&mut lexer, Token::Knob("t".to_string()), 0, 7, 8);
+        assert_token(
+            &mut lexer,
+            Token::Err(LexError::UnexpectedChar('$')),
+            0,
+            10,
+            10,
+        );
+    }
+
+    // ==================== Float Tests ====================
+
+    #[test]
+    fn test_float_simple() {
+        let mut lexer = Lexer::new("1.5");
+        assert_token(&mut lexer, Token::Float(1.5), 0, 0, 2);
+        assert_token(&mut lexer, Token::Eof, 0, 3, 3);
+    }
+
+    #[test]
+    fn test_float_leading_dot() {
+        let mut lexer = Lexer::new(".25*t");
+        assert_token(&mut lexer, Token::Float(0.25), 0, 0, 2);
+        assert_token(&mut lexer, Token::Op(Operator::Mul), 0, 3, 3);
+        assert_token(&mut lexer, Token::Variable, 0, 4, 4);
+    }
+
+    #[test]
+    fn test_float_leading_zero() {
+        let mut lexer = Lexer::new("0.5");
+        assert_token(&mut lexer, Token::Float(0.5), 0, 0, 2);
+    }
+
+    #[test]
+    fn test_float_trailing_dot() {
+        let mut lexer = Lexer::new("3.+t");
+        assert_token(&mut lexer, Token::Float(3.0), 0, 0, 1);
+        assert_token(&mut lexer, Token::Op(Operator::Plus), 0, 2, 2);
+    }
+
+    #[test]
+    fn test_float_exponent() {
+        let mut lexer = Lexer::new("2e3 4.5E-1 1e+2");
+        assert_token(&mut lexer, Token::Float(2000.0), 0, 0, 2);
+        assert_token(&mut lexer, Token::Float(0.45), 0, 4, 9);
+        assert_token(&mut lexer, Token::Float(100.0), 0, 11, 14);
+    }
+
+    #[test]
+    fn test_float_exponent_needs_digits() {
+        // The 'e' isn't part of the number, it's the start of a name
+        let mut lexer = Lexer::new("2e");
+        assert_token(&mut lexer, Token::Number(2), 0, 0, 0);
+        assert_token(&mut lexer, Token::Ident("e".to_string()), 0, 1, 1);
+    }
+
+    #[test]
+    fn test_lone_dot() {
+        let mut lexer = Lexer::new(". 5");
+        assert_token(
+            &mut lexer,
+            Token::Err(LexError::UnexpectedChar('.')),
+            0,
+            0,
+            0,
+        );
+        assert_token(&mut lexer, Token::Number(5), 0, 2, 2);
+    }
+
+    #[test]
+    fn test_line_comments() {
+        let mut lexer = Lexer::new("t // by someone\n// t*2\n>>4//");
+        assert_token(&mut lexer, Token::Variable, 0, 0, 0);
+        assert_token(&mut lexer, Token::Op(Operator::Rsh), 2, 0, 1);
+        assert_token(&mut lexer, Token::Number(4), 2, 2, 2);
+        assert_token(&mut lexer, Token::Eof, 2, 5, 5);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let mut lexer = Lexer::new("t/**/*/* one\r\n * two */ 3 /*/ t */>>4");
+        assert_token(&mut lexer, Token::Variable, 0, 0, 0);
+        assert_token(&mut lexer, Token::Op(Operator::Mul), 0, 5, 5);
+        assert_token(&mut lexer, Token::Number(3), 1, 10, 10);
+        assert_token(&mut lexer, Token::Op(Operator::Rsh), 1, 20, 21);
+        assert_token(&mut lexer, Token::Number(4), 1, 22, 22);
+    }
+
+    #[test]
+    fn test_division_is_not_a_comment() {
+        let mut lexer = Lexer::new("t/ /2");
+        assert_token(&mut lexer, Token::Variable, 0, 0, 0);
+        assert_token(&mut lexer, Token::Op(Operator::Div), 0, 1, 1);
+        assert_token(&mut lexer, Token::Op(Operator::Div), 0, 3, 3);
+        assert_token(&mut lexer, Token::Number(2), 0, 4, 4);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let mut lexer = Lexer::new("t>>4 /* t*2\n*");
+        assert_token(&mut lexer, Token::Variable, 0, 0, 0);
+        assert_token(&mut lexer, Token::Op(Operator::Rsh), 0, 1, 2);
+        assert_token(&mut lexer, Token::Number(4), 0, 3, 3);
+        assert_token(
+            &mut lexer,
+            Token::Err(LexError::UnterminatedComment),
+            0,
+            5,
+            6,
+        );
+        assert_token(&mut lexer, Token::Eof, 1, 1, 1);
+    }
+
     #[test]
     fn test_zero_followed_by_variable() {
         let mut lexer = Lexer::new("0t");
@@ -0,0 +1,434 @@
+//! Finds what a beat probably doesn't mean, without evaluating it: shifts and divisions that can't work, `& 0`,
+//! comparisons that always go the same way, C's precedence traps, and `=` where `==` was likely meant.
+//!
+//! Runs before folding, so it sees the beat as it was written. Classic beats are linted with the C type of every
+//! node from [`typecheck::types`], floatbeats only with what doesn't need types.
+use super::ctype::{CInt, CType};
+use super::eval::{Arithmetic, Value};
+use super::typecheck;
+use super::{ASTNode, Lint, NodeId, Operator, Parsed, Place, TType, Warning};
+
+/// In the order they're written
+pub fn lint(parsed: &Parsed, t_type: TType) -> Vec<Warning> {
+    let types = match parsed.arithmetic {
+        Arithmetic::Float => None,
+        Arithmetic::Int | Arithmetic::C => {
+            Some(typecheck::types(&parsed.nodes, &parsed.roots, t_type))
+        }
+    };
+    let mut linter = Linter {
+        parsed,
+        types,
+        t: match t_type {
+            TType::Int => CType::Int,
+            TType::Unsigned => CType::UInt,
+        },
+        found: Vec::new(),
+    };
+    for &root in &parsed.roots {
+        linter.visit(root);
+    }
+    let mut found = linter.found;
+    found.sort_by_key(|warning| {
+        let span = warning.span();
+        (span.line, span.start)
+    });
+    found
+}
+
+/// C's precedence is known to surprise when `inner` is an operand of `outer` without parentheses. Like
+/// `-Wparentheses`, mixing shifts with the bitwise operators is fine: `t>>4&t` is everywhere.
+fn is_trap(outer: Operator, inner: Operator) -> bool {
+    match outer {
+        // `t & 4 == 0` is `t & (4 == 0)`
+        Operator::And | Operator::Or | Operator::BitXor => is_comparison(inner),
+        // `1 << t + 1` is `1 << (t + 1)`
+        Operator::Lsh | Operator::Rsh => matches!(inner, Operator::Plus | Operator::Minus),
+        Operator::LogOr => inner == Operator::LogAnd,
+        _ => false,
+    }
+}
+
+fn is_comparison(op: Operator) -> bool {
+    matches!(
+        op,
+        Operator::Eq | Operator::Ne | Operator::Gt | Operator::Lt | Operator::Ge | Operator::Le
+    )
+}
+
+/// Smallest and largest value
+type Range = (i128, i128);
+
+fn within(range: Range, bounds: Range) -> bool {
+    bounds.0 <= range.0 && range.1 <= bounds.1
+}
+
+struct Linter<'a> {
+    parsed: &'a Parsed,
+    /// By [`NodeId`], for classic beats only
+    types: Option<Vec<CType>>,
+    /// Type of 't'
+    t: CType,
+    found: Vec<Warning>,
+}
+
+impl Linter<'_> {
+    fn record(&mut self, lint: Lint, id: NodeId) {
+        self.found.push(Warning::Lint(lint, self.parsed.spans[id]));
+    }
+
+    /// Nodes added after parsing weren't written in any
+    fn parentheses(&self, id: NodeId) -> u8 {
+        self.parsed.parentheses.get(id).copied().unwrap_or(0)
+    }
+
+    /// Lints the node and everything below it
+    fn visit(&mut self, id: NodeId) {
+        let parsed = self.parsed;
+        match &parsed.nodes[id] {
+            ASTNode::Assign(op, place, value) => {
+                if let Some(base) = op.compound_base() {
+                    let ty = self.types.as_ref().map(|_| match place {
+                        Place::T => self.t,
+                        Place::Local(_) => CType::Int,
+                    });
+                    self.operation(id, base, None, ty, *value);
+                }
+                self.visit(*value);
+            }
+            ASTNode::Unary(op, operand) => {
+                if *op == Operator::LogNot {
+                    self.condition(*operand);
+                }
+                self.visit(*operand);
+            }
+            ASTNode::Cast(_, operand) => self.visit(*operand),
+            ASTNode::Binary(op, left, right) => {
+                let ty = self.types.as_ref().map(|types| types[*left]);
+                self.operation(id, *op, Some(*left), ty, *right);
+                if is_comparison(*op) {
+                    self.comparison(id, *op, *left, *right);
+                }
+                if matches!(op, Operator::LogAnd | Operator::LogOr) {
+                    self.condition(*left);
+                    self.condition(*right);
+                }
+                for (operand, other) in [(*left, *right), (*right, *left)] {
+                    match parsed.nodes[operand] {
+                        // `t > 0 & t < 99` is like `&&` without short-circuiting, which is fine
+                        ASTNode::Binary(inner, ..)
+                            if is_comparison(inner) && self.is_boolean(other) => {}
+                        ASTNode::Binary(inner, ..)
+                            if self.parentheses(operand) == 0 && is_trap(*op, inner) =>
+                        {
+                            self.record(Lint::Precedence(inner, *op), operand);
+                        }
+                        _ => {}
+                    }
+                }
+                self.visit(*left);
+                self.visit(*right);
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                self.condition(*cond);
+                self.visit(*cond);
+                self.visit(*true_branch);
+                self.visit(*false_branch);
+            }
+            ASTNode::Call(_, args) => {
+                for arg in args {
+                    self.visit(*arg);
+                }
+            }
+            ASTNode::Index(table, index) => {
+                if let ASTNode::Array(items) = &parsed.nodes[*table] {
+                    for item in items {
+                        self.visit(*item);
+                    }
+                }
+                self.visit(*index);
+            }
+            ASTNode::Literal(_)
+            | ASTNode::TypedLiteral(_)
+            | ASTNode::FloatLiteral(_)
+            | ASTNode::Variable
+            | ASTNode::Local(_)
+            | ASTNode::Knob(_)
+            | ASTNode::Str(_)
+            | ASTNode::Array(_)
+            | ASTNode::Error(_) => {}
+        }
+    }
+
+    /// `left op right`, where a missing `left` is the variable of a compound assignment. `left_type` is only known
+    /// for classic beats.
+    fn operation(
+        &mut self,
+        id: NodeId,
+        op: Operator,
+        left: Option<NodeId>,
+        left_type: Option<CType>,
+        right: NodeId,
+    ) {
+        let is_zero = |id: NodeId| self.constant(id).is_some_and(|c| c.wide() == 0);
+        match op {
+            Operator::Div | Operator::Mod if is_zero(right) => {
+                self.record(Lint::DivisionByZero, id);
+            }
+            Operator::And if is_zero(right) || left.is_some_and(is_zero) => {
+                self.record(Lint::AndZero, id);
+            }
+            Operator::Lsh | Operator::Rsh => {
+                // JavaScript only uses the low 5 bits of the count, which is just as surprising
+                let width = left_type.map_or(32, CType::width);
+                match self.constant(right) {
+                    Some(count) if !(0..width as i128).contains(&count.wide()) => {
+                        self.record(Lint::ShiftCount(count.wide(), width), id);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Only for classic beats, where every value has a C type to bound it
+    fn comparison(&mut self, id: NodeId, op: Operator, left: NodeId, right: NodeId) {
+        let Some(types) = &self.types else {
+            return;
+        };
+        let (a, b) = (self.range(left), self.range(right));
+        // Folding takes care of these, and they may be on purpose
+        if a.0 == a.1 && b.0 == b.1 {
+            return;
+        }
+        // Both are converted to the common type first, which may change their value
+        let common = types[left].common(types[right]).bounds();
+        if !within(a, common) || !within(b, common) {
+            return;
+        }
+        let disjoint = a.1 < b.0 || b.1 < a.0;
+        let always = match op {
+            Operator::Lt if a.1 < b.0 => Some(true),
+            Operator::Lt if a.0 >= b.1 => Some(false),
+            Operator::Le if a.1 <= b.0 => Some(true),
+            Operator::Le if a.0 > b.1 => Some(false),
+            Operator::Gt if a.0 > b.1 => Some(true),
+            Operator::Gt if a.1 <= b.0 => Some(false),
+            Operator::Ge if a.0 >= b.1 => Some(true),
+            Operator::Ge if a.1 < b.0 => Some(false),
+            Operator::Eq if disjoint => Some(false),
+            Operator::Ne if disjoint => Some(true),
+            _ => None,
+        };
+        if let Some(always) = always {
+            self.record(Lint::AlwaysSame(always), id);
+        }
+    }
+
+    /// `id` decides which way a ternary or logical operator goes. C asks for an extra pair of parentheses around
+    /// an assignment that's meant to be there.
+    fn condition(&mut self, id: NodeId) {
+        if matches!(self.parsed.nodes[id], ASTNode::Assign(Operator::Assign, ..))
+            && self.parentheses(id) < 2
+        {
+            self.record(Lint::AssignInCondition, id);
+        }
+    }
+
+    /// Always 0 or 1, like a comparison
+    fn is_boolean(&self, id: NodeId) -> bool {
+        match self.parsed.nodes[id] {
+            ASTNode::Binary(op, ..) if is_comparison(op) => true,
+            ASTNode::Binary(Operator::LogAnd | Operator::LogOr, ..)
+            | ASTNode::Unary(Operator::LogNot, _) => true,
+            ASTNode::Binary(Operator::And | Operator::Or | Operator::BitXor, left, right) => {
+                self.is_boolean(left) && self.is_boolean(right)
+            }
+            _ => false,
+        }
+    }
+
+    /// Every value the node may have, as far as it's easy to tell. Only for classic beats.
+    fn range(&self, id: NodeId) -> Range {
+        let types = self.types.as_ref().expect("only classic beats have ranges");
+        let full = types[id].bounds();
+        if let Some(c) = self.constant(id) {
+            return (c.wide(), c.wide());
+        }
+        let range = match &self.parsed.nodes[id] {
+            ASTNode::Cast(ty, operand) => {
+                let range = self.range(*operand);
+                if within(range, ty.bounds()) {
+                    range
+                } else {
+                    ty.bounds()
+                }
+            }
+            // Whichever side can't be negative limits it
+            ASTNode::Binary(Operator::And, left, right) => {
+                match (self.range(*left), self.range(*right)) {
+                    (a, b) if a.0 >= 0 && b.0 >= 0 => (0, a.1.min(b.1)),
+                    (a, _) if a.0 >= 0 => (0, a.1),
+                    (_, b) if b.0 >= 0 => (0, b.1),
+                    _ => full,
+                }
+            }
+            ASTNode::Binary(Operator::Mod, left, right) => match self.constant(*right) {
+                Some(divisor) if divisor.wide() != 0 => {
+                    let largest = CInt::cast(types[id], divisor).wide().abs() - 1;
+                    if types[id].is_unsigned() || self.range(*left).0 >= 0 {
+                        (0, largest)
+                    } else {
+                        (-largest, largest)
+                    }
+                }
+                _ => full,
+            },
+            ASTNode::Binary(Operator::Rsh, left, right) => match self.constant(*right) {
+                Some(count) if (0..types[id].width() as i128).contains(&count.wide()) => {
+                    let range = self.range(*left);
+                    (range.0 >> count.wide(), range.1 >> count.wide())
+                }
+                _ => full,
+            },
+            ASTNode::Binary(op, ..) if is_comparison(*op) => (0, 1),
+            ASTNode::Binary(Operator::LogAnd | Operator::LogOr, ..)
+            | ASTNode::Unary(Operator::LogNot, _) => (0, 1),
+            ASTNode::Ternary(_, true_branch, false_branch) => {
+                let (a, b) = (self.range(*true_branch), self.range(*false_branch));
+                (a.0.min(b.0), a.1.max(b.1))
+            }
+            _ => full,
+        };
+        (range.0.max(full.0), range.1.min(full.1))
+    }
+
+    /// Value of the node if it's the same for every 't'. Floatbeats only have constant literals.
+    fn constant(&self, id: NodeId) -> Option<CInt> {
+        let classic = self.types.is_some();
+        match &self.parsed.nodes[id] {
+            ASTNode::Literal(n) => Some(CInt::from_int(*n)),
+            ASTNode::TypedLiteral(c) => Some(*c),
+            ASTNode::FloatLiteral(f)
+                if !classic && f.fract() == 0.0 && f.abs() <= i32::MAX as f64 =>
+            {
+                Some(CInt::from_int(*f as i32))
+            }
+            ASTNode::Unary(op, operand) if classic || *op == Operator::Minus => {
+                self.constant(*operand).map(|v| CInt::unary(*op, v))
+            }
+            ASTNode::Cast(ty, operand) => self.constant(*operand).map(|v| CInt::cast(*ty, v)),
+            ASTNode::Binary(op, left, right) if classic => {
+                let (l, r) = (self.constant(*left)?, self.constant(*right)?);
+                Some(CInt::binary(*op, l, r))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Mode, Span};
+
+    fn lints_in(source: &str, mode: Mode, t_type: TType) -> Vec<Lint> {
+        let parsed = Parsed::new(source, mode, t_type).unwrap();
+        lint(&parsed, t_type)
+            .into_iter()
+            .map(|warning| match warning {
+                Warning::Lint(lint, _) => lint,
+                Warning::Undefined(..) => panic!("Not a lint: {warning}"),
+            })
+            .collect()
+    }
+
+    fn lints(source: &str) -> Vec<Lint> {
+        lints_in(source, Mode::Classic, TType::Int)
+    }
+
+    #[test]
+    fn test_constant_operands() {
+        assert_eq!(lints("t >> 32"), vec![Lint::ShiftCount(32, 32)]);
+        assert_eq!(lints("t << -1"), vec![Lint::ShiftCount(-1, 32)]);
+        assert_eq!(lints("(long)t << 40"), vec![]);
+        assert_eq!(lints("a <<= 16 * 2"), vec![Lint::ShiftCount(32, 32)]);
+        assert_eq!(lints("t / (1 - 1)"), vec![Lint::DivisionByZero]);
+        assert_eq!(lints("t %= 0"), vec![Lint::DivisionByZero]);
+        assert_eq!(lints("0 & t"), vec![Lint::AndZero]);
+        assert_eq!(lints("t * (t >> 8 & 0)"), vec![Lint::AndZero]);
+        assert_eq!(lints("t / (t & 1)"), vec![]);
+        assert_eq!(
+            lints_in("t >> 32", Mode::Float, TType::Int),
+            vec![Lint::ShiftCount(32, 32)]
+        );
+        assert_eq!(
+            lints_in("t / 0.0", Mode::Float, TType::Int),
+            vec![Lint::DivisionByZero]
+        );
+    }
+
+    #[test]
+    fn test_always_same_comparisons() {
+        assert_eq!(lints("(t & 255) > 255"), vec![Lint::AlwaysSame(false)]);
+        assert_eq!(
+            lints("(unsigned char)t <= 255"),
+            vec![Lint::AlwaysSame(true)]
+        );
+        assert_eq!(lints("t % 8 == 8"), vec![Lint::AlwaysSame(false)]);
+        assert_eq!(lints("(t >> 28) != 16"), vec![Lint::AlwaysSame(true)]);
+        assert_eq!(lints("(t ? 1 : 2) < 3"), vec![Lint::AlwaysSame(true)]);
+        assert_eq!(
+            lints_in("t >= 0", Mode::Classic, TType::Unsigned),
+            vec![Lint::AlwaysSame(true)]
+        );
+        // -1 is converted to `unsigned` first, so it depends on 't'
+        assert_eq!(lints_in("t > -1", Mode::Classic, TType::Unsigned), vec![]);
+        assert_eq!(lints("(t & 255) > 254"), vec![]);
+        assert_eq!(lints("t % 8 == 7"), vec![]);
+        // Constant on both sides, so it's folded and may well be on purpose
+        assert_eq!(lints("1 > 2"), vec![]);
+        assert_eq!(lints_in("t % 8 > 8", Mode::Float, TType::Int), vec![]);
+    }
+
+    #[test]
+    fn test_precedence_traps() {
+        assert_eq!(
+            lints("t & 4 == 0"),
+            vec![Lint::AndZero, Lint::Precedence(Operator::Eq, Operator::And)]
+        );
+        assert_eq!(
+            lints("1 << t + 1"),
+            vec![Lint::Precedence(Operator::Plus, Operator::Lsh)]
+        );
+        assert_eq!(
+            lints("t > 1 && t < 9 || t"),
+            vec![Lint::Precedence(Operator::LogAnd, Operator::LogOr)]
+        );
+        assert_eq!(lints("(t & 4) == 0"), vec![]);
+        assert_eq!(lints("(1 << t) + 1"), vec![]);
+        assert_eq!(lints("t >> 4 & t"), vec![]);
+        assert_eq!(lints("t > 0 & t < 99 | !t"), vec![]);
+    }
+
+    #[test]
+    fn test_assign_in_condition() {
+        assert_eq!(lints("(a = 1) ? t : 0"), vec![Lint::AssignInCondition]);
+        assert_eq!(lints("t && (a = 1)"), vec![Lint::AssignInCondition]);
+        assert_eq!(lints("!(a = t)"), vec![Lint::AssignInCondition]);
+        assert_eq!(lints("((a = t)) ? a : 0"), vec![]);
+        assert_eq!(lints("(a == 1) ? t : 0"), vec![]);
+        assert_eq!(lints("a = t > 1 ? t : 0"), vec![]);
+    }
+
+    #[test]
+    fn test_in_written_order() {
+        let parsed = Parsed::new("t / 0 + (t << 40)", Mode::Classic, TType::Int).unwrap();
+        let spans: Vec<Span> = lint(&parsed, TType::Int)
+            .iter()
+            .map(Warning::span)
+            .collect();
+        assert_eq!(spans, vec![Span::new(0, 2, 2), Span::new(0, 11, 12)]);
+    }
+}
@@ -1,112 +1,292 @@
-//! Pratt Parser intended to handle a single statement in a C subset.
+//! Pratt Parser intended to handle a C subset: expressions, optionally with assignments and `;` between them.
 //!
-//! Will wrap multiple errors (including Lexer errors) and has some (unreliable) recoverability.
+//! Will wrap multiple errors (including Lexer errors). After a syntax error it skips ahead to the next `)`, `:`, `,`
+//! or `;` it can carry on from, so every independent error is reported at once.
 //!
 //! LLM SLOP PRESENCE: EXTREME
-use crate::parser::Spanned;
+use crate::parser::{Span, Spanned};
 
+use super::ctype::CType;
 use super::lex::Lexer;
-use super::{ASTNode, NodeId, Operator, ParseError, Token};
+use super::{
+    ASTNode, Builtin, KnobId, LocalId, MAX_KNOBS, MAX_LOCALS, Mode, NodeId, Operator, ParseError,
+    Place, Token,
+};
 
-/// Wraps a lexer and pulls tokens out to build an AST. Must process a single statement with at least one expression.
+/// Right-associative and looser than the ternary, so `a = b ? c : d` assigns the ternary.
+pub(super) const ASSIGN_BP: (u8, u8) = (8, 7);
+/// Tighter than [`Operator::Comma`], so call arguments are separated by it rather than sequenced
+pub(super) const ARGUMENT_BP: u8 = 5;
+/// Operand of `-` `!` `~`. Tighter than any infix operator.
+pub(super) const PREFIX_BP: u8 = 99;
+/// Indexing binds tighter than any prefix operator: `-"12"[t]` negates the element
+const INDEX_BP: u8 = 100;
+/// Names that start a cast when they follow a '('. `float` and `double` aren't allowed, but they're recognized.
+const TYPE_KEYWORDS: &[&str] = &[
+    "signed", "unsigned", "char", "short", "int", "long", "float", "double",
+];
+
+/// Wraps a lexer and pulls tokens out to build an AST. Must process at least one expression.
 pub struct Parser<'a, 'b> {
     lexer: Lexer<'a>,
     current: Spanned<Token>,
+    /// Of the token before `current`
+    previous: Span,
     arena: &'b mut Vec<ASTNode>,
+    /// None are fatal. Parsing carries on around each, so they're all reported at once
     errors: Vec<ParseError>,
+    /// Float literals are only accepted in [`Mode::Float`]
+    mode: Mode,
+    /// Names of local variables. Index is the [`LocalId`].
+    locals: Vec<String>,
+    /// Names of knobs, without the `$`. Index is the [`KnobId`].
+    knobs: Vec<String>,
+    /// Of every node in the arena, by [`NodeId`]. For operators it's the operator itself.
+    spans: Vec<Span>,
+    /// How many pairs of parentheses every node was written in, by [`NodeId`]
+    parentheses: Vec<u8>,
 }
 
 impl<'a, 'b> Parser<'a, 'b> {
-    pub fn new(input: &'a str, arena: &'b mut Vec<ASTNode>) -> Self {
+    pub fn new(input: &'a str, arena: &'b mut Vec<ASTNode>, mode: Mode) -> Self {
         let mut lexer = Lexer::new(input);
         let current = lexer.next();
         Parser {
             lexer,
             current,
+            previous: Span::new(0, 0, 0),
             arena,
             errors: Vec::new(),
+            mode,
+            locals: Vec::new(),
+            knobs: Vec::new(),
+            spans: Vec::new(),
+            parentheses: Vec::new(),
         }
     }
 
     fn advance(&mut self) {
+        self.previous = self.current.span;
         self.current = self.lexer.next();
     }
 
+    /// Names of the local variables, indexed by [`LocalId`]
+    pub fn into_locals(self) -> Vec<String> {
+        self.locals
+    }
+
+    /// Names of the knobs, indexed by [`KnobId`]
+    pub fn take_knobs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.knobs)
+    }
+
+    /// Where each node of the arena came from, indexed by [`NodeId`]. Nodes added later should get the span of the
+    /// one they stand in for.
+    pub fn take_spans(&mut self) -> Vec<Span> {
+        std::mem::take(&mut self.spans)
+    }
+
+    /// How many pairs of parentheses each node of the arena was written in, indexed by [`NodeId`]. Nodes added later
+    /// weren't written at all.
+    pub fn take_parentheses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.parentheses)
+    }
+
     pub fn parse(&mut self) -> Result<NodeId, Vec<ParseError>> {
-        let result = self.parse_bp(0);
-        match result {
-            // We may still have errors overall even if the root parse is Ok.
-            Ok(root) => {
-                if self.errors.is_empty() {
-                    Ok(root)
-                } else {
-                    Err(std::mem::take(&mut self.errors))
-                }
+        let root = self.parse_bp(0);
+        // Whatever is left over only gets parsed for its errors
+        while *self.current != Token::Eof {
+            if self.at_closing() {
+                let token = self.current.node.clone();
+                self.errors
+                    .push(ParseError::UnexpectedToken(token, self.current.span));
+                self.advance();
+            } else {
+                self.parse_bp(0);
             }
-            Err(e) => {
-                self.errors.push(e);
-                Err(std::mem::take(&mut self.errors))
+        }
+        if self.errors.is_empty() {
+            Ok(root)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Panic mode: skips tokens until one the expression being parsed could end at, which is ')', ']', ':', ',',
+    /// ';' or the end. Anything opened while skipping is skipped whole, so a ':' only counts when its '?' came
+    /// before the error. Lexer errors on the way are still reported.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        let mut ternaries = 0usize;
+        loop {
+            match *self.current {
+                Token::Eof => return,
+                Token::Op(Operator::Lparen | Operator::Lbracket) => depth += 1,
+                Token::Op(Operator::Rparen | Operator::Rbracket) if depth == 0 => return,
+                Token::Op(Operator::Rparen | Operator::Rbracket) => depth -= 1,
+                Token::Op(Operator::Question) if depth == 0 => ternaries += 1,
+                Token::Op(Operator::Colon) if depth == 0 && ternaries > 0 => ternaries -= 1,
+                Token::Op(Operator::Colon | Operator::Comma | Operator::Semicolon)
+                    if depth == 0 =>
+                {
+                    return;
+                }
+                Token::Err(ref e) => {
+                    let err = ParseError::LexError(e.clone(), self.current.span);
+                    self.errors.push(err);
+                }
+                _ => {}
             }
+            self.advance();
         }
     }
 
-    fn push_node(&mut self, node: ASTNode) -> NodeId {
+    /// At a token that only ends something: ')', ']' or ':'
+    fn at_closing(&self) -> bool {
+        matches!(
+            *self.current,
+            Token::Op(Operator::Rparen | Operator::Rbracket | Operator::Colon)
+        )
+    }
+
+    fn push_node(&mut self, node: ASTNode, span: Span) -> NodeId {
         let id = self.arena.len();
         self.arena.push(node);
+        self.spans.push(span);
+        self.parentheses.push(0);
         id
     }
 
-    fn parse_bp(&mut self, min_bp: u8) -> Result<NodeId, ParseError> {
+    /// Never fails: errors are collected and the node is [`ASTNode::Error`], or whatever could be made of it.
+    fn parse_bp(&mut self, min_bp: u8) -> NodeId {
         let mut left = match *self.current {
             Token::Number(n) => {
-                let node = ASTNode::Literal(n);
+                let (node, span) = (ASTNode::Literal(n), self.current.span);
+                self.advance();
+                self.push_node(node, span)
+            }
+            Token::TypedNumber(c) => {
+                let node = match self.mode {
+                    Mode::Classic => ASTNode::TypedLiteral(c),
+                    // JavaScript doesn't have suffixes, but the value is still fine
+                    Mode::Float => ASTNode::FloatLiteral(c.wide() as f64),
+                };
+                let span = self.current.span;
+                self.advance();
+                self.push_node(node, span)
+            }
+            Token::Float(f) => {
+                if self.mode == Mode::Classic {
+                    self.errors
+                        .push(ParseError::FloatInClassic(self.current.span));
+                }
+                let (node, span) = (ASTNode::FloatLiteral(f), self.current.span);
                 self.advance();
-                self.push_node(node)
+                self.push_node(node, span)
             }
             Token::Variable => {
-                let node = ASTNode::Variable;
+                let span = self.current.span;
+                self.advance();
+                self.push_node(ASTNode::Variable, span)
+            }
+            Token::Ident(ref name) => {
+                let (name, span) = (name.clone(), self.current.span);
                 self.advance();
-                self.push_node(node)
+                self.parse_name(name, span)
+            }
+            Token::Knob(ref name) => {
+                let (name, span) = (name.clone(), self.current.span);
+                self.advance();
+                match self.knob(name, span) {
+                    Some(id) => self.push_node(ASTNode::Knob(id), span),
+                    None => self.push_node(ASTNode::Error(span), span),
+                }
+            }
+            Token::Char(c) if self.mode == Mode::Classic => {
+                let (node, span) = (ASTNode::Literal(c as i32), self.current.span);
+                self.advance();
+                self.push_node(node, span)
+            }
+            // JavaScript doesn't have characters, only strings
+            Token::Str(_) | Token::Char(_) => {
+                let (text, span) = match *self.current {
+                    Token::Char(c) => (c.to_string(), self.current.span),
+                    Token::Str(ref text) => (text.clone(), self.current.span),
+                    _ => unreachable!(),
+                };
+                self.advance();
+                self.expect_index(span);
+                self.push_node(ASTNode::Str(text), span)
+            }
+            Token::Op(Operator::Lbracket) => {
+                let span = self.current.span;
+                // Nothing before it, so it may be the whole beat
+                let first = min_bp == 0 && self.arena.is_empty();
+                self.advance();
+                let items = self.parse_list(span, Operator::Rbracket);
+                if first && *self.current == Token::Eof {
+                    // `[left, right]` is a stereo beat
+                    if items.len() != 2 {
+                        self.errors.push(ParseError::Channels(items.len(), span));
+                    }
+                } else {
+                    self.expect_index(span);
+                }
+                self.push_node(ASTNode::Array(items), span)
             }
             Token::Op(Operator::Lparen) => {
+                let span = self.current.span;
                 self.advance();
-                let expr = self.parse_bp(0)?;
-                if let Token::Op(Operator::Rparen) = *self.current {
-                    self.advance();
-                    expr
+                if self.at_type_name() {
+                    self.parse_cast(span)
                 } else {
-                    return Err(ParseError::UnmatchedParenthesis(self.current.span));
+                    let expr = self.parse_bp(0);
+                    if let Token::Op(Operator::Rparen) = *self.current {
+                        self.advance();
+                        self.parentheses[expr] = self.parentheses[expr].saturating_add(1);
+                    } else {
+                        // Carry on as if it was closed here
+                        self.errors
+                            .push(ParseError::UnmatchedParenthesis(self.current.span, span));
+                    }
+                    expr
                 }
             }
+            // Missing operand. These are left for what they close or continue.
+            Token::Op(
+                op @ (Operator::Rparen
+                | Operator::Rbracket
+                | Operator::Colon
+                | Operator::Comma
+                | Operator::Semicolon),
+            ) => {
+                let span = self.current.span;
+                self.errors.push(ParseError::UnexpectedPrefix(op, span));
+                self.push_node(ASTNode::Error(span), span)
+            }
             Token::Op(op) => {
                 // Prefix operators handling (Unary minus, etc.)
                 let (_, right_bp) = match op {
                     Operator::Minus | Operator::Plus | Operator::LogNot | Operator::BitNot => {
-                        ((), 99)
+                        ((), PREFIX_BP)
+                    }
+                    _ => {
+                        self.errors
+                            .push(ParseError::UnexpectedPrefix(op, self.current.span));
+                        // Skipped, as if it wasn't there
+                        self.advance();
+                        return self.parse_bp(min_bp);
                     }
-                    _ => return Err(ParseError::UnexpectedPrefix(op, self.current.span)),
                 };
 
                 // Need to consume the operator
-                let op_val = op;
+                let (op_val, span) = (op, self.current.span);
                 self.advance();
-                let right = self.parse_bp(right_bp)?;
+                let right = self.parse_bp(right_bp);
 
                 match op_val {
-                    Operator::Minus => {
-                        let zero = self.push_node(ASTNode::Literal(0));
-                        self.push_node(ASTNode::Binary(Operator::Minus, zero, right))
-                    }
                     Operator::Plus => right,
-                    Operator::LogNot => {
-                        let zero = self.push_node(ASTNode::Literal(0));
-                        self.push_node(ASTNode::Binary(Operator::LogNot, zero, right))
-                    }
-                    Operator::BitNot => {
-                        let zero = self.push_node(ASTNode::Literal(0));
-                        self.push_node(ASTNode::Binary(Operator::BitNot, zero, right))
-                    }
-                    _ => unreachable!(),
+                    _ => self.push_node(ASTNode::Unary(op_val, right), span),
                 }
             }
             Token::Err(ref e) => {
@@ -114,9 +294,13 @@ impl<'a, 'b> Parser<'a, 'b> {
                 let err = ParseError::LexError(e.clone(), span);
                 self.errors.push(err);
                 self.advance();
-                self.push_node(ASTNode::Error(span))
+                self.push_node(ASTNode::Error(span), span)
+            }
+            Token::Eof => {
+                let span = self.current.span;
+                self.errors.push(ParseError::UnexpectedEof(span));
+                return self.push_node(ASTNode::Error(span), span);
             }
-            Token::Eof => return Err(ParseError::UnexpectedEof(self.current.span)),
         };
 
         loop {
@@ -128,37 +312,94 @@ impl<'a, 'b> Parser<'a, 'b> {
                     let err = ParseError::LexError(e.clone(), span);
                     self.errors.push(err);
                     self.advance();
-                    // TODO: This probably leaves a lot of gaps but not high priority
-                    // this is best-effort to getting all errors we can at once
-                    break;
+                    self.synchronize();
+                    continue;
                 }
                 ref t => {
-                    // Need to clone state on this path to send a rich error
-                    return Err(ParseError::ExpectedOperator(
-                        t.clone(),
-                        self.current.span.clone(),
-                    ));
+                    let err =
+                        ParseError::ExpectedOperator(t.clone(), self.current.span, self.previous);
+                    self.errors.push(err);
+                    self.synchronize();
+                    continue;
                 }
             };
 
+            if op.is_assignment() {
+                let (l_bp, r_bp) = ASSIGN_BP;
+                if l_bp < min_bp {
+                    break;
+                }
+                let span = self.current.span;
+                self.advance();
+                let value = self.parse_bp(r_bp);
+
+                left = match self.arena[left] {
+                    ASTNode::Variable => self.push_node(ASTNode::Assign(op, Place::T, value), span),
+                    ASTNode::Local(id) => {
+                        self.push_node(ASTNode::Assign(op, Place::Local(id), value), span)
+                    }
+                    // Already reported
+                    ASTNode::Error(_) => left,
+                    _ => {
+                        self.errors.push(ParseError::InvalidAssignment(op, span));
+                        self.push_node(ASTNode::Error(span), span)
+                    }
+                };
+                continue;
+            }
+
+            if let Operator::Lbracket = op {
+                if INDEX_BP < min_bp {
+                    break;
+                }
+                let span = self.current.span;
+                self.advance(); // consume '['
+                let index = self.parse_bp(0);
+                if *self.current == Token::Op(Operator::Rbracket) {
+                    self.advance(); // consume ']'
+                } else {
+                    // Carry on as if it was closed here
+                    self.errors
+                        .push(ParseError::UnmatchedBracket(self.current.span, span));
+                }
+
+                left = match self.arena[left] {
+                    ASTNode::Str(_) | ASTNode::Array(_) => {
+                        self.push_node(ASTNode::Index(left, index), span)
+                    }
+                    // Already reported
+                    ASTNode::Error(_) => left,
+                    _ => {
+                        self.errors.push(ParseError::NotIndexable(span));
+                        self.push_node(ASTNode::Error(span), span)
+                    }
+                };
+                continue;
+            }
+
             // Postfix ?
             if let Operator::Question = op {
                 let (l_bp, r_bp) = infix_binding_power(op);
                 if l_bp < min_bp {
                     break;
                 }
+                let span = self.current.span;
                 self.advance(); // consume '?'
 
-                let true_branch = self.parse_bp(0)?;
+                let true_branch = self.parse_bp(0);
 
-                if let Token::Op(Operator::Colon) = *self.current {
+                let false_branch = if let Token::Op(Operator::Colon) = *self.current {
                     self.advance(); // consume ':'
-                    let false_branch = self.parse_bp(r_bp)?;
-                    left = self.push_node(ASTNode::Ternary(left, true_branch, false_branch));
-                    continue;
+                    self.parse_bp(r_bp)
                 } else {
-                    return Err(ParseError::ExpectedTernaryColon(self.current.span));
-                }
+                    let missing = self.current.span;
+                    self.errors
+                        .push(ParseError::ExpectedTernaryColon(missing, span));
+                    self.push_node(ASTNode::Error(missing), missing)
+                };
+                let ternary = ASTNode::Ternary(left, true_branch, false_branch);
+                left = self.push_node(ternary, span);
+                continue;
             }
 
             if let Some((l_bp, r_bp)) = binding_power(op) {
@@ -166,9 +407,14 @@ impl<'a, 'b> Parser<'a, 'b> {
                     break;
                 }
 
+                let span = self.current.span;
                 self.advance();
-                let right = self.parse_bp(r_bp)?;
-                left = self.push_node(ASTNode::Binary(op, left, right));
+                // A trailing ';' is fine, like the end of a C statement
+                if op == Operator::Semicolon && *self.current == Token::Eof {
+                    break;
+                }
+                let right = self.parse_bp(r_bp);
+                left = self.push_node(ASTNode::Binary(op, left, right), span);
                 continue;
             } else {
                 // Not an infix operator (e.g. Rparen) or unknown
@@ -176,11 +422,165 @@ impl<'a, 'b> Parser<'a, 'b> {
             }
         }
 
-        Ok(left)
+        left
+    }
+
+    /// Continues after a name has been consumed. It's a call if it's a [`Builtin`] or followed by '(', and a local
+    /// variable otherwise. Unknown functions still have their arguments parsed for more errors.
+    fn parse_name(&mut self, name: String, span: Span) -> NodeId {
+        let builtin = Builtin::from_name(&name);
+        let is_call = *self.current == Token::Op(Operator::Lparen);
+        if builtin.is_none() {
+            if !is_call {
+                let node = match self.local(name, span) {
+                    Some(id) => ASTNode::Local(id),
+                    None => ASTNode::Error(span),
+                };
+                return self.push_node(node, span);
+            }
+            self.errors.push(ParseError::UnknownIdentifier(name, span));
+        }
+
+        let args = if is_call {
+            let open = self.current.span;
+            self.advance();
+            Some(self.parse_list(open, Operator::Rparen))
+        } else {
+            None
+        };
+
+        match (builtin, args) {
+            (Some(builtin), Some(args)) => {
+                if args.len() != builtin.arity() {
+                    self.errors.push(ParseError::WrongArity(
+                        builtin,
+                        builtin.arity(),
+                        args.len(),
+                        span,
+                    ));
+                }
+                self.push_node(ASTNode::Call(builtin, args), span)
+            }
+            (Some(builtin), None) => {
+                self.errors.push(ParseError::ExpectedCall(builtin, span));
+                self.push_node(ASTNode::Error(span), span)
+            }
+            (None, _) => self.push_node(ASTNode::Error(span), span),
+        }
+    }
+
+    fn at_type_name(&self) -> bool {
+        matches!(*self.current, Token::Ident(ref name) if TYPE_KEYWORDS.contains(&name.as_str()))
+    }
+
+    /// Continues after the '(' of a cast, through the ')' and its operand. Casts only make sense in C, and only
+    /// to the integer types.
+    fn parse_cast(&mut self, open: Span) -> NodeId {
+        let mut words = Vec::new();
+        while self.at_type_name() {
+            if let Token::Ident(ref name) = *self.current {
+                words.push(name.clone());
+            }
+            self.advance();
+        }
+        let closed = *self.current == Token::Op(Operator::Rparen);
+        let end = if closed {
+            self.current.span
+        } else {
+            self.previous
+        };
+        let span = if end.line == open.line {
+            Span::new(open.line, open.start, end.end)
+        } else {
+            open
+        };
+        if closed {
+            self.advance();
+        } else {
+            // Carry on as if it was closed here
+            self.errors
+                .push(ParseError::UnmatchedParenthesis(self.current.span, open));
+        }
+        let operand = self.parse_bp(PREFIX_BP);
+
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match CType::from_keywords(&words) {
+            Some(ty) if self.mode == Mode::Classic => {
+                self.push_node(ASTNode::Cast(ty, operand), span)
+            }
+            _ => {
+                self.errors
+                    .push(ParseError::InvalidCast(words.join(" "), span));
+                self.push_node(ASTNode::Error(span), span)
+            }
+        }
+    }
+
+    /// Slot of a local variable, declaring it on first use
+    fn local(&mut self, name: String, span: Span) -> Option<LocalId> {
+        if let Some(id) = self.locals.iter().position(|n| *n == name) {
+            Some(id)
+        } else if self.locals.len() < MAX_LOCALS {
+            self.locals.push(name);
+            Some(self.locals.len() - 1)
+        } else {
+            self.errors.push(ParseError::TooManyLocals(name, span));
+            None
+        }
+    }
+
+    /// Slot of a knob, like [`Parser::local`]
+    fn knob(&mut self, name: String, span: Span) -> Option<KnobId> {
+        if let Some(id) = self.knobs.iter().position(|n| *n == name) {
+            Some(id)
+        } else if self.knobs.len() < MAX_KNOBS {
+            self.knobs.push(name);
+            Some(self.knobs.len() - 1)
+        } else {
+            self.errors.push(ParseError::TooManyKnobs(name, span));
+            None
+        }
+    }
+
+    /// Comma separated expressions up to and including the `close` ')' or ']'. The opening one must already be
+    /// consumed. A trailing comma is fine, like in JavaScript. If it isn't closed, it ends where the items do.
+    fn parse_list(&mut self, open: Span, close: Operator) -> Vec<NodeId> {
+        let mut items = Vec::new();
+        loop {
+            if *self.current == Token::Op(close) {
+                self.advance();
+                return items;
+            }
+            items.push(self.parse_bp(ARGUMENT_BP));
+            match *self.current {
+                Token::Op(Operator::Comma) => self.advance(),
+                Token::Op(op) if op == close => {
+                    self.advance();
+                    return items;
+                }
+                _ if close == Operator::Rbracket => {
+                    self.errors
+                        .push(ParseError::UnmatchedBracket(self.current.span, open));
+                    return items;
+                }
+                _ => {
+                    self.errors
+                        .push(ParseError::UnmatchedParenthesis(self.current.span, open));
+                    return items;
+                }
+            }
+        }
+    }
+
+    /// String and array literals don't have a value on their own, they must be indexed right away
+    fn expect_index(&mut self, span: Span) {
+        if *self.current != Token::Op(Operator::Lbracket) {
+            self.errors.push(ParseError::Unindexed(span));
+        }
     }
 }
 
-fn binding_power(op: Operator) -> Option<(u8, u8)> {
+pub(super) fn binding_power(op: Operator) -> Option<(u8, u8)> {
     match op {
         // Multiplicative
         Operator::Mul | Operator::Div | Operator::Mod => Some((80, 81)),
@@ -199,11 +599,14 @@ fn binding_power(op: Operator) -> Option<(u8, u8)> {
         // Logical
         Operator::LogAnd => Some((25, 26)),
         Operator::LogOr => Some((20, 21)),
+        // Sequencing
+        Operator::Comma => Some((4, 5)),
+        Operator::Semicolon => Some((2, 3)),
         _ => None,
     }
 }
 
-fn infix_binding_power(op: Operator) -> (u8, u8) {
+pub(super) fn infix_binding_power(op: Operator) -> (u8, u8) {
     match op {
         Operator::Question => (10, 9), // Right associative?
         _ => (0, 0),
@@ -221,7 +624,7 @@ mod tests {
     #[test]
     fn test_basic_arithmetic() {
         let mut arena = Vec::new();
-        let mut p = Parser::new("1 + 2 * 3", &mut arena);
+        let mut p = Parser::new("1 + 2 * 3", &mut arena, Mode::Classic);
         let root = p.parse().unwrap();
 
         // 1 + (2 * 3)
@@ -242,7 +645,7 @@ mod tests {
     #[test]
     fn test_recovery() {
         let mut arena = Vec::new();
-        let mut p = Parser::new("t + @", &mut arena);
+        let mut p = Parser::new("t + @", &mut arena, Mode::Classic);
         let result = p.parse();
 
         match result {
@@ -261,10 +664,29 @@ mod tests {
         assert!(matches!(arena[2], ASTNode::Binary(Operator::Plus, _, _)));
     }
 
+    #[test]
+    fn test_unterminated_comment() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("t >> 4 /* t * 2\n", &mut arena, Mode::Classic);
+        let errors = p.parse().unwrap_err();
+
+        assert!(matches!(
+            errors[0],
+            ParseError::LexError(
+                LexError::UnterminatedComment,
+                Span {
+                    line: 0,
+                    start: 7,
+                    end: 8
+                }
+            )
+        ));
+    }
+
     #[test]
     fn test_multiple_errors() {
         let mut arena = Vec::new();
-        let mut p = Parser::new("@ + @", &mut arena);
+        let mut p = Parser::new("@ + @", &mut arena, Mode::Classic);
         let result = p.parse();
 
         match result {
@@ -280,7 +702,7 @@ mod tests {
         let mut arena = Vec::new();
         // t > 128 ? t : 0
         // > is (50, 51). ? is (10, 9).
-        let mut p = Parser::new("t > 128 ? t : 0", &mut arena);
+        let mut p = Parser::new("t > 128 ? t : 0", &mut arena, Mode::Classic);
         let root = p.parse().unwrap();
 
         if let ASTNode::Ternary(cond, _, _) = &arena[root] {
@@ -295,7 +717,7 @@ mod tests {
         let mut arena = Vec::new();
         // t ? t ? 1 : 2 : 0
         // Should parse as t ? (t ? 1 : 2) : 0 because of right associativity (10, 9)
-        let mut p = Parser::new("t ? t ? 1 : 2 : 0", &mut arena);
+        let mut p = Parser::new("t ? t ? 1 : 2 : 0", &mut arena, Mode::Classic);
         let root = p.parse().unwrap();
 
         if let ASTNode::Ternary(cond, true_branch, false_branch) = &arena[root] {
@@ -319,7 +741,7 @@ mod tests {
         let mut arena = Vec::new();
         // t ? @ : @
         // Should produce 2 errors and still form a Ternary node
-        let mut p = Parser::new("t ? @ : @", &mut arena);
+        let mut p = Parser::new("t ? @ : @", &mut arena, Mode::Classic);
         let result = p.parse();
 
         match result {
@@ -337,11 +759,342 @@ mod tests {
         assert!(matches!(arena[root], ASTNode::Ternary(_, _, _)));
     }
 
+    #[test]
+    fn test_float_literal_mode() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("t * 0.5", &mut arena, Mode::Float);
+        let root = p.parse().unwrap();
+        if let ASTNode::Binary(Operator::Mul, _, r) = &arena[root] {
+            assert_eq!(arena[*r], ASTNode::FloatLiteral(0.5));
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+
+        let mut arena = Vec::new();
+        let mut p = Parser::new("t * 0.5 + .25", &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 2);
+                assert!(matches!(errors[0], ParseError::FloatInClassic(_)));
+                assert!(matches!(errors[1], ParseError::FloatInClassic(_)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_call() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("pow(t, 2) + sin(t)", &mut arena, Mode::Float);
+        let root = p.parse().unwrap();
+
+        if let ASTNode::Binary(Operator::Plus, l, r) = &arena[root] {
+            if let ASTNode::Call(Builtin::Pow, args) = &arena[*l] {
+                assert_eq!(args.len(), 2);
+                assert_eq!(arena[args[0]], ASTNode::Variable);
+                assert_eq!(arena[args[1]], ASTNode::Literal(2));
+            } else {
+                panic!("Left call wrong: {:?}", arena[*l]);
+            }
+            assert!(matches!(&arena[*r], ASTNode::Call(Builtin::Sin, args) if args.len() == 1));
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut arena = Vec::new();
+        // Wrong arity, unknown function, and a function that isn't called
+        let mut p = Parser::new("sin(t, 1) + foo(@) + cos", &mut arena, Mode::Float);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 4);
+                assert!(matches!(
+                    errors[0],
+                    ParseError::WrongArity(Builtin::Sin, 1, 2, _)
+                ));
+                assert!(matches!(errors[1], ParseError::UnknownIdentifier(ref n, _) if n == "foo"));
+                assert!(matches!(errors[2], ParseError::LexError(_, _)));
+                assert!(matches!(
+                    errors[3],
+                    ParseError::ExpectedCall(Builtin::Cos, _)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_call_no_args() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("random() * t", &mut arena, Mode::Float);
+        let root = p.parse().unwrap();
+        if let ASTNode::Binary(Operator::Mul, l, _) = &arena[root] {
+            assert_eq!(arena[*l], ASTNode::Call(Builtin::Random, vec![]));
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+    }
+
+    #[test]
+    fn test_assignment_and_sequence() {
+        let mut arena = Vec::new();
+        // (a = (t >> 4)), (b += a); a
+        let mut p = Parser::new("a = t >> 4, b += a; a", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+
+        let ASTNode::Binary(Operator::Semicolon, l, r) = &arena[root] else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        };
+        assert_eq!(arena[*r], ASTNode::Local(0));
+        let ASTNode::Binary(Operator::Comma, first, second) = &arena[*l] else {
+            panic!("Comma structure wrong: {:?}", arena[*l]);
+        };
+        if let ASTNode::Assign(Operator::Assign, Place::Local(0), value) = &arena[*first] {
+            assert!(matches!(
+                arena[*value],
+                ASTNode::Binary(Operator::Rsh, _, _)
+            ));
+        } else {
+            panic!("First assignment wrong: {:?}", arena[*first]);
+        }
+        if let ASTNode::Assign(Operator::PlusAssign, Place::Local(1), value) = &arena[*second] {
+            assert_eq!(arena[*value], ASTNode::Local(0));
+        } else {
+            panic!("Second assignment wrong: {:?}", arena[*second]);
+        }
+    }
+
+    #[test]
+    fn test_assignment_right_associative() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("t = a = t ? 1 : 2;", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+
+        if let ASTNode::Assign(Operator::Assign, Place::T, inner) = &arena[root] {
+            if let ASTNode::Assign(Operator::Assign, Place::Local(0), value) = &arena[*inner] {
+                assert!(matches!(arena[*value], ASTNode::Ternary(_, _, _)));
+            } else {
+                panic!("Inner assignment wrong: {:?}", arena[*inner]);
+            }
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+    }
+
+    #[test]
+    fn test_commas_in_call_are_arguments() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("min((a = t, a * 2), a = 3)", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+
+        if let ASTNode::Call(Builtin::Min, args) = &arena[root] {
+            assert_eq!(args.len(), 2);
+            assert!(matches!(
+                arena[args[0]],
+                ASTNode::Binary(Operator::Comma, _, _)
+            ));
+            assert!(matches!(arena[args[1]], ASTNode::Assign(_, _, _)));
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+    }
+
+    #[test]
+    fn test_assignment_errors() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("t * 2 = 3, sin = 1, foo(t)", &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 3);
+                assert!(matches!(errors[0], ParseError::InvalidAssignment(..)));
+                assert!(matches!(
+                    errors[1],
+                    ParseError::ExpectedCall(Builtin::Sin, _)
+                ));
+                assert!(matches!(errors[2], ParseError::UnknownIdentifier(ref n, _) if n == "foo"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        let source: Vec<String> = (0..=MAX_LOCALS).map(|i| format!("v{i} = {i}")).collect();
+        let source = source.join(", ");
+        let mut arena = Vec::new();
+        let mut p = Parser::new(&source, &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 1);
+                assert!(
+                    matches!(errors[0], ParseError::TooManyLocals(ref n, _) if *n == format!("v{MAX_LOCALS}"))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_knobs() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("$b * t + $a + $b", &mut arena, Mode::Classic);
+        p.parse().unwrap();
+        assert_eq!(p.take_knobs(), ["b", "a"]);
+
+        let source: Vec<String> = (0..=MAX_KNOBS).map(|i| format!("$k{i}")).collect();
+        let source = source.join(" + ") + ", $k0 = 1";
+        let mut arena = Vec::new();
+        match Parser::new(&source, &mut arena, Mode::Classic).parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 2);
+                assert!(
+                    matches!(errors[0], ParseError::TooManyKnobs(ref n, _) if *n == format!("k{MAX_KNOBS}"))
+                );
+                assert!(matches!(errors[1], ParseError::InvalidAssignment(..)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_binds_tightest() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("-[1, 2,][t & 1] * 2", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+
+        let ASTNode::Binary(Operator::Mul, l, _) = &arena[root] else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        };
+        let ASTNode::Unary(Operator::Minus, negated) = &arena[*l] else {
+            panic!("Negation wrong: {:?}", arena[*l]);
+        };
+        if let ASTNode::Index(table, index) = &arena[*negated] {
+            assert!(matches!(&arena[*table], ASTNode::Array(items) if items.len() == 2));
+            assert!(matches!(
+                arena[*index],
+                ASTNode::Binary(Operator::And, _, _)
+            ));
+        } else {
+            panic!("Index wrong: {:?}", arena[*negated]);
+        }
+    }
+
+    #[test]
+    fn test_char_literal_mode() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("'a'", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+        assert_eq!(arena[root], ASTNode::Literal(97));
+
+        let mut arena = Vec::new();
+        let mut p = Parser::new("'a'[0]", &mut arena, Mode::Float);
+        let root = p.parse().unwrap();
+        if let ASTNode::Index(table, _) = &arena[root] {
+            assert_eq!(arena[*table], ASTNode::Str("a".to_string()));
+        } else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        }
+    }
+
+    #[test]
+    fn test_index_errors() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("\"abc\" + t[1] + [1, 2]", &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 3);
+                assert!(matches!(errors[0], ParseError::Unindexed(_)));
+                assert!(matches!(errors[1], ParseError::NotIndexable(_)));
+                assert!(matches!(errors[2], ParseError::Unindexed(_)));
+            }
+        }
+
+        let mut arena = Vec::new();
+        let mut p = Parser::new("[1, 2][t", &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 1);
+                assert!(matches!(errors[0], ParseError::UnmatchedBracket(..)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cast_binds_like_prefix() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("(unsigned char)t * 2", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+
+        let ASTNode::Binary(Operator::Mul, l, _) = &arena[root] else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        };
+        if let ASTNode::Cast(ty, operand) = &arena[*l] {
+            assert_eq!(*ty, CType::UChar);
+            assert_eq!(arena[*operand], ASTNode::Variable);
+        } else {
+            panic!("Cast wrong: {:?}", arena[*l]);
+        }
+    }
+
+    #[test]
+    fn test_parentheses_are_counted() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("((t = 1)) ? (t) : t + 1", &mut arena, Mode::Classic);
+        let root = p.parse().unwrap();
+        let parentheses = p.take_parentheses();
+
+        let ASTNode::Ternary(cond, true_branch, false_branch) = arena[root] else {
+            panic!("Top structure wrong: {:?}", arena[root]);
+        };
+        assert_eq!(parentheses[cond], 2);
+        assert_eq!(parentheses[true_branch], 1);
+        assert_eq!(parentheses[false_branch], 0);
+        assert_eq!(parentheses[root], 0);
+    }
+
+    #[test]
+    fn test_cast_errors() {
+        let mut arena = Vec::new();
+        let mut p = Parser::new("(float)t + (short char)t", &mut arena, Mode::Classic);
+        match p.parse() {
+            Ok(_) => panic!("Should have returned errors"),
+            Err(errors) => {
+                assert_eq!(errors.len(), 2);
+                assert!(matches!(
+                    errors[0],
+                    ParseError::InvalidCast(ref ty, Span { line: 0, start: 0, end: 6 }) if ty == "float"
+                ));
+                assert!(
+                    matches!(errors[1], ParseError::InvalidCast(ref ty, _) if ty == "short char")
+                );
+            }
+        }
+
+        // Floatbeats have no integer types
+        let mut arena = Vec::new();
+        let mut p = Parser::new("(int)t", &mut arena, Mode::Float);
+        assert!(matches!(
+            p.parse().unwrap_err()[0],
+            ParseError::InvalidCast(..)
+        ));
+
+        let mut arena = Vec::new();
+        let mut p = Parser::new("(unsigned t", &mut arena, Mode::Classic);
+        assert!(matches!(
+            p.parse().unwrap_err()[0],
+            ParseError::UnmatchedParenthesis(..)
+        ));
+    }
+
     #[test]
     fn test_recovery_in_parens() {
         let mut arena = Vec::new();
         // (@ + 1) * t
-        let mut p = Parser::new("(@ + 1) * t", &mut arena);
+        let mut p = Parser::new("(@ + 1) * t", &mut arena, Mode::Classic);
         match p.parse() {
             Ok(_) => panic!("Should have returned errors"),
             Err(errors) => {
@@ -362,4 +1115,112 @@ mod tests {
             panic!("Root structure wrong");
         }
     }
+
+    /// Every error of a broken beat, in order
+    fn errors(input: &str, mode: Mode) -> Vec<ParseError> {
+        let mut arena = Vec::new();
+        let mut p = Parser::new(input, &mut arena, mode);
+        p.parse().unwrap_err()
+    }
+
+    /// Span of a single character
+    fn at(line: usize, start: usize) -> Span {
+        Span::new(line, start, start)
+    }
+
+    #[test]
+    fn test_recovery_corpus() {
+        let corpus = [
+            (
+                "t ? 1 2 : 3",
+                Mode::Classic,
+                vec![ParseError::ExpectedOperator(
+                    Token::Number(2),
+                    at(0, 6),
+                    at(0, 4),
+                )],
+            ),
+            (
+                "t * (2 3 ? 4 : 5) + 1",
+                Mode::Classic,
+                vec![ParseError::ExpectedOperator(
+                    Token::Number(3),
+                    at(0, 7),
+                    at(0, 5),
+                )],
+            ),
+            (
+                "sin(t 2, cos(t) 3)",
+                Mode::Float,
+                vec![
+                    ParseError::ExpectedOperator(Token::Number(2), at(0, 6), at(0, 4)),
+                    ParseError::ExpectedOperator(Token::Number(3), at(0, 16), at(0, 14)),
+                    ParseError::WrongArity(Builtin::Sin, 1, 2, Span::new(0, 0, 2)),
+                ],
+            ),
+            (
+                "t ? 1 ; 2",
+                Mode::Classic,
+                vec![ParseError::ExpectedTernaryColon(at(0, 9), at(0, 2))],
+            ),
+            (
+                "[1, 2 3][t",
+                Mode::Classic,
+                vec![
+                    ParseError::ExpectedOperator(Token::Number(3), at(0, 6), at(0, 4)),
+                    ParseError::UnmatchedBracket(at(0, 10), at(0, 8)),
+                ],
+            ),
+            (
+                "(int t * 2",
+                Mode::Classic,
+                vec![ParseError::UnmatchedParenthesis(at(0, 5), at(0, 0))],
+            ),
+            (
+                "t + * 3",
+                Mode::Classic,
+                vec![ParseError::UnexpectedPrefix(Operator::Mul, at(0, 4))],
+            ),
+            (
+                "t) + 1 ] : 2",
+                Mode::Classic,
+                vec![
+                    ParseError::UnexpectedToken(Token::Op(Operator::Rparen), at(0, 1)),
+                    ParseError::UnexpectedToken(Token::Op(Operator::Rbracket), at(0, 7)),
+                    ParseError::UnexpectedToken(Token::Op(Operator::Colon), at(0, 9)),
+                ],
+            ),
+            // Lexer errors are still reported while skipping
+            (
+                "t @ 3 + @",
+                Mode::Classic,
+                vec![
+                    ParseError::LexError(LexError::UnexpectedChar('@'), at(0, 2)),
+                    ParseError::LexError(LexError::UnexpectedChar('@'), at(0, 8)),
+                ],
+            ),
+            (
+                "t * 2 3;\nt + ;\n(t >> 4",
+                Mode::Classic,
+                vec![
+                    ParseError::ExpectedOperator(Token::Number(3), at(0, 6), at(0, 4)),
+                    ParseError::UnexpectedPrefix(Operator::Semicolon, at(1, 4)),
+                    ParseError::UnmatchedParenthesis(at(2, 7), at(2, 0)),
+                ],
+            ),
+            (
+                "a = t >> 4 3,\nb = (a & 7 ? 1 : ,\nc = min(a, b;\na + b + c",
+                Mode::Classic,
+                vec![
+                    ParseError::ExpectedOperator(Token::Number(3), at(0, 11), at(0, 9)),
+                    ParseError::UnexpectedPrefix(Operator::Comma, at(1, 17)),
+                    ParseError::UnmatchedParenthesis(at(2, 12), at(2, 7)),
+                    ParseError::UnmatchedParenthesis(at(3, 9), at(1, 4)),
+                ],
+            ),
+        ];
+        for (input, mode, expected) in corpus {
+            assert_eq!(errors(input, mode), expected, "{input:?}");
+        }
+    }
 }
@@ -0,0 +1,502 @@
+//! Turns an AST back into source text. Parentheses come from the parser's binding powers, so only the ones that
+//! change how it parses are printed, and printing what was parsed from the output gives the same output.
+use super::ctype::{CInt, CType};
+use super::parse::{ARGUMENT_BP, ASSIGN_BP, PREFIX_BP, binding_power, infix_binding_power};
+use super::{ASTNode, NodeId, Operator, Place, PrintStyle};
+
+/// Longest a node may be on one line in [`PrintStyle::Expand`] before it's split
+const EXPAND_WIDTH: usize = 40;
+/// Per level, in [`PrintStyle::Expand`]
+const INDENT: &str = "  ";
+
+/// `locals` are the names of [`ASTNode::Local`]s, by [`super::LocalId`], and `knobs` of [`ASTNode::Knob`]s without
+/// the `$`
+pub fn print(
+    nodes: &[ASTNode],
+    root: NodeId,
+    locals: &[String],
+    knobs: &[String],
+    style: PrintStyle,
+) -> String {
+    let printer = Printer {
+        nodes,
+        locals,
+        knobs,
+        style,
+    };
+    match style {
+        PrintStyle::Expand => printer.node(root, Some(0)),
+        _ => printer.node(root, None),
+    }
+}
+
+/// Where a node is printed, which decides if it needs parentheses
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    /// Left operand of an operator with this left binding power
+    Left(u8),
+    /// Parsed with this minimum binding power, like a right operand
+    Right(u8),
+}
+
+/// Operands that can't be split bind like this
+const ATOM: (u8, u8) = (u8::MAX, u8::MAX);
+
+struct Printer<'a> {
+    nodes: &'a [ASTNode],
+    locals: &'a [String],
+    knobs: &'a [String],
+    style: PrintStyle,
+}
+
+impl Printer<'_> {
+    /// Binding powers of the outermost operator, as the parser sees them
+    fn binding(&self, id: NodeId) -> (u8, u8) {
+        match self.nodes[id] {
+            ASTNode::Binary(op, _, _) => binding_power(op).unwrap_or(ATOM),
+            ASTNode::Ternary(..) => infix_binding_power(Operator::Question),
+            ASTNode::Assign(..) => ASSIGN_BP,
+            // Printed with a prefix `-`
+            ASTNode::Unary(..) | ASTNode::Cast(..) => (u8::MAX, PREFIX_BP),
+            ASTNode::Literal(n) if n < 0 && n != i32::MIN => (u8::MAX, PREFIX_BP),
+            ASTNode::TypedLiteral(c) if c.wide() < 0 && c.bits() != i64::MIN => {
+                (u8::MAX, PREFIX_BP)
+            }
+            ASTNode::FloatLiteral(f) if f.is_sign_negative() && f.is_finite() => {
+                (u8::MAX, PREFIX_BP)
+            }
+            _ => ATOM,
+        }
+    }
+
+    fn needs_parens(&self, id: NodeId, slot: Slot) -> bool {
+        let (l_bp, r_bp) = self.binding(id);
+        match slot {
+            // Otherwise the operator would become part of the operand's right side
+            Slot::Left(parent) => r_bp <= parent,
+            Slot::Right(min_bp) => l_bp < min_bp,
+        }
+    }
+
+    /// With its parentheses, if it needs them
+    fn operand(&self, id: NodeId, slot: Slot, indent: Option<usize>) -> String {
+        if self.needs_parens(id, slot) {
+            self.wrapped("(", id, ")", indent)
+        } else {
+            self.node(id, indent)
+        }
+    }
+
+    /// Between `open` and `close`, and on lines of its own if it's too long
+    fn wrapped(&self, open: &str, id: NodeId, close: &str, indent: Option<usize>) -> String {
+        let inline = self.node(id, None);
+        match indent {
+            Some(indent) if inline.len() > EXPAND_WIDTH => {
+                let inner = self.node(id, Some(indent + 1));
+                format!(
+                    "{open}\n{}{inner}\n{}{close}",
+                    INDENT.repeat(indent + 1),
+                    INDENT.repeat(indent)
+                )
+            }
+            _ => format!("{open}{inline}{close}"),
+        }
+    }
+
+    /// Split over several lines, continued at `indent`, if it's [`PrintStyle::Expand`] and too long
+    fn node(&self, id: NodeId, indent: Option<usize>) -> String {
+        match indent {
+            Some(indent) => {
+                let inline = self.node(id, None);
+                if inline.len() <= EXPAND_WIDTH {
+                    inline
+                } else {
+                    self.expanded(id, indent).unwrap_or(inline)
+                }
+            }
+            None => self.inline(id),
+        }
+    }
+
+    fn inline(&self, id: NodeId) -> String {
+        match &self.nodes[id] {
+            ASTNode::Literal(n) => self.int(*n),
+            ASTNode::TypedLiteral(c) => self.typed(*c),
+            ASTNode::FloatLiteral(f) => self.float(*f),
+            ASTNode::Variable => "t".to_string(),
+            ASTNode::Local(local) => self.locals[*local].clone(),
+            ASTNode::Knob(knob) => format!("${}", self.knobs[*knob]),
+            ASTNode::Assign(op, place, value) => {
+                let value = self.operand(*value, Slot::Right(ASSIGN_BP.1), None);
+                self.infix(&self.place(*place), *op, &value)
+            }
+            ASTNode::Unary(op, operand) => {
+                let operand = self.operand(*operand, Slot::Right(PREFIX_BP), None);
+                prefix(op.symbol(), &operand)
+            }
+            ASTNode::Cast(ty, operand) => {
+                let operand = self.operand(*operand, Slot::Right(PREFIX_BP), None);
+                format!("({ty}){operand}")
+            }
+            ASTNode::Binary(op, left, right) => {
+                let (l_bp, r_bp) = binding_power(*op).unwrap_or(ATOM);
+                let left = self.operand(*left, Slot::Left(l_bp), None);
+                let right = self.operand(*right, Slot::Right(r_bp), None);
+                self.infix(&left, *op, &right)
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                let (l_bp, r_bp) = infix_binding_power(Operator::Question);
+                let cond = self.operand(*cond, Slot::Left(l_bp), None);
+                let true_branch = self.node(*true_branch, None);
+                let false_branch = self.operand(*false_branch, Slot::Right(r_bp), None);
+                let body = self.infix(&true_branch, Operator::Colon, &false_branch);
+                self.infix(&cond, Operator::Question, &body)
+            }
+            ASTNode::Call(builtin, args) => format!("{builtin}({})", self.list(args)),
+            ASTNode::Str(s) => quote(s),
+            ASTNode::Array(items) => format!("[{}]", self.list(items)),
+            ASTNode::Index(table, index) => {
+                format!("{}[{}]", self.node(*table, None), self.node(*index, None))
+            }
+            // A beat with errors can't be parsed, so it's never printed
+            ASTNode::Error(_) => String::new(),
+        }
+    }
+
+    /// None if it can't be split
+    fn expanded(&self, id: NodeId, indent: usize) -> Option<String> {
+        let newline = |indent: usize| format!("\n{}", INDENT.repeat(indent));
+        let expanded = match &self.nodes[id] {
+            ASTNode::Assign(op, place, value) => {
+                let value = self.operand(*value, Slot::Right(ASSIGN_BP.1), Some(indent + 1));
+                self.infix(&self.place(*place), *op, &value)
+            }
+            ASTNode::Unary(op, operand) => {
+                let operand = self.operand(*operand, Slot::Right(PREFIX_BP), Some(indent));
+                prefix(op.symbol(), &operand)
+            }
+            ASTNode::Cast(ty, operand) => {
+                let operand = self.operand(*operand, Slot::Right(PREFIX_BP), Some(indent));
+                format!("({ty}){operand}")
+            }
+            // Every statement on a line of its own
+            ASTNode::Binary(op @ (Operator::Comma | Operator::Semicolon), _, _) => self
+                .chain(id, *op)
+                .into_iter()
+                .map(|(operand, slot)| self.operand(operand, slot, Some(indent)))
+                .collect::<Vec<_>>()
+                .join(&format!("{op}{}", newline(indent))),
+            // Every operand on a line of its own, after the operator
+            ASTNode::Binary(op, _, _) => {
+                let mut lines = self
+                    .chain(id, *op)
+                    .into_iter()
+                    .map(|(operand, slot)| self.operand(operand, slot, Some(indent + 1)));
+                let mut expanded = lines.next().unwrap_or_default();
+                for line in lines {
+                    expanded.push_str(&newline(indent));
+                    expanded.push_str(&prefix(&format!("{op} "), &line));
+                }
+                expanded
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                let (l_bp, r_bp) = infix_binding_power(Operator::Question);
+                let cond = self.operand(*cond, Slot::Left(l_bp), Some(indent + 1));
+                let true_branch = self.node(*true_branch, Some(indent + 1));
+                let false_branch = self.operand(*false_branch, Slot::Right(r_bp), Some(indent + 1));
+                format!(
+                    "{cond}{}? {true_branch}{}: {false_branch}",
+                    newline(indent),
+                    newline(indent)
+                )
+            }
+            ASTNode::Call(builtin, args) => {
+                format!("{builtin}({}", self.expanded_list(args, indent, ")"))
+            }
+            ASTNode::Array(items) => format!("[{}", self.expanded_list(items, indent, "]")),
+            ASTNode::Index(table, index) => {
+                let table = self.node(*table, Some(indent));
+                table + &self.wrapped("[", *index, "]", Some(indent))
+            }
+            _ => return None,
+        };
+        Some(expanded)
+    }
+
+    /// Operands of `op` where it's repeated on the left, like `a | b | c`, from left to right
+    fn chain(&self, id: NodeId, op: Operator) -> Vec<(NodeId, Slot)> {
+        let (l_bp, r_bp) = binding_power(op).unwrap_or(ATOM);
+        let mut operands = Vec::new();
+        let mut id = id;
+        loop {
+            match self.nodes[id] {
+                ASTNode::Binary(inner, left, right) if inner == op => {
+                    operands.push((right, Slot::Right(r_bp)));
+                    if self.needs_parens(left, Slot::Left(l_bp)) {
+                        operands.push((left, Slot::Left(l_bp)));
+                        break;
+                    }
+                    id = left;
+                }
+                _ => {
+                    operands.push((id, Slot::Left(l_bp)));
+                    break;
+                }
+            }
+        }
+        operands.reverse();
+        operands
+    }
+
+    /// Arguments or array items, separated by commas
+    fn list(&self, items: &[NodeId]) -> String {
+        let separator = match self.style {
+            PrintStyle::Minify => ",",
+            _ => ", ",
+        };
+        items
+            .iter()
+            .map(|item| self.operand(*item, Slot::Right(ARGUMENT_BP), None))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    /// One item per line, then `close` on a line of its own. The opening bracket is already written.
+    fn expanded_list(&self, items: &[NodeId], indent: usize, close: &str) -> String {
+        let mut expanded = String::new();
+        for (i, item) in items.iter().enumerate() {
+            expanded.push('\n');
+            expanded.push_str(&INDENT.repeat(indent + 1));
+            expanded.push_str(&self.operand(*item, Slot::Right(ARGUMENT_BP), Some(indent + 1)));
+            if i + 1 < items.len() {
+                expanded.push(',');
+            }
+        }
+        format!("{expanded}\n{}{close}", INDENT.repeat(indent))
+    }
+
+    fn infix(&self, left: &str, op: Operator, right: &str) -> String {
+        match (self.style, op) {
+            (PrintStyle::Minify, _) => prefix(&format!("{left}{op}"), right),
+            (_, Operator::Comma | Operator::Semicolon) => format!("{left}{op} {right}"),
+            _ => format!("{left} {op} {right}"),
+        }
+    }
+
+    fn place(&self, place: Place) -> String {
+        match place {
+            Place::T => "t".to_string(),
+            Place::Local(local) => self.locals[local].clone(),
+        }
+    }
+
+    fn int(&self, n: i32) -> String {
+        match n {
+            // Can't be negated from a literal, 2147483648 is too big
+            i32::MIN => format!("({})", self.infix("-2147483647", Operator::Minus, "1")),
+            _ => n.to_string(),
+        }
+    }
+
+    /// With the suffix that gives it its type back. Only folded beats have negative ones.
+    fn typed(&self, c: CInt) -> String {
+        let suffix = match c.ty() {
+            CType::UInt => "U",
+            CType::Long => "L",
+            CType::ULong => "UL",
+            _ => "",
+        };
+        match c.bits() {
+            i64::MIN if c.ty() == CType::Long => format!(
+                "({})",
+                self.infix("-9223372036854775807L", Operator::Minus, "1")
+            ),
+            _ => format!("{}{suffix}", c.wide()),
+        }
+    }
+
+    /// Only folded beats have the special values, they can't be written as literals
+    fn float(&self, f: f64) -> String {
+        if f.is_nan() {
+            "(0/0)".to_string()
+        } else if f.is_infinite() {
+            if f > 0.0 { "(1/0)" } else { "(-1/0)" }.to_string()
+        } else {
+            let magnitude = f.abs();
+            let plain = magnitude.to_string();
+            let exponent = format!("{magnitude:e}");
+            // Without a '.' it's lexed as an integer, which saturates at u64::MAX
+            let text = if (!plain.contains('.') && magnitude > u64::MAX as f64)
+                || (self.style == PrintStyle::Minify && exponent.len() < plain.len())
+            {
+                exponent
+            } else {
+                plain
+            };
+            if f.is_sign_negative() {
+                prefix("-", &text)
+            } else {
+                text
+            }
+        }
+    }
+}
+
+/// Keeps `- -x` from becoming `--x`, which C reads as a decrement
+fn prefix(prefix: &str, text: &str) -> String {
+    if prefix.ends_with('-') && text.starts_with('-') {
+        format!("{prefix} {text}")
+    } else {
+        format!("{prefix}{text}")
+    }
+}
+
+/// Double quoted, with the escapes the lexer understands
+fn quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::eval::Arithmetic;
+    use crate::parser::{Mode, TType, fold::fold, format, parse::Parser, typecheck};
+
+    fn canonical(source: &str) -> String {
+        format(source, Mode::Classic, PrintStyle::Canonical).unwrap()
+    }
+
+    fn minified(source: &str) -> String {
+        format(source, Mode::Classic, PrintStyle::Minify).unwrap()
+    }
+
+    #[test]
+    fn test_minimal_parens() {
+        assert_eq!(canonical("((t*2))+(1)"), "t * 2 + 1");
+        assert_eq!(canonical("(t+2)*1"), "(t + 2) * 1");
+        assert_eq!(canonical("t-(1-2)"), "t - (1 - 2)");
+        assert_eq!(canonical("(t-1)-2"), "t - 1 - 2");
+        assert_eq!(canonical("(t>>4)&(t>>8)"), "t >> 4 & t >> 8");
+        assert_eq!(canonical("-(t*2)"), "-(t * 2)");
+    }
+
+    #[test]
+    fn test_right_associative() {
+        assert_eq!(canonical("a=(b=t)"), "a = b = t");
+        assert_eq!(canonical("t?1:(t?2:3)"), "t ? 1 : t ? 2 : 3");
+        assert_eq!(canonical("(t?1:t)?2:3"), "(t ? 1 : t) ? 2 : 3");
+        assert_eq!(canonical("t?(a=1):(a=2)"), "t ? a = 1 : (a = 2)");
+        assert_eq!(canonical("(a=t)+1"), "(a = t) + 1");
+    }
+
+    #[test]
+    fn test_sequences_and_lists() {
+        assert_eq!(canonical("a=t>>4,b=t;a|b;"), "a = t >> 4, b = t; a | b");
+        assert_eq!(canonical("pow((t,2),3)"), "pow((t, 2), 3)");
+        assert_eq!(canonical("[t,(1,2)][t&1]"), "[t, (1, 2)][t & 1]");
+        assert_eq!(canonical("\"a\\\"b\\n\"[t]"), "\"a\\\"b\\n\"[t]");
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(minified("t * ( t >> 5 | t >> 8 )"), "t*(t>>5|t>>8)");
+        assert_eq!(minified("t - -t"), "t- -t");
+        assert_eq!(minified("- -t"), "- -t");
+        assert_eq!(minified("sin( t , )"), "sin(t)");
+        assert_eq!(minified("a = 1, b = 2"), "a=1,b=2");
+    }
+
+    #[test]
+    fn test_expand() {
+        let source = "a = t * (t >> 12 & 7) * (t >> 4 | t >> 9 | t >> 3), \
+            b = t >> 6 & t >> 9 ? a - (t >> 3) : [t, t * 2, t * 3 & t >> 4, t * 5 ^ t >> 7][t >> 14 & 3]; a ^ b";
+        let expanded = format(source, Mode::Classic, PrintStyle::Expand).unwrap();
+        let expected = [
+            "a = t",
+            "  * (t >> 12 & 7)",
+            "  * (t >> 4 | t >> 9 | t >> 3),",
+            "b = t >> 6 & t >> 9",
+            "  ? a - (t >> 3)",
+            "  : [",
+            "      t,",
+            "      t * 2,",
+            "      t * 3 & t >> 4,",
+            "      t * 5 ^ t >> 7",
+            "    ][t >> 14 & 3];",
+            "a ^ b",
+        ];
+        assert_eq!(expanded, expected.join("\n"));
+        assert_eq!(canonical(&expanded), canonical(source));
+
+        let source = "t > max(1234567890 + t * 99999, t >> 3 & 1234567 | t * 7654321)";
+        let expanded = format(source, Mode::Classic, PrintStyle::Expand).unwrap();
+        let expected = [
+            "t",
+            "> max(",
+            "    1234567890 + t * 99999,",
+            "    t >> 3 & 1234567 | t * 7654321",
+            "  )",
+        ];
+        assert_eq!(expanded, expected.join("\n"));
+        assert_eq!(canonical(&expanded), canonical(source));
+        // Short enough for one line
+        assert_eq!(
+            format("t*((t>>12|t>>8)&63)", Mode::Classic, PrintStyle::Expand).unwrap(),
+            "t * ((t >> 12 | t >> 8) & 63)"
+        );
+    }
+
+    #[test]
+    fn test_folded_literals() {
+        let print_folded = |source: &str, mode: Mode| {
+            let mut nodes = Vec::new();
+            let mut parser = Parser::new(source, &mut nodes, mode);
+            let root = parser.parse().unwrap();
+            let mut spans = parser.take_spans();
+            let knobs = parser.take_knobs();
+            let locals = parser.into_locals();
+            let (root, arithmetic) = match mode {
+                Mode::Classic => typecheck::check(&mut nodes, &mut spans, root, TType::Int),
+                Mode::Float => (root, Arithmetic::Float),
+            };
+            let root = fold(&mut nodes, root, arithmetic, Default::default());
+            print(&nodes, root, &locals, &knobs, PrintStyle::Minify)
+        };
+        assert_eq!(
+            print_folded("-2147483647-1", Mode::Classic),
+            "(-2147483647-1)"
+        );
+        assert_eq!(print_folded("t-(0-5)", Mode::Classic), "t- -5");
+        assert_eq!(print_folded("0/0+t", Mode::Float), "(0/0)+t");
+        assert_eq!(print_folded("-1/0", Mode::Float), "(-1/0)");
+        assert_eq!(print_folded("1e10*1", Mode::Float), "1e10");
+        assert_eq!(print_folded("-0.25", Mode::Float), "-0.25");
+        assert_eq!(print_folded("0u-1+t", Mode::Classic), "4294967295U+t");
+        assert_eq!(
+            print_folded("-(1L<<63)", Mode::Classic),
+            "(-9223372036854775807L-1)"
+        );
+        assert_eq!(print_folded("t-(0L-5)", Mode::Classic), "t- -5L");
+    }
+
+    #[test]
+    fn test_casts() {
+        assert_eq!(canonical("(unsigned  char)(t*2)"), "(unsigned char)(t * 2)");
+        assert_eq!(canonical("((short)t)*2"), "(short)t * 2");
+        assert_eq!(canonical("(long)-t"), "(long)-t");
+        assert_eq!(minified("(unsigned int)t >> 1u"), "(unsigned)t>>1U");
+    }
+}
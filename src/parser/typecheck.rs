@@ -0,0 +1,247 @@
+//! Finds the C type of every node of a classic beat, and decides if it can be evaluated as `i32` or needs [`CInt`].
+//!
+//! [`CInt`] values carry their type, so most conversions happen on their own. The ones that can't be seen from a
+//! value alone are made explicit with [`ASTNode::Cast`]: both branches of a ternary have the type of the pair, array
+//! elements are `int` like in `(int[]){...}`, and whatever is assigned takes the type of its variable.
+//!
+//! [`CInt`]: super::ctype::CInt
+use super::ctype::CType;
+use super::eval::Arithmetic;
+use super::{ASTNode, NodeId, Operator, Place, Span, TType};
+
+/// Returns the new root, and what the beat must be evaluated with. Nodes are only added or rewritten for
+/// [`Arithmetic::C`], and `spans` is kept in step with them.
+pub fn check(
+    nodes: &mut Vec<ASTNode>,
+    spans: &mut Vec<Span>,
+    root: NodeId,
+    t_type: TType,
+) -> (NodeId, Arithmetic) {
+    let t = t_ctype(t_type);
+    let types = types(nodes, &[root], t_type);
+    if t == CType::Int && types.iter().all(|ty| *ty == CType::Int) {
+        return (root, Arithmetic::Int);
+    }
+
+    let mut checker = Checker {
+        nodes,
+        spans,
+        types,
+        t,
+    };
+    checker.convert_children(root);
+    if t == CType::Int {
+        return (root, Arithmetic::C);
+    }
+    // 't' starts out as an `int`, so it's converted once before anything else. It isn't in the source.
+    let span = Span::new(0, 0, 0);
+    let variable = checker.push(ASTNode::Variable, CType::Int, span);
+    let cast = checker.push(ASTNode::Cast(t, variable), t, span);
+    let start = checker.push(ASTNode::Assign(Operator::Assign, Place::T, cast), t, span);
+    let ty = checker.types[root];
+    let root = checker.push(ASTNode::Binary(Operator::Semicolon, start, root), ty, span);
+    (root, Arithmetic::C)
+}
+
+/// Promoted C type of every node, by [`NodeId`]. Nodes that aren't below any of `roots` are `int`.
+pub fn types(nodes: &[ASTNode], roots: &[NodeId], t_type: TType) -> Vec<CType> {
+    let mut types = Types {
+        nodes,
+        types: vec![CType::Int; nodes.len()],
+        t: t_ctype(t_type),
+    };
+    for &root in roots {
+        types.ty(root);
+    }
+    types.types
+}
+
+fn t_ctype(t_type: TType) -> CType {
+    match t_type {
+        TType::Int => CType::Int,
+        TType::Unsigned => CType::UInt,
+    }
+}
+
+/// C type of a variable
+fn place_type(place: Place, t: CType) -> CType {
+    match place {
+        Place::T => t,
+        Place::Local(_) => CType::Int,
+    }
+}
+
+struct Types<'a> {
+    nodes: &'a [ASTNode],
+    /// Promoted, by [`NodeId`]
+    types: Vec<CType>,
+    /// Type of 't'
+    t: CType,
+}
+
+impl Types<'_> {
+    /// Records the type of the node and everything below it
+    fn ty(&mut self, id: NodeId) -> CType {
+        let ty = match self.nodes[id].clone() {
+            ASTNode::TypedLiteral(c) => c.ty(),
+            ASTNode::Variable => self.t,
+            ASTNode::Assign(_, place, value) => {
+                self.ty(value);
+                place_type(place, self.t)
+            }
+            ASTNode::Unary(op, operand) => {
+                let ty = self.ty(operand);
+                match op {
+                    Operator::LogNot => CType::Int,
+                    _ => ty,
+                }
+            }
+            ASTNode::Cast(ty, operand) => {
+                self.ty(operand);
+                ty.promoted()
+            }
+            ASTNode::Binary(op, left, right) => {
+                let (l, r) = (self.ty(left), self.ty(right));
+                binary(op, l, r)
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                self.ty(cond);
+                let (t, f) = (self.ty(true_branch), self.ty(false_branch));
+                t.common(f)
+            }
+            ASTNode::Call(_, args) => {
+                for arg in args {
+                    self.ty(arg);
+                }
+                CType::Int
+            }
+            ASTNode::Index(table, index) => {
+                self.ty(index);
+                if let ASTNode::Array(items) = self.nodes[table].clone() {
+                    for item in items {
+                        self.ty(item);
+                    }
+                }
+                CType::Int
+            }
+            ASTNode::Literal(_)
+            | ASTNode::FloatLiteral(_)
+            | ASTNode::Local(_)
+            | ASTNode::Knob(_)
+            | ASTNode::Str(_)
+            | ASTNode::Array(_)
+            | ASTNode::Error(_) => CType::Int,
+        };
+        self.types[id] = ty;
+        ty
+    }
+}
+
+struct Checker<'a> {
+    nodes: &'a mut Vec<ASTNode>,
+    spans: &'a mut Vec<Span>,
+    /// Promoted, by [`NodeId`]
+    types: Vec<CType>,
+    /// Type of 't'
+    t: CType,
+}
+
+impl Checker<'_> {
+    fn push(&mut self, node: ASTNode, ty: CType, span: Span) -> NodeId {
+        self.nodes.push(node);
+        self.types.push(ty);
+        self.spans.push(span);
+        self.nodes.len() - 1
+    }
+
+    /// In place, so whatever refers to the node now refers to the cast
+    fn convert(&mut self, id: NodeId, ty: CType) {
+        if self.types[id] != ty {
+            let moved = self.push(self.nodes[id].clone(), self.types[id], self.spans[id]);
+            self.nodes[id] = ASTNode::Cast(ty, moved);
+            self.types[id] = ty.promoted();
+        }
+    }
+
+    /// Makes the conversions C would do to the node's operands explicit, and everything below them
+    fn convert_children(&mut self, id: NodeId) {
+        match self.nodes[id].clone() {
+            ASTNode::Assign(op, place, value) => {
+                self.convert_children(value);
+                let ty = place_type(place, self.t);
+                match op.compound_base() {
+                    // `a += b` is `a = a + b`, but the sum may not have the type of `a`
+                    Some(base) if binary(base, ty, self.types[value]) != ty => {
+                        let current = match place {
+                            Place::T => ASTNode::Variable,
+                            Place::Local(local) => ASTNode::Local(local),
+                        };
+                        let span = self.spans[id];
+                        let current = self.push(current, ty, span);
+                        let result = binary(base, ty, self.types[value]);
+                        let value = self.push(ASTNode::Binary(base, current, value), result, span);
+                        self.convert(value, ty);
+                        self.nodes[id] = ASTNode::Assign(Operator::Assign, place, value);
+                    }
+                    Some(_) => {}
+                    None => self.convert(value, ty),
+                }
+            }
+            ASTNode::Unary(_, operand) | ASTNode::Cast(_, operand) => {
+                self.convert_children(operand)
+            }
+            ASTNode::Binary(_, left, right) => {
+                self.convert_children(left);
+                self.convert_children(right);
+            }
+            ASTNode::Ternary(cond, true_branch, false_branch) => {
+                self.convert_children(cond);
+                self.convert_children(true_branch);
+                self.convert_children(false_branch);
+                let ty = self.types[id];
+                self.convert(true_branch, ty);
+                self.convert(false_branch, ty);
+            }
+            ASTNode::Call(_, args) => {
+                for arg in args {
+                    self.convert_children(arg);
+                }
+            }
+            ASTNode::Index(table, index) => {
+                self.convert_children(index);
+                if let ASTNode::Array(items) = self.nodes[table].clone() {
+                    for item in items {
+                        self.convert_children(item);
+                        self.convert(item, CType::Int);
+                    }
+                }
+            }
+            ASTNode::Literal(_)
+            | ASTNode::TypedLiteral(_)
+            | ASTNode::FloatLiteral(_)
+            | ASTNode::Variable
+            | ASTNode::Local(_)
+            | ASTNode::Knob(_)
+            | ASTNode::Str(_)
+            | ASTNode::Array(_)
+            | ASTNode::Error(_) => {}
+        }
+    }
+}
+
+/// Type of `l op r`
+fn binary(op: Operator, l: CType, r: CType) -> CType {
+    match op {
+        Operator::Lsh | Operator::Rsh => l,
+        Operator::LogAnd
+        | Operator::LogOr
+        | Operator::Eq
+        | Operator::Ne
+        | Operator::Gt
+        | Operator::Lt
+        | Operator::Ge
+        | Operator::Le => CType::Int,
+        Operator::Comma | Operator::Semicolon => r,
+        _ => l.common(r),
+    }
+}